@@ -0,0 +1,19 @@
+// Passes the git revision being built to the crate as GIT_REVISION, which checkpoints are saved
+// with. Builds outside a git checkout go without it
+
+use std::process::Command;
+
+fn main() {
+    let revision = Command::new("git")
+        .args(["describe", "--always", "--dirty"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(revision) = revision {
+        println!("cargo:rustc-env=GIT_REVISION={}", revision.trim());
+    }
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/index");
+    println!("cargo:rerun-if-changed=src");
+}
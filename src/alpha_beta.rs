@@ -0,0 +1,113 @@
+use rand::{rngs::StdRng, seq::SliceRandom};
+
+use crate::game::{move_indices, Game, Players, Policy};
+
+/// Classical baseline player, a fixed depth minimax search with alpha-beta pruning that scores
+/// the positions at the depth limit with Game::heuristic_value. Only as strong as the heuristic,
+/// games without one are searched for forced wins and losses only
+#[derive(Clone, Copy, Debug)]
+pub struct AlphaBetaPolicy {
+    pub depth: usize,
+}
+
+impl AlphaBetaPolicy {
+    /// Searches every position to the end of the game, perfect play for games small enough to
+    /// search whole like tic-tac-toe or 3x3 Hex
+    pub fn exact() -> Self {
+        Self { depth: usize::MAX }
+    }
+
+    // Value for Player, and whether it is proven, which it is when no position the value depends
+    // on was scored by the heuristic. Player maximizes and Opponent minimizes, which also works
+    // for games where the same player moves several times in a row. Chance events are not
+    // searched, the heuristic scores the position before them
+    fn search<const N: usize, const I: usize, T: Game<N, I>>(
+        game: &mut T,
+        depth: usize,
+        mut alpha: f32,
+        mut beta: f32,
+    ) -> anyhow::Result<(f32, bool)> {
+        if let Some(value) = game.terminal_value(Players::Player) {
+            return Ok((value, true));
+        }
+        if depth == 0 || game.chance_outcomes().is_some() {
+            return Ok((game.heuristic_value(), false));
+        }
+        let maximizing = game.current_player() == Players::Player;
+        let mut best = if maximizing { -f32::MAX } else { f32::MAX };
+        let mut proven = true;
+        for mv in move_indices(game) {
+            game.try_perform_move(mv)?;
+            let (value, exact) = Self::search(game, depth - 1, alpha, beta)?;
+            game.undo_move()?;
+            proven &= exact;
+            if maximizing {
+                best = best.max(value);
+                alpha = alpha.max(value);
+            } else {
+                best = best.min(value);
+                beta = beta.min(value);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+        Ok((best, proven))
+    }
+
+    // Root moves with their values for Player
+    fn move_values<const N: usize, const I: usize, T: Game<N, I>>(
+        &self,
+        game: &T,
+    ) -> anyhow::Result<Vec<(usize, f32)>> {
+        let mut game = game.clone();
+        let mut values = Vec::new();
+        for mv in move_indices(&game) {
+            game.try_perform_move(mv)?;
+            let (value, _) = Self::search(&mut game, self.depth.saturating_sub(1), -1.0, 1.0)?;
+            game.undo_move()?;
+            values.push((mv, value));
+        }
+        Ok(values)
+    }
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>> Policy<N, I, T> for AlphaBetaPolicy {
+    // Ties between equally good moves are broken randomly, so games against it vary
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> anyhow::Result<usize> {
+        let mut values = self.move_values(game)?;
+        values.shuffle(rng);
+        let sign = match game.current_player() {
+            Players::Player => 1.0,
+            Players::Opponent => -1.0,
+        };
+        let (best_move, _) = values
+            .into_iter()
+            .max_by(|(_, a), (_, b)| (sign * a).total_cmp(&(sign * b)))
+            .ok_or_else(|| anyhow::anyhow!("No moves available"))?;
+        Ok(best_move)
+    }
+
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> anyhow::Result<Vec<usize>> {
+        games
+            .iter()
+            .map(|game| self.select_move(*game, rng))
+            .collect()
+    }
+
+    fn predict_score(&self, game: &T) -> anyhow::Result<f32> {
+        Ok(Self::search(&mut game.clone(), self.depth, -1.0, 1.0)?.0)
+    }
+
+    fn can_predict_score(&self) -> bool {
+        true
+    }
+
+    // Positions the search sees the end of from here, all of them for AlphaBetaPolicy::exact
+    fn exact_score(&self, game: &T) -> Option<f32> {
+        match Self::search(&mut game.clone(), self.depth, -1.0, 1.0) {
+            Ok((value, true)) => Some(value),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,406 @@
+use std::fmt::Display;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::{
+    game::{encode_board, swap_board, Cell, Game, Players},
+    mcts::GameStats,
+};
+
+const SIDE: usize = 10;
+const SQUARES: usize = SIDE * SIDE;
+pub const MOVES: usize = SQUARES * SQUARES;
+pub const STATE_LEN: usize = SQUARES * 4;
+const DIRECTIONS: [(isize, isize); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Square {
+    Empty,
+    Amazon(Players),
+    Arrow,
+}
+
+// Player's amazons, Opponent's amazons and arrows
+impl Cell for Square {
+    const FEATURES: usize = 3;
+
+    fn encode(&self, out: &mut [f32]) {
+        out.fill(0.0);
+        match self {
+            Square::Amazon(Players::Player) => out[0] = 1.0,
+            Square::Amazon(Players::Opponent) => out[1] = 1.0,
+            Square::Arrow => out[2] = 1.0,
+            Square::Empty => {}
+        }
+    }
+
+    fn swap(&self) -> Self {
+        match self {
+            Square::Amazon(player) => Square::Amazon(player.swap()),
+            square => *square,
+        }
+    }
+}
+
+/// The Game of the Amazons on a 10x10 board. A turn is a queen move of one of your amazons
+/// followed by an arrow shot like a queen from where it landed, the arrow blocks its square for
+/// the rest of the game. Whoever cannot move loses. A whole turn as one index would need
+/// 100^3 moves, so the two halves are separate moves by the same player: queen moves are
+/// from * 100 + to, and the arrow that follows is the index of its target square. Use
+/// try_perform_moves to play both at once
+#[derive(Debug, Clone)]
+pub struct Amazons {
+    // Row major from the top left, Player starts on the bottom half
+    board: [Square; SQUARES],
+    current_player: Players,
+    // The amazon that moved and still has to shoot
+    shooting_from: Option<usize>,
+    // The moves played, with where the amazon had to shoot from before arrows, for undo_move
+    history: Vec<(usize, Option<usize>)>,
+}
+
+impl Amazons {
+    /// Move index of the queen move of the amazon on `from` to `to`
+    pub fn move_index(from: usize, to: usize) -> usize {
+        from * SQUARES + to
+    }
+
+    // Empty squares a queen on `from` reaches
+    fn reachable(&self, from: usize) -> Vec<usize> {
+        let (row, column) = ((from / SIDE) as isize, (from % SIDE) as isize);
+        let mut squares = Vec::new();
+        for (dr, dc) in DIRECTIONS {
+            let (mut r, mut c) = (row + dr, column + dc);
+            while (0..SIDE as isize).contains(&r) && (0..SIDE as isize).contains(&c) {
+                let square = r as usize * SIDE + c as usize;
+                if self.board[square] != Square::Empty {
+                    break;
+                }
+                squares.push(square);
+                r += dr;
+                c += dc;
+            }
+        }
+        squares
+    }
+
+    fn legal_moves(&self) -> Vec<usize> {
+        if let Some(from) = self.shooting_from {
+            return self.reachable(from);
+        }
+        (0..SQUARES)
+            .filter(|square| self.board[*square] == Square::Amazon(self.current_player))
+            .flat_map(|from| {
+                self.reachable(from)
+                    .into_iter()
+                    .map(move |to| Self::move_index(from, to))
+            })
+            .collect()
+    }
+
+    fn square_name(square: usize) -> String {
+        let (row, column) = (square / SIDE, square % SIDE);
+        format!("{}{}", (b'a' + column as u8) as char, SIDE - row)
+    }
+
+    fn parse_square(text: &str) -> Result<usize> {
+        let mut chars = text.chars();
+        let column = chars.next().context("Empty square")?;
+        let rank: usize = chars
+            .as_str()
+            .parse()
+            .with_context(|| format!("Bad square '{}'", text))?;
+        ensure!(
+            ('a'..='j').contains(&column) && (1..=SIDE).contains(&rank),
+            "Square '{}' is outside the board",
+            text
+        );
+        Ok((SIDE - rank) * SIDE + (column as usize - 'a' as usize))
+    }
+
+    // Where a square ends up under one of the 8 rotations and reflections of the board,
+    // transposed from 4 on, then mirrored left to right and top to bottom by the two lowest bits
+    fn symmetric(square: usize, symmetry: usize) -> usize {
+        let (mut row, mut column) = (square / SIDE, square % SIDE);
+        if symmetry >= 4 {
+            (row, column) = (column, row);
+        }
+        if symmetry & 1 == 1 {
+            column = SIDE - 1 - column;
+        }
+        if symmetry & 2 == 2 {
+            row = SIDE - 1 - row;
+        }
+        row * SIDE + column
+    }
+}
+
+impl Game<MOVES, STATE_LEN> for Amazons {
+    fn winning_player(&self) -> Option<Players> {
+        self.game_ended().then(|| self.current_player.swap())
+    }
+
+    fn available_moves(&self) -> [bool; MOVES] {
+        let mut moves = [false; MOVES];
+        for mv in self.legal_moves() {
+            moves[mv] = true;
+        }
+        moves
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(
+            self.legal_moves().contains(&space),
+            "Tried to make an illegal amazons move"
+        );
+        self.history.push((space, self.shooting_from));
+        match self.shooting_from.take() {
+            Some(_) => {
+                self.board[space] = Square::Arrow;
+                self.current_player = self.current_player.swap();
+            }
+            None => {
+                let (from, to) = (space / SQUARES, space % SQUARES);
+                self.board[from] = Square::Empty;
+                self.board[to] = Square::Amazon(self.current_player);
+                self.shooting_from = Some(to);
+            }
+        }
+        Ok(())
+    }
+
+    fn undo_move(&mut self) -> Result<()> {
+        let (space, shooting_from) = self.history.pop().context("No move to undo")?;
+        match shooting_from {
+            Some(_) => {
+                self.board[space] = Square::Empty;
+                self.current_player = self.current_player.swap();
+            }
+            None => {
+                let (from, to) = (space / SQUARES, space % SQUARES);
+                self.board[to] = Square::Empty;
+                self.board[from] = Square::Amazon(self.current_player);
+            }
+        }
+        self.shooting_from = shooting_from;
+        Ok(())
+    }
+
+    // White, which is Player, on a4, d1, g1 and j4, Black on a7, d10, g10 and j7
+    fn new() -> Self {
+        let mut board = [Square::Empty; SQUARES];
+        for square in ["a4", "d1", "g1", "j4"] {
+            board[Self::parse_square(square).unwrap()] = Square::Amazon(Players::Player);
+        }
+        for square in ["a7", "d10", "g10", "j7"] {
+            board[Self::parse_square(square).unwrap()] = Square::Amazon(Players::Opponent);
+        }
+        Self {
+            board,
+            current_player: Players::Player,
+            shooting_from: None,
+            history: Vec::new(),
+        }
+    }
+
+    // After a queen move there is always somewhere to shoot, at least the square it came from
+    fn game_ended(&self) -> bool {
+        self.shooting_from.is_none() && self.legal_moves().is_empty()
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    fn flip_board(&mut self) {
+        swap_board(&mut self.board);
+        self.current_player = self.current_player.swap();
+    }
+
+    // Planes of Player's amazons, Opponent's amazons, arrows and the amazon that has to shoot
+    fn get_game_state_slice(&self) -> [f32; STATE_LEN] {
+        let mut out_slice: [f32; STATE_LEN] = encode_board(&self.board);
+        if let Some(from) = self.shooting_from {
+            out_slice[3 * SQUARES + from] = 1.0;
+        }
+        out_slice
+    }
+
+    // Queen moves and arrows do not change under the 8 rotations and reflections of the board.
+    // Which kind of move an index is depends on whether an amazon has to shoot, which the last
+    // plane tells
+    fn get_game_variations(
+        stats: &GameStats<MOVES, STATE_LEN>,
+    ) -> Vec<GameStats<MOVES, STATE_LEN>> {
+        let shooting = stats.game_state[3 * SQUARES..].contains(&1.0);
+        let symmetric_move = |mv: usize, symmetry: usize| {
+            if shooting {
+                Self::symmetric(mv, symmetry)
+            } else {
+                Self::move_index(
+                    Self::symmetric(mv / SQUARES, symmetry),
+                    Self::symmetric(mv % SQUARES, symmetry),
+                )
+            }
+        };
+        (0..8)
+            .map(|symmetry| {
+                let mut variation = stats.clone();
+                for plane in 0..4 {
+                    for square in 0..SQUARES {
+                        variation.game_state[plane * SQUARES + Self::symmetric(square, symmetry)] =
+                            stats.game_state[plane * SQUARES + square];
+                    }
+                }
+                variation.node_visits = [0.0; MOVES];
+                for (mv, visits) in stats.node_visits.iter().enumerate() {
+                    if *visits > 0.0 {
+                        variation.node_visits[symmetric_move(mv, symmetry)] = *visits;
+                    }
+                }
+                variation.best_move_index = symmetric_move(stats.best_move_index, symmetry);
+                variation
+            })
+            .collect()
+    }
+
+    // Queen moves like "d1-d7", arrows like "/d8" as the second half of "d1-d7/d8"
+    fn move_to_string(&self, mv: usize) -> String {
+        match self.shooting_from {
+            Some(_) => format!("/{}", Self::square_name(mv)),
+            None => format!(
+                "{}-{}",
+                Self::square_name(mv / SQUARES),
+                Self::square_name(mv % SQUARES)
+            ),
+        }
+    }
+
+    fn move_from_string(&self, text: &str) -> Result<usize> {
+        let text = text.trim();
+        match (self.shooting_from, text.split_once('-')) {
+            (Some(_), _) => Self::parse_square(text.trim_start_matches('/')),
+            (None, Some((from, to))) => Ok(Self::move_index(
+                Self::parse_square(from)?,
+                Self::parse_square(to)?,
+            )),
+            (None, None) => bail!("Expected a queen move like d1-d7, got '{}'", text),
+        }
+    }
+}
+
+impl Display for Amazons {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in self.board.chunks_exact(SIDE) {
+            let row: String = row
+                .iter()
+                .map(|square| match square {
+                    Square::Empty => '.',
+                    Square::Amazon(Players::Player) => 'X',
+                    Square::Amazon(Players::Opponent) => 'O',
+                    Square::Arrow => '#',
+                })
+                .collect();
+            writeln!(f, "{row}")?;
+        }
+        let next_player = match self.current_player {
+            Players::Player => "X",
+            Players::Opponent => "O",
+        };
+        writeln!(f, "Next player: {}", next_player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    use super::*;
+    use crate::game::move_indices;
+
+    #[test]
+    fn the_arrow_follows_with_the_same_player() -> Result<()> {
+        let start = Amazons::new();
+        let mut game = start.clone();
+        game.try_perform_move(game.move_from_string("d1-d7")?)?;
+        assert_eq!(game.current_player(), Players::Player);
+        assert_eq!(game.shooting_from, Amazons::parse_square("d7").ok());
+        // Only arrows from the amazon that moved, and it can shoot back where it came from
+        let arrows = move_indices(&game);
+        assert!(arrows.iter().all(|square| *square < SQUARES));
+        assert!(arrows.contains(&Amazons::parse_square("d1")?));
+        assert!(game
+            .try_perform_move(game.move_from_string("/a1")?)
+            .is_err());
+        game.try_perform_move(game.move_from_string("/d8")?)?;
+        assert_eq!(game.current_player(), Players::Opponent);
+        assert_eq!(game.shooting_from, None);
+
+        game.undo_move()?;
+        assert_eq!(game.current_player(), Players::Player);
+        assert_eq!(game.shooting_from, Amazons::parse_square("d7").ok());
+        game.undo_move()?;
+        assert_eq!(game.board, start.board);
+        assert_eq!(game.shooting_from, None);
+        assert!(game.undo_move().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn variations_map_legal_moves_to_legal_moves() -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut game = Amazons::new();
+        let mut symmetric: Vec<Amazons> = (0..8)
+            .map(|symmetry| {
+                let mut other = Amazons::new();
+                for square in 0..SQUARES {
+                    other.board[Amazons::symmetric(square, symmetry)] = game.board[square];
+                }
+                other
+            })
+            .collect();
+        for _ in 0..20 {
+            let moves = move_indices(&game);
+            // Visits that tell which move each one came from
+            let mut node_visits = [0.0; MOVES];
+            for mv in &moves {
+                node_visits[*mv] = *mv as f32 + 1.0;
+            }
+            let variations = Amazons::get_game_variations(&GameStats {
+                best_move_index: moves[0],
+                game_state: game.get_game_state_slice(),
+                node_visits,
+                value: 0.0,
+                score: 0.0,
+                extra_targets: Vec::new(),
+            });
+            assert_eq!(variations.len(), 8);
+            let mv = *moves.choose(&mut rng).unwrap();
+            for (variation, other) in variations.iter().zip(symmetric.iter_mut()) {
+                assert_eq!(variation.game_state, other.get_game_state_slice());
+                let available = other.available_moves();
+                let mapped: Vec<usize> = (0..MOVES)
+                    .filter(|new| variation.node_visits[*new] > 0.0)
+                    .collect();
+                assert_eq!(mapped.len(), moves.len());
+                assert!(mapped.iter().all(|new| available[*new]));
+                let new = mapped
+                    .into_iter()
+                    .find(|new| variation.node_visits[*new] == mv as f32 + 1.0)
+                    .unwrap();
+                other.try_perform_move(new)?;
+            }
+            game.try_perform_move(mv)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use std::fmt::Display;
+
+use rand::rngs::StdRng;
+
+use crate::game::{resolve_chance, Game, Policy};
+
+/// Results of games played from the start position with the same policy on both sides, to see
+/// how much moving first is worth before picking komi or a swap rule, and to catch new games
+/// that are decided far too often for one side
+#[derive(Debug, Clone, Default)]
+pub struct BalanceReport {
+    pub games: usize,
+    pub first_player_wins: usize,
+    pub second_player_wins: usize,
+    pub draws: usize,
+    /// Moves over all games, consecutive moves of the same player counted separately
+    pub total_moves: usize,
+}
+
+impl BalanceReport {
+    pub fn first_player_win_rate(&self) -> f32 {
+        self.first_player_wins as f32 / self.games.max(1) as f32
+    }
+
+    pub fn draw_rate(&self) -> f32 {
+        self.draws as f32 / self.games.max(1) as f32
+    }
+
+    pub fn average_length(&self) -> f32 {
+        self.total_moves as f32 / self.games.max(1) as f32
+    }
+}
+
+impl Display for BalanceReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} games: first player {} ({:.1}%), second player {}, draws {} ({:.1}%)",
+            self.games,
+            self.first_player_wins,
+            self.first_player_win_rate() * 100.0,
+            self.second_player_wins,
+            self.draws,
+            self.draw_rate() * 100.0
+        )?;
+        writeln!(f, "Average length {:.1} moves", self.average_length())
+    }
+}
+
+/// Plays `games` games with `policy` moving for both players, RandomPolicy for random play or an
+/// MctsPolicy for MCTS against itself. Games stopped by is_draw_by_rule count as draws
+pub fn first_player_advantage<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
+    games: usize,
+    policy: &U,
+    rng: &mut StdRng,
+) -> anyhow::Result<BalanceReport> {
+    let mut report = BalanceReport {
+        games,
+        ..Default::default()
+    };
+    for _ in 0..games {
+        let mut game = T::new();
+        resolve_chance(&mut game, rng)?;
+        let first_player = game.current_player();
+        while !game.game_ended() && !game.is_draw_by_rule() {
+            let mv = policy.select_move(&game, rng)?;
+            game.try_perform_move(mv)?;
+            report.total_moves += 1;
+            resolve_chance(&mut game, rng)?;
+        }
+        match game.winning_player().filter(|_| !game.is_draw_by_rule()) {
+            Some(player) if player == first_player => report.first_player_wins += 1,
+            Some(_) => report.second_player_wins += 1,
+            None => report.draws += 1,
+        }
+    }
+    Ok(report)
+}
+
+/// Results of `policy` against a fixed opponent, with the colours alternating between games
+#[derive(Debug, Clone, Default)]
+pub struct MatchReport {
+    pub games: usize,
+    pub wins: usize,
+    pub losses: usize,
+    pub draws: usize,
+}
+
+impl MatchReport {
+    /// Points per game, a draw is worth half a win
+    pub fn score(&self) -> f32 {
+        (self.wins as f32 + self.draws as f32 / 2.0) / self.games.max(1) as f32
+    }
+}
+
+impl Display for MatchReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} games: {} wins, {} losses, {} draws, scoring {:.1}%",
+            self.games,
+            self.wins,
+            self.losses,
+            self.draws,
+            self.score() * 100.0
+        )
+    }
+}
+
+/// Plays `games` games of `policy` against `opponent`, policy moving first in the even ones. To
+/// benchmark a model against a baseline of known strength like AlphaBetaPolicy or HeuristicPolicy
+pub fn play_match<
+    const N: usize,
+    const I: usize,
+    T: Game<N, I>,
+    U: Policy<N, I, T>,
+    V: Policy<N, I, T>,
+>(
+    games: usize,
+    policy: &U,
+    opponent: &V,
+    rng: &mut StdRng,
+) -> anyhow::Result<MatchReport> {
+    let mut report = MatchReport {
+        games,
+        ..Default::default()
+    };
+    for game_number in 0..games {
+        let mut game = T::new();
+        resolve_chance(&mut game, rng)?;
+        let side = if game_number % 2 == 0 {
+            game.current_player()
+        } else {
+            game.current_player().swap()
+        };
+        while !game.game_ended() && !game.is_draw_by_rule() {
+            let mv = if game.current_player() == side {
+                policy.select_move(&game, rng)?
+            } else {
+                opponent.select_move(&game, rng)?
+            };
+            game.try_perform_move(mv)?;
+            resolve_chance(&mut game, rng)?;
+        }
+        match game.winning_player().filter(|_| !game.is_draw_by_rule()) {
+            Some(player) if player == side => report.wins += 1,
+            Some(_) => report.losses += 1,
+            None => report.draws += 1,
+        }
+    }
+    Ok(report)
+}
@@ -0,0 +1,223 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+};
+
+use anyhow::{ensure, Context, Result};
+use rand::{rngs::StdRng, seq::SliceRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::game::{resolve_chance, Game, Policy};
+
+/// How often each move was played in the first plies of a set of games, by position_hash of the
+/// position it was played in. Moves are indices into the game as it is, not the flipped board
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OpeningBook {
+    pub plies: usize,
+    positions: HashMap<u64, BTreeMap<usize, usize>>,
+}
+
+impl OpeningBook {
+    /// Book over the first `plies` moves of `records`, the move lists of Dataset::records, which
+    /// replay from the `start` the dataset was created from. Only games without chance events can
+    /// be replayed from their moves
+    pub fn from_records<const N: usize, const I: usize, T: Game<N, I>>(
+        start: &T,
+        records: &[Vec<usize>],
+        plies: usize,
+    ) -> Result<Self> {
+        let mut book = Self {
+            plies,
+            ..Default::default()
+        };
+        for (i, record) in records.iter().enumerate() {
+            let mut game = start.clone();
+            for mv in record.iter().take(plies) {
+                book.add(game.position_hash(), *mv);
+                game.try_perform_move(*mv)
+                    .with_context(|| format!("Record {} does not replay", i))?;
+            }
+        }
+        Ok(book)
+    }
+
+    /// Book over the first `plies` moves of `games` games played by `policy` against itself, an
+    /// MctsPolicy for self-play
+    pub fn from_self_play<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
+        games: usize,
+        plies: usize,
+        policy: &U,
+        rng: &mut StdRng,
+    ) -> Result<Self> {
+        let mut book = Self {
+            plies,
+            ..Default::default()
+        };
+        for _ in 0..games {
+            let mut game = T::new();
+            for _ in 0..plies {
+                resolve_chance(&mut game, rng)?;
+                if game.game_ended() || game.is_draw_by_rule() {
+                    break;
+                }
+                let mv = policy.select_move(&game, rng)?;
+                book.add(game.position_hash(), mv);
+                game.try_perform_move(mv)?;
+            }
+        }
+        Ok(book)
+    }
+
+    pub fn add(&mut self, hash: u64, mv: usize) {
+        *self
+            .positions
+            .entry(hash)
+            .or_default()
+            .entry(mv)
+            .or_default() += 1;
+    }
+
+    /// Moves played in the position with how often they were played
+    pub fn lookup(&self, hash: u64) -> Option<&BTreeMap<usize, usize>> {
+        self.positions.get(&hash)
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        let contents = fs::read_to_string(path).with_context(|| format!("Reading {}", path))?;
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+/// Plays a move from the book with the probability it was played with while the position has
+/// been seen at least `min_games` times, then falls back to `policy`. Evaluation goes to `policy`
+/// throughout
+pub struct BookPolicy<P> {
+    pub book: OpeningBook,
+    pub policy: P,
+    pub min_games: usize,
+}
+
+impl<P> BookPolicy<P> {
+    pub fn new(book: OpeningBook, policy: P, min_games: usize) -> Result<Self> {
+        ensure!(min_games > 0, "A book move needs at least one game");
+        Ok(Self {
+            book,
+            policy,
+            min_games,
+        })
+    }
+
+    fn book_move<const N: usize, const I: usize, T: Game<N, I>>(
+        &self,
+        game: &T,
+        rng: &mut StdRng,
+    ) -> Option<usize> {
+        let available = game.available_moves();
+        let moves: Vec<(usize, usize)> = self
+            .book
+            .lookup(game.position_hash())?
+            .iter()
+            .filter(|(mv, _)| **mv < N && available[**mv])
+            .map(|(mv, count)| (*mv, *count))
+            .collect();
+        if moves.iter().map(|(_, count)| count).sum::<usize>() < self.min_games {
+            return None;
+        }
+        moves
+            .choose_weighted(rng, |(_, count)| *count)
+            .ok()
+            .map(|(mv, _)| *mv)
+    }
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>, P: Policy<N, I, T>> Policy<N, I, T>
+    for BookPolicy<P>
+{
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> Result<usize> {
+        match self.book_move(game, rng) {
+            Some(mv) => Ok(mv),
+            None => self.policy.select_move(game, rng),
+        }
+    }
+
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> Result<Vec<usize>> {
+        games
+            .iter()
+            .map(|game| self.select_move(*game, rng))
+            .collect()
+    }
+
+    fn predict_score(&self, game: &T) -> Result<f32> {
+        self.policy.predict_score(game)
+    }
+
+    fn can_predict_score(&self) -> bool {
+        self.policy.can_predict_score()
+    }
+
+    fn predict_priors(&self, game: &T) -> Result<[f32; N]> {
+        self.policy.predict_priors(game)
+    }
+
+    fn cache_stats(&self) -> Option<crate::cache::CacheStats> {
+        self.policy.cache_stats()
+    }
+
+    fn exact_score(&self, game: &T) -> Option<f32> {
+        self.policy.exact_score(game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::{game::RandomPolicy, mnk::TicTacToe};
+
+    #[test]
+    fn replays_records_from_the_start() -> Result<()> {
+        let start = TicTacToe::from_moves(&[4])?;
+        let records = [vec![0, 8, 2], vec![0, 2], vec![2, 6]];
+        let book = OpeningBook::from_records(&start, &records, 2)?;
+        let counts = book.lookup(start.position_hash()).unwrap();
+        assert_eq!(counts.iter().collect::<Vec<_>>(), [(&0, &2), (&2, &1)]);
+        let after = TicTacToe::from_moves(&[4, 0])?;
+        assert_eq!(book.lookup(after.position_hash()).unwrap().len(), 2);
+        // The third ply is past the book
+        assert!(book
+            .lookup(TicTacToe::from_moves(&[4, 0, 8])?.position_hash())
+            .is_none());
+        // Records do not replay from another start
+        assert!(OpeningBook::from_records(&start, &[vec![4]], 2).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn plays_book_moves_seen_often_enough() -> Result<()> {
+        let start = TicTacToe::from_moves(&[4])?;
+        let book = OpeningBook::from_records(&start, &[vec![0], vec![0], vec![2]], 1)?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let policy = BookPolicy::new(book.clone(), RandomPolicy::default(), 3)?;
+        for _ in 0..50 {
+            assert!([0, 2].contains(&policy.select_move(&start, &mut rng)?));
+        }
+        // Three games are too few for four, so the moves come from the random policy
+        let policy = BookPolicy::new(book, RandomPolicy::default(), 4)?;
+        let moves = (0..50)
+            .map(|_| policy.select_move(&start, &mut rng))
+            .collect::<Result<Vec<_>>>()?;
+        assert!(moves.iter().any(|mv| ![0, 2].contains(mv)));
+        assert!(BookPolicy::new(OpeningBook::default(), RandomPolicy::default(), 0).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+use std::fmt::Display;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    game::{
+        encode_board, simple_board_planes, swap_board, Cell, Game, Players, SimpleBoardState,
+        SpatialGame,
+    },
+    mcts::GameStats,
+    render::{AsciiRenderer, Board, BoardRenderer},
+};
+
+const SIDE: usize = 8;
+const SQUARES: usize = SIDE * SIDE;
+pub const MOVES: usize = SQUARES * SQUARES;
+
+/// Breakthrough on an 8x8 board. Pawns move one square forward, straight onto an empty square or
+/// diagonally onto an empty or enemy square, capturing it. Reaching the far row wins, and since
+/// a player without pawns cannot move there are no draws
+#[derive(Debug, Clone)]
+pub struct Breakthrough {
+    // Row major, Player starts on the bottom two rows and moves up
+    board: [SimpleBoardState; SQUARES],
+    current_player: Players,
+    winning_player: Option<Players>,
+    // From, to and what was on the target square for every move played, for undo_move
+    history: Vec<(usize, usize, SimpleBoardState)>,
+}
+
+impl Breakthrough {
+    /// Move index of moving the pawn on `from` to `to`
+    pub fn move_index(from: usize, to: usize) -> usize {
+        from * SQUARES + to
+    }
+
+    fn forward(player: Players) -> isize {
+        match player {
+            Players::Player => -1,
+            Players::Opponent => 1,
+        }
+    }
+
+    fn legal_moves(&self) -> Vec<(usize, usize)> {
+        let own: SimpleBoardState = self.current_player.into();
+        let forward = Self::forward(self.current_player);
+        let mut moves = Vec::new();
+        for from in 0..SQUARES {
+            if self.board[from] != own {
+                continue;
+            }
+            let row = (from / SIDE) as isize + forward;
+            if !(0..SIDE as isize).contains(&row) {
+                continue;
+            }
+            for dc in [-1, 0, 1] {
+                let column = (from % SIDE) as isize + dc;
+                if !(0..SIDE as isize).contains(&column) {
+                    continue;
+                }
+                let to = row as usize * SIDE + column as usize;
+                let target = self.board[to];
+                let allowed = target == SimpleBoardState::Empty || (dc != 0 && target != own);
+                if allowed {
+                    moves.push((from, to));
+                }
+            }
+        }
+        moves
+    }
+}
+
+impl Game<MOVES, { SQUARES * 2 }> for Breakthrough {
+    fn winning_player(&self) -> Option<Players> {
+        self.winning_player
+    }
+
+    fn available_moves(&self) -> [bool; MOVES] {
+        let mut moves = [false; MOVES];
+        if self.winning_player.is_some() {
+            return moves;
+        }
+        for (from, to) in self.legal_moves() {
+            moves[Self::move_index(from, to)] = true;
+        }
+        moves
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        let (from, to) = (space / SQUARES, space % SQUARES);
+        ensure!(
+            self.legal_moves().contains(&(from, to)),
+            "Tried to make an illegal breakthrough move"
+        );
+        self.history.push((from, to, self.board[to]));
+        self.board[from] = SimpleBoardState::Empty;
+        self.board[to] = self.current_player.into();
+        let far_row = match self.current_player {
+            Players::Player => 0,
+            Players::Opponent => SIDE - 1,
+        };
+        let opponent: SimpleBoardState = self.current_player.swap().into();
+        if to / SIDE == far_row || !self.board.contains(&opponent) {
+            self.winning_player = Some(self.current_player);
+        }
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    // Winning ends the game, so nobody had won before the last move
+    fn undo_move(&mut self) -> Result<()> {
+        let (from, to, captured) = self.history.pop().context("No move to undo")?;
+        self.current_player = self.current_player.swap();
+        self.board[from] = self.current_player.into();
+        self.board[to] = captured;
+        self.winning_player = None;
+        Ok(())
+    }
+
+    fn new() -> Self {
+        let mut board = [SimpleBoardState::Empty; SQUARES];
+        board[..2 * SIDE].fill(SimpleBoardState::Opponent);
+        board[SQUARES - 2 * SIDE..].fill(SimpleBoardState::Player);
+        Self {
+            board,
+            current_player: Players::Player,
+            winning_player: None,
+            history: Vec::new(),
+        }
+    }
+
+    fn game_ended(&self) -> bool {
+        self.winning_player.is_some()
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    // Rotates the board half a turn as well, so Player keeps moving up
+    fn flip_board(&mut self) {
+        self.board.reverse();
+        swap_board(&mut self.board);
+        self.current_player = self.current_player.swap();
+        self.winning_player = self.winning_player.map(|player| player.swap());
+        for (from, to, captured) in self.history.iter_mut() {
+            (*from, *to) = (SQUARES - 1 - *from, SQUARES - 1 - *to);
+            *captured = captured.swap();
+        }
+    }
+
+    fn flipped_move(&self, mv: usize) -> usize {
+        Self::move_index(SQUARES - 1 - mv / SQUARES, SQUARES - 1 - mv % SQUARES)
+    }
+
+    fn get_game_state_slice(&self) -> [f32; SQUARES * 2] {
+        encode_board(&self.board)
+    }
+
+    fn board(&self) -> Option<Board> {
+        Some(Board::read(self))
+    }
+
+    // The board is symmetric left to right
+    fn get_game_variations(
+        stats: &GameStats<MOVES, { SQUARES * 2 }>,
+    ) -> Vec<GameStats<MOVES, { SQUARES * 2 }>> {
+        let mirror = |square: usize| square - square % SIDE + SIDE - 1 - square % SIDE;
+        let mirror_move = |mv: usize| Self::move_index(mirror(mv / SQUARES), mirror(mv % SQUARES));
+        let mut mirrored = stats.clone();
+        for square in 0..SQUARES {
+            mirrored.game_state[mirror(square)] = stats.game_state[square];
+            mirrored.game_state[SQUARES + mirror(square)] = stats.game_state[SQUARES + square];
+        }
+        for mv in 0..MOVES {
+            mirrored.node_visits[mirror_move(mv)] = stats.node_visits[mv];
+        }
+        mirrored.best_move_index = mirror_move(stats.best_move_index);
+        vec![stats.clone(), mirrored]
+    }
+}
+
+impl SpatialGame<MOVES, { SQUARES * 2 }> for Breakthrough {
+    fn plane_shape(&self) -> (usize, usize, usize) {
+        (3, SIDE, SIDE)
+    }
+
+    fn spatial_planes(&self) -> Vec<f32> {
+        simple_board_planes(&self.board, self.current_player)
+    }
+}
+
+impl Display for Breakthrough {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", AsciiRenderer.render(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Player to move with only the given pawns on the board
+    fn position(player: &[usize], opponent: &[usize]) -> Breakthrough {
+        let mut game = Breakthrough::new();
+        game.board = [SimpleBoardState::Empty; SQUARES];
+        for square in player {
+            game.board[*square] = SimpleBoardState::Player;
+        }
+        for square in opponent {
+            game.board[*square] = SimpleBoardState::Opponent;
+        }
+        game
+    }
+
+    #[test]
+    fn pawns_capture_diagonally_only() -> Result<()> {
+        let mut game = position(&[35], &[27, 28]);
+        let mut moves = game.legal_moves();
+        moves.sort();
+        assert_eq!(moves, vec![(35, 26), (35, 28)]);
+        game.try_perform_move(Breakthrough::move_index(35, 28))?;
+        assert_eq!(game.board[28], SimpleBoardState::Player);
+        assert_eq!(game.winning_player(), None);
+        game.undo_move()?;
+        assert_eq!(game.board[28], SimpleBoardState::Opponent);
+        assert_eq!(game.board[35], SimpleBoardState::Player);
+
+        // Taking the last pawn wins
+        let mut game = position(&[35], &[28]);
+        game.try_perform_move(Breakthrough::move_index(35, 28))?;
+        assert_eq!(game.winning_player(), Some(Players::Player));
+        Ok(())
+    }
+
+    #[test]
+    fn reaching_the_far_row_wins() -> Result<()> {
+        let mut game = position(&[8], &[63]);
+        game.try_perform_move(Breakthrough::move_index(8, 0))?;
+        assert_eq!(game.winning_player(), Some(Players::Player));
+        assert!(game.game_ended());
+
+        let mut game = position(&[9], &[54]);
+        game.current_player = Players::Opponent;
+        game.try_perform_move(Breakthrough::move_index(54, 62))?;
+        assert_eq!(game.winning_player(), Some(Players::Opponent));
+        Ok(())
+    }
+}
@@ -0,0 +1,281 @@
+use std::{cell::RefCell, collections::HashMap};
+
+use rand::rngs::StdRng;
+
+use crate::game::{Game, Policy};
+
+const NONE: usize = usize::MAX;
+
+struct Entry<V> {
+    key: u64,
+    value: V,
+    prev: usize,
+    next: usize,
+}
+
+/// Fixed capacity least recently used cache keyed by position hashes
+pub struct LruCache<V> {
+    capacity: usize,
+    slots: HashMap<u64, usize>,
+    entries: Vec<Entry<V>>,
+    // Most recently used entry
+    head: usize,
+    // Least recently used entry, evicted first
+    tail: usize,
+}
+
+impl<V> LruCache<V> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "Cache capacity must be positive");
+        Self {
+            capacity,
+            slots: HashMap::with_capacity(capacity),
+            entries: Vec::with_capacity(capacity),
+            head: NONE,
+            tail: NONE,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get_mut(&mut self, key: u64) -> Option<&mut V> {
+        let slot = *self.slots.get(&key)?;
+        self.move_to_front(slot);
+        Some(&mut self.entries[slot].value)
+    }
+
+    /// Inserts a value, returns true if another entry had to be evicted to make room
+    pub fn insert(&mut self, key: u64, value: V) -> bool {
+        if let Some(slot) = self.slots.get(&key).copied() {
+            self.entries[slot].value = value;
+            self.move_to_front(slot);
+            return false;
+        }
+        if self.entries.len() < self.capacity {
+            let slot = self.entries.len();
+            self.entries.push(Entry {
+                key,
+                value,
+                prev: NONE,
+                next: NONE,
+            });
+            self.slots.insert(key, slot);
+            self.push_front(slot);
+            return false;
+        }
+        // Reuse the least recently used slot
+        let slot = self.tail;
+        self.unlink(slot);
+        self.slots.remove(&self.entries[slot].key);
+        self.entries[slot].key = key;
+        self.entries[slot].value = value;
+        self.slots.insert(key, slot);
+        self.push_front(slot);
+        true
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = (self.entries[slot].prev, self.entries[slot].next);
+        if prev == NONE {
+            self.head = next;
+        } else {
+            self.entries[prev].next = next;
+        }
+        if next == NONE {
+            self.tail = prev;
+        } else {
+            self.entries[next].prev = prev;
+        }
+    }
+
+    fn push_front(&mut self, slot: usize) {
+        self.entries[slot].prev = NONE;
+        self.entries[slot].next = self.head;
+        if self.head != NONE {
+            self.entries[self.head].prev = slot;
+        }
+        self.head = slot;
+        if self.tail == NONE {
+            self.tail = slot;
+        }
+    }
+
+    fn move_to_front(&mut self, slot: usize) {
+        if self.head != slot {
+            self.unlink(slot);
+            self.push_front(slot);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: usize,
+    pub misses: usize,
+    pub evictions: usize,
+}
+
+impl CacheStats {
+    pub fn since(&self, earlier: &CacheStats) -> CacheStats {
+        CacheStats {
+            hits: self.hits - earlier.hits,
+            misses: self.misses - earlier.misses,
+            evictions: self.evictions - earlier.evictions,
+        }
+    }
+
+    pub fn hit_rate(&self) -> f32 {
+        self.hits as f32 / (self.hits + self.misses).max(1) as f32
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Evaluation<const N: usize> {
+    priors: Option<[f32; N]>,
+    score: Option<f32>,
+}
+
+struct EvalCache<const N: usize> {
+    entries: LruCache<Evaluation<N>>,
+    stats: CacheStats,
+}
+
+/// Wraps a policy and remembers its priors and score predictions per position, so transposed or
+/// revisited positions skip the network. Entries stay valid across games as long as the inner
+/// policy does not change
+pub struct CachedPolicy<const N: usize, P> {
+    pub policy: P,
+    cache: RefCell<EvalCache<N>>,
+}
+
+impl<const N: usize, P> CachedPolicy<N, P> {
+    pub fn new(policy: P, capacity: usize) -> Self {
+        Self {
+            policy,
+            cache: RefCell::new(EvalCache {
+                entries: LruCache::new(capacity),
+                stats: CacheStats::default(),
+            }),
+        }
+    }
+
+    fn lookup<R: Copy>(
+        &self,
+        hash: u64,
+        get: impl Fn(&Evaluation<N>) -> Option<R>,
+        set: impl Fn(&mut Evaluation<N>, R),
+        evaluate: impl FnOnce() -> anyhow::Result<R>,
+    ) -> anyhow::Result<R> {
+        let mut cache = self.cache.borrow_mut();
+        let cached = cache.entries.get_mut(hash).and_then(|entry| get(entry));
+        if let Some(result) = cached {
+            cache.stats.hits += 1;
+            return Ok(result);
+        }
+        cache.stats.misses += 1;
+        // The inner policy does not know about the cache, so it is fine to hold the borrow
+        let result = evaluate()?;
+        if let Some(entry) = cache.entries.get_mut(hash) {
+            set(entry, result);
+        } else {
+            let mut entry = Evaluation::default();
+            set(&mut entry, result);
+            if cache.entries.insert(hash, entry) {
+                cache.stats.evictions += 1;
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>, P: Policy<N, I, T>> Policy<N, I, T>
+    for CachedPolicy<N, P>
+{
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> anyhow::Result<usize> {
+        self.policy.select_move(game, rng)
+    }
+
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> anyhow::Result<Vec<usize>> {
+        self.policy.select_moves_batch(games, rng)
+    }
+
+    fn predict_score(&self, game: &T) -> anyhow::Result<f32> {
+        self.lookup(
+            game.position_hash(),
+            |entry| entry.score,
+            |entry, score| entry.score = Some(score),
+            || self.policy.predict_score(game),
+        )
+    }
+
+    fn can_predict_score(&self) -> bool {
+        self.policy.can_predict_score()
+    }
+
+    fn predict_priors(&self, game: &T) -> anyhow::Result<[f32; N]> {
+        self.lookup(
+            game.position_hash(),
+            |entry| entry.priors,
+            |entry, priors| entry.priors = Some(priors),
+            || self.policy.predict_priors(game),
+        )
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.cache.borrow().stats)
+    }
+
+    fn exact_score(&self, game: &T) -> Option<f32> {
+        self.policy.exact_score(game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+    use crate::{
+        game::RandomPolicy,
+        mcts::{mcts, MctsConfig},
+        mnk::TicTacToe,
+    };
+
+    #[test]
+    fn evicts_the_least_recently_used_entry() {
+        let mut cache = LruCache::new(2);
+        assert!(!cache.insert(1, 'a'));
+        assert!(!cache.insert(2, 'b'));
+        // Reading 1 leaves 2 as the least recently used
+        assert_eq!(cache.get_mut(1), Some(&mut 'a'));
+        assert!(cache.insert(3, 'c'));
+        assert_eq!(cache.get_mut(2), None);
+        assert_eq!(cache.get_mut(1), Some(&mut 'a'));
+        assert_eq!(cache.get_mut(3), Some(&mut 'c'));
+    }
+
+    // Tic-tac-toe reaches the same positions through different move orders, and from generation
+    // 10 on every leaf is scored by predict_score
+    #[test]
+    fn hits_on_transpositions() -> anyhow::Result<()> {
+        let policy = CachedPolicy::new(RandomPolicy { rollouts: 4 }, 10_000);
+        let config = MctsConfig {
+            simulations: 2000,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let search = mcts(&TicTacToe::new(), &policy, 10, &config, &mut rng)?;
+        let stats = search.cache_stats.unwrap();
+        assert!(stats.hits > 0);
+        assert_eq!(stats.evictions, 0);
+        // The next search counts its own lookups only, most of them are of positions the first
+        // one already evaluated
+        let search = mcts(&TicTacToe::new(), &policy, 10, &config, &mut rng)?;
+        let again = search.cache_stats.unwrap();
+        assert!(again.hits > stats.hits);
+        assert!(again.misses < stats.misses);
+        Ok(())
+    }
+}
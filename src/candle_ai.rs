@@ -1,124 +1,955 @@
-use std::iter::zip;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
 
-use candle_core::{DType, Device, Tensor};
-use candle_nn::{linear, Linear, Module, Optimizer, VarBuilder, VarMap};
+use anyhow::{bail, ensure, Context};
+use candle_core::{DType, Device, Shape, Tensor, Var};
+use candle_nn::init::NormalOrUniform;
+use candle_nn::var_builder::SimpleBackend;
+use candle_nn::{linear, Dropout, Init, Linear, Module, ModuleT, Optimizer, VarBuilder, VarMap};
 use itertools::Itertools;
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-use crate::model::TrainableModel;
+use crate::checkpoint;
+use crate::fit::{
+    self, fit_minibatches, softmax, LossTensor, LossWeights, MinibatchFit, Samples, TrainingRows,
+};
+use crate::model::{
+    Architecture, FitConfig, ModelConfig, TrainReport, TrainableModel, TrainingMetrics,
+};
 
-const DEVICE: Device = Device::Cpu;
+/// Environment variable choosing the device models run on: cpu, cuda, cuda:<ordinal> or metal.
+/// The accelerators need the crate's cuda or metal feature
+pub const DEVICE_VARIABLE: &str = "ALPHA_SCUFFED_DEVICE";
+/// Environment variable choosing the device of the copies self-play predicts with, like
+/// DEVICE_VARIABLE, which it falls back to. Lets training use a GPU while self-play stays on the
+/// CPU
+pub const SELF_PLAY_DEVICE_VARIABLE: &str = "ALPHA_SCUFFED_SELF_PLAY_DEVICE";
+/// Environment variable choosing the float type the self-play copies compute in, f32 (the
+/// default), f16 or bf16. The smaller types are faster on GPUs, training_loop reports how far
+/// their predictions are from the trained model
+pub const SELF_PLAY_DTYPE_VARIABLE: &str = "ALPHA_SCUFFED_SELF_PLAY_DTYPE";
+
+/// What a model is used for, each can have its own device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Training,
+    SelfPlay,
+}
+
+/// The device asked for `role`, empty for the default
+pub(crate) fn requested_device(role: Role) -> String {
+    let variable = |name| std::env::var(name).unwrap_or_default();
+    match role {
+        Role::SelfPlay if !variable(SELF_PLAY_DEVICE_VARIABLE).is_empty() => {
+            variable(SELF_PLAY_DEVICE_VARIABLE)
+        }
+        _ => variable(DEVICE_VARIABLE),
+    }
+}
+
+/// The float type asked for the self-play copies, F32 when none or an unknown one is asked for
+pub fn self_play_dtype() -> DType {
+    let requested = std::env::var(SELF_PLAY_DTYPE_VARIABLE).unwrap_or_default();
+    match requested.as_str() {
+        "" | "f32" => DType::F32,
+        "f16" => DType::F16,
+        "bf16" => DType::BF16,
+        _ => {
+            eprintln!("Unknown type '{}' for self-play, using f32", requested);
+            DType::F32
+        }
+    }
+}
+
+/// The training device, see device_for
+pub fn select_device() -> Device {
+    device_for(Role::Training)
+}
+
+/// The device asked for `role`, the CPU when none or an unavailable one is asked for
+pub fn device_for(role: Role) -> Device {
+    let requested = requested_device(role);
+    let device = match requested.as_str() {
+        "" | "cpu" => return Device::Cpu,
+        "metal" => Device::new_metal(0).map_err(anyhow::Error::from),
+        name => match name.strip_prefix("cuda") {
+            Some("") => Device::new_cuda(0).map_err(anyhow::Error::from),
+            Some(ordinal) => ordinal
+                .strip_prefix(':')
+                .and_then(|ordinal| ordinal.parse().ok())
+                .context("Expected cuda:<ordinal>")
+                .and_then(|ordinal| Ok(Device::new_cuda(ordinal)?)),
+            None => Err(anyhow::anyhow!("Unknown device")),
+        },
+    };
+    device.unwrap_or_else(|err| {
+        eprintln!(
+            "Device '{}' is not available, using the CPU: {}",
+            requested, err
+        );
+        Device::Cpu
+    })
+}
 
 pub struct SimpleModel<const N: usize, const I: usize> {
-    layer1: Linear,
-    layer2: Linear,
+    config: ModelConfig,
+    // Hidden layers, each followed by a relu and the dropout
+    layers: Vec<Linear>,
+    dropout: Dropout,
     visit_head: Linear,
     score_head: Linear,
-    //varmap: VarMap,
-    optimizer: candle_nn::AdamW,
+    // Auxiliary head for the final score margin, trains the shared layers on how much a game is
+    // won by in games that have a margin
+    margin_head: Linear,
+    // Auxiliary head for the ownership of every cell, with ModelConfig::ownership
+    ownership_head: Option<Linear>,
+    // Owns the weights of all layers, for saving and loading them as safetensors
+    weights: Weights,
+    // Generation of the next call to train, for the learning rate schedule
+    generation: usize,
+    device: Device,
+    /// Weight of the value loss relative to the policy cross-entropy
+    pub value_weight: f32,
+    /// Weight of the margin loss relative to the policy cross-entropy
+    pub margin_weight: f32,
+    /// Weight of the ownership loss relative to the policy cross-entropy
+    pub ownership_weight: f32,
 }
 
-impl<const N: usize, const I: usize> TrainableModel<N, I> for SimpleModel<N, I> {
-    fn new() -> anyhow::Result<Self> {
-        let hidden_dim = 32;
-        let varmap = VarMap::new();
-        let vb = VarBuilder::from_varmap(&varmap, DType::F32, &DEVICE);
-        let optim_config = candle_nn::ParamsAdamW {
-            lr: 1e-2,
-            ..Default::default()
-        };
-        let layer1 = linear(I, hidden_dim, vb.pp("layer 1"))?;
-        let layer2 = linear(hidden_dim, hidden_dim, vb.pp("layer 2"))?;
-        let visit_head = linear(hidden_dim, N, vb.pp("layer 3"))?;
-        let score_head = linear(hidden_dim, 1, vb.pp("score_head"))?;
-        let optimizer = candle_nn::AdamW::new(varmap.all_vars(), optim_config)?;
+impl<const N: usize, const I: usize> SimpleModel<N, I> {
+    /// Model on `device`, TrainableModel::new picks it with select_device
+    pub fn with_device(config: ModelConfig, device: Device) -> anyhow::Result<Self> {
+        Self::build(config, Weights::trainable(config.seed), device)
+    }
+
+    /// Model with the weights saved at `path` that can only predict, see Weights::inference
+    pub fn for_inference(config: ModelConfig, path: &str, device: Device) -> anyhow::Result<Self> {
+        let model = Self::build(config, Weights::inference::<N, I>(path, &device)?, device)?;
+        model.weights.check_unused(path)?;
+        Ok(model)
+    }
+
+    /// Copy that only predicts, computing in `dtype`. F16 is faster on GPUs, check it against
+    /// the model with compare_precision
+    pub fn with_dtype(&self, dtype: DType) -> anyhow::Result<Self> {
+        Self::build(
+            self.config,
+            self.weights.frozen(dtype, &self.device)?,
+            self.device.clone(),
+        )
+    }
+
+    fn build(config: ModelConfig, weights: Weights, device: Device) -> anyhow::Result<Self> {
+        ensure!(config.depth > 0, "The model needs a hidden layer");
+        let hidden_size = config.hidden_size;
+        let vb = weights.var_builder(&device);
+        let layers = (0..config.depth)
+            .map(|layer| {
+                let inputs = if layer == 0 { I } else { hidden_size };
+                linear(inputs, hidden_size, vb.pp(format!("layer {}", layer + 1)))
+            })
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        let visit_head = linear(hidden_size, N, vb.pp("visit_head"))?;
+        let score_head = linear(hidden_size, 1, vb.pp("score_head"))?;
+        let margin_head = linear(hidden_size, 1, vb.pp("margin_head"))?;
+        let ownership_head = config
+            .ownership
+            .then(|| linear(hidden_size, N, vb.pp("ownership_head")))
+            .transpose()?;
         Ok(Self {
-            layer1,
-            layer2,
+            config,
+            layers,
+            dropout: dropout(config.dropout)?,
             visit_head,
             score_head,
-            optimizer,
+            margin_head,
+            ownership_head,
+            weights,
+            generation: 0,
+            device,
+            value_weight: 1.0,
+            margin_weight: 1.0,
+            ownership_weight: 1.0,
+        })
+    }
+}
+
+impl<const N: usize, const I: usize> CandleModel for SimpleModel<N, I> {
+    fn heads(&self, xs: &Tensor, train: bool) -> candle_core::Result<Heads> {
+        let mut x = xs.clone();
+        for layer in &self.layers {
+            x = self.dropout.forward_t(&layer.forward(&x)?.relu()?, train)?;
+        }
+        Ok(Heads {
+            visit_logits: self.visit_head.forward(&x)?,
+            score: self.score_head.forward(&x)?.tanh()?,
+            margin: self.margin_head.forward(&x)?.tanh()?,
+            ownership: self
+                .ownership_head
+                .as_ref()
+                .map(|head| head.forward(&x)?.tanh())
+                .transpose()?,
+        })
+    }
+
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn loss_weights(&self) -> LossWeights {
+        LossWeights {
+            value: self.value_weight,
+            margin: self.margin_weight,
+            ownership: self.ownership_weight,
+        }
+    }
+
+    fn fit_config(&self) -> &FitConfig {
+        &self.config.fit
+    }
+
+    fn weights(&self) -> &Weights {
+        &self.weights
+    }
+
+    fn weights_mut(&mut self) -> &mut Weights {
+        &mut self.weights
+    }
+
+    fn generation(&self) -> usize {
+        self.generation
+    }
+}
+
+/// Outputs of a model for a batch of states, one row per state
+pub(crate) type Heads = fit::Heads<Tensor>;
+
+/// What the training and prediction shared by the candle models need from a model
+pub(crate) trait CandleModel {
+    /// The heads for a batch of flat states. `train` is set for the batches trained on, for
+    /// layers like batch norm that work differently then
+    fn heads(&self, xs: &Tensor, train: bool) -> candle_core::Result<Heads>;
+    fn device(&self) -> &Device;
+    fn loss_weights(&self) -> LossWeights;
+    fn fit_config(&self) -> &FitConfig;
+    fn weights(&self) -> &Weights;
+    fn weights_mut(&mut self) -> &mut Weights;
+    /// Generation set with TrainableModel::set_generation
+    fn generation(&self) -> usize;
+}
+
+/// The weights of a model, variables for training or fixed tensors for inference
+pub(crate) enum Weights {
+    /// The optimizer is made on the first step, so models that are never trained go without
+    Trainable {
+        varmap: VarMap,
+        optimizer: Option<candle_nn::AdamW>,
+        seed: Option<u64>,
+    },
+    /// Tensors that nothing writes to, so copies of the model can share them. The layers get
+    /// them in `dtype`, which the inputs are converted to as well
+    Inference {
+        tensors: HashMap<String, Tensor>,
+        seed: Option<u64>,
+        dtype: DType,
+        // Names of the tensors the layers were built from
+        used: Arc<Mutex<HashSet<String>>>,
+    },
+}
+
+impl Weights {
+    /// Variables that are initialized from `seed` as the layers are built from var_builder, a
+    /// random seed when there is none
+    pub(crate) fn trainable(seed: Option<u64>) -> Self {
+        Weights::Trainable {
+            varmap: VarMap::new(),
+            optimizer: None,
+            seed: Some(seed.unwrap_or_else(rand::random)),
+        }
+    }
+
+    /// The tensors saved at `path`, read once into memory, without variables or optimizer state.
+    /// They have to be saved by a model of N moves and I state values
+    pub(crate) fn inference<const N: usize, const I: usize>(
+        path: &str,
+        device: &Device,
+    ) -> anyhow::Result<Self> {
+        checkpoint::check_dimensions::<N, I>(path)?;
+        let tensors = candle_core::safetensors::load(path, device)
+            .with_context(|| format!("Loading model weights from {}", path))?;
+        Ok(Weights::Inference {
+            tensors,
+            seed: checkpoint::init_seed(path)?,
+            dtype: DType::F32,
+            used: Default::default(),
         })
     }
 
-    fn train(&mut self, dataset: crate::dataset::Dataset<N, I>) -> anyhow::Result<()> {
-        const EPOCHS: usize = 100;
-        let x = Tensor::from_vec(
-            dataset.game_states.iter().cloned().flatten().collect(),
-            (dataset.game_states.len(), I),
-            &DEVICE,
-        )?;
-        let scores_vec = dataset.scores.to_vec();
-        let visit_vec = dataset.visit_stats.clone();
-        let test: Vec<_> = zip(scores_vec, visit_vec)
-            .map(|(score, visits)| visits.iter().cloned().chain([score]).collect::<Vec<_>>())
-            .flatten()
-            .collect();
-        let y = Tensor::from_vec(test, (dataset.visit_stats.len(), N + 1), &DEVICE)?;
-        eprintln!("x = {:#?}", x);
-        eprintln!("y = {:#?}", y);
-        for epoch in 0..EPOCHS {
-            let output = self.forward(&x)?;
-            let loss = candle_nn::loss::mse(&output, &y)?;
-            self.optimizer.backward_step(&loss)?;
-            if (epoch + 1) % 10 == 0 {
-                println!("Train Loss: {}", loss.to_scalar::<f32>()?);
+    /// Copies of the weights on `device` in `dtype` that can only predict. Candle's layers run in
+    /// F16, in BF16 only on accelerators, and not in the integer types
+    pub(crate) fn frozen(&self, dtype: DType, device: &Device) -> anyhow::Result<Self> {
+        ensure!(
+            dtype.is_float(),
+            "Models cannot run in {:?}, only in float types",
+            dtype
+        );
+        let convert = |tensor: &Tensor| tensor.to_device(device)?.to_dtype(dtype)?.copy();
+        let tensors = match self {
+            Weights::Trainable { varmap, .. } => varmap
+                .data()
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(name, var)| Ok((name.clone(), convert(var.as_tensor())?)))
+                .collect::<candle_core::Result<_>>()?,
+            Weights::Inference { tensors, .. } => tensors
+                .iter()
+                .map(|(name, tensor)| Ok((name.clone(), convert(tensor)?)))
+                .collect::<candle_core::Result<_>>()?,
+        };
+        Ok(Weights::Inference {
+            tensors,
+            seed: self.seed(),
+            dtype,
+            used: Default::default(),
+        })
+    }
+
+    /// Type the layers compute in
+    pub(crate) fn dtype(&self) -> DType {
+        match self {
+            Weights::Trainable { .. } => DType::F32,
+            Weights::Inference { dtype, .. } => *dtype,
+        }
+    }
+
+    pub(crate) fn var_builder(&self, device: &Device) -> VarBuilder<'static> {
+        match self {
+            Weights::Trainable {
+                varmap,
+                seed: Some(seed),
+                ..
+            } => {
+                let init = SeededInit {
+                    varmap: varmap.clone(),
+                    rng: Mutex::new(StdRng::seed_from_u64(*seed)),
+                };
+                VarBuilder::from_backend(Box::new(init), DType::F32, device.clone())
+            }
+            Weights::Trainable { varmap, .. } => {
+                VarBuilder::from_varmap(varmap, DType::F32, device)
+            }
+            Weights::Inference {
+                tensors,
+                dtype,
+                used,
+                ..
+            } => {
+                let saved = SavedTensors {
+                    tensors: tensors.clone(),
+                    used: used.clone(),
+                };
+                VarBuilder::from_backend(Box::new(saved), *dtype, device.clone())
+            }
+        }
+    }
+
+    /// Seed the weights were initialized with, None for weights saved without one
+    pub(crate) fn seed(&self) -> Option<u64> {
+        match self {
+            Weights::Trainable { seed, .. } | Weights::Inference { seed, .. } => *seed,
+        }
+    }
+
+    /// Variables to optimize, none for inference
+    fn vars(&self) -> Vec<Var> {
+        match self {
+            Weights::Trainable { varmap, .. } => varmap.all_vars(),
+            Weights::Inference { .. } => Vec::new(),
+        }
+    }
+
+    fn optimizer(&mut self, learning_rate: f64) -> anyhow::Result<&mut candle_nn::AdamW> {
+        match self {
+            Weights::Trainable {
+                varmap, optimizer, ..
+            } => match optimizer {
+                Some(optimizer) => Ok(optimizer),
+                None => Ok(optimizer.insert(self::optimizer(varmap, learning_rate)?)),
+            },
+            Weights::Inference { .. } => bail!("A model loaded for inference cannot be trained"),
+        }
+    }
+
+    pub(crate) fn reset_optimizer(&mut self) {
+        if let Weights::Trainable { optimizer, .. } = self {
+            *optimizer = None;
+        }
+    }
+
+    /// Overwrites the variables with the tensors saved at `path` by a model of N moves and I state
+    /// values, and the seed with theirs
+    pub(crate) fn load<const N: usize, const I: usize>(
+        &mut self,
+        path: &str,
+    ) -> anyhow::Result<()> {
+        checkpoint::check_dimensions::<N, I>(path)?;
+        match self {
+            Weights::Trainable { varmap, seed, .. } => {
+                varmap
+                    .load(path)
+                    .with_context(|| format!("Loading model weights from {}", path))?;
+                *seed = checkpoint::init_seed(path)?;
+                checkpoint::check_unused(path, varmap.data().lock().unwrap().keys())
+            }
+            Weights::Inference { .. } => bail!("The weights of a model for inference are fixed"),
+        }
+    }
+
+    /// Saves the tensors with `metadata` and the seed in the metadata
+    pub(crate) fn save(
+        &self,
+        path: &str,
+        mut metadata: HashMap<String, String>,
+    ) -> anyhow::Result<()> {
+        match self {
+            Weights::Trainable { varmap, .. } => varmap.save(path)?,
+            Weights::Inference { tensors, .. } => candle_core::safetensors::save(tensors, path)?,
+        }
+        if let Some(seed) = self.seed() {
+            metadata.insert(checkpoint::INIT_SEED.to_string(), seed.to_string());
+        }
+        checkpoint::write_metadata(path, &metadata)
+    }
+
+    /// Fails when the file at `path` the weights for inference were loaded from has tensors the
+    /// model was not built from. Loading into variables checks this by itself
+    pub(crate) fn check_unused(&self, path: &str) -> anyhow::Result<()> {
+        match self {
+            Weights::Trainable { .. } => Ok(()),
+            Weights::Inference { used, .. } => {
+                checkpoint::check_unused(path, used.lock().unwrap().iter())
+            }
+        }
+    }
+
+    pub(crate) fn parameter_count(&self) -> usize {
+        match self {
+            Weights::Trainable { varmap, .. } => {
+                varmap.all_vars().iter().map(|var| var.elem_count()).sum()
+            }
+            Weights::Inference { tensors, .. } => tensors.values().map(Tensor::elem_count).sum(),
+        }
+    }
+
+    /// Names and shapes of all tensors
+    pub(crate) fn shapes(&self) -> Vec<(String, Vec<usize>)> {
+        let shape = |(name, tensor): (&String, &Tensor)| (name.clone(), tensor.dims().to_vec());
+        match self {
+            Weights::Trainable { varmap, .. } => {
+                let data = varmap.data().lock().unwrap();
+                data.iter()
+                    .map(|(name, var)| shape((name, var.as_tensor())))
+                    .collect()
+            }
+            Weights::Inference { tensors, .. } => tensors.iter().map(shape).collect(),
+        }
+    }
+
+    // Copies of all variables by name, for going back to the weights of an earlier epoch
+    fn snapshot(&self) -> candle_core::Result<Vec<(String, Tensor)>> {
+        let Weights::Trainable { varmap, .. } = self else {
+            return Ok(Vec::new());
+        };
+        let data = varmap.data().lock().unwrap();
+        data.iter()
+            .map(|(name, var)| Ok((name.clone(), var.as_tensor().copy()?)))
+            .collect()
+    }
+
+    fn restore(&self, snapshot: &[(String, Tensor)]) -> candle_core::Result<()> {
+        let Weights::Trainable { varmap, .. } = self else {
+            return Ok(());
+        };
+        let data = varmap.data().lock().unwrap();
+        for (name, tensor) in snapshot {
+            if let Some(var) = data.get(name) {
+                var.set(tensor)?;
             }
         }
         Ok(())
     }
+}
+
+// Saved tensors that remember which ones the layers asked for
+struct SavedTensors {
+    tensors: HashMap<String, Tensor>,
+    used: Arc<Mutex<HashSet<String>>>,
+}
 
-    fn predict(&self, state: [f32; I]) -> Result<([f32; N], f32), anyhow::Error> {
-        let state_tensor = Tensor::from_slice(&state, (1, I), &DEVICE)?;
-        let predictions = self.forward(&state_tensor)?;
-        let predictions: Vec<f32> = predictions.squeeze(0)?.to_vec1()?;
-        let visits: [f32; N] = predictions[0..N].try_into()?;
-        let score = predictions[N];
-        Ok((visits, score))
+impl SimpleBackend for SavedTensors {
+    fn get(
+        &self,
+        shape: Shape,
+        name: &str,
+        _: Init,
+        dtype: DType,
+        device: &Device,
+    ) -> candle_core::Result<Tensor> {
+        let tensor = self.get_unchecked(name, dtype, device)?;
+        if tensor.shape() != &shape {
+            candle_core::bail!(
+                "shape mismatch on {name}: {shape:?} <> {:?}",
+                tensor.shape()
+            );
+        }
+        Ok(tensor)
     }
 
-    fn predict_moves(&self, state: [f32; I]) -> anyhow::Result<[f32; N]> {
-        /*
-        let state_tensor = Tensor::from_slice(&state, (1, I), &DEVICE)?;
-        let visits = self.forward(&state_tensor)?;
-        let visits: Vec<f32> = visits.squeeze(0)?.to_vec1()?;
-        let visits_array: [f32; N] = visits
-            .try_into()
-            .expect("wrong output dimension from visits prediction SimpleModel");
-        Ok(visits_array)
-        */
-        Ok(self.predict(state)?.0)
+    fn get_unchecked(
+        &self,
+        name: &str,
+        dtype: DType,
+        device: &Device,
+    ) -> candle_core::Result<Tensor> {
+        let tensor =
+            self.tensors
+                .get(name)
+                .ok_or_else(|| candle_core::Error::CannotFindTensor {
+                    path: name.to_string(),
+                })?;
+        self.used.lock().unwrap().insert(name.to_string());
+        tensor.to_dtype(dtype)?.to_device(device)
     }
 
-    fn predict_score(&self, state: [f32; I]) -> anyhow::Result<f32> {
-        Ok(self.predict(state)?.1)
+    fn contains_tensor(&self, name: &str) -> bool {
+        self.tensors.contains_key(name)
+    }
+}
+
+// Makes the variables the layers ask for with values from a seeded generator rather than the
+// device's, which candle cannot seed on the CPU. Models build their layers in the same order every
+// time, so the same seed gives the same weights
+struct SeededInit {
+    varmap: VarMap,
+    rng: Mutex<StdRng>,
+}
+
+impl SimpleBackend for SeededInit {
+    fn get(
+        &self,
+        shape: Shape,
+        name: &str,
+        init: Init,
+        dtype: DType,
+        device: &Device,
+    ) -> candle_core::Result<Tensor> {
+        let mut data = self.varmap.data().lock().unwrap();
+        if let Some(var) = data.get(name) {
+            if var.shape() != &shape {
+                candle_core::bail!("shape mismatch on {name}: {shape:?} <> {:?}", var.shape());
+            }
+            return Ok(var.as_tensor().clone());
+        }
+        let values = initial_values(&shape, init, &mut self.rng.lock().unwrap());
+        let var = Var::from_tensor(&Tensor::from_vec(values, shape, device)?.to_dtype(dtype)?)?;
+        let tensor = var.as_tensor().clone();
+        data.insert(name.to_string(), var);
+        Ok(tensor)
+    }
+
+    fn get_unchecked(&self, name: &str, _: DType, _: &Device) -> candle_core::Result<Tensor> {
+        candle_core::bail!("{name} has no shape to initialize it with")
+    }
+
+    fn contains_tensor(&self, name: &str) -> bool {
+        self.varmap.data().lock().unwrap().contains_key(name)
+    }
+}
+
+// Values from the distribution Init::var draws them from
+fn initial_values(shape: &Shape, init: Init, rng: &mut StdRng) -> Vec<f32> {
+    let count = shape.elem_count();
+    let mut uniform = |lo: f64, up: f64| -> Vec<f32> {
+        (0..count)
+            .map(|_| (lo + (up - lo) * rng.gen::<f64>()) as f32)
+            .collect()
+    };
+    match init {
+        Init::Const(value) => vec![value as f32; count],
+        Init::Uniform { lo, up } => uniform(lo, up),
+        Init::Randn { mean, stdev } => normal_values(count, mean, stdev, rng),
+        Init::Kaiming {
+            dist,
+            fan,
+            non_linearity,
+        } => {
+            let stdev = non_linearity.gain() / (fan.for_shape(shape) as f64).sqrt();
+            match dist {
+                NormalOrUniform::Uniform => uniform(-3f64.sqrt() * stdev, 3f64.sqrt() * stdev),
+                NormalOrUniform::Normal => normal_values(count, 0.0, stdev, rng),
+            }
+        }
+    }
+}
+
+// Box-Muller, rand itself has no normal distribution
+fn normal_values(count: usize, mean: f64, stdev: f64, rng: &mut StdRng) -> Vec<f32> {
+    (0..count)
+        .map(|_| {
+            let radius = (-2.0 * (1.0 - rng.gen::<f64>()).ln()).sqrt();
+            let angle = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
+            (mean + stdev * radius * angle.cos()) as f32
+        })
+        .collect()
+}
+
+// ILLEGAL_LOGIT for the illegal moves of each row, 0 for the legal ones
+fn illegal_penalty<const N: usize>(
+    legal_moves: &[[bool; N]],
+    device: &Device,
+) -> candle_core::Result<Tensor> {
+    Tensor::from_vec(
+        fit::illegal_penalty(legal_moves),
+        (legal_moves.len(), N),
+        device,
+    )
+}
+
+impl LossTensor for Tensor {
+    fn row_index(&self, batch: &[u32]) -> anyhow::Result<Self> {
+        Ok(Tensor::from_slice(batch, batch.len(), self.device())?)
+    }
+
+    fn select_rows(&self, index: &Self) -> anyhow::Result<Self> {
+        Ok(self.index_select(index, 0)?)
+    }
+
+    fn plus(&self, other: &Self) -> anyhow::Result<Self> {
+        Ok(self.broadcast_add(other)?)
+    }
+
+    fn minus(&self, other: &Self) -> anyhow::Result<Self> {
+        Ok(self.broadcast_sub(other)?)
+    }
+
+    fn times(&self, other: &Self) -> anyhow::Result<Self> {
+        Ok(self.broadcast_mul(other)?)
+    }
+
+    fn scaled(&self, factor: f64) -> anyhow::Result<Self> {
+        Ok((self * factor)?)
+    }
+
+    fn squared(&self) -> anyhow::Result<Self> {
+        Ok(self.sqr()?)
+    }
+
+    fn log_softmax_rows(&self) -> anyhow::Result<Self> {
+        Ok(candle_nn::ops::log_softmax(self, 1)?)
+    }
+
+    fn row_sums(&self) -> anyhow::Result<Self> {
+        Ok(self.sum_keepdim(1)?)
+    }
+
+    fn sum_all(&self) -> anyhow::Result<Self> {
+        Ok(Tensor::sum_all(self)?)
+    }
+
+    fn mean_all(&self) -> anyhow::Result<Self> {
+        Ok(Tensor::mean_all(self)?)
     }
+
+    fn scalar(&self) -> anyhow::Result<f32> {
+        Ok(self.to_scalar()?)
+    }
+}
+
+// A model with the samples it is fit to
+struct CandleFit<'a, M> {
+    model: &'a mut M,
+    samples: Samples<Tensor>,
 }
 
-impl<const N: usize, const I: usize> Module for SimpleModel<N, I> {
-    fn forward(&self, xs: &Tensor) -> candle_core::Result<Tensor> {
-        let x = self.layer1.forward(xs)?;
-        let x = x.relu()?;
-        let x = self.layer2.forward(&x)?;
-        let x = x.relu()?;
-        let visit_logits = self.visit_head.forward(&x)?;
-        let score = self.score_head.forward(&x)?.tanh()?;
-        let visit_dist = candle_nn::ops::softmax(&visit_logits, 1)?;
-        Ok(Tensor::cat(&[&visit_dist, &score], 1)?)
+impl<M: CandleModel> MinibatchFit for CandleFit<'_, M> {
+    type Snapshot = Vec<(String, Tensor)>;
+
+    fn step(
+        &mut self,
+        batch: &[u32],
+        learning_rate: f64,
+    ) -> anyhow::Result<Option<TrainingMetrics>> {
+        let fit = *self.model.fit_config();
+        let loss_weights = self.model.loss_weights();
+        self.model
+            .weights_mut()
+            .optimizer(fit.learning_rate)?
+            .set_learning_rate(learning_rate);
+        let losses = self
+            .samples
+            .losses(batch, |xs| Ok(self.model.heads(xs, true)?))?;
+        let total = losses.total(loss_weights)?;
+        match clipped_step(self.model, &total, fit.max_grad_norm, fit.learning_rate)? {
+            true => Ok(Some(losses.metrics()?)),
+            false => Ok(None),
+        }
+    }
+
+    fn evaluate(&self, batch: &[u32]) -> anyhow::Result<TrainingMetrics> {
+        self.samples
+            .losses(batch, |xs| Ok(self.model.heads(xs, false)?))?
+            .metrics()
+    }
+
+    fn snapshot(&self) -> anyhow::Result<Self::Snapshot> {
+        Ok(self.model.weights().snapshot()?)
+    }
+
+    fn restore(&mut self, snapshot: Self::Snapshot) -> anyhow::Result<()> {
+        Ok(self.model.weights().restore(&snapshot)?)
     }
 }
 
-pub fn softmax<const N: usize>(data: Vec<[f32; N]>) -> anyhow::Result<Vec<[f32; N]>> {
-    let mut out = Vec::new();
-    let length = data.len();
-    let flattened: Vec<_> = data.iter().cloned().flatten().collect();
-    let tensor = Tensor::from_vec(flattened, (length, N), &DEVICE)?;
-    let softmaxed = candle_nn::ops::softmax(&tensor, 1)?;
+pub(crate) fn train<const N: usize, const I: usize, M: CandleModel>(
+    model: &mut M,
+    dataset: crate::dataset::Dataset<N, I>,
+) -> anyhow::Result<TrainReport> {
+    let device = model.device();
+    let samples = Samples::new::<N, I>(TrainingRows::new(&dataset), |values, shape| {
+        Ok(Tensor::from_slice(values, shape, device)?)
+    })?;
+    let positions = dataset.source_positions();
+    let fit = *model.fit_config();
+    let generation = model.generation();
+    let loss_weights = model.loss_weights();
+    let mut candle_fit = CandleFit { model, samples };
+    fit_minibatches(&mut candle_fit, &positions, &fit, generation, loss_weights)
+}
 
-    for thing in softmaxed.flatten_all()?.to_vec1::<f32>()?.chunks_exact(N) {
-        let mut pain = [0.0; N];
-        pain[..N].copy_from_slice(&thing[..N]);
-        out.push(pain);
+// Takes an optimizer step on `loss` with the gradients scaled down to a global norm of at most
+// `max_norm`. A loss or gradients that are not finite would turn the weights into NaN for good,
+// so then the step is skipped and false returned
+fn clipped_step<M: CandleModel>(
+    model: &mut M,
+    loss: &Tensor,
+    max_norm: Option<f64>,
+    learning_rate: f64,
+) -> anyhow::Result<bool> {
+    if !loss.to_scalar::<f32>()?.is_finite() {
+        return Ok(false);
+    }
+    let mut grads = loss.backward()?;
+    let vars = model.weights().vars();
+    let mut squared_norm = 0.0;
+    for var in &vars {
+        if let Some(grad) = grads.get(var.as_tensor()) {
+            squared_norm += grad.sqr()?.sum_all()?.to_scalar::<f32>()? as f64;
+        }
     }
+    let norm = squared_norm.sqrt();
+    if !norm.is_finite() {
+        return Ok(false);
+    }
+    if let Some(max_norm) = max_norm.filter(|max_norm| norm > *max_norm) {
+        for var in &vars {
+            if let Some(grad) = grads.remove(var.as_tensor()) {
+                grads.insert(var.as_tensor(), (grad * (max_norm / norm))?);
+            }
+        }
+    }
+    model.weights_mut().optimizer(learning_rate)?.step(&grads)?;
+    Ok(true)
+}
+
+/// Dropout of `rate`, which has to be in [0, 1)
+pub(crate) fn dropout(rate: f32) -> anyhow::Result<Dropout> {
+    ensure!(
+        (0.0..1.0).contains(&rate),
+        "The dropout rate {} is not in [0, 1)",
+        rate
+    );
+    Ok(Dropout::new(rate))
+}
 
-    Ok(out)
+/// Policy logits with the illegal moves penalized, value and margin of a single state
+pub(crate) fn predict<const N: usize, const I: usize, M: CandleModel>(
+    model: &M,
+    state: [f32; I],
+    legal_moves: &[bool; N],
+) -> anyhow::Result<([f32; N], f32, f32)> {
+    let heads = model.heads(&state_tensor(model, state)?, false)?;
+    // The penalty is added in F32, it would be -inf in the smaller float types
+    let logits = heads.visit_logits.to_dtype(DType::F32)?;
+    let logits = (logits + illegal_penalty(&[*legal_moves], model.device())?)?;
+    let logits: Vec<f32> = logits.squeeze(0)?.to_vec1()?;
+    let score = first_value(&heads.score)?;
+    let margin = first_value(&heads.margin)?;
+    Ok((logits.try_into().unwrap(), score, margin))
+}
+
+// A single state as a batch of one in the type the model computes in
+fn state_tensor<const I: usize, M: CandleModel>(
+    model: &M,
+    state: [f32; I],
+) -> candle_core::Result<Tensor> {
+    Tensor::from_slice(&state, (1, I), model.device())?.to_dtype(model.weights().dtype())
+}
+
+fn first_value(output: &Tensor) -> candle_core::Result<f32> {
+    Ok(output.squeeze(0)?.to_dtype(DType::F32)?.to_vec1::<f32>()?[0])
+}
+
+/// Ownership of every cell of a single state, None for models without the ownership head
+pub(crate) fn predict_ownership<const N: usize, const I: usize, M: CandleModel>(
+    model: &M,
+    state: [f32; I],
+) -> anyhow::Result<Option<[f32; N]>> {
+    let Some(ownership) = model.heads(&state_tensor(model, state)?, false)?.ownership else {
+        return Ok(None);
+    };
+    let ownership: Vec<f32> = ownership.squeeze(0)?.to_dtype(DType::F32)?.to_vec1()?;
+    Ok(Some(ownership.try_into().unwrap()))
+}
+
+fn optimizer(varmap: &VarMap, learning_rate: f64) -> anyhow::Result<candle_nn::AdamW> {
+    let optim_config = candle_nn::ParamsAdamW {
+        lr: learning_rate,
+        ..Default::default()
+    };
+    Ok(candle_nn::AdamW::new(varmap.all_vars(), optim_config)?)
+}
+
+impl<const N: usize, const I: usize> TrainableModel<N, I> for SimpleModel<N, I> {
+    type Config = ModelConfig;
+
+    fn new(config: &ModelConfig) -> anyhow::Result<Self> {
+        Self::with_device(*config, select_device())
+    }
+
+    fn reset_optimizer(&mut self) -> anyhow::Result<()> {
+        self.weights.reset_optimizer();
+        Ok(())
+    }
+
+    fn set_generation(&mut self, generation: usize) {
+        self.generation = generation;
+    }
+
+    fn save(&self, path: &str) -> anyhow::Result<()> {
+        let metadata = checkpoint::model_metadata::<N, I>(&self.config, self.generation);
+        self.weights.save(path, metadata)
+    }
+
+    fn parameter_count(&self) -> usize {
+        self.weights.parameter_count()
+    }
+
+    fn init_seed(&self) -> Option<u64> {
+        self.weights.seed()
+    }
+
+    fn summary(&self) -> Architecture {
+        Architecture::from_shapes(self.weights.shapes(), 1)
+    }
+
+    // A new model built with the config saved with the weights, the default for files saved
+    // without one, whose variables the saved weights then overwrite
+    fn load(path: &str) -> anyhow::Result<Self> {
+        let config = checkpoint::saved_config(path)?.unwrap_or_default();
+        let mut model = Self::new(&config)?;
+        model.weights.load::<N, I>(path)?;
+        Ok(model)
+    }
+
+    fn load_inference(path: &str) -> anyhow::Result<Self> {
+        let config = checkpoint::saved_config(path)?.unwrap_or_default();
+        Self::for_inference(config, path, select_device())
+    }
+
+    fn for_self_play(&self) -> anyhow::Result<Self> {
+        let device = device_for(Role::SelfPlay);
+        let weights = self.weights.frozen(self_play_dtype(), &device)?;
+        Self::build(self.config, weights, device)
+    }
+
+    fn train(&mut self, dataset: crate::dataset::Dataset<N, I>) -> anyhow::Result<TrainReport> {
+        train(self, dataset)
+    }
+
+    fn predict(
+        &self,
+        state: [f32; I],
+        legal_moves: &[bool; N],
+    ) -> Result<([f32; N], f32), anyhow::Error> {
+        let (logits, score, _) = predict(self, state, legal_moves)?;
+        Ok((softmax(logits, 1.0), score))
+    }
+
+    fn predict_moves(&self, state: [f32; I], legal_moves: &[bool; N]) -> anyhow::Result<[f32; N]> {
+        Ok(self.predict(state, legal_moves)?.0)
+    }
+
+    fn predict_logits(&self, state: [f32; I], legal_moves: &[bool; N]) -> anyhow::Result<[f32; N]> {
+        Ok(predict(self, state, legal_moves)?.0)
+    }
+
+    fn predict_score(&self, state: [f32; I]) -> anyhow::Result<f32> {
+        Ok(predict(self, state, &[true; N])?.1)
+    }
+
+    fn predict_margin(&self, state: [f32; I]) -> anyhow::Result<Option<f32>> {
+        Ok(Some(predict(self, state, &[true; N])?.2))
+    }
+
+    fn predict_ownership(&self, state: [f32; I]) -> anyhow::Result<Option<[f32; N]>> {
+        predict_ownership(self, state)
+    }
+}
+
+// Not Module, whose forward cannot tell training from inference
+impl<const N: usize, const I: usize> ModuleT for SimpleModel<N, I> {
+    /// The visit logits, score, margin and the ownership if the model has it of every row of
+    /// `xs` side by side, with dropout in training
+    fn forward_t(&self, xs: &Tensor, train: bool) -> candle_core::Result<Tensor> {
+        let heads = self.heads(xs, train)?;
+        let mut outputs = vec![heads.visit_logits, heads.score, heads.margin];
+        outputs.extend(heads.ownership);
+        Tensor::cat(&outputs, 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dataset::{create_dataset, SelfPlayConfig};
+    use crate::game::{Game, RandomPolicy};
+    use crate::mcts::MctsConfig;
+    use crate::mnk::TicTacToe;
+    use crate::model::compare_precision;
+
+    #[test]
+    fn f16_predictions_stay_close_to_f32() -> anyhow::Result<()> {
+        let config = SelfPlayConfig {
+            mcts: MctsConfig {
+                simulations: 50,
+                ..Default::default()
+            },
+            seed: Some(0),
+            ..Default::default()
+        };
+        let dataset = create_dataset(&TicTacToe::new(), 10, &RandomPolicy::default(), 0, &config)?;
+        let mut model = SimpleModel::<9, 18>::new(&ModelConfig::default())?;
+        model.train(dataset.clone())?;
+        let reduced = model.with_dtype(DType::F16)?;
+        let report = compare_precision(&model, &reduced, &dataset, 200)?;
+        assert!(
+            report.max_policy_error < 0.01 && report.max_value_error < 0.01,
+            "{}",
+            report
+        );
+        Ok(())
+    }
 }
@@ -1,46 +1,19 @@
 use std::fmt::Display;
 
-use anyhow::Ok;
+use anyhow::{bail, ensure, Context, Ok, Result};
 use rand::seq::IteratorRandom;
 
 use crate::{
-    game::{Game, Players, SimpleBoardState},
+    game::{
+        board_from_string, board_to_string, encode_board, simple_board_planes, swap_board, Game,
+        Players, SimpleBoardState, SpatialGame,
+    },
     mcts::GameStats,
+    mnk::TicTacToe,
+    render::{Board, BoardRenderer, UnicodeRenderer},
 };
 
 impl Checkers {
-    pub fn print(&self) {
-        let mut display_board = Vec::with_capacity(9);
-        for space in &self.board {
-            display_board.push(match space {
-                SimpleBoardState::Empty => " ",
-                SimpleBoardState::Player => "X",
-                SimpleBoardState::Opponent => "O",
-            });
-        }
-        let one = display_board[0];
-        let two = display_board[1];
-        let three = display_board[2];
-        let four = display_board[3];
-        let five = display_board[4];
-        let six = display_board[5];
-        let seven = display_board[6];
-        let eight = display_board[7];
-        let nine = display_board[8];
-        println!("╔═╦═╦═╗");
-        println!("║{one}║{two}║{three}║");
-        println!("╠═╬═╬═╣");
-        println!("║{four}║{five}║{six}║");
-        println!("╠═╬═╬═╣");
-        println!("║{seven}║{eight}║{nine}║");
-        println!("╚═╩═╩═╝");
-        let next_player = match self.current_player {
-            Players::Player => "X",
-            Players::Opponent => "O",
-        };
-        println!("Next player: {}", next_player);
-    }
-
     fn validate_board_state(&self) {
         let player_pieces = self
             .board
@@ -57,37 +30,19 @@ impl Checkers {
     }
 }
 
+impl SpatialGame<9, 18> for Checkers {
+    fn plane_shape(&self) -> (usize, usize, usize) {
+        (3, 3, 3)
+    }
+
+    fn spatial_planes(&self) -> Vec<f32> {
+        simple_board_planes(&self.board, self.current_player)
+    }
+}
+
 impl Display for Checkers {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut display_board = Vec::with_capacity(9);
-        for space in &self.board {
-            display_board.push(match space {
-                SimpleBoardState::Empty => " ",
-                SimpleBoardState::Player => "X",
-                SimpleBoardState::Opponent => "O",
-            });
-        }
-        let one = display_board[0];
-        let two = display_board[1];
-        let three = display_board[2];
-        let four = display_board[3];
-        let five = display_board[4];
-        let six = display_board[5];
-        let seven = display_board[6];
-        let eight = display_board[7];
-        let nine = display_board[8];
-        writeln!(f, "╔═╦═╦═╗")?;
-        writeln!(f, "║{one}║{two}║{three}║")?;
-        writeln!(f, "╠═╬═╬═╣")?;
-        writeln!(f, "║{four}║{five}║{six}║")?;
-        writeln!(f, "╠═╬═╬═╣")?;
-        writeln!(f, "║{seven}║{eight}║{nine}║")?;
-        writeln!(f, "╚═╩═╩═╝")?;
-        let next_player = match self.current_player {
-            Players::Player => "X",
-            Players::Opponent => "O",
-        };
-        writeln!(f, "Next player: {}", next_player)
+        write!(f, "{}", UnicodeRenderer::default().render(self))
     }
 }
 
@@ -133,19 +88,32 @@ impl Game<9, 18> for Checkers {
         moves
     }
 
-    fn perform_move(&mut self, space: usize) {
-        assert!(self.board[space] == SimpleBoardState::Empty);
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(
+            self.board.get(space) == Some(&SimpleBoardState::Empty),
+            "Tried to make move on occupied square"
+        );
         self.board[space] = self.current_player.into();
+        self.history.push(space);
         self.current_player = match self.current_player {
             Players::Player => Players::Opponent,
             Players::Opponent => Players::Player,
         };
+        Ok(())
+    }
+
+    fn undo_move(&mut self) -> Result<()> {
+        let space = self.history.pop().context("No move to undo")?;
+        self.board[space] = SimpleBoardState::Empty;
+        self.current_player = self.current_player.swap();
+        Ok(())
     }
 
     fn new() -> Self {
         Self {
             board: [SimpleBoardState::Empty; 9],
             current_player: Players::Player,
+            history: Vec::new(),
         }
     }
 
@@ -160,39 +128,53 @@ impl Game<9, 18> for Checkers {
     }
 
     fn flip_board(&mut self) {
-        let flipped_board = self.board.map(|square| square.swap());
-        self.board = flipped_board;
+        swap_board(&mut self.board);
         self.current_player = self.current_player.swap();
     }
 
     fn get_game_state_slice(&self) -> [f32; 18] {
-        let mut out_slice = [0.0; 18];
-        for i in 0..self.board.len() {
-            out_slice[i] = match self.board[i] {
-                SimpleBoardState::Player => 1.0,
-                _ => 0.0,
-            }
-        }
-        for i in 0..self.board.len() {
-            out_slice[self.board.len() + i] = match self.board[i] {
-                SimpleBoardState::Opponent => 1.0,
-                _ => 0.0,
-            }
+        encode_board(&self.board)
+    }
+
+    fn board(&self) -> Option<Board> {
+        Some(Board::read(self))
+    }
+
+    // Squares are numbered 1 to 9 row by row, like a phone keypad
+    fn move_to_string(&self, mv: usize) -> String {
+        (mv + 1).to_string()
+    }
+
+    fn move_from_string(&self, text: &str) -> Result<usize> {
+        match text.trim().parse::<usize>().ok() {
+            Some(square @ 1..=9) => Ok(square - 1),
+            _ => bail!("'{}' is not a square from 1 to 9", text),
         }
-        out_slice
     }
 
+    fn to_position_string(&self) -> Result<String> {
+        Ok(board_to_string(&self.board, 3, self.current_player))
+    }
+
+    fn from_position_string(position: &str) -> Result<Self> {
+        let (board, current_player) = board_from_string(position, 3, 3)?;
+        Ok(Self {
+            board: board.try_into().unwrap(),
+            current_player,
+            history: Vec::new(),
+        })
+    }
+
+    // The board, moves and state layout are the same as the 3x3 m,n,k-game, so its 8 rotations
+    // and reflections apply as they are
     fn get_game_variations(stats: &GameStats<9, 18>) -> Vec<GameStats<9, 18>> {
-        vec![stats.clone()]
+        TicTacToe::get_game_variations(stats)
     }
 }
 
 #[allow(unused)]
 fn run_random_checkers() {
-    let mut game = Checkers {
-        board: [SimpleBoardState::Empty; 9],
-        current_player: Players::Player,
-    };
+    let mut game = Checkers::new();
     while !game.game_ended() {
         let next_move = game
             .available_moves()
@@ -207,6 +189,7 @@ fn run_random_checkers() {
     }
 }
 
+// Despite the name this is tic-tac-toe, checkers itself is draughts::Draughts
 #[derive(Debug, Clone)]
 pub struct Checkers {
     // 3x3 board
@@ -216,4 +199,6 @@ pub struct Checkers {
     // 6 7 8
     board: [SimpleBoardState; 9],
     current_player: Players,
+    // Squares played so far, for undo_move
+    history: Vec<usize>,
 }
@@ -0,0 +1,275 @@
+//! Metadata in the header of the safetensors files models are saved as. The tensor libraries save
+//! without any, so it is added to the file afterwards
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    io::Read,
+};
+
+use anyhow::{ensure, Context, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{Map, Value};
+
+/// Key of the seed the weights were initialized with
+pub const INIT_SEED: &str = "init_seed";
+/// Key of the N of the model, the number of moves
+pub const MOVES: &str = "moves";
+/// Key of the I of the model, the length of the state slice
+pub const STATE_LEN: &str = "state_len";
+/// Key of the model's config as JSON, which loading builds the model from
+pub const ARCHITECTURE: &str = "architecture";
+/// Key of the generation the model was set to when it was saved
+pub const GENERATION: &str = "generation";
+/// Key of the type name of the game the model was trained on
+pub const GAME: &str = "game";
+/// Key of Dataset::content_hash of the samples the model was last trained on
+pub const DATA_HASH: &str = "data_hash";
+/// Key of the git revision of the build that saved the model, see build.rs
+pub const GIT_REVISION: &str = "git_revision";
+
+const METADATA: &str = "__metadata__";
+
+// The header is its length as 8 little endian bytes followed by that much JSON
+fn parse_header(header: &[u8]) -> Result<Map<String, Value>> {
+    match serde_json::from_slice(header)? {
+        Value::Object(header) => Ok(header),
+        _ => anyhow::bail!("The header is not a JSON object"),
+    }
+}
+
+fn read_header(path: &str) -> Result<Map<String, Value>> {
+    let mut file = fs::File::open(path)?;
+    let mut length = [0; 8];
+    file.read_exact(&mut length)?;
+    let mut header = vec![0; u64::from_le_bytes(length) as usize];
+    file.read_exact(&mut header)?;
+    parse_header(&header)
+}
+
+/// Metadata of the safetensors file at `path`, empty when it has none
+pub fn read_metadata(path: &str) -> Result<HashMap<String, String>> {
+    let read = || -> Result<_> {
+        let metadata = match read_header(path)?.remove(METADATA) {
+            Some(metadata) => serde_json::from_value(metadata)?,
+            None => HashMap::new(),
+        };
+        Ok(metadata)
+    };
+    read().with_context(|| format!("Reading the metadata of {}", path))
+}
+
+/// Names of the tensors saved at `path`
+pub fn tensor_names(path: &str) -> Result<HashSet<String>> {
+    let mut header =
+        read_header(path).with_context(|| format!("Reading the tensors of {}", path))?;
+    header.remove(METADATA);
+    Ok(header.keys().cloned().collect())
+}
+
+/// Fails when the file at `path` has tensors that are not among the `used` ones of the model
+/// loading it, a model with fewer layers than the one that saved them
+pub fn check_unused<'a>(path: &str, used: impl IntoIterator<Item = &'a String>) -> Result<()> {
+    let mut unused = tensor_names(path)?;
+    for name in used {
+        unused.remove(name);
+    }
+    let mut unused: Vec<String> = unused.into_iter().collect();
+    unused.sort();
+    ensure!(
+        unused.is_empty(),
+        "{} has tensors the model does not use, it was saved by another architecture: {}",
+        path,
+        unused.join(", ")
+    );
+    Ok(())
+}
+
+/// Adds `metadata` to the safetensors file at `path`, replacing the values of keys it already has
+pub fn write_metadata(path: &str, metadata: &HashMap<String, String>) -> Result<()> {
+    let write = || -> Result<()> {
+        let bytes = fs::read(path)?;
+        ensure!(bytes.len() >= 8, "The file is too short for a header");
+        let length = u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize;
+        let data_start = 8 + length;
+        ensure!(bytes.len() >= data_start, "The header is cut off");
+        let mut header = parse_header(&bytes[8..data_start])?;
+        let mut all = match header.remove(METADATA) {
+            Some(existing) => serde_json::from_value(existing)?,
+            None => HashMap::new(),
+        };
+        all.extend(metadata.clone());
+        header.insert(METADATA.to_string(), serde_json::to_value(all)?);
+        let mut header = serde_json::to_vec(&header)?;
+        // Padded with spaces so the tensors stay aligned to 8 bytes, like the writers do
+        header.resize(header.len().next_multiple_of(8), b' ');
+        let mut file = (header.len() as u64).to_le_bytes().to_vec();
+        file.extend(header);
+        file.extend(&bytes[data_start..]);
+        Ok(fs::write(path, file)?)
+    };
+    write().with_context(|| format!("Writing metadata to {}", path))
+}
+
+/// What a model of N moves and I state values with `config` saves with its weights
+pub fn model_metadata<const N: usize, const I: usize>(
+    config: &impl Serialize,
+    generation: usize,
+) -> HashMap<String, String> {
+    let architecture = serde_json::to_string(config).expect("Model configs serialize to JSON");
+    let mut metadata = HashMap::from([
+        (MOVES.to_string(), N.to_string()),
+        (STATE_LEN.to_string(), I.to_string()),
+        (ARCHITECTURE.to_string(), architecture),
+        (GENERATION.to_string(), generation.to_string()),
+    ]);
+    if let Some(revision) = option_env!("GIT_REVISION") {
+        metadata.insert(GIT_REVISION.to_string(), revision.to_string());
+    }
+    metadata
+}
+
+/// What training adds to the checkpoint of a model trained on game T
+pub fn training_metadata<T>(data_hash: u64) -> HashMap<String, String> {
+    HashMap::from([
+        (GAME.to_string(), std::any::type_name::<T>().to_string()),
+        (DATA_HASH.to_string(), format!("{:016x}", data_hash)),
+    ])
+}
+
+/// Fails when the weights at `path` were saved by a model for another number of moves or state
+/// values, which would otherwise load as long as the layer sizes happen to fit and then predict
+/// garbage. Files saved without the dimensions pass
+pub fn check_dimensions<const N: usize, const I: usize>(path: &str) -> Result<()> {
+    let metadata = read_metadata(path)?;
+    let game = match metadata.get(GAME) {
+        Some(game) => format!(" for {}", game),
+        None => String::new(),
+    };
+    for (key, expected) in [(MOVES, N), (STATE_LEN, I)] {
+        let Some(saved) = metadata.get(key) else {
+            continue;
+        };
+        ensure!(
+            *saved == expected.to_string(),
+            "{} was saved with {} {}{} but the model has {}",
+            path,
+            key,
+            saved,
+            game,
+            expected
+        );
+    }
+    Ok(())
+}
+
+/// Fails when the model at `path` was trained on another game than T. Games with the same
+/// dimensions, like 7x7 Hex, the game of Y and Havannah, pass check_dimensions but their models
+/// are not interchangeable. Files saved without the game pass
+pub fn check_game<T>(path: &str) -> Result<()> {
+    let Some(saved) = saved_game(path)? else {
+        return Ok(());
+    };
+    let game = std::any::type_name::<T>();
+    ensure!(
+        saved == game,
+        "{} was trained on {} but is loaded for {}",
+        path,
+        saved,
+        game
+    );
+    Ok(())
+}
+
+/// The game the model at `path` was trained on, None for files saved without it
+pub fn saved_game(path: &str) -> Result<Option<String>> {
+    Ok(read_metadata(path)?.remove(GAME))
+}
+
+/// The config the model at `path` was saved with, which it has to be built with to load. None for
+/// files saved without one, and before it was JSON
+pub fn saved_config<C: DeserializeOwned>(path: &str) -> Result<Option<C>> {
+    let metadata = read_metadata(path)?;
+    let Some(architecture) = metadata.get(ARCHITECTURE) else {
+        return Ok(None);
+    };
+    let Ok(value) = serde_json::from_str::<Value>(architecture) else {
+        return Ok(None);
+    };
+    let config = serde_json::from_value(value).with_context(|| {
+        format!(
+            "{} was saved by another kind of model, with {}",
+            path, architecture
+        )
+    })?;
+    Ok(Some(config))
+}
+
+/// The INIT_SEED of the weights saved at `path`, None for files saved without one
+pub fn init_seed(path: &str) -> Result<Option<u64>> {
+    read_metadata(path)?
+        .get(INIT_SEED)
+        .map(|seed| {
+            seed.parse()
+                .with_context(|| format!("Init seed '{}' of {} is not a number", seed, path))
+        })
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candle_ai::{select_device, SimpleModel};
+    use crate::game_of_y::GameOfY;
+    use crate::havannah::Havannah4;
+    use crate::hex::Hex;
+    use crate::model::{ModelConfig, TrainableModel};
+
+    #[test]
+    fn loads_with_the_saved_config() -> anyhow::Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("checkpoint_{}.safetensors", std::process::id()));
+        let path = path.to_str().unwrap();
+        let config = ModelConfig {
+            depth: 3,
+            hidden_size: 16,
+            seed: Some(0),
+            ..Default::default()
+        };
+        let model = SimpleModel::<9, 18>::new(&config)?;
+        model.save(path)?;
+        let loaded = SimpleModel::<9, 18>::load(path)?;
+        let inference = SimpleModel::<9, 18>::load_inference(path)?;
+        let state = [0.0; 18];
+        let shallow = SimpleModel::<9, 18>::for_inference(
+            ModelConfig { depth: 2, ..config },
+            path,
+            select_device(),
+        );
+        std::fs::remove_file(path)?;
+        assert_eq!(loaded.parameter_count(), model.parameter_count());
+        assert_eq!(inference.parameter_count(), model.parameter_count());
+        assert_eq!(loaded.predict_score(state)?, model.predict_score(state)?);
+        let error = shallow.err().expect("A model without a layer loads");
+        assert!(error.to_string().contains("does not use"), "{}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_models_of_another_game() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("game_{}.safetensors", std::process::id()));
+        let path = path.to_str().unwrap();
+        let model = SimpleModel::<49, 98>::new(&ModelConfig::default())?;
+        model.save(path)?;
+        write_metadata(path, &training_metadata::<Hex<49, 98>>(0))?;
+        let same = check_game::<Hex<49, 98>>(path);
+        let y = check_game::<GameOfY<49, 98>>(path);
+        let havannah = check_game::<Havannah4>(path);
+        std::fs::remove_file(path)?;
+        same?;
+        let error = y.err().expect("A Hex model loads for the game of Y");
+        assert!(error.to_string().contains("trained on"), "{}", error);
+        assert!(havannah.is_err(), "A Hex model loads for Havannah");
+        Ok(())
+    }
+}
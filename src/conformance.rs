@@ -0,0 +1,245 @@
+//! Invariants every Game implementation has to keep, checked over random games. A new game opts
+//! in with a line in the conformance_tests! list at the end of this file
+
+use anyhow::{ensure, Context, Result};
+use rand::{rngs::StdRng, seq::SliceRandom};
+
+use crate::game::{move_indices, resolve_chance, Game, Players};
+
+// Moves after which a random game is taken to never end
+const MAX_MOVES: usize = 10_000;
+// Unavailable moves tried in every position, to see that they are rejected
+const ILLEGAL_SAMPLES: usize = 3;
+
+// Everything about a position the invariants compare, states are compared bit for bit
+#[derive(PartialEq, Debug)]
+struct Snapshot {
+    state: Vec<u32>,
+    current_player: Players,
+    winning_player: Option<Players>,
+    game_ended: bool,
+    available_moves: Vec<bool>,
+    hash: u64,
+}
+
+impl Snapshot {
+    fn of<const N: usize, const I: usize, T: Game<N, I>>(game: &T) -> Self {
+        Self {
+            state: game
+                .get_game_state_slice()
+                .iter()
+                .map(|value| value.to_bits())
+                .collect(),
+            current_player: game.current_player(),
+            winning_player: game.winning_player(),
+            game_ended: game.game_ended(),
+            available_moves: game.available_moves().to_vec(),
+            hash: game.position_hash(),
+        }
+    }
+}
+
+fn check_position<const N: usize, const I: usize, T: Game<N, I>>(
+    game: &T,
+    rng: &mut StdRng,
+) -> Result<()> {
+    let snapshot = Snapshot::of(game);
+    ensure!(
+        game.get_game_state_slice()
+            .iter()
+            .all(|value| value.is_finite()),
+        "State slice has values that are not finite"
+    );
+
+    let finished = game.game_ended() || game.is_draw_by_rule();
+    ensure!(
+        game.winning_player().is_none() || finished,
+        "There is a winner but the game goes on"
+    );
+    match (
+        game.terminal_value(Players::Player),
+        game.terminal_value(Players::Opponent),
+    ) {
+        (None, None) => ensure!(!finished, "Finished game without a terminal value"),
+        (Some(player), Some(opponent)) => {
+            ensure!(finished, "Terminal value for a game that goes on");
+            ensure!(
+                (-1.0..=1.0).contains(&player) && player == -opponent,
+                "Terminal values {} and {} are not opposite values in [-1, 1]",
+                player,
+                opponent
+            );
+        }
+        _ => anyhow::bail!("Terminal value for only one of the players"),
+    }
+    let moves = move_indices(game);
+    if !finished && game.chance_outcomes().is_none() {
+        ensure!(!moves.is_empty(), "Game goes on but has no available moves");
+    }
+    if let Some(pass) = game.pass_move() {
+        ensure!(pass < N, "Pass move {} is outside the moves", pass);
+    }
+
+    let mut flipped = game.clone();
+    flipped.flip_board();
+    ensure!(
+        flipped.current_player() == game.current_player().swap()
+            && flipped.winning_player() == game.winning_player().map(|player| player.swap()),
+        "flip_board does not swap the players"
+    );
+    let flipped_moves = flipped.available_moves();
+    for mv in &moves {
+        ensure!(
+            flipped_moves[game.flipped_move(*mv)],
+            "Move {} is not available after flip_board as {}",
+            mv,
+            game.flipped_move(*mv)
+        );
+        ensure!(
+            flipped.flipped_move(game.flipped_move(*mv)) == *mv,
+            "flipped_move of move {} does not give it back when flipped again",
+            mv
+        );
+    }
+    flipped.flip_board();
+    ensure!(
+        Snapshot::of(&flipped) == snapshot,
+        "flip_board twice changes the position"
+    );
+
+    if game.chance_outcomes().is_some() {
+        return Ok(());
+    }
+    let mut played = game.clone();
+    for mv in &moves {
+        let text = game.move_to_string(*mv);
+        ensure!(
+            game.move_from_string(&text).ok() == Some(*mv),
+            "Move {} written as '{}' does not parse back",
+            mv,
+            text
+        );
+        played
+            .try_perform_move(*mv)
+            .with_context(|| format!("Available move {} was rejected", mv))?;
+        played.undo_move()?;
+        ensure!(
+            Snapshot::of(&played) == snapshot,
+            "Playing and undoing move {} changes the position",
+            mv
+        );
+    }
+    // What happens to moves after the end is up to the game, nothing plays them
+    if finished {
+        return Ok(());
+    }
+    let unavailable: Vec<usize> = (0..N).filter(|mv| !snapshot.available_moves[*mv]).collect();
+    for mv in unavailable.choose_multiple(rng, ILLEGAL_SAMPLES) {
+        if played.try_perform_move(*mv).is_ok() {
+            anyhow::bail!("Unavailable move {} was accepted", mv);
+        }
+        ensure!(
+            Snapshot::of(&played) == snapshot,
+            "Rejecting move {} changed the position",
+            mv
+        );
+    }
+    Ok(())
+}
+
+/// Plays `games` random games and checks every position along the way. Positions are consistent
+/// about whether the game is over, who won and what it is worth, flip_board and flipped_move
+/// are their own inverses, every available move can be played, undone and written down, and
+/// while the game goes on unavailable moves are rejected without touching it. Games also have
+/// to end, and replaying the moves with Game::from_moves has to give the same position
+pub fn check<const N: usize, const I: usize, T: Game<N, I>>(
+    games: usize,
+    rng: &mut StdRng,
+) -> Result<()> {
+    for game_number in 0..games {
+        let mut game = T::new();
+        let mut moves = Vec::new();
+        let mut chance = false;
+        loop {
+            check_position(&game, rng)
+                .with_context(|| format!("Game {} after moves {:?}", game_number, moves))?;
+            if game.chance_outcomes().is_some() {
+                chance = true;
+                resolve_chance(&mut game, rng)?;
+                continue;
+            }
+            if game.game_ended() || game.is_draw_by_rule() {
+                break;
+            }
+            ensure!(
+                moves.len() < MAX_MOVES,
+                "Game {} did not end after {} moves",
+                game_number,
+                MAX_MOVES
+            );
+            let mv = *move_indices(&game).choose(rng).unwrap();
+            game.try_perform_move(mv)?;
+            moves.push(mv);
+        }
+        if !chance {
+            let replayed = T::from_moves(&moves)?;
+            ensure!(
+                Snapshot::of(&replayed) == Snapshot::of(&game),
+                "Game {} replayed from its moves {:?} ends in another position",
+                game_number,
+                moves
+            );
+        }
+    }
+    Ok(())
+}
+
+/// One `#[test]` per game running `check` over GAMES seeded random games, like
+/// `conformance_tests!(tic_tac_toe: mnk::TicTacToe, tak: tak::Tak => 3)`, where `=> 3` plays
+/// fewer games for games with long or slow random playouts
+#[cfg(test)]
+macro_rules! conformance_tests {
+    ($($name:ident: $game:ty $(=> $games:expr)?),* $(,)?) => {
+        $(
+            #[test]
+            fn $name() -> Result<()> {
+                use rand::SeedableRng;
+                let games = GAMES $(.min($games))?;
+                check::<_, _, $game>(games, &mut StdRng::seed_from_u64(0))
+            }
+        )*
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        amazons, breakthrough, checkers, connect_four, draughts, dyn_game, game_of_y, go, havannah,
+        hex, kalah, mnk, nim, othello, qubic, tak,
+    };
+
+    const GAMES: usize = 2000;
+
+    // Debug builds take a few milliseconds per random game of the small games, but up to seconds
+    // for the long ones, so those play fewer
+    conformance_tests!(
+        tic_tac_toe: mnk::TicTacToe,
+        checkers: checkers::Checkers,
+        connect_four: connect_four::ConnectFour,
+        hex5: hex::Hex<25, 50> => 1000,
+        padded_hex: dyn_game::Padded<36, 108, hex::DynHex> => 500,
+        padded_tic_tac_toe: dyn_game::Padded<16, 48, dyn_game::Dynamic<9, 18, mnk::TicTacToe>>,
+        game_of_y: game_of_y::GameOfY<49, 98> => 500,
+        havannah4: havannah::Havannah4 => 100,
+        go7: go::Go7 => 50,
+        othello: othello::Othello => 100,
+        breakthrough: breakthrough::Breakthrough => 100,
+        draughts: draughts::Draughts => 100,
+        amazons: amazons::Amazons => 20,
+        qubic: qubic::Qubic => 5,
+        kalah: kalah::Kalah,
+        tak: tak::Tak => 3,
+        nim: nim::Nim,
+    );
+}
@@ -0,0 +1,207 @@
+use std::fmt::Display;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    game::{
+        encode_board, simple_board_planes, swap_board, Game, Players, SimpleBoardState, SpatialGame,
+    },
+    mcts::GameStats,
+    render::{AsciiRenderer, Board, BoardRenderer},
+};
+
+const COLUMNS: usize = 7;
+const ROWS: usize = 6;
+const SQUARES: usize = COLUMNS * ROWS;
+
+#[derive(Debug, Clone)]
+pub struct ConnectFour {
+    // Row major, row 0 is the top row. A move is the column a piece is dropped in
+    board: [SimpleBoardState; SQUARES],
+    current_player: Players,
+    winning_player: Option<Players>,
+    // Columns played so far, for undo_move
+    history: Vec<usize>,
+}
+
+impl ConnectFour {
+    fn square(&self, row: usize, column: usize) -> SimpleBoardState {
+        self.board[row * COLUMNS + column]
+    }
+
+    // Pieces of the same player in a row through (row, column) along the direction, both ways
+    fn line_length(&self, row: usize, column: usize, direction: (isize, isize)) -> usize {
+        let piece = self.square(row, column);
+        let mut length = 1;
+        for sign in [1, -1] {
+            let (mut r, mut c) = (row as isize, column as isize);
+            loop {
+                r += direction.0 * sign;
+                c += direction.1 * sign;
+                if r < 0 || r >= ROWS as isize || c < 0 || c >= COLUMNS as isize {
+                    break;
+                }
+                if self.square(r as usize, c as usize) != piece {
+                    break;
+                }
+                length += 1;
+            }
+        }
+        length
+    }
+}
+
+impl Game<COLUMNS, { SQUARES * 2 }> for ConnectFour {
+    fn winning_player(&self) -> Option<Players> {
+        self.winning_player
+    }
+
+    fn available_moves(&self) -> [bool; COLUMNS] {
+        let mut moves = [false; COLUMNS];
+        if self.winning_player.is_some() {
+            return moves;
+        }
+        for (column, available) in moves.iter_mut().enumerate() {
+            *available = self.square(0, column) == SimpleBoardState::Empty;
+        }
+        moves
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(space < COLUMNS, "Tried to drop a piece outside the board");
+        let row = (0..ROWS)
+            .rev()
+            .find(|row| self.square(*row, space) == SimpleBoardState::Empty)
+            .context("Tried to drop a piece in a full column")?;
+        self.board[row * COLUMNS + space] = self.current_player.into();
+        let directions = [(0, 1), (1, 0), (1, 1), (1, -1)];
+        if directions
+            .into_iter()
+            .any(|direction| self.line_length(row, space, direction) >= 4)
+        {
+            self.winning_player = Some(self.current_player);
+        }
+        self.history.push(space);
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    // The last piece dropped in a column is its highest one, and nobody had won before it
+    fn undo_move(&mut self) -> Result<()> {
+        let column = self.history.pop().context("No move to undo")?;
+        let row = (0..ROWS)
+            .find(|row| self.square(*row, column) != SimpleBoardState::Empty)
+            .unwrap();
+        self.board[row * COLUMNS + column] = SimpleBoardState::Empty;
+        self.winning_player = None;
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    fn new() -> Self {
+        Self {
+            board: [SimpleBoardState::Empty; SQUARES],
+            current_player: Players::Player,
+            winning_player: None,
+            history: Vec::new(),
+        }
+    }
+
+    fn game_ended(&self) -> bool {
+        self.winning_player.is_some() || !self.available_moves().iter().any(|x| *x)
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    fn flip_board(&mut self) {
+        swap_board(&mut self.board);
+        self.current_player = self.current_player.swap();
+        self.winning_player = self.winning_player.map(|player| player.swap());
+    }
+
+    fn get_game_state_slice(&self) -> [f32; SQUARES * 2] {
+        encode_board(&self.board)
+    }
+
+    fn board(&self) -> Option<Board> {
+        Some(Board::read(self))
+    }
+
+    // The board is symmetric left to right
+    fn get_game_variations(
+        stats: &GameStats<COLUMNS, { SQUARES * 2 }>,
+    ) -> Vec<GameStats<COLUMNS, { SQUARES * 2 }>> {
+        let mut game_state = stats.game_state;
+        for row in game_state.chunks_exact_mut(COLUMNS) {
+            row.reverse();
+        }
+        let mut node_visits = stats.node_visits;
+        node_visits.reverse();
+        let mirrored = GameStats {
+            best_move_index: COLUMNS - stats.best_move_index - 1,
+            game_state,
+            node_visits,
+            ..stats.clone()
+        };
+        vec![stats.clone(), mirrored]
+    }
+}
+
+impl SpatialGame<COLUMNS, { SQUARES * 2 }> for ConnectFour {
+    fn plane_shape(&self) -> (usize, usize, usize) {
+        (3, ROWS, COLUMNS)
+    }
+
+    fn spatial_planes(&self) -> Vec<f32> {
+        simple_board_planes(&self.board, self.current_player)
+    }
+}
+
+// The column numbers on top, they are the moves
+impl Display for ConnectFour {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "0 1 2 3 4 5 6")?;
+        write!(f, "{}", AsciiRenderer.render(self))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn four_in_a_row_wins_in_every_direction() -> Result<()> {
+        let lines: [&[usize]; 4] = [
+            // Along the bottom row
+            &[0, 0, 1, 1, 2, 2, 3],
+            // Up a column
+            &[0, 1, 0, 1, 0, 1, 0],
+            // Rising to the right
+            &[0, 1, 1, 2, 2, 3, 2, 3, 3, 6, 3],
+            // Rising to the left
+            &[6, 5, 5, 4, 4, 3, 4, 3, 3, 0, 3],
+        ];
+        for columns in lines {
+            let (last, before) = columns.split_last().unwrap();
+            let mut game = ConnectFour::new();
+            for column in before {
+                game.try_perform_move(*column)?;
+            }
+            assert_eq!(game.winning_player(), None, "{:?}", columns);
+            game.try_perform_move(*last)?;
+            assert_eq!(
+                game.winning_player(),
+                Some(Players::Player),
+                "{:?}",
+                columns
+            );
+            assert!(game.game_ended());
+            assert_eq!(game.available_moves(), [false; COLUMNS]);
+            game.undo_move()?;
+            assert_eq!(game.winning_player(), None);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,102 @@
+//! Connectivity on hex boards shared by the connection games. Boards are skewed rectangles of
+//! width * height hexes, mostly squares. Games played on other shapes mark the squares outside
+//! their shape as unplayable
+
+use tinyvec::ArrayVec;
+
+fn check_connection(squares: usize, index: isize) -> Option<u16> {
+    if index >= 0 && index < squares as isize {
+        Some(index as u16)
+    } else {
+        None
+    }
+}
+
+/// Neighbours of `index` on a skewed square board of hexes
+pub fn hex_connections(index: usize, side_length: usize) -> ArrayVec<[u16; 6]> {
+    rectangular_hex_connections(index, side_length, side_length)
+}
+
+/// Neighbours of `index` on a skewed rectangle, the square at (x, y) has index x + y * width
+pub fn rectangular_hex_connections(
+    index: usize,
+    width: usize,
+    height: usize,
+) -> ArrayVec<[u16; 6]> {
+    let mut out = ArrayVec::<[u16; 6]>::default();
+    let coords = (index % width, index / width);
+    let squares = width * height;
+    let index = index as isize;
+    // false negative
+    let upper_left_wall = coords.0 == 0;
+    let lower_left_wall = coords.1 == height - 1;
+    let left_wall = upper_left_wall || lower_left_wall;
+    //false positive
+    let upper_right_wall = coords.1 == 0;
+    let lower_right_wall = coords.0 == width - 1;
+    let width = width as isize;
+    let right_wall = upper_right_wall || lower_right_wall;
+
+    //upper left connection
+    if !upper_left_wall {
+        if let Some(connection) = check_connection(squares, index - 1) {
+            out.push(connection);
+        }
+    };
+    //upper right connection
+    if !upper_right_wall {
+        if let Some(connection) = check_connection(squares, index - width) {
+            out.push(connection);
+        }
+    };
+    //left connection
+    if !left_wall {
+        if let Some(connection) = check_connection(squares, index + width - 1) {
+            out.push(connection);
+        }
+    };
+    //lower left connection
+    if !lower_left_wall {
+        if let Some(connection) = check_connection(squares, index + width) {
+            out.push(connection);
+        }
+    };
+    //lower right connection
+    if !lower_right_wall {
+        if let Some(connection) = check_connection(squares, index + 1) {
+            out.push(connection);
+        }
+    };
+    // right connection
+    if !right_wall {
+        if let Some(connection) = check_connection(squares, index - width + 1) {
+            out.push(connection);
+        }
+    };
+
+    out
+}
+
+/// Every square reachable from `start` through squares accepted by `passable`, starting with
+/// `start` itself which is included whether it is passable or not
+pub fn flood_fill(
+    start: usize,
+    side_length: usize,
+    passable: impl Fn(usize) -> bool,
+) -> Vec<usize> {
+    let mut seen = vec![false; side_length * side_length];
+    let mut reached = vec![start];
+    let mut i = 0;
+    seen[start] = true;
+    while i < reached.len() {
+        for connection in hex_connections(reached[i], side_length) {
+            let connection = connection as usize;
+            if !seen[connection] && passable(connection) {
+                seen[connection] = true;
+                reached.push(connection);
+            }
+        }
+        i += 1;
+    }
+    reached
+}
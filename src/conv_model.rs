@@ -0,0 +1,383 @@
+//! Convolutional model over the SpatialGame::spatial_planes of the positions, so the convolutions
+//! see which squares are next to each other. Models get the state of the canonical position, whose
+//! spatial planes are the planes of the state, as encode_board writes them, and the plane of the
+//! player to move, which is always Player there. spatial_input rebuilds them from the state
+
+use anyhow::ensure;
+use candle_core::{DType, Device, Tensor};
+use candle_nn::{conv2d, linear, Conv2d, Conv2dConfig, Dropout, Linear, Module, ModuleT};
+
+use crate::candle_ai::{
+    self, device_for, select_device, self_play_dtype, CandleModel, Heads, Role, Weights,
+};
+use crate::checkpoint;
+use crate::fit::{softmax, LossWeights};
+use crate::game::SpatialGame;
+use crate::model::{Architecture, FitConfig, ModelConfig, TrainReport, TrainableModel};
+
+pub struct ConvModel<const N: usize, const I: usize> {
+    // depth convolutions of hidden_size filters, and a value layer of hidden_size
+    config: ModelConfig,
+    // Channels, height and width of the spatial planes
+    shape: (usize, usize, usize),
+    // 3x3 convolutions keeping the board size, each followed by a relu
+    convs: Vec<Conv2d>,
+    policy_conv: Conv2d,
+    policy_head: Linear,
+    value_conv: Conv2d,
+    value_hidden: Linear,
+    dropout: Dropout,
+    score_head: Linear,
+    margin_head: Linear,
+    // 1x1 convolution to a plane of cell ownership, with ModelConfig::ownership
+    ownership_conv: Option<Conv2d>,
+    weights: Weights,
+    generation: usize,
+    device: Device,
+    /// Weight of the value loss relative to the policy cross-entropy
+    pub value_weight: f32,
+    /// Weight of the margin loss relative to the policy cross-entropy
+    pub margin_weight: f32,
+    /// Weight of the ownership loss relative to the policy cross-entropy
+    pub ownership_weight: f32,
+}
+
+impl<const N: usize, const I: usize> ConvModel<N, I> {
+    /// Model on `device`, TrainableModel::new picks it with select_device
+    pub fn with_device(config: ModelConfig, device: Device) -> anyhow::Result<Self> {
+        Self::build(config, Weights::trainable(config.seed), device)
+    }
+
+    /// Model with the weights saved at `path` that can only predict
+    pub fn for_inference(config: ModelConfig, path: &str, device: Device) -> anyhow::Result<Self> {
+        let weights = Weights::inference::<N, I>(path, &device)?;
+        let model = Self::build(config, weights, device)?;
+        model.weights.check_unused(path)?;
+        Ok(model)
+    }
+
+    fn build(config: ModelConfig, weights: Weights, device: Device) -> anyhow::Result<Self> {
+        ensure!(config.depth > 0, "The model needs a convolution");
+        let (height, width) = match config.board {
+            Some(board) => board,
+            None => square_board(N)?,
+        };
+        let filters = config.hidden_size;
+        let squares = height * width;
+        let channels = plane_count(I, height, width)? + 1;
+        let vb = weights.var_builder(&device);
+        let same = Conv2dConfig {
+            padding: 1,
+            ..Default::default()
+        };
+        let convs = (0..config.depth)
+            .map(|layer| {
+                let inputs = if layer == 0 { channels } else { filters };
+                conv2d(inputs, filters, 3, same, vb.pp(format!("conv {}", layer)))
+            })
+            .collect::<candle_core::Result<Vec<_>>>()?;
+        let policy_conv = conv2d(filters, 2, 1, Default::default(), vb.pp("policy_conv"))?;
+        let policy_head = linear(2 * squares, N, vb.pp("policy_head"))?;
+        let value_conv = conv2d(filters, 1, 1, Default::default(), vb.pp("value_conv"))?;
+        let value_hidden = linear(squares, filters, vb.pp("value_hidden"))?;
+        let score_head = linear(filters, 1, vb.pp("score_head"))?;
+        let margin_head = linear(filters, 1, vb.pp("margin_head"))?;
+        ensure!(
+            !config.ownership || squares == N,
+            "The ownership head needs one move per square"
+        );
+        let ownership_conv = config
+            .ownership
+            .then(|| conv2d(filters, 1, 1, Default::default(), vb.pp("ownership_conv")))
+            .transpose()?;
+        Ok(Self {
+            config,
+            shape: (channels, height, width),
+            convs,
+            policy_conv,
+            policy_head,
+            value_conv,
+            value_hidden,
+            dropout: candle_ai::dropout(config.dropout)?,
+            score_head,
+            margin_head,
+            ownership_conv,
+            weights,
+            generation: 0,
+            device,
+            value_weight: 1.0,
+            margin_weight: 1.0,
+            ownership_weight: 1.0,
+        })
+    }
+
+    /// Copy that only predicts, computing in `dtype`
+    pub fn with_dtype(&self, dtype: DType) -> anyhow::Result<Self> {
+        let weights = self.weights.frozen(dtype, &self.device)?;
+        Self::build(self.config, weights, self.device.clone())
+    }
+
+    /// The model with the weights saved at `path`, which have to be for the same configuration
+    pub fn with_weights(mut self, path: &str) -> anyhow::Result<Self> {
+        self.weights.load::<N, I>(path)?;
+        Ok(self)
+    }
+}
+
+impl<const N: usize, const I: usize> CandleModel for ConvModel<N, I> {
+    fn heads(&self, xs: &Tensor, train: bool) -> candle_core::Result<Heads> {
+        let (_, height, width) = self.shape;
+        let mut x = spatial_input(xs, height, width)?;
+        for conv in &self.convs {
+            x = conv.forward(&x)?.relu()?;
+        }
+        let policy = self.policy_conv.forward(&x)?.relu()?.flatten_from(1)?;
+        let visit_logits = self.policy_head.forward(&policy)?;
+        let value = self.value_conv.forward(&x)?.relu()?.flatten_from(1)?;
+        let value = self.value_hidden.forward(&value)?.relu()?;
+        let value = self.dropout.forward_t(&value, train)?;
+        Ok(Heads {
+            visit_logits,
+            score: self.score_head.forward(&value)?.tanh()?,
+            margin: self.margin_head.forward(&value)?.tanh()?,
+            ownership: self
+                .ownership_conv
+                .as_ref()
+                .map(|conv| conv.forward(&x)?.flatten_from(1)?.tanh())
+                .transpose()?,
+        })
+    }
+
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn loss_weights(&self) -> LossWeights {
+        LossWeights {
+            value: self.value_weight,
+            margin: self.margin_weight,
+            ownership: self.ownership_weight,
+        }
+    }
+
+    fn fit_config(&self) -> &FitConfig {
+        &self.config.fit
+    }
+
+    fn weights(&self) -> &Weights {
+        &self.weights
+    }
+
+    fn weights_mut(&mut self) -> &mut Weights {
+        &mut self.weights
+    }
+
+    fn generation(&self) -> usize {
+        self.generation
+    }
+}
+
+/// Height and width of a square board with one move per square
+pub(crate) fn square_board(moves: usize) -> anyhow::Result<(usize, usize)> {
+    let side = (moves as f64).sqrt().round() as usize;
+    ensure!(
+        side * side == moves,
+        "{} moves are not a square board, the board shape has to be given",
+        moves
+    );
+    Ok((side, side))
+}
+
+/// The spatial planes of the canonical positions of a batch of states, the planes of the states
+/// followed by the plane of ones of Player to move
+pub(crate) fn spatial_input(
+    xs: &Tensor,
+    height: usize,
+    width: usize,
+) -> candle_core::Result<Tensor> {
+    let (rows, values) = xs.dims2()?;
+    let planes = xs.reshape((rows, values / (height * width), height, width))?;
+    let to_move = Tensor::ones((rows, 1, height, width), xs.dtype(), xs.device())?;
+    Tensor::cat(&[&planes, &to_move], 1)
+}
+
+/// Height and width of the board of `game`, for ModelConfig::board. Fails unless its states are
+/// the planes spatial_planes starts with, which is how spatial_input reads them
+pub fn spatial_board<const N: usize, const I: usize, T: SpatialGame<N, I>>(
+    game: &T,
+) -> anyhow::Result<(usize, usize)> {
+    let (channels, height, width) = game.plane_shape();
+    ensure!(
+        plane_count(I, height, width)? + 1 == channels,
+        "{} planes of {}x{} are not a state of {} values and the player to move",
+        channels,
+        height,
+        width,
+        I
+    );
+    Ok((height, width))
+}
+
+/// Number of planes of `height` × `width` squares in a state of `state_len` values
+pub(crate) fn plane_count(state_len: usize, height: usize, width: usize) -> anyhow::Result<usize> {
+    let squares = height * width;
+    ensure!(
+        squares > 0 && state_len % squares == 0,
+        "State of {} values is not whole planes of {}x{}",
+        state_len,
+        height,
+        width
+    );
+    Ok(state_len / squares)
+}
+
+impl<const N: usize, const I: usize> TrainableModel<N, I> for ConvModel<N, I> {
+    type Config = ModelConfig;
+
+    fn new(config: &ModelConfig) -> anyhow::Result<Self> {
+        Self::with_device(*config, select_device())
+    }
+
+    fn reset_optimizer(&mut self) -> anyhow::Result<()> {
+        self.weights.reset_optimizer();
+        Ok(())
+    }
+
+    fn set_generation(&mut self, generation: usize) {
+        self.generation = generation;
+    }
+
+    fn save(&self, path: &str) -> anyhow::Result<()> {
+        let metadata = checkpoint::model_metadata::<N, I>(&self.config, self.generation);
+        self.weights.save(path, metadata)
+    }
+
+    fn parameter_count(&self) -> usize {
+        self.weights.parameter_count()
+    }
+
+    fn init_seed(&self) -> Option<u64> {
+        self.weights.seed()
+    }
+
+    fn summary(&self) -> Architecture {
+        let (_, height, width) = self.shape;
+        Architecture::from_shapes(self.weights.shapes(), height * width)
+    }
+
+    // Built with the config saved with the weights, the default for files saved without one
+    fn load(path: &str) -> anyhow::Result<Self> {
+        let config = checkpoint::saved_config(path)?.unwrap_or_default();
+        Self::new(&config)?.with_weights(path)
+    }
+
+    fn load_inference(path: &str) -> anyhow::Result<Self> {
+        let config = checkpoint::saved_config(path)?.unwrap_or_default();
+        Self::for_inference(config, path, select_device())
+    }
+
+    fn for_self_play(&self) -> anyhow::Result<Self> {
+        let device = device_for(Role::SelfPlay);
+        let weights = self.weights.frozen(self_play_dtype(), &device)?;
+        Self::build(self.config, weights, device)
+    }
+
+    fn train(&mut self, dataset: crate::dataset::Dataset<N, I>) -> anyhow::Result<TrainReport> {
+        candle_ai::train(self, dataset)
+    }
+
+    fn predict(&self, state: [f32; I], legal_moves: &[bool; N]) -> anyhow::Result<([f32; N], f32)> {
+        let (logits, score, _) = candle_ai::predict(self, state, legal_moves)?;
+        Ok((softmax(logits, 1.0), score))
+    }
+
+    fn predict_moves(&self, state: [f32; I], legal_moves: &[bool; N]) -> anyhow::Result<[f32; N]> {
+        Ok(self.predict(state, legal_moves)?.0)
+    }
+
+    fn predict_logits(&self, state: [f32; I], legal_moves: &[bool; N]) -> anyhow::Result<[f32; N]> {
+        Ok(candle_ai::predict(self, state, legal_moves)?.0)
+    }
+
+    fn predict_score(&self, state: [f32; I]) -> anyhow::Result<f32> {
+        Ok(candle_ai::predict(self, state, &[true; N])?.1)
+    }
+
+    fn predict_margin(&self, state: [f32; I]) -> anyhow::Result<Option<f32>> {
+        Ok(Some(candle_ai::predict(self, state, &[true; N])?.2))
+    }
+
+    fn predict_ownership(&self, state: [f32; I]) -> anyhow::Result<Option<[f32; N]>> {
+        candle_ai::predict_ownership(self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    use super::*;
+    use crate::connect_four::ConnectFour;
+    use crate::dyn_game::Padded;
+    use crate::game::{move_indices, Game, Players};
+    use crate::go::Go7;
+    use crate::hex::{DynHex, Hex};
+    use crate::othello::{self, Othello};
+
+    fn check_input<const N: usize, const I: usize, T: SpatialGame<N, I>>() -> anyhow::Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let (_, height, width) = T::new().plane_shape();
+        for _ in 0..3 {
+            let mut game = T::new();
+            while !game.game_ended() && !game.is_draw_by_rule() {
+                let (state, to_move) = game.canonical_state();
+                let mut canonical = game.clone();
+                if to_move == Players::Opponent {
+                    canonical.flip_board();
+                }
+                let xs = Tensor::from_slice(&state, (1, I), &Device::Cpu)?;
+                let input = spatial_input(&xs, height, width)?.flatten_all()?;
+                assert_eq!(input.to_vec1::<f32>()?, canonical.spatial_planes());
+                let mv = *move_indices(&game).choose(&mut rng).unwrap();
+                game.try_perform_move(mv)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn input_is_the_spatial_planes_of_the_canonical_position() -> anyhow::Result<()> {
+        check_input::<25, 50, Hex<25, 50>>()?;
+        check_input::<50, 147, Go7>()?;
+        check_input::<65, { othello::STATE_LEN }, Othello>()?;
+        check_input::<7, 84, ConnectFour>()?;
+        check_input::<36, 108, Padded<36, 108, DynHex>>()
+    }
+
+    #[test]
+    fn loads_with_the_board_it_was_saved_with() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("conv_{}.safetensors", std::process::id()));
+        let path = path.to_str().unwrap();
+        // Seven moves on a 6x7 board
+        let config = ModelConfig {
+            hidden_size: 8,
+            seed: Some(0),
+            board: Some(spatial_board(&ConnectFour::new())?),
+            ..Default::default()
+        };
+        let model = ConvModel::<7, 84>::new(&config)?;
+        model.save(path)?;
+        let loaded = ConvModel::<7, 84>::load(path);
+        let inference = ConvModel::<7, 84>::load_inference(path);
+        std::fs::remove_file(path)?;
+        let state = ConnectFour::from_moves(&[3, 3, 2])?.get_game_state_slice();
+        let legal = [true; 7];
+        let (moves, score) = model.predict(state, &legal)?;
+        for loaded in [loaded?, inference?] {
+            let (loaded_moves, loaded_score) = loaded.predict(state, &legal)?;
+            assert_eq!(loaded_score, score);
+            assert_eq!(loaded_moves, moves);
+        }
+        Ok(())
+    }
+}
@@ -1,71 +1,386 @@
-use std::{fmt::Display, fs};
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+};
 
-use serde::{Deserialize, Serialize};
+use anyhow::{ensure, Context};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 
 use crate::{
-    candle_ai::softmax,
-    game::{Game, Policy},
-    mcts,
+    game::{forced_pass, resolve_chance, Game, Players, Policy},
+    mcts::{analyze, GameStats, MctsConfig, SearchMode},
 };
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Dataset<const N: usize, const I: usize> {
     pub game_states: Vec<[f32; I]>,
     pub visit_stats: Vec<[f32; N]>,
     pub scores: Vec<f32>,
+    /// Legal moves of every sample, which the model masks its policy with. Datasets saved before
+    /// there were masks have none, every move counts as legal then
+    pub legal_moves: Vec<[bool; N]>,
+    /// GameStats::extra_targets of every sample, empty for games without them and games that
+    /// were resigned. Datasets saved before there were extra targets have none at all
+    pub extra_targets: Vec<Vec<f32>>,
+    /// Game::ownership of every sample in its canonical frame, empty for games without it and
+    /// games that were resigned
+    pub ownership: Vec<Vec<f32>>,
+    /// Moves of every game played, as seen from the unflipped board. They replay from the start
+    /// position of create_dataset, which Game::from_moves starts from too unless it was given a
+    /// game of its own. Chance outcomes are not recorded
+    pub records: Vec<Vec<usize>>,
+    /// Position every sample is a variation of, the variations of one position share it. Empty
+    /// in datasets saved before, see source_positions
+    pub positions: Vec<usize>,
+    /// How resigning went in the games of create_dataset, None when resignation was off and for
+    /// datasets that were not just played
+    pub resignations: Option<ResignationReport>,
+}
+
+/// Resigned games of a create_dataset run and how often the games played out regardless show
+/// resigning to have been wrong
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ResignationReport {
+    /// Games ended by a resignation
+    pub resigned: usize,
+    /// Games that would have been resigned and were played out instead
+    pub checked: usize,
+    /// Checked games the resigning player did not go on to lose
+    pub false_resignations: usize,
+}
+
+impl std::fmt::Display for ResignationReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "resigned {} games, {} of {} played out games would have been wrongly resigned",
+            self.resigned, self.false_resignations, self.checked
+        )
+    }
+}
+
+impl<const N: usize, const I: usize> Dataset<N, I> {
+    /// Hash of the training targets and states, the same samples in the same order give the same
+    /// hash within a build
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        let values = self.game_states.iter().flatten();
+        let values = values.chain(self.visit_stats.iter().flatten());
+        for value in values.chain(&self.scores) {
+            value.to_bits().hash(&mut hasher);
+        }
+        self.legal_moves.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// The position of every sample, every sample is its own position when none were recorded
+    pub fn source_positions(&self) -> Vec<usize> {
+        match self.positions.is_empty() {
+            true => (0..self.game_states.len()).collect(),
+            false => self.positions.clone(),
+        }
+    }
+
+    /// Adds the samples and records of `other` after these. Targets only one of them has are
+    /// filled in for the other's samples, every move legal and no extra targets or ownership.
+    /// The positions of `other` are numbered after these
+    pub fn append(&mut self, other: Dataset<N, I>) {
+        let samples = self.game_states.len();
+        let total = samples + other.game_states.len();
+        if !self.positions.is_empty() || !other.positions.is_empty() {
+            self.positions = self.source_positions();
+            let offset = self
+                .positions
+                .iter()
+                .max()
+                .map_or(0, |position| position + 1);
+            let other_positions = other.source_positions();
+            self.positions
+                .extend(other_positions.iter().map(|position| position + offset));
+        }
+        extend_padded(
+            &mut self.legal_moves,
+            other.legal_moves,
+            samples,
+            total,
+            [true; N],
+        );
+        extend_padded(
+            &mut self.extra_targets,
+            other.extra_targets,
+            samples,
+            total,
+            vec![],
+        );
+        extend_padded(&mut self.ownership, other.ownership, samples, total, vec![]);
+        self.game_states.extend(other.game_states);
+        self.visit_stats.extend(other.visit_stats);
+        self.scores.extend(other.scores);
+        self.records.extend(other.records);
+    }
+
+    /// The samples at `indices` in that order, which may repeat. Records are whole games and not
+    /// samples, the subset has none
+    pub fn subset(&self, indices: &[usize]) -> Dataset<N, I> {
+        let pick = |values: &[Vec<f32>]| match values.is_empty() {
+            true => vec![],
+            false => indices.iter().map(|i| values[*i].clone()).collect(),
+        };
+        Dataset {
+            game_states: indices.iter().map(|i| self.game_states[*i]).collect(),
+            visit_stats: indices.iter().map(|i| self.visit_stats[*i]).collect(),
+            scores: indices.iter().map(|i| self.scores[*i]).collect(),
+            legal_moves: match self.legal_moves.is_empty() {
+                true => vec![],
+                false => indices.iter().map(|i| self.legal_moves[*i]).collect(),
+            },
+            extra_targets: pick(&self.extra_targets),
+            ownership: pick(&self.ownership),
+            records: vec![],
+            positions: match self.positions.is_empty() {
+                true => vec![],
+                false => indices.iter().map(|i| self.positions[*i]).collect(),
+            },
+            resignations: None,
+        }
+    }
+}
+
+// Targets missing from both stay missing
+fn extend_padded<T: Clone>(
+    values: &mut Vec<T>,
+    other: Vec<T>,
+    samples: usize,
+    total: usize,
+    missing: T,
+) {
+    if values.is_empty() && other.is_empty() {
+        return;
+    }
+    values.resize(samples, missing.clone());
+    values.extend(other);
+    values.resize(total, missing);
+}
+
+#[derive(Clone, Debug)]
+pub struct ResignConfig {
+    /// A player resigns when the root value of their searches stays below this
+    pub threshold: f32,
+    /// for this many of their consecutive moves
+    pub consecutive_moves: usize,
+    /// Fraction of games played out regardless, used to measure how often resigning was wrong
+    pub disabled_fraction: f32,
+}
+
+impl Default for ResignConfig {
+    fn default() -> Self {
+        Self {
+            threshold: -0.95,
+            consecutive_moves: 3,
+            disabled_fraction: 0.1,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SelfPlayConfig {
+    pub mcts: MctsConfig,
+    pub resignation: Option<ResignConfig>,
+    /// Seed for all randomness in self-play, the same seed reproduces the same games
+    pub seed: Option<u64>,
+    /// Temperature of the policy target, see visit_target. Not applied to the improved policy of a
+    /// Gumbel search, which is the target as it is
+    pub target_temperature: f32,
+}
+
+impl Default for SelfPlayConfig {
+    fn default() -> Self {
+        Self {
+            mcts: MctsConfig::default(),
+            resignation: None,
+            seed: None,
+            target_temperature: 1.0,
+        }
+    }
 }
 
-// TODO: remove Display requirement
-pub fn create_dataset<
-    const N: usize,
-    const I: usize,
-    T: Game<N, I> + Display,
-    U: Policy<N, I, T>,
->(
+impl SelfPlayConfig {
+    pub fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+}
+
+/// Policy target from the visits of a search, visits^(1/temperature) normalized to sum to 1. A
+/// temperature of 1 gives the visit distribution, lower ones sharpen it and 0 puts everything on
+/// the most visited move
+pub fn visit_target<const N: usize>(visits: [f32; N], temperature: f32) -> [f32; N] {
+    let most = visits.iter().copied().fold(0.0, f32::max);
+    if most <= 0.0 {
+        return visits;
+    }
+    if temperature <= 0.0 {
+        let best = visits.iter().position(|visits| *visits == most).unwrap();
+        return std::array::from_fn(|mv| if mv == best { 1.0 } else { 0.0 });
+    }
+    // Relative to the most visited move, so the power cannot overflow
+    let powered = visits.map(|visits| (visits / most).powf(1.0 / temperature));
+    let total: f32 = powered.iter().sum();
+    powered.map(|value| value / total)
+}
+
+/// Plays `num_games` self-play games from `start`, usually T::new but e.g. a board size picked at
+/// runtime in a dyn_game::Padded
+pub fn create_dataset<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
+    start: &T,
     num_games: usize,
-    policy: U,
+    policy: &U,
     generation: usize,
+    config: &SelfPlayConfig,
 ) -> anyhow::Result<Dataset<N, I>> {
     let mut game_states: Vec<[f32; I]> = Vec::new();
     let mut scores: Vec<f32> = Vec::new();
     let mut visit_stats: Vec<[f32; N]> = Vec::new();
+    let mut extra_targets: Vec<Vec<f32>> = Vec::new();
+    let mut ownership: Vec<Vec<f32>> = Vec::new();
+    let mut legal_moves: Vec<[bool; N]> = Vec::new();
+    let mut resignations = ResignationReport::default();
+    let mut records: Vec<Vec<usize>> = Vec::new();
+    let mut positions: Vec<usize> = Vec::new();
+    let mut rng = config.rng();
     for i in 0..num_games {
-        let mut game = T::new();
-        let mut flipped = false;
-        while !game.game_ended() {
-            if flipped {
-                game.flip_board();
+        let mut game = start.clone();
+        let mut moves = Vec::new();
+        let resignation_enabled = config
+            .resignation
+            .as_ref()
+            .is_some_and(|resign| rng.gen::<f32>() >= resign.disabled_fraction);
+        let mut low_value_streaks = [0; 2];
+        let mut would_resign: Option<Players> = None;
+        // Samples wait for the end of the game, which some of their targets depend on
+        let mut samples = Vec::new();
+        loop {
+            resolve_chance(&mut game, &mut rng)?;
+            if game.game_ended() || game.is_draw_by_rule() {
+                break;
             }
-            println!("{}", game);
-            if flipped {
-                game.flip_board();
+            // A forced pass is played without a search, its sample would only teach the policy
+            // head what the move generator already knows
+            if let Some(pass) = forced_pass(&game) {
+                moves.push(pass);
+                game.try_perform_move(pass)?;
+                continue;
             }
 
-            let game_stats = mcts::<N, I, T, U>(&game, &policy, generation)?;
-            game.perform_move(game_stats.best_move_index);
-            game.flip_board();
-            flipped = !flipped;
+            // The samples are in the canonical frame of the player to move, the best move is
+            // mapped back to the game
+            let analysis = analyze(&game, policy, generation, &config.mcts, &mut rng)?;
+            let value = analysis.search.stats.value;
+            let to_move = game.current_player();
+            samples.push((analysis.search.stats, to_move, game.canonical_moves()));
+
+            if let Some(resign) = &config.resignation {
+                let streak = &mut low_value_streaks[usize::from(to_move == Players::Opponent)];
+                if value < resign.threshold {
+                    *streak += 1;
+                } else {
+                    *streak = 0;
+                }
+                if *streak >= resign.consecutive_moves && would_resign.is_none() {
+                    would_resign = Some(to_move);
+                    if resignation_enabled {
+                        break;
+                    }
+                }
+            }
 
-            let variations = T::get_game_variations(&game_stats);
-            for stats in variations {
+            moves.push(analysis.best_move);
+            game.try_perform_move(analysis.best_move)?;
+        }
+        for (mut game_stats, to_move, legal) in samples {
+            let position = positions.last().map_or(0, |position| position + 1);
+            if game.has_score_margin() {
+                game_stats
+                    .extra_targets
+                    .extend(game.terminal_value(to_move));
+            }
+            // game_variations maps the visits into every variation, so the legal moves are
+            // mapped along by passing them as visits
+            let legal_variations = game.game_variations(&GameStats {
+                node_visits: legal.map(f32::from),
+                ..game_stats.clone()
+            });
+            // The ownership is of the finished board, which is moved into the frame of the player
+            // to move the way flip_board moves the squares, and into the variations the same way
+            let ownership_variations = game.ownership(to_move).map(|owners| {
+                let mut canonical = [0.0; N];
+                for mv in 0..N {
+                    match to_move {
+                        Players::Player => canonical[mv] = owners[mv],
+                        Players::Opponent => canonical[game.flipped_move(mv)] = owners[mv],
+                    }
+                }
+                game.game_variations(&GameStats {
+                    node_visits: canonical,
+                    ..game_stats.clone()
+                })
+            });
+            for (variation, (stats, legal)) in game
+                .game_variations(&game_stats)
+                .into_iter()
+                .zip(legal_variations)
+                .enumerate()
+            {
                 game_states.push(stats.game_state);
-                scores.push(stats.score);
+                positions.push(position);
+                scores.push(stats.value);
                 visit_stats.push(stats.node_visits);
+                extra_targets.push(stats.extra_targets);
+                legal_moves.push(legal.node_visits.map(|legal| legal > 0.0));
+                ownership.push(
+                    ownership_variations
+                        .as_ref()
+                        .map_or(Vec::new(), |owners| owners[variation].node_visits.to_vec()),
+                );
             }
         }
+        records.push(moves);
         if i % 10 == 0 {
             println!("Simulated {} games", i);
         }
-        if flipped {
-            game.flip_board();
+        if let Some(resigning_player) = would_resign {
+            if resignation_enabled {
+                resignations.resigned += 1;
+            } else {
+                resignations.checked += 1;
+                if game.winning_player() != Some(resigning_player.swap()) {
+                    resignations.false_resignations += 1;
+                }
+            }
         }
-        println!("{}", game);
     }
-    visit_stats = softmax(visit_stats)?;
+    let gumbel = matches!(config.mcts.search_mode, SearchMode::Gumbel { .. });
+    let visit_stats = visit_stats
+        .into_iter()
+        .map(|visits| match gumbel {
+            true => visits,
+            false => visit_target(visits, config.target_temperature),
+        })
+        .collect();
     Ok(Dataset {
         game_states,
         scores,
         visit_stats,
+        legal_moves,
+        extra_targets,
+        ownership,
+        records,
+        positions,
+        resignations: config.resignation.is_some().then_some(resignations),
     })
 }
 
@@ -102,6 +417,16 @@ impl<const N: usize, const I: usize> From<SerializableDataset<N, I>> for Dataset
             game_states: x,
             visit_stats: y,
             scores: value.scores,
+            legal_moves: value
+                .legal_moves
+                .chunks_exact(N)
+                .map(|chunk| chunk.try_into().unwrap())
+                .collect(),
+            extra_targets: value.extra_targets,
+            ownership: value.ownership,
+            records: value.records,
+            positions: value.positions,
+            resignations: None,
         }
     }
 }
@@ -113,6 +438,21 @@ pub struct SerializableDataset<const N: usize, const I: usize> {
     scores: Vec<f32>,
     states_width: usize,
     visits_width: usize,
+    // Missing in datasets saved before games were recorded
+    #[serde(default)]
+    records: Vec<Vec<usize>>,
+    // Missing in datasets saved before extra targets
+    #[serde(default)]
+    extra_targets: Vec<Vec<f32>>,
+    // Flattened like node_visits, missing in datasets saved before masks
+    #[serde(default)]
+    legal_moves: Vec<bool>,
+    // Missing in datasets saved before ownership targets
+    #[serde(default)]
+    ownership: Vec<Vec<f32>>,
+    // Missing in datasets saved before source positions
+    #[serde(default)]
+    positions: Vec<usize>,
 }
 
 impl<const N: usize, const I: usize> From<Dataset<N, I>> for SerializableDataset<N, I> {
@@ -125,14 +465,156 @@ impl<const N: usize, const I: usize> From<Dataset<N, I>> for SerializableDataset
             scores: value.scores,
             states_width: I,
             visits_width: N,
+            records: value.records,
+            extra_targets: value.extra_targets,
+            legal_moves: value.legal_moves.iter().flatten().copied().collect(),
+            ownership: value.ownership,
+            positions: value.positions,
         }
     }
 }
 
+impl<const N: usize, const I: usize> SerializableDataset<N, I> {
+    // Whether the file is for this game and its parts are the same number of samples, which the
+    // conversion to a Dataset relies on
+    pub(crate) fn validate(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.states_width == I && self.visits_width == N,
+            "The dataset has states of {} values and {} moves but the game has {} and {}",
+            self.states_width,
+            self.visits_width,
+            I,
+            N
+        );
+        ensure!(
+            self.game_states.len() % I == 0
+                && self.node_visits.len() % N == 0
+                && self.legal_moves.len() % N == 0,
+            "The states, visits or legal moves are not whole samples"
+        );
+        let samples = self.game_states.len() / I;
+        let counts = [
+            ("visits", self.node_visits.len() / N, false),
+            ("scores", self.scores.len(), false),
+            ("legal moves", self.legal_moves.len() / N, true),
+            ("extra targets", self.extra_targets.len(), true),
+            ("ownership", self.ownership.len(), true),
+            ("positions", self.positions.len(), true),
+        ];
+        for (name, count, optional) in counts {
+            ensure!(
+                count == samples || (optional && count == 0),
+                "The dataset has {} states but {} {}",
+                samples,
+                count,
+                name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Dataset saved by save_dataset at `path`, which has to be for a game with N moves and states of
+/// I values
+pub fn load_dataset<const N: usize, const I: usize>(path: &str) -> anyhow::Result<Dataset<N, I>> {
+    let data: SerializableDataset<N, I> = read_data(path)?;
+    data.validate()
+        .with_context(|| format!("Dataset {} does not fit", path))?;
+    Ok(data.into())
+}
+
+/// Writes the dataset to `path` in the format its extension names, see write_data
 pub fn save_dataset<const N: usize, const I: usize>(
     data: &SerializableDataset<N, I>,
-    name: String,
-) {
-    let data_json = serde_json::to_string_pretty(&data).unwrap();
-    fs::write(format!("./{}.json", name), data_json).unwrap();
+    path: &str,
+) -> anyhow::Result<()> {
+    write_data(path, data)
+}
+
+/// Writes `data` as JSON to paths ending in .json, as bincode compressed with zstd to paths
+/// ending in .zst and as plain bincode otherwise. JSON is for other tools to read, it is several
+/// times the size of bincode and slow to parse
+pub(crate) fn write_data<S: Serialize>(path: &str, data: &S) -> anyhow::Result<()> {
+    let bytes = if path.ends_with(".json") {
+        serde_json::to_vec(data)?
+    } else if path.ends_with(".zst") {
+        zstd::encode_all(
+            &bincode::serialize(data)?[..],
+            zstd::DEFAULT_COMPRESSION_LEVEL,
+        )?
+    } else {
+        bincode::serialize(data)?
+    };
+    fs::write(path, bytes).with_context(|| format!("Writing {}", path))
+}
+
+/// Reads what write_data wrote to `path`
+pub(crate) fn read_data<S: DeserializeOwned>(path: &str) -> anyhow::Result<S> {
+    let bytes = fs::read(path).with_context(|| format!("Reading {}", path))?;
+    let parse = || -> anyhow::Result<S> {
+        Ok(if path.ends_with(".json") {
+            serde_json::from_slice(&bytes)?
+        } else if path.ends_with(".zst") {
+            bincode::deserialize(&zstd::decode_all(&bytes[..])?)?
+        } else {
+            bincode::deserialize(&bytes)?
+        })
+    };
+    parse().with_context(|| format!("Parsing {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{game::RandomPolicy, mnk::TicTacToe};
+
+    fn resigning_config(disabled_fraction: f32) -> SelfPlayConfig {
+        SelfPlayConfig {
+            mcts: MctsConfig {
+                simulations: 50,
+                ..Default::default()
+            },
+            // Every value is below the threshold, so the first player resigns right away
+            resignation: Some(ResignConfig {
+                threshold: 2.0,
+                consecutive_moves: 1,
+                disabled_fraction,
+            }),
+            seed: Some(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn resigns_when_the_value_stays_low() -> anyhow::Result<()> {
+        let policy = RandomPolicy::default();
+        let dataset = create_dataset(&TicTacToe::new(), 3, &policy, 0, &resigning_config(0.0))?;
+        assert!(dataset.records.iter().all(Vec::is_empty));
+        // One position per game, in the 8 symmetries of the board
+        assert_eq!(dataset.scores.len(), 3 * 8);
+        let report = dataset.resignations.context("No resignation report")?;
+        assert_eq!((report.resigned, report.checked), (3, 0));
+        // The games that ignore resignation are played out
+        let dataset = create_dataset(&TicTacToe::new(), 3, &policy, 0, &resigning_config(1.0))?;
+        for record in &dataset.records {
+            let game = TicTacToe::from_moves(record)?;
+            assert!(game.game_ended() || game.is_draw_by_rule());
+        }
+        let report = dataset.resignations.context("No resignation report")?;
+        assert_eq!((report.resigned, report.checked), (0, 3));
+        assert!(report.false_resignations <= report.checked);
+        Ok(())
+    }
+
+    #[test]
+    fn targets_are_proportional_to_visits() {
+        let target = visit_target([300.0, 100.0, 0.0], 1.0);
+        assert!((target[0] - 0.75).abs() < 1e-6, "{:?}", target);
+        assert!((target[1] - 0.25).abs() < 1e-6, "{:?}", target);
+        assert_eq!(target[2], 0.0);
+        // Squared at temperature 0.5, 9:1
+        let sharp = visit_target([300.0, 100.0, 0.0], 0.5);
+        assert!((sharp[0] - 0.9).abs() < 1e-6, "{:?}", sharp);
+        assert_eq!(visit_target([300.0, 100.0, 0.0], 0.0), [1.0, 0.0, 0.0]);
+    }
 }
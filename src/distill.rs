@@ -0,0 +1,91 @@
+//! Distillation: a small model trained on what a large one predicts rather than on the search
+//! results, which gives a cheap model for rollouts or deployment that plays like the large one
+
+use anyhow::Result;
+
+use crate::dataset::Dataset;
+use crate::model::{TrainReport, TrainableModel};
+
+/// `dataset` with its targets replaced by the predictions of `teacher`: its moves over the legal
+/// moves softened by `temperature`, its values, and its margins and ownership for the samples
+/// that have those targets. The teacher sees the canonical states the dataset holds
+pub fn teacher_targets<const N: usize, const I: usize, M: TrainableModel<N, I>>(
+    teacher: &M,
+    dataset: &Dataset<N, I>,
+    temperature: f32,
+) -> Result<Dataset<N, I>> {
+    let mut targets = dataset.clone();
+    for (i, state) in dataset.game_states.iter().enumerate() {
+        let legal = dataset.legal_moves.get(i).copied().unwrap_or([true; N]);
+        targets.visit_stats[i] = teacher.predict_moves_at(*state, &legal, temperature)?;
+        targets.scores[i] = teacher.predict_score(*state)?;
+        if let Some(extra) = targets
+            .extra_targets
+            .get_mut(i)
+            .filter(|extra| !extra.is_empty())
+        {
+            if let Some(margin) = teacher.predict_margin(*state)? {
+                extra[0] = margin;
+            }
+        }
+        if let Some(ownership) = targets
+            .ownership
+            .get_mut(i)
+            .filter(|owners| owners.len() == N)
+        {
+            if let Some(predicted) = teacher.predict_ownership(*state)? {
+                *ownership = predicted.to_vec();
+            }
+        }
+    }
+    Ok(targets)
+}
+
+/// Trains `student` to match `teacher` on the states of `dataset`, see teacher_targets
+pub fn distill<const N: usize, const I: usize, T: TrainableModel<N, I>, S: TrainableModel<N, I>>(
+    teacher: &T,
+    student: &mut S,
+    dataset: &Dataset<N, I>,
+    temperature: f32,
+) -> Result<TrainReport> {
+    student.train(teacher_targets(teacher, dataset, temperature)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{candle_ai::SimpleModel, model::ModelConfig};
+
+    #[test]
+    fn copies_margins_and_ownership_only_where_there_are_targets() -> Result<()> {
+        let teacher = SimpleModel::<9, 18>::new(&ModelConfig {
+            depth: 2,
+            hidden_size: 16,
+            seed: Some(0),
+            ownership: true,
+            ..Default::default()
+        })?;
+        let mut corner = [true; 9];
+        corner[0] = false;
+        let dataset = Dataset {
+            game_states: vec![[0.0; 18]; 3],
+            visit_stats: vec![[0.0; 9]; 3],
+            scores: vec![1.0; 3],
+            legal_moves: vec![[true; 9], corner, [true; 9]],
+            extra_targets: vec![vec![5.0, 2.0], vec![], vec![]],
+            ownership: vec![vec![], vec![0.5; 9], vec![]],
+            ..Default::default()
+        };
+        let targets = teacher_targets(&teacher, &dataset, 1.0)?;
+        let state = [0.0; 18];
+        let margin = teacher.predict_margin(state)?.unwrap();
+        let ownership = teacher.predict_ownership(state)?.unwrap();
+        assert_eq!(targets.scores, vec![teacher.predict_score(state)?; 3]);
+        // Only the margin is the teacher's, the other extra targets stay
+        assert_eq!(targets.extra_targets, [vec![margin, 2.0], vec![], vec![]]);
+        assert_eq!(targets.ownership, [vec![], ownership.to_vec(), vec![]]);
+        assert_eq!(targets.visit_stats[1][0], 0.0);
+        assert!((targets.visit_stats[1].iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+use std::fmt::Display;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    game::{canonical_hash, encode_board, repetitions, swap_board, Cell, Game, Players},
+    mcts::GameStats,
+};
+
+const SQUARES: usize = 32;
+pub const MOVES: usize = SQUARES * SQUARES;
+pub const STATE_LEN: usize = SQUARES * 5;
+// Plies without a capture or a man moving before the game is a draw
+const QUIET_PLY_LIMIT: usize = 80;
+// Occurrences of the same position with the same player to move that draw the game
+const REPETITION_LIMIT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Piece {
+    Man(Players),
+    King(Players),
+}
+
+impl Piece {
+    fn owner(&self) -> Players {
+        match self {
+            Piece::Man(player) | Piece::King(player) => *player,
+        }
+    }
+
+    // Row directions the piece may move in, Player's men move up the board
+    fn row_directions(&self) -> &'static [isize] {
+        match self {
+            Piece::Man(Players::Player) => &[-1],
+            Piece::Man(Players::Opponent) => &[1],
+            Piece::King(_) => &[-1, 1],
+        }
+    }
+}
+
+// Men and kings of Player, then of Opponent
+impl Cell for Piece {
+    const FEATURES: usize = 4;
+
+    fn encode(&self, out: &mut [f32]) {
+        out.fill(0.0);
+        let feature = match self {
+            Piece::Man(Players::Player) => 0,
+            Piece::King(Players::Player) => 1,
+            Piece::Man(Players::Opponent) => 2,
+            Piece::King(Players::Opponent) => 3,
+        };
+        out[feature] = 1.0;
+    }
+
+    fn swap(&self) -> Self {
+        match self {
+            Piece::Man(player) => Piece::Man(player.swap()),
+            Piece::King(player) => Piece::King(player.swap()),
+        }
+    }
+}
+
+/// English draughts (American checkers) on the 32 dark squares of an 8x8 board. Captures are
+/// forced, every jump of a multi-jump is a separate move by the same player, and men reaching the
+/// far row are crowned, which ends the turn
+#[derive(Debug, Clone)]
+pub struct Draughts {
+    // Dark squares numbered row by row from the top, 4 per row
+    board: [Option<Piece>; SQUARES],
+    current_player: Players,
+    // Square of the piece that has to continue its multi-jump
+    jumping: Option<usize>,
+    quiet_plies: usize,
+    history: Vec<Undo>,
+    // canonical_hash after every move, to detect repetitions
+    positions: Vec<u64>,
+}
+
+// What undo_move needs to restore a move
+#[derive(Debug, Clone)]
+struct Undo {
+    from: usize,
+    to: usize,
+    // The moving piece before it was possibly crowned
+    piece: Piece,
+    captured: Option<(usize, Piece)>,
+    jumping: Option<usize>,
+    quiet_plies: usize,
+}
+
+impl Draughts {
+    fn coordinates(square: usize) -> (isize, isize) {
+        let row = square / 4;
+        let column = 2 * (square % 4) + usize::from(row % 2 == 0);
+        (row as isize, column as isize)
+    }
+
+    fn square_at(row: isize, column: isize) -> Option<usize> {
+        let on_board = (0..8).contains(&row) && (0..8).contains(&column);
+        (on_board && (row + column) % 2 == 1).then(|| (row * 4 + column / 2) as usize)
+    }
+
+    /// Move index of moving the piece on `from` to `to`
+    pub fn move_index(from: usize, to: usize) -> usize {
+        from * SQUARES + to
+    }
+
+    // Captures as (from, to) pairs available to the piece on `from`
+    fn jumps_from(&self, from: usize) -> Vec<(usize, usize)> {
+        let Some(piece) = self.board[from] else {
+            return Vec::new();
+        };
+        let (row, column) = Self::coordinates(from);
+        let mut jumps = Vec::new();
+        for dr in piece.row_directions() {
+            for dc in [-1, 1] {
+                let over = Self::square_at(row + dr, column + dc);
+                let to = Self::square_at(row + 2 * dr, column + 2 * dc);
+                if let (Some(over), Some(to)) = (over, to) {
+                    let captures = self.board[over].is_some_and(|p| p.owner() != piece.owner());
+                    if captures && self.board[to].is_none() {
+                        jumps.push((from, to));
+                    }
+                }
+            }
+        }
+        jumps
+    }
+
+    fn steps_from(&self, from: usize) -> Vec<(usize, usize)> {
+        let Some(piece) = self.board[from] else {
+            return Vec::new();
+        };
+        let (row, column) = Self::coordinates(from);
+        let mut steps = Vec::new();
+        for dr in piece.row_directions() {
+            for dc in [-1, 1] {
+                if let Some(to) = Self::square_at(row + dr, column + dc) {
+                    if self.board[to].is_none() {
+                        steps.push((from, to));
+                    }
+                }
+            }
+        }
+        steps
+    }
+
+    fn legal_moves(&self) -> Vec<(usize, usize)> {
+        if let Some(from) = self.jumping {
+            return self.jumps_from(from);
+        }
+        let own: Vec<usize> = (0..SQUARES)
+            .filter(|square| self.board[*square].is_some_and(|p| p.owner() == self.current_player))
+            .collect();
+        let jumps: Vec<_> = own.iter().flat_map(|from| self.jumps_from(*from)).collect();
+        if !jumps.is_empty() {
+            return jumps;
+        }
+        own.iter().flat_map(|from| self.steps_from(*from)).collect()
+    }
+}
+
+impl Game<MOVES, STATE_LEN> for Draughts {
+    // The player to move loses when they cannot move, the quiet ply limit is a draw
+    fn winning_player(&self) -> Option<Players> {
+        if self.quiet_plies >= QUIET_PLY_LIMIT || !self.legal_moves().is_empty() {
+            return None;
+        }
+        Some(self.current_player.swap())
+    }
+
+    fn available_moves(&self) -> [bool; MOVES] {
+        let mut moves = [false; MOVES];
+        if self.quiet_plies >= QUIET_PLY_LIMIT {
+            return moves;
+        }
+        for (from, to) in self.legal_moves() {
+            moves[Self::move_index(from, to)] = true;
+        }
+        moves
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        let (from, to) = (space / SQUARES, space % SQUARES);
+        ensure!(
+            self.legal_moves().contains(&(from, to)),
+            "Tried to make an illegal draughts move"
+        );
+        let mut piece = self.board[from].take().unwrap();
+        let mut undo = Undo {
+            from,
+            to,
+            piece,
+            captured: None,
+            jumping: self.jumping,
+            quiet_plies: self.quiet_plies,
+        };
+        let (from_row, from_column) = Self::coordinates(from);
+        let (to_row, to_column) = Self::coordinates(to);
+        let captured = (from_row - to_row).abs() == 2;
+        if captured {
+            let over = Self::square_at((from_row + to_row) / 2, (from_column + to_column) / 2);
+            let over = over.unwrap();
+            undo.captured = Some((over, self.board[over].take().unwrap()));
+        }
+        self.history.push(undo);
+        let crowned = match piece {
+            Piece::Man(Players::Player) => to_row == 0,
+            Piece::Man(Players::Opponent) => to_row == 7,
+            Piece::King(_) => false,
+        };
+        if crowned {
+            piece = Piece::King(piece.owner());
+        }
+        if captured || matches!(piece, Piece::Man(_)) || crowned {
+            self.quiet_plies = 0;
+        } else {
+            self.quiet_plies += 1;
+        }
+        self.board[to] = Some(piece);
+        if captured && !crowned && !self.jumps_from(to).is_empty() {
+            self.jumping = Some(to);
+        } else {
+            self.jumping = None;
+            self.current_player = self.current_player.swap();
+        }
+        self.positions.push(canonical_hash(self));
+        Ok(())
+    }
+
+    fn undo_move(&mut self) -> Result<()> {
+        let undo = self.history.pop().context("No move to undo")?;
+        self.positions.pop();
+        self.board[undo.to] = None;
+        self.board[undo.from] = Some(undo.piece);
+        if let Some((square, piece)) = undo.captured {
+            self.board[square] = Some(piece);
+        }
+        self.jumping = undo.jumping;
+        self.quiet_plies = undo.quiet_plies;
+        self.current_player = undo.piece.owner();
+        Ok(())
+    }
+
+    fn new() -> Self {
+        let mut board = [None; SQUARES];
+        for square in 0..12 {
+            board[square] = Some(Piece::Man(Players::Opponent));
+            board[SQUARES - 1 - square] = Some(Piece::Man(Players::Player));
+        }
+        Self {
+            board,
+            current_player: Players::Player,
+            jumping: None,
+            quiet_plies: 0,
+            history: Vec::new(),
+            positions: Vec::new(),
+        }
+    }
+
+    fn game_ended(&self) -> bool {
+        self.quiet_plies >= QUIET_PLY_LIMIT || self.legal_moves().is_empty()
+    }
+
+    fn is_draw_by_rule(&self) -> bool {
+        self.quiet_plies >= QUIET_PLY_LIMIT || repetitions(&self.positions) >= REPETITION_LIMIT
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    // Rotates the board half a turn as well, so Player's men keep moving up
+    fn flip_board(&mut self) {
+        self.board.reverse();
+        swap_board(&mut self.board);
+        self.jumping = self.jumping.map(|square| SQUARES - 1 - square);
+        self.current_player = self.current_player.swap();
+        for undo in self.history.iter_mut() {
+            undo.from = SQUARES - 1 - undo.from;
+            undo.to = SQUARES - 1 - undo.to;
+            undo.piece = undo.piece.swap();
+            undo.captured = undo
+                .captured
+                .map(|(square, piece)| (SQUARES - 1 - square, piece.swap()));
+            undo.jumping = undo.jumping.map(|square| SQUARES - 1 - square);
+        }
+    }
+
+    fn flipped_move(&self, mv: usize) -> usize {
+        Self::move_index(SQUARES - 1 - mv / SQUARES, SQUARES - 1 - mv % SQUARES)
+    }
+
+    // Planes of Player men, Player kings, Opponent men, Opponent kings and the jumping piece
+    fn get_game_state_slice(&self) -> [f32; STATE_LEN] {
+        let mut out_slice: [f32; STATE_LEN] = encode_board(&self.board);
+        if let Some(square) = self.jumping {
+            out_slice[4 * SQUARES + square] = 1.0;
+        }
+        out_slice
+    }
+
+    fn get_game_variations(
+        stats: &GameStats<MOVES, STATE_LEN>,
+    ) -> Vec<GameStats<MOVES, STATE_LEN>> {
+        vec![stats.clone()]
+    }
+}
+
+impl Display for Draughts {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for row in 0..8 {
+            let row: String = (0..8)
+                .map(|column| match Self::square_at(row, column) {
+                    None => ' ',
+                    Some(square) => match self.board[square] {
+                        None => '.',
+                        Some(Piece::Man(Players::Player)) => 'x',
+                        Some(Piece::King(Players::Player)) => 'X',
+                        Some(Piece::Man(Players::Opponent)) => 'o',
+                        Some(Piece::King(Players::Opponent)) => 'O',
+                    },
+                })
+                .collect();
+            writeln!(f, "{row}")?;
+        }
+        let next_player = match self.current_player {
+            Players::Player => "x",
+            Players::Opponent => "o",
+        };
+        writeln!(f, "Next player: {}", next_player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const X: Piece = Piece::Man(Players::Player);
+    const O: Piece = Piece::Man(Players::Opponent);
+
+    // Player to move with only the given pieces on the board
+    fn position(pieces: &[(usize, Piece)]) -> Draughts {
+        let mut game = Draughts::new();
+        game.board = [None; SQUARES];
+        for &(square, piece) in pieces {
+            game.board[square] = Some(piece);
+        }
+        game
+    }
+
+    fn moves(game: &Draughts) -> Vec<usize> {
+        let available = game.available_moves();
+        (0..MOVES).filter(|mv| available[*mv]).collect()
+    }
+
+    #[test]
+    fn captures_are_forced() -> Result<()> {
+        let mut game = position(&[(21, X), (28, X), (17, O)]);
+        assert_eq!(moves(&game), vec![Draughts::move_index(21, 14)]);
+        assert!(game.try_perform_move(Draughts::move_index(28, 24)).is_err());
+        assert!(game.try_perform_move(Draughts::move_index(21, 16)).is_err());
+        game.try_perform_move(Draughts::move_index(21, 14))?;
+        assert_eq!(game.board[17], None);
+        assert_eq!(game.winning_player(), Some(Players::Player));
+        game.undo_move()?;
+        assert_eq!(game.board, position(&[(21, X), (28, X), (17, O)]).board);
+        assert_eq!(game.current_player(), Players::Player);
+        Ok(())
+    }
+
+    #[test]
+    fn multi_jumps_keep_the_player() -> Result<()> {
+        let start = position(&[(29, X), (25, O), (18, O), (0, O)]);
+        let mut game = start.clone();
+        game.try_perform_move(Draughts::move_index(29, 22))?;
+        assert_eq!(game.jumping, Some(22));
+        assert_eq!(game.current_player(), Players::Player);
+        assert_eq!(moves(&game), vec![Draughts::move_index(22, 15)]);
+        game.try_perform_move(Draughts::move_index(22, 15))?;
+        assert_eq!(game.jumping, None);
+        assert_eq!(game.current_player(), Players::Opponent);
+        assert_eq!((game.board[25], game.board[18]), (None, None));
+
+        game.undo_move()?;
+        assert_eq!(game.jumping, Some(22));
+        assert_eq!(game.current_player(), Players::Player);
+        assert_eq!(game.board[18], Some(O));
+        game.undo_move()?;
+        assert_eq!(game.board, start.board);
+        assert_eq!(game.jumping, None);
+        Ok(())
+    }
+
+    #[test]
+    fn crowning_ends_the_turn() -> Result<()> {
+        let mut game = position(&[(8, X), (5, O), (6, O)]);
+        game.try_perform_move(Draughts::move_index(8, 1))?;
+        assert_eq!(game.board[1], Some(Piece::King(Players::Player)));
+        // The new king could jump on, but not in this turn
+        assert!(!game.jumps_from(1).is_empty());
+        assert_eq!(game.jumping, None);
+        assert_eq!(game.current_player(), Players::Opponent);
+        game.undo_move()?;
+        assert_eq!(game.board[8], Some(X));
+        Ok(())
+    }
+
+    #[test]
+    fn men_do_not_capture_backwards() {
+        let mut game = position(&[(17, X), (22, O)]);
+        assert_eq!(
+            moves(&game),
+            vec![Draughts::move_index(17, 13), Draughts::move_index(17, 14)]
+        );
+        game.board[17] = Some(Piece::King(Players::Player));
+        assert_eq!(moves(&game), vec![Draughts::move_index(17, 26)]);
+    }
+
+    #[test]
+    fn flipped_moves_follow_the_flipped_board() -> Result<()> {
+        let mut game = Draughts::new();
+        game.try_perform_move(Draughts::move_index(21, 17))?;
+        for mv in moves(&game) {
+            assert_eq!(game.flipped_move(game.flipped_move(mv)), mv);
+            let mut played = game.clone();
+            played.try_perform_move(mv)?;
+            let mut flipped = game.clone();
+            flipped.flip_board();
+            assert!(flipped.available_moves()[game.flipped_move(mv)]);
+            flipped.try_perform_move(game.flipped_move(mv))?;
+            flipped.flip_board();
+            assert_eq!(flipped.board, played.board);
+            assert_eq!(flipped.current_player(), played.current_player());
+        }
+        let mut twice = game.clone();
+        twice.flip_board();
+        twice.flip_board();
+        assert_eq!(twice.board, game.board);
+        assert_eq!(twice.get_game_state_slice(), game.get_game_state_slice());
+        Ok(())
+    }
+}
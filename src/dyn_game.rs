@@ -0,0 +1,691 @@
+use std::fmt::Display;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use rand::rngs::StdRng;
+
+use crate::{
+    cache::CacheStats,
+    game::{Game, Players, Policy},
+    mcts::GameStats,
+};
+
+/// Runtime sized counterpart of Game, the number of moves and the state length are properties of
+/// the value instead of the type, so a board size can come from the command line. Object safe,
+/// so a game picked at runtime can be held as `Box<dyn DynGame>`. See Game for what the methods
+/// have to do
+pub trait DynGame {
+    fn move_count(&self) -> usize;
+    fn state_len(&self) -> usize;
+    fn winning_player(&self) -> Option<Players>;
+    fn available_moves(&self) -> Vec<bool>;
+    fn try_perform_move(&mut self, space: usize) -> Result<()>;
+    fn undo_move(&mut self) -> Result<()>;
+    fn game_ended(&self) -> bool;
+    fn is_draw_by_rule(&self) -> bool {
+        false
+    }
+    fn terminal_value(&self, perspective: Players) -> Option<f32> {
+        if !self.game_ended() && !self.is_draw_by_rule() {
+            return None;
+        }
+        Some(
+            match self.winning_player().filter(|_| !self.is_draw_by_rule()) {
+                Some(player) if player == perspective => 1.0,
+                Some(_) => -1.0,
+                None => 0.0,
+            },
+        )
+    }
+    fn current_player(&self) -> Players;
+    fn flip_board(&mut self);
+    fn flipped_move(&self, mv: usize) -> usize {
+        mv
+    }
+    fn get_game_state_slice(&self) -> Vec<f32>;
+    /// Symmetric copies of a sample like Game::get_game_variations, starting with the sample
+    /// itself. Only the sample by default
+    fn variations(&self, variation: &Variation) -> Vec<Variation> {
+        vec![variation.clone()]
+    }
+    fn pass_move(&self) -> Option<usize> {
+        None
+    }
+    /// See Game::grid, which Padded needs
+    fn grid(&self) -> Option<(usize, usize)> {
+        None
+    }
+    fn heuristic_value(&self) -> f32 {
+        0.0
+    }
+    fn chance_outcomes(&self) -> Option<Vec<(usize, f32)>> {
+        None
+    }
+    fn try_apply_chance_outcome(&mut self, _outcome: usize) -> Result<()> {
+        bail!("Game has no chance events")
+    }
+    fn move_to_string(&self, mv: usize) -> String {
+        mv.to_string()
+    }
+    fn move_from_string(&self, text: &str) -> Result<usize> {
+        let mv = text
+            .trim()
+            .parse()
+            .with_context(|| format!("'{}' is not a move", text))?;
+        ensure!(mv < self.move_count(), "Move {} is out of range", mv);
+        Ok(mv)
+    }
+}
+
+/// The parts of a sample that change between the variations of a DynGame, the state and visits
+/// are as long as its state_len and move_count
+#[derive(Clone, Debug, PartialEq)]
+pub struct Variation {
+    pub state: Vec<f32>,
+    pub visits: Vec<f32>,
+    pub best_move: usize,
+}
+
+/// Runtime sized games that can be set up without arguments, needed for Game::new of Padded
+pub trait NewDynGame: DynGame + Clone {
+    /// The largest variant whose grid fits in `height` × `width`, None if even the smallest one
+    /// is larger
+    fn fitting(height: usize, width: usize) -> Option<Self>;
+}
+
+/// Exposes a const generic Game as a DynGame
+#[derive(Clone)]
+pub struct Dynamic<const N: usize, const I: usize, T: Game<N, I>>(pub T);
+
+impl<const N: usize, const I: usize, T: Game<N, I>> DynGame for Dynamic<N, I, T> {
+    fn move_count(&self) -> usize {
+        N
+    }
+
+    fn state_len(&self) -> usize {
+        I
+    }
+
+    fn winning_player(&self) -> Option<Players> {
+        self.0.winning_player()
+    }
+
+    fn available_moves(&self) -> Vec<bool> {
+        self.0.available_moves().to_vec()
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        self.0.try_perform_move(space)
+    }
+
+    fn undo_move(&mut self) -> Result<()> {
+        self.0.undo_move()
+    }
+
+    fn game_ended(&self) -> bool {
+        self.0.game_ended()
+    }
+
+    fn is_draw_by_rule(&self) -> bool {
+        self.0.is_draw_by_rule()
+    }
+
+    fn terminal_value(&self, perspective: Players) -> Option<f32> {
+        self.0.terminal_value(perspective)
+    }
+
+    fn current_player(&self) -> Players {
+        self.0.current_player()
+    }
+
+    fn flip_board(&mut self) {
+        self.0.flip_board()
+    }
+
+    fn flipped_move(&self, mv: usize) -> usize {
+        self.0.flipped_move(mv)
+    }
+
+    fn get_game_state_slice(&self) -> Vec<f32> {
+        self.0.get_game_state_slice().to_vec()
+    }
+
+    fn variations(&self, variation: &Variation) -> Vec<Variation> {
+        let stats = GameStats {
+            best_move_index: variation.best_move,
+            game_state: variation.state.clone().try_into().unwrap(),
+            node_visits: variation.visits.clone().try_into().unwrap(),
+            value: 0.0,
+            score: 0.0,
+            extra_targets: Vec::new(),
+        };
+        self.0
+            .game_variations(&stats)
+            .into_iter()
+            .map(|stats| Variation {
+                state: stats.game_state.to_vec(),
+                visits: stats.node_visits.to_vec(),
+                best_move: stats.best_move_index,
+            })
+            .collect()
+    }
+
+    fn pass_move(&self) -> Option<usize> {
+        self.0.pass_move()
+    }
+
+    fn grid(&self) -> Option<(usize, usize)> {
+        self.0.grid()
+    }
+
+    fn heuristic_value(&self) -> f32 {
+        self.0.heuristic_value()
+    }
+
+    fn chance_outcomes(&self) -> Option<Vec<(usize, f32)>> {
+        self.0.chance_outcomes()
+    }
+
+    fn try_apply_chance_outcome(&mut self, outcome: usize) -> Result<()> {
+        self.0.try_apply_chance_outcome(outcome)
+    }
+
+    fn move_to_string(&self, mv: usize) -> String {
+        self.0.move_to_string(mv)
+    }
+
+    fn move_from_string(&self, text: &str) -> Result<usize> {
+        self.0.move_from_string(text)
+    }
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>> NewDynGame for Dynamic<N, I, T> {
+    fn fitting(height: usize, width: usize) -> Option<Self> {
+        let game = T::new();
+        let (rows, columns) = game.grid()?;
+        (rows <= height && columns <= width).then_some(Self(game))
+    }
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I> + Display> Display for Dynamic<N, I, T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Exposes a DynGame on a grid as a const generic Game on the largest square grid of at most N
+/// cells, so it can be searched, played in self-play and trained on. Smaller boards keep every
+/// cell where it is on the larger grid, the cells past their edges are never available and their
+/// state is zero. The state planes are followed by a plane of ones on the cells of the board, so
+/// a model tells the board apart from the padding, and moves past the cells of the grid, like
+/// passing, come after the cells of the larger grid. One compiled size then covers every board up
+/// to it
+#[derive(Clone)]
+pub struct Padded<const N: usize, const I: usize, G>(G);
+
+impl<const N: usize, const I: usize, G: DynGame> Padded<N, I, G> {
+    /// Side of the grid the game is padded to
+    pub const SIDE: usize = N.isqrt();
+
+    pub fn fit(game: G) -> Result<Self> {
+        let (height, width) = game.grid().context("Only games on a grid can be padded")?;
+        let cells = height * width;
+        ensure!(
+            height <= Self::SIDE && width <= Self::SIDE,
+            "A {}x{} grid does not fit Padded<{}, {}>",
+            height,
+            width,
+            N,
+            I
+        );
+        ensure!(
+            cells > 0 && game.move_count() >= cells && game.state_len().is_multiple_of(cells),
+            "The moves and the state of the game are not laid out on its {}x{} grid",
+            height,
+            width
+        );
+        let planes = game.state_len() / cells + 1;
+        let extra_moves = game.move_count() - cells;
+        ensure!(
+            Self::SIDE * Self::SIDE + extra_moves <= N && planes * Self::SIDE * Self::SIDE <= I,
+            "{} moves and {} planes of the game do not fit Padded<{}, {}>",
+            game.move_count(),
+            planes,
+            N,
+            I
+        );
+        Ok(Self(game))
+    }
+
+    fn grid(&self) -> (usize, usize) {
+        self.0.grid().unwrap()
+    }
+
+    /// Index of the game's move `mv` on the padded grid
+    pub fn padded_move(&self, mv: usize) -> usize {
+        let (height, width) = self.grid();
+        match mv < height * width {
+            true => mv / width * Self::SIDE + mv % width,
+            false => Self::SIDE * Self::SIDE + mv - height * width,
+        }
+    }
+
+    /// The game's move at index `mv` of the padded grid, None for the padding
+    pub fn unpadded_move(&self, mv: usize) -> Option<usize> {
+        let (height, width) = self.grid();
+        let cells = Self::SIDE * Self::SIDE;
+        if mv >= cells {
+            let mv = height * width + mv - cells;
+            return (mv < self.0.move_count()).then_some(mv);
+        }
+        let (row, column) = (mv / Self::SIDE, mv % Self::SIDE);
+        (row < height && column < width).then_some(row * width + column)
+    }
+
+    // The planes of `state` on the padded grid, followed by the plane of the board's cells
+    fn pad_state(&self, state: &[f32]) -> [f32; I] {
+        let (height, width) = self.grid();
+        let cells = height * width;
+        let mut padded = [0.0; I];
+        for (plane, values) in state
+            .chunks(cells)
+            .chain([&vec![1.0; cells][..]])
+            .enumerate()
+        {
+            for (cell, value) in values.iter().enumerate() {
+                padded[plane * Self::SIDE * Self::SIDE + self.padded_move(cell)] = *value;
+            }
+        }
+        padded
+    }
+
+    fn unpad_state(&self, padded: &[f32; I]) -> Vec<f32> {
+        let (height, width) = self.grid();
+        let cells = height * width;
+        (0..self.0.state_len())
+            .map(|i| padded[i / cells * Self::SIDE * Self::SIDE + self.padded_move(i % cells)])
+            .collect()
+    }
+
+    fn pad_visits(&self, visits: &[f32]) -> [f32; N] {
+        let mut padded = [0.0; N];
+        for (mv, value) in visits.iter().enumerate() {
+            padded[self.padded_move(mv)] = *value;
+        }
+        padded
+    }
+}
+
+impl<const N: usize, const I: usize, G: NewDynGame> Game<N, I> for Padded<N, I, G> {
+    fn winning_player(&self) -> Option<Players> {
+        self.0.winning_player()
+    }
+
+    fn available_moves(&self) -> [bool; N] {
+        let mut available = [false; N];
+        for (mv, legal) in self.0.available_moves().into_iter().enumerate() {
+            available[self.padded_move(mv)] = legal;
+        }
+        available
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        let mv = self
+            .unpadded_move(space)
+            .with_context(|| format!("Move {} is off the board", space))?;
+        self.0.try_perform_move(mv)
+    }
+
+    fn undo_move(&mut self) -> Result<()> {
+        self.0.undo_move()
+    }
+
+    // The largest variant of the game that fits, use fit for the others
+    fn new() -> Self {
+        G::fitting(Self::SIDE, Self::SIDE)
+            .and_then(|game| Self::fit(game).ok())
+            .unwrap_or_else(|| panic!("No variant of the game fits Padded<{}, {}>", N, I))
+    }
+
+    fn game_ended(&self) -> bool {
+        self.0.game_ended()
+    }
+
+    fn is_draw_by_rule(&self) -> bool {
+        self.0.is_draw_by_rule()
+    }
+
+    fn terminal_value(&self, perspective: Players) -> Option<f32> {
+        self.0.terminal_value(perspective)
+    }
+
+    fn current_player(&self) -> Players {
+        self.0.current_player()
+    }
+
+    fn flip_board(&mut self) {
+        self.0.flip_board()
+    }
+
+    fn flipped_move(&self, mv: usize) -> usize {
+        match self.unpadded_move(mv) {
+            Some(mv) => self.padded_move(self.0.flipped_move(mv)),
+            None => mv,
+        }
+    }
+
+    fn get_game_state_slice(&self) -> [f32; I] {
+        self.pad_state(&self.0.get_game_state_slice())
+    }
+
+    // Symmetries depend on the size, which the type does not know
+    fn get_game_variations(stats: &GameStats<N, I>) -> Vec<GameStats<N, I>> {
+        vec![stats.clone()]
+    }
+
+    // The game's own variations of the board, padded again
+    fn game_variations(&self, stats: &GameStats<N, I>) -> Vec<GameStats<N, I>> {
+        let variation = Variation {
+            state: self.unpad_state(&stats.game_state),
+            visits: (0..self.0.move_count())
+                .map(|mv| stats.node_visits[self.padded_move(mv)])
+                .collect(),
+            best_move: self.unpadded_move(stats.best_move_index).unwrap_or(0),
+        };
+        self.0
+            .variations(&variation)
+            .into_iter()
+            .map(|variation| GameStats {
+                best_move_index: self.padded_move(variation.best_move),
+                game_state: self.pad_state(&variation.state),
+                node_visits: self.pad_visits(&variation.visits),
+                ..stats.clone()
+            })
+            .collect()
+    }
+
+    fn pass_move(&self) -> Option<usize> {
+        self.0.pass_move().map(|mv| self.padded_move(mv))
+    }
+
+    fn heuristic_value(&self) -> f32 {
+        self.0.heuristic_value()
+    }
+
+    fn chance_outcomes(&self) -> Option<Vec<(usize, f32)>> {
+        self.0.chance_outcomes()
+    }
+
+    fn try_apply_chance_outcome(&mut self, outcome: usize) -> Result<()> {
+        self.0.try_apply_chance_outcome(outcome)
+    }
+
+    fn grid(&self) -> Option<(usize, usize)> {
+        Some((Self::SIDE, Self::SIDE))
+    }
+
+    fn move_to_string(&self, mv: usize) -> String {
+        match self.unpadded_move(mv) {
+            Some(mv) => self.0.move_to_string(mv),
+            None => mv.to_string(),
+        }
+    }
+
+    fn move_from_string(&self, text: &str) -> Result<usize> {
+        Ok(self.padded_move(self.0.move_from_string(text)?))
+    }
+}
+
+impl<const N: usize, const I: usize, G: Display> Display for Padded<N, I, G> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// Plays a const generic game on a grid with a policy for Padded games of at least its size,
+/// going through Dynamic. A model trained on a runtime sized game can then play the fixed size
+/// variant that encodes its positions the same way, like a PaddedHex model playing Hex<25, 50>
+pub struct PaddedPolicy<const PN: usize, const PI: usize, P>(pub P);
+
+impl<const PN: usize, const PI: usize, P> PaddedPolicy<PN, PI, P> {
+    fn pad<const N: usize, const I: usize, T: Game<N, I>>(
+        game: &T,
+    ) -> Result<Padded<PN, PI, Dynamic<N, I, T>>> {
+        Padded::fit(Dynamic(game.clone()))
+    }
+}
+
+impl<const N: usize, const I: usize, T, const PN: usize, const PI: usize, P> Policy<N, I, T>
+    for PaddedPolicy<PN, PI, P>
+where
+    T: Game<N, I>,
+    P: Policy<PN, PI, Padded<PN, PI, Dynamic<N, I, T>>>,
+{
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> Result<usize> {
+        let padded = Self::pad(game)?;
+        let mv = self.0.select_move(&padded, rng)?;
+        padded
+            .unpadded_move(mv)
+            .with_context(|| format!("Move {} is off the board", mv))
+    }
+
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> Result<Vec<usize>> {
+        let padded = games
+            .into_iter()
+            .map(Self::pad)
+            .collect::<Result<Vec<_>>>()?;
+        let moves = self.0.select_moves_batch(padded.iter().collect(), rng)?;
+        padded
+            .iter()
+            .zip(moves)
+            .map(|(game, mv)| {
+                game.unpadded_move(mv)
+                    .with_context(|| format!("Move {} is off the board", mv))
+            })
+            .collect()
+    }
+
+    fn predict_score(&self, game: &T) -> Result<f32> {
+        self.0.predict_score(&Self::pad(game)?)
+    }
+
+    fn can_predict_score(&self) -> bool {
+        self.0.can_predict_score()
+    }
+
+    // The padding is never available, so leaving it out loses nothing
+    fn predict_priors(&self, game: &T) -> Result<[f32; N]> {
+        let padded = Self::pad(game)?;
+        let priors = self.0.predict_priors(&padded)?;
+        Ok(std::array::from_fn(|mv| priors[padded.padded_move(mv)]))
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.0.cache_stats()
+    }
+
+    fn exact_score(&self, game: &T) -> Option<f32> {
+        self.0.exact_score(&Self::pad(game).ok()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{seq::SliceRandom, SeedableRng};
+
+    use super::*;
+    use crate::{
+        candle_ai::SimpleModel,
+        dataset::{create_dataset, SelfPlayConfig},
+        game::{move_indices, perft, RandomPolicy},
+        hex::{DynHex, Hex},
+        mcts::MctsConfig,
+        mnk::TicTacToe,
+        model::{AiPolicy, ModelConfig, TrainableModel},
+    };
+
+    type PaddedTicTacToe = Padded<16, 48, Dynamic<9, 18, TicTacToe>>;
+
+    #[test]
+    fn round_trip_keeps_the_game() -> Result<()> {
+        for depth in 1..=5 {
+            let padded = perft(&mut PaddedTicTacToe::new(), depth)?;
+            assert_eq!(padded, perft(&mut TicTacToe::new(), depth)?);
+        }
+        // The centre is 5 on the 4x4 grid
+        let game = PaddedTicTacToe::from_moves(&[5, 0])?;
+        let fixed = TicTacToe::from_moves(&[4, 0])?;
+        let available = game.available_moves();
+        let state = game.get_game_state_slice();
+        for (row, column) in (0..4).flat_map(|row| (0..4).map(move |column| (row, column))) {
+            let cell = row * 4 + column;
+            if row == 3 || column == 3 {
+                assert!(!available[cell]);
+                assert!([0, 16, 32].iter().all(|plane| state[plane + cell] == 0.0));
+                continue;
+            }
+            let fixed_cell = row * 3 + column;
+            assert_eq!(available[cell], fixed.available_moves()[fixed_cell]);
+            for plane in 0..2 {
+                assert_eq!(
+                    state[plane * 16 + cell],
+                    fixed.get_game_state_slice()[plane * 9 + fixed_cell]
+                );
+            }
+            // The plane of the cells on the board
+            assert_eq!(state[32 + cell], 1.0);
+        }
+        assert!(game.clone().try_perform_move(3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn dyn_hex_plays_like_hex() -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..20 {
+            let mut dynamic = Padded::<25, 75, _>::fit(DynHex::new(5)?)?;
+            let mut fixed = Hex::<25, 50>::new();
+            loop {
+                assert_eq!(dynamic.available_moves(), fixed.available_moves());
+                assert_eq!(
+                    dynamic.get_game_state_slice()[..50],
+                    fixed.get_game_state_slice()
+                );
+                assert_eq!(dynamic.current_player(), fixed.current_player());
+                assert_eq!(dynamic.winning_player(), fixed.winning_player());
+                let (mut dynamic_flipped, mut fixed_flipped) = (dynamic.clone(), fixed.clone());
+                dynamic_flipped.flip_board();
+                fixed_flipped.flip_board();
+                assert_eq!(
+                    dynamic_flipped.get_game_state_slice()[..50],
+                    fixed_flipped.get_game_state_slice()
+                );
+                if fixed.game_ended() {
+                    break;
+                }
+                let mv = *move_indices(&fixed).choose(&mut rng).unwrap();
+                assert_eq!(dynamic.flipped_move(mv), fixed.flipped_move(mv));
+                assert_eq!(dynamic.move_to_string(mv), fixed.move_to_string(mv));
+                dynamic.try_perform_move(mv)?;
+                fixed.try_perform_move(mv)?;
+            }
+            assert!(dynamic.game_ended());
+            assert!(dynamic.get_game_state_slice()[50..]
+                .iter()
+                .all(|x| *x == 1.0));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn cells_keep_their_place_across_sides() -> Result<()> {
+        let mut small = Padded::<36, 108, _>::fit(DynHex::new(3)?)?;
+        let mut large = Padded::<36, 108, _>::fit(DynHex::new(6)?)?;
+        for cell in ["b2", "a3", "c1"] {
+            let mv = small.move_from_string(cell)?;
+            assert_eq!(mv, large.move_from_string(cell)?);
+            small.try_perform_move(mv)?;
+            large.try_perform_move(mv)?;
+        }
+        // The stones are on the same planes, only the cells on the board differ
+        let (small, large) = (small.get_game_state_slice(), large.get_game_state_slice());
+        assert_eq!(small[..72], large[..72]);
+        assert_eq!(small[72..].iter().sum::<f32>(), 9.0);
+        assert_eq!(large[72..].iter().sum::<f32>(), 36.0);
+        Ok(())
+    }
+
+    #[test]
+    fn padded_variations_rotate_within_the_side() -> Result<()> {
+        let mut game = Padded::<16, 48, _>::fit(DynHex::new(3)?)?;
+        game.try_perform_move(game.padded_move(1))?;
+        game.try_perform_move(game.padded_move(5))?;
+        let fixed = Hex::<9, 18>::from_moves(&[1, 5])?;
+        let visits: [f32; 9] = std::array::from_fn(|mv| mv as f32);
+        let stats = GameStats {
+            best_move_index: game.padded_move(2),
+            game_state: game.get_game_state_slice(),
+            node_visits: game.pad_visits(&visits),
+            value: 0.5,
+            score: 0.0,
+            extra_targets: Vec::new(),
+        };
+        let expected = Hex::<9, 18>::get_game_variations(&GameStats {
+            best_move_index: 2,
+            game_state: fixed.get_game_state_slice(),
+            node_visits: visits,
+            value: 0.5,
+            score: 0.0,
+            extra_targets: Vec::new(),
+        });
+        let variations = game.game_variations(&stats);
+        assert_eq!(variations.len(), expected.len());
+        // Rotated within the 3x3 board, which stays in the corner of the grid
+        for (padded, fixed) in variations.iter().zip(&expected) {
+            assert_eq!(
+                padded.best_move_index,
+                game.padded_move(fixed.best_move_index)
+            );
+            assert_eq!(padded.game_state, game.pad_state(&fixed.game_state));
+            assert_eq!(padded.node_visits, game.pad_visits(&fixed.node_visits));
+            assert_eq!(padded.value, 0.5);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn trains_on_a_padded_board() -> Result<()> {
+        let config = SelfPlayConfig {
+            mcts: MctsConfig {
+                simulations: 20,
+                ..Default::default()
+            },
+            seed: Some(0),
+            ..Default::default()
+        };
+        let start = Padded::<16, 48, _>::fit(DynHex::new(3)?)?;
+        let dataset = create_dataset(&start, 3, &RandomPolicy::default(), 0, &config)?;
+        let padding: Vec<usize> = (0..16)
+            .filter(|mv| start.unpadded_move(*mv).is_none())
+            .collect();
+        assert!(dataset
+            .visit_stats
+            .iter()
+            .all(|visits| padding.iter().all(|mv| visits[*mv] == 0.0)));
+        let mut model = SimpleModel::<16, 48>::new(&ModelConfig::default())?;
+        model.train(dataset)?;
+        // The model trained on padded 3x3 boards plays the 3x3 Hex through Dynamic
+        let policy = PaddedPolicy::<16, 48, _>(AiPolicy::new(model));
+        let game = Hex::<9, 18>::from_moves(&[4])?;
+        let priors = policy.predict_priors(&game)?;
+        assert!(
+            (priors.iter().sum::<f32>() - 1.0).abs() < 1e-4,
+            "{:?}",
+            priors
+        );
+        assert_eq!(priors[4], 0.0);
+        let mv = policy.select_move(&game, &mut StdRng::seed_from_u64(0))?;
+        assert!(game.available_moves()[mv]);
+        Ok(())
+    }
+}
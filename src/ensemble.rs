@@ -0,0 +1,172 @@
+//! Several models playing as one. Their move probabilities and values are averaged, which plays
+//! stronger than any one of them, and how far they are apart says how sure they are of a position
+
+use anyhow::{ensure, Result};
+use rand::{rngs::StdRng, seq::SliceRandom};
+
+use crate::fit::softmax;
+use crate::game::{Game, Policy};
+use crate::model::{AiPolicy, TrainableModel};
+
+pub struct EnsemblePolicy<const N: usize, const I: usize, M: TrainableModel<N, I>> {
+    /// The models with the temperatures of their own priors
+    pub members: Vec<AiPolicy<N, I, M>>,
+    /// Temperature of the averaged moves select_move samples, 0 always plays the most likely move
+    pub move_temperature: f32,
+}
+
+/// How much the members of an ensemble disagree about a position
+#[derive(Clone, Copy, Debug)]
+pub struct Disagreement {
+    /// Standard deviation of the members' values
+    pub value_spread: f32,
+    /// Mean KL divergence of the members' priors from the averaged priors, the Jensen-Shannon
+    /// divergence of the members. 0 when they agree and at most ln of the number of members
+    pub policy_divergence: f32,
+}
+
+impl<const N: usize, const I: usize, M: TrainableModel<N, I>> EnsemblePolicy<N, I, M> {
+    pub fn new(models: Vec<M>) -> Result<Self> {
+        ensure!(!models.is_empty(), "An ensemble needs at least one model");
+        Ok(Self {
+            members: models.into_iter().map(AiPolicy::new).collect(),
+            move_temperature: 0.0,
+        })
+    }
+
+    /// Ensemble of the checkpoints at `paths`, loaded for inference
+    pub fn load(paths: &[&str]) -> Result<Self> {
+        Self::new(
+            paths
+                .iter()
+                .map(|path| M::load_inference(path))
+                .collect::<Result<_>>()?,
+        )
+    }
+
+    pub fn disagreement<T: Game<N, I>>(&self, game: &T) -> Result<Disagreement> {
+        let priors = self.member_priors(game)?;
+        let scores = self.member_scores(game)?;
+        let mean_priors = mean_of(&priors);
+        let mean_score = scores.iter().sum::<f32>() / scores.len() as f32;
+        let variance = scores
+            .iter()
+            .map(|score| (score - mean_score).powi(2))
+            .sum::<f32>()
+            / scores.len() as f32;
+        // Moves the mean gives nothing have nothing from any member either
+        let divergence = priors
+            .iter()
+            .flat_map(|member| {
+                (0..N)
+                    .filter(|mv| member[*mv] > 0.0)
+                    .map(|mv| member[mv] * (member[mv] / mean_priors[mv]).ln())
+            })
+            .sum::<f32>()
+            / priors.len() as f32;
+        Ok(Disagreement {
+            value_spread: variance.sqrt(),
+            policy_divergence: divergence,
+        })
+    }
+
+    fn member_priors<T: Game<N, I>>(&self, game: &T) -> Result<Vec<[f32; N]>> {
+        self.members
+            .iter()
+            .map(|member| member.predict_priors(game))
+            .collect()
+    }
+
+    fn member_scores<T: Game<N, I>>(&self, game: &T) -> Result<Vec<f32>> {
+        self.members
+            .iter()
+            .map(|member| member.predict_score(game))
+            .collect()
+    }
+}
+
+fn mean_of<const N: usize>(members: &[[f32; N]]) -> [f32; N] {
+    std::array::from_fn(|mv| {
+        members.iter().map(|member| member[mv]).sum::<f32>() / members.len() as f32
+    })
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>, M: TrainableModel<N, I>> Policy<N, I, T>
+    for EnsemblePolicy<N, I, M>
+{
+    // The averaged priors sharpened by the move temperature, like a softmax of their logarithms
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> Result<usize> {
+        let priors = self.predict_priors(game)?;
+        if self.move_temperature > 0.0 {
+            let moves = softmax(priors.map(f32::ln), self.move_temperature);
+            let indices: Vec<usize> = (0..N).collect();
+            return Ok(*indices.choose_weighted(rng, |mv| moves[*mv])?);
+        }
+        let next_move = priors
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .expect("NaN value encountered")
+            .0;
+        Ok(next_move)
+    }
+
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> Result<Vec<usize>> {
+        games
+            .iter()
+            .map(|game| self.select_move(*game, rng))
+            .collect()
+    }
+
+    fn predict_score(&self, game: &T) -> Result<f32> {
+        let scores = self.member_scores(game)?;
+        Ok(scores.iter().sum::<f32>() / scores.len() as f32)
+    }
+
+    fn can_predict_score(&self) -> bool {
+        true
+    }
+
+    fn predict_priors(&self, game: &T) -> Result<[f32; N]> {
+        Ok(mean_of(&self.member_priors(game)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{candle_ai::SimpleModel, mnk::TicTacToe, model::ModelConfig};
+
+    fn member(seed: u64) -> Result<SimpleModel<9, 18>> {
+        SimpleModel::new(&ModelConfig {
+            depth: 2,
+            hidden_size: 16,
+            seed: Some(seed),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn identical_members_agree() -> Result<()> {
+        let game = TicTacToe::new();
+        let ensemble = EnsemblePolicy::new(vec![member(0)?, member(0)?])?;
+        let disagreement = ensemble.disagreement(&game)?;
+        assert!(disagreement.value_spread.abs() < 1e-6, "{:?}", disagreement);
+        assert!(
+            disagreement.policy_divergence.abs() < 1e-6,
+            "{:?}",
+            disagreement
+        );
+        // Members of other seeds do not, by at most ln 2 for two
+        let ensemble = EnsemblePolicy::new(vec![member(0)?, member(1)?])?;
+        let disagreement = ensemble.disagreement(&game)?;
+        assert!(disagreement.value_spread > 0.0, "{:?}", disagreement);
+        assert!(disagreement.policy_divergence > 0.0, "{:?}", disagreement);
+        assert!(
+            disagreement.policy_divergence <= 2f32.ln(),
+            "{:?}",
+            disagreement
+        );
+        Ok(())
+    }
+}
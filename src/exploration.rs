@@ -0,0 +1,257 @@
+//! Wrappers that make another policy explore, for more varied self-play data or to see how much a
+//! policy's choices matter. Only the moves are perturbed, the priors and scores stay the inner
+//! policy's so a search with them is unchanged
+
+use anyhow::{ensure, Result};
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+
+use crate::fit::softmax;
+use crate::game::{Game, Policy, RandomPolicy};
+
+/// Plays a random move with probability epsilon and the move of `policy` otherwise. Random moves
+/// pass only when nothing else is available, see RandomPolicy
+pub struct EpsilonGreedy<P> {
+    pub policy: P,
+    pub epsilon: f32,
+}
+
+impl<P> EpsilonGreedy<P> {
+    pub fn new(policy: P, epsilon: f32) -> Result<Self> {
+        ensure!(
+            (0.0..=1.0).contains(&epsilon),
+            "Epsilon {} is not a probability",
+            epsilon
+        );
+        Ok(Self { policy, epsilon })
+    }
+}
+
+/// Samples moves from the priors of `policy` at a temperature, above 1 flatter and below 1 sharper
+/// than the priors. 0 plays the most likely move
+pub struct Boltzmann<P> {
+    pub policy: P,
+    pub temperature: f32,
+}
+
+impl<P> Boltzmann<P> {
+    pub fn new(policy: P, temperature: f32) -> Result<Self> {
+        ensure!(
+            temperature >= 0.0,
+            "Temperature {} is negative",
+            temperature
+        );
+        Ok(Self {
+            policy,
+            temperature,
+        })
+    }
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>, P: Policy<N, I, T>> Policy<N, I, T>
+    for EpsilonGreedy<P>
+{
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> Result<usize> {
+        if rng.gen::<f32>() < self.epsilon {
+            return RandomPolicy::default().select_move(game, rng);
+        }
+        self.policy.select_move(game, rng)
+    }
+
+    // The games playing randomly are taken out first, so the rest still go to the inner policy
+    // as one batch
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> Result<Vec<usize>> {
+        let random: Vec<bool> = games
+            .iter()
+            .map(|_| rng.gen::<f32>() < self.epsilon)
+            .collect();
+        let chosen = games
+            .iter()
+            .zip(&random)
+            .filter(|(_, random)| !**random)
+            .map(|(game, _)| *game)
+            .collect();
+        let mut chosen = self.policy.select_moves_batch(chosen, rng)?.into_iter();
+        games
+            .iter()
+            .zip(random)
+            .map(|(game, random)| {
+                if random {
+                    RandomPolicy::default().select_move(*game, rng)
+                } else {
+                    Ok(chosen.next().expect("A move for every game"))
+                }
+            })
+            .collect()
+    }
+
+    fn predict_score(&self, game: &T) -> Result<f32> {
+        self.policy.predict_score(game)
+    }
+
+    fn can_predict_score(&self) -> bool {
+        self.policy.can_predict_score()
+    }
+
+    fn predict_priors(&self, game: &T) -> Result<[f32; N]> {
+        self.policy.predict_priors(game)
+    }
+
+    fn cache_stats(&self) -> Option<crate::cache::CacheStats> {
+        self.policy.cache_stats()
+    }
+
+    fn exact_score(&self, game: &T) -> Option<f32> {
+        self.policy.exact_score(game)
+    }
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>, P: Policy<N, I, T>> Policy<N, I, T>
+    for Boltzmann<P>
+{
+    // Policies whose priors give the available moves nothing play their own move
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> Result<usize> {
+        let available = game.available_moves();
+        let priors = self.policy.predict_priors(game)?;
+        if !(0..N).any(|mv| available[mv] && priors[mv] > 0.0) {
+            return self.policy.select_move(game, rng);
+        }
+        let logits: [f32; N] = std::array::from_fn(|mv| {
+            if available[mv] {
+                priors[mv].ln()
+            } else {
+                f32::NEG_INFINITY
+            }
+        });
+        let moves = softmax(logits, self.temperature);
+        let indices: Vec<usize> = (0..N).collect();
+        Ok(*indices.choose_weighted(rng, |mv| moves[*mv])?)
+    }
+
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> Result<Vec<usize>> {
+        games
+            .iter()
+            .map(|game| self.select_move(*game, rng))
+            .collect()
+    }
+
+    fn predict_score(&self, game: &T) -> Result<f32> {
+        self.policy.predict_score(game)
+    }
+
+    fn can_predict_score(&self) -> bool {
+        self.policy.can_predict_score()
+    }
+
+    fn predict_priors(&self, game: &T) -> Result<[f32; N]> {
+        self.policy.predict_priors(game)
+    }
+
+    fn cache_stats(&self) -> Option<crate::cache::CacheStats> {
+        self.policy.cache_stats()
+    }
+
+    fn exact_score(&self, game: &T) -> Option<f32> {
+        self.policy.exact_score(game)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{game::move_indices, mnk::TicTacToe};
+    use rand::SeedableRng;
+
+    // Plays the first available move, while its priors favour the last
+    struct FirstMove;
+
+    impl Policy<9, 18, TicTacToe> for FirstMove {
+        fn select_move(&self, game: &TicTacToe, _rng: &mut StdRng) -> Result<usize> {
+            Ok(move_indices(game)[0])
+        }
+
+        fn select_moves_batch(
+            &self,
+            games: Vec<&TicTacToe>,
+            rng: &mut StdRng,
+        ) -> Result<Vec<usize>> {
+            games
+                .iter()
+                .map(|game| self.select_move(game, rng))
+                .collect()
+        }
+
+        fn predict_score(&self, _game: &TicTacToe) -> Result<f32> {
+            Ok(0.0)
+        }
+
+        fn can_predict_score(&self) -> bool {
+            true
+        }
+
+        fn predict_priors(&self, game: &TicTacToe) -> Result<[f32; 9]> {
+            let available = game.available_moves();
+            let total: f32 = (0..9)
+                .filter(|mv| available[*mv])
+                .map(|mv| mv as f32 + 1.0)
+                .sum();
+            Ok(std::array::from_fn(|mv| match available[mv] {
+                true => (mv as f32 + 1.0) / total,
+                false => 0.0,
+            }))
+        }
+    }
+
+    // Empty boards between boards where only the last square is left
+    fn games() -> Result<Vec<TicTacToe>> {
+        let last_square = TicTacToe::from_moves(&[0, 1, 2, 4, 3, 5, 7, 6])?;
+        Ok((0..20)
+            .map(|game| match game % 2 {
+                0 => TicTacToe::new(),
+                _ => last_square.clone(),
+            })
+            .collect())
+    }
+
+    // The moves played on every game, and how many of the empty boards got the first square
+    fn play(epsilon: f32, games: &[TicTacToe]) -> Result<(Vec<usize>, usize)> {
+        let policy = EpsilonGreedy::new(FirstMove, epsilon)?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let moves = policy.select_moves_batch(games.iter().collect(), &mut rng)?;
+        for (game, mv) in games.iter().zip(&moves) {
+            assert!(game.available_moves()[*mv], "{:?}", moves);
+        }
+        // Any move on the boards with one square left is that square
+        assert!(
+            moves.iter().skip(1).step_by(2).all(|mv| *mv == 8),
+            "{:?}",
+            moves
+        );
+        let first_squares = moves.iter().step_by(2).filter(|mv| **mv == 0).count();
+        Ok((moves, first_squares))
+    }
+
+    #[test]
+    fn epsilon_greedy_keeps_the_order_of_the_games() -> Result<()> {
+        let games = games()?;
+        assert_eq!(play(0.0, &games)?.1, 10);
+        // Random moves on some of the empty boards and the inner policy's on the others
+        let (moves, first_squares) = play(0.5, &games)?;
+        assert!((3..10).contains(&first_squares), "{:?}", moves);
+        // Random moves on all of them
+        let (moves, first_squares) = play(1.0, &games)?;
+        assert!(first_squares < 3, "{:?}", moves);
+        Ok(())
+    }
+
+    #[test]
+    fn boltzmann_without_temperature_plays_the_most_likely_move() -> Result<()> {
+        let policy = Boltzmann::new(FirstMove, 0.0)?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut game = TicTacToe::new();
+        game.try_perform_move(8)?;
+        for _ in 0..10 {
+            assert_eq!(policy.select_move(&game, &mut rng)?, 7);
+        }
+        Ok(())
+    }
+}
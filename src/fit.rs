@@ -0,0 +1,512 @@
+//! The parts of training that do not depend on the tensor library: the samples as flat rows,
+//! the losses over the LossTensor operations, minibatches, the validation split and early
+//! stopping. Backends implement MinibatchFit for the steps themselves
+
+use std::{collections::HashSet, iter::zip, time::Instant};
+
+use anyhow::{ensure, Result};
+use rand::{rngs::StdRng, seq::SliceRandom};
+
+use crate::dataset::Dataset;
+use crate::model::{EpochReport, FitConfig, LastBatch, TrainReport, TrainingMetrics};
+
+// Added to the logits of illegal moves so they get no probability. Not -inf, which would turn
+// their zero targets into NaN in the cross-entropy
+pub(crate) const ILLEGAL_LOGIT: f32 = -1e9;
+
+/// ILLEGAL_LOGIT for the illegal moves of each row, 0 for the legal ones, one row after the other
+pub(crate) fn illegal_penalty<const N: usize>(legal_moves: &[[bool; N]]) -> Vec<f32> {
+    legal_moves
+        .iter()
+        .flatten()
+        .map(|legal| if *legal { 0.0 } else { ILLEGAL_LOGIT })
+        .collect()
+}
+
+/// The samples of a dataset as row-major values, for backends to make their tensors from
+pub(crate) struct TrainingRows {
+    pub rows: usize,
+    /// rows × I states
+    pub states: Vec<f32>,
+    /// rows × N visit distributions over the legal moves
+    pub visits: Vec<f32>,
+    pub scores: Vec<f32>,
+    /// Margin of every sample, 0 for the ones without
+    pub margins: Vec<f32>,
+    /// 1 for the samples that have a margin, 0 for the others
+    pub margin_mask: Vec<f32>,
+    pub has_margin: Vec<bool>,
+    /// rows × N ownership of the cells, 0 for the samples without
+    pub ownership: Vec<f32>,
+    /// 1 for the samples that have the ownership, 0 for the others
+    pub ownership_mask: Vec<f32>,
+    pub has_ownership: Vec<bool>,
+    /// rows × N illegal_penalty
+    pub penalty: Vec<f32>,
+}
+
+impl TrainingRows {
+    pub(crate) fn new<const N: usize, const I: usize>(dataset: &Dataset<N, I>) -> Self {
+        let rows = dataset.game_states.len();
+        let legal_moves: Vec<[bool; N]> = (0..rows)
+            .map(|i| dataset.legal_moves.get(i).copied().unwrap_or([true; N]))
+            .collect();
+        // The softmaxed visits leave a little on illegal moves, which the masked policy could
+        // never match
+        let visits = zip(&dataset.visit_stats, &legal_moves)
+            .flat_map(|(visits, legal)| {
+                let total: f32 = zip(visits, legal)
+                    .filter(|(_, legal)| **legal)
+                    .map(|(visits, _)| visits)
+                    .sum();
+                zip(*visits, *legal).map(move |(visits, legal)| {
+                    if legal && total > 0.0 {
+                        visits / total
+                    } else {
+                        0.0
+                    }
+                })
+            })
+            .collect();
+        let margins: Vec<Option<f32>> = (0..rows)
+            .map(|i| {
+                dataset
+                    .extra_targets
+                    .get(i)
+                    .and_then(|targets| targets.first().copied())
+            })
+            .collect();
+        let has_margin: Vec<bool> = margins.iter().map(Option::is_some).collect();
+        let has_ownership: Vec<bool> = (0..rows)
+            .map(|i| {
+                dataset
+                    .ownership
+                    .get(i)
+                    .is_some_and(|owners| owners.len() == N)
+            })
+            .collect();
+        let ownership = (0..rows)
+            .flat_map(|i| match has_ownership[i] {
+                true => dataset.ownership[i].clone(),
+                false => vec![0.0; N],
+            })
+            .collect();
+        Self {
+            rows,
+            states: dataset.game_states.iter().flatten().copied().collect(),
+            visits,
+            scores: dataset.scores.to_vec(),
+            margins: margins
+                .iter()
+                .map(|margin| margin.unwrap_or_default())
+                .collect(),
+            margin_mask: mask(&has_margin),
+            has_margin,
+            ownership,
+            ownership_mask: mask(&has_ownership),
+            has_ownership,
+            penalty: illegal_penalty(&legal_moves),
+        }
+    }
+
+    /// Samples of `batch` that have a margin, at least 1 to divide the margin loss by
+    pub(crate) fn margin_samples(&self, batch: &[u32]) -> usize {
+        count_in(batch, &self.has_margin)
+    }
+
+    /// Samples of `batch` that have the ownership, at least 1 like margin_samples
+    pub(crate) fn ownership_samples(&self, batch: &[u32]) -> usize {
+        count_in(batch, &self.has_ownership)
+    }
+}
+
+fn mask(has: &[bool]) -> Vec<f32> {
+    has.iter().map(|has| if *has { 1.0 } else { 0.0 }).collect()
+}
+
+fn count_in(batch: &[u32], has: &[bool]) -> usize {
+    batch.iter().filter(|i| has[**i as usize]).count().max(1)
+}
+
+/// The tensor operations the losses are made of, which every backend implements for its tensors
+/// so they all train on the same losses. Tensors are rows × columns
+pub(crate) trait LossTensor: Sized {
+    /// Index of the rows in `batch`, on the device of `self`
+    fn row_index(&self, batch: &[u32]) -> Result<Self>;
+    fn select_rows(&self, index: &Self) -> Result<Self>;
+    fn plus(&self, other: &Self) -> Result<Self>;
+    fn minus(&self, other: &Self) -> Result<Self>;
+    /// Elementwise product, a single column is repeated over all columns
+    fn times(&self, other: &Self) -> Result<Self>;
+    fn scaled(&self, factor: f64) -> Result<Self>;
+    fn squared(&self) -> Result<Self>;
+    fn log_softmax_rows(&self) -> Result<Self>;
+    /// Sum of every row as a single column
+    fn row_sums(&self) -> Result<Self>;
+    fn sum_all(&self) -> Result<Self>;
+    fn mean_all(&self) -> Result<Self>;
+    /// The value of a tensor with a single element
+    fn scalar(&self) -> Result<f32>;
+}
+
+/// Outputs of a model for a batch of states, one row per state
+pub(crate) struct Heads<T> {
+    pub visit_logits: T,
+    pub score: T,
+    pub margin: T,
+    /// Ownership of every cell, for models with the ownership head
+    pub ownership: Option<T>,
+}
+
+/// The whole dataset as tensors on the model's device, batches are picked out of it by row
+pub(crate) struct Samples<T> {
+    x: T,
+    visits: T,
+    scores: T,
+    margins: T,
+    margin_mask: T,
+    ownership: T,
+    ownership_mask: T,
+    penalty: T,
+    moves: usize,
+    rows: TrainingRows,
+}
+
+impl<T: LossTensor> Samples<T> {
+    /// `tensor` makes a tensor of the values with the shape rows × columns
+    pub(crate) fn new<const N: usize, const I: usize>(
+        rows: TrainingRows,
+        tensor: impl Fn(&[f32], (usize, usize)) -> Result<T>,
+    ) -> Result<Self> {
+        let count = rows.rows;
+        Ok(Self {
+            x: tensor(&rows.states, (count, I))?,
+            visits: tensor(&rows.visits, (count, N))?,
+            scores: tensor(&rows.scores, (count, 1))?,
+            margins: tensor(&rows.margins, (count, 1))?,
+            margin_mask: tensor(&rows.margin_mask, (count, 1))?,
+            ownership: tensor(&rows.ownership, (count, N))?,
+            ownership_mask: tensor(&rows.ownership_mask, (count, 1))?,
+            penalty: tensor(&rows.penalty, (count, N))?,
+            moves: N,
+            rows,
+        })
+    }
+
+    /// Cross-entropy between the visit distribution and the policy over the legal moves, and the
+    /// squared errors of the value, of the margin where the sample has one and of the ownership
+    /// of every cell where the sample has it. `heads` runs the model on the states of the batch
+    pub(crate) fn losses(
+        &self,
+        batch: &[u32],
+        heads: impl FnOnce(&T) -> Result<Heads<T>>,
+    ) -> Result<Losses<T>> {
+        let margin_samples = self.rows.margin_samples(batch);
+        let ownership_cells = self.rows.ownership_samples(batch) * self.moves;
+        let index = self.x.row_index(batch)?;
+        let select = |tensor: &T| tensor.select_rows(&index);
+        let heads = heads(&select(&self.x)?)?;
+        let log_policy = heads
+            .visit_logits
+            .plus(&select(&self.penalty)?)?
+            .log_softmax_rows()?;
+        let policy = select(&self.visits)?
+            .times(&log_policy)?
+            .row_sums()?
+            .mean_all()?
+            .scaled(-1.0)?;
+        let value = heads
+            .score
+            .minus(&select(&self.scores)?)?
+            .squared()?
+            .mean_all()?;
+        let margin = heads
+            .margin
+            .minus(&select(&self.margins)?)?
+            .times(&select(&self.margin_mask)?)?
+            .squared()?
+            .sum_all()?
+            .scaled(1.0 / margin_samples as f64)?;
+        let ownership = heads
+            .ownership
+            .map(|ownership| {
+                ownership
+                    .minus(&select(&self.ownership)?)?
+                    .times(&select(&self.ownership_mask)?)?
+                    .squared()?
+                    .sum_all()?
+                    .scaled(1.0 / ownership_cells as f64)
+            })
+            .transpose()?;
+        Ok(Losses {
+            policy,
+            value,
+            margin,
+            ownership,
+        })
+    }
+}
+
+/// The losses of a batch as single element tensors
+pub(crate) struct Losses<T> {
+    pub policy: T,
+    pub value: T,
+    pub margin: T,
+    /// None for models without the ownership head
+    pub ownership: Option<T>,
+}
+
+impl<T: LossTensor> Losses<T> {
+    /// The policy loss plus the weighted other losses, what is minimized
+    pub(crate) fn total(&self, weights: LossWeights) -> Result<T> {
+        let total = self
+            .policy
+            .plus(&self.value.scaled(weights.value as f64)?)?
+            .plus(&self.margin.scaled(weights.margin as f64)?)?;
+        match &self.ownership {
+            Some(ownership) => total.plus(&ownership.scaled(weights.ownership as f64)?),
+            None => Ok(total),
+        }
+    }
+
+    pub(crate) fn metrics(&self) -> Result<TrainingMetrics> {
+        Ok(TrainingMetrics {
+            policy_loss: self.policy.scalar()?,
+            value_loss: self.value.scalar()?,
+            margin_loss: self.margin.scalar()?,
+            ownership_loss: match &self.ownership {
+                Some(ownership) => ownership.scalar()?,
+                None => 0.0,
+            },
+            skipped_steps: 0,
+        })
+    }
+}
+
+/// Weights of the auxiliary losses relative to the policy cross-entropy
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct LossWeights {
+    pub value: f32,
+    pub margin: f32,
+    pub ownership: f32,
+}
+
+impl LossWeights {
+    /// The policy loss plus the weighted other losses, what is minimized
+    pub(crate) fn total(&self, metrics: &TrainingMetrics) -> f32 {
+        metrics.policy_loss
+            + self.value * metrics.value_loss
+            + self.margin * metrics.margin_loss
+            + self.ownership * metrics.ownership_loss
+    }
+}
+
+/// A model being fit to TrainingRows by fit_minibatches, rows are referred to by index
+pub(crate) trait MinibatchFit {
+    /// Copy of the weights to go back to
+    type Snapshot;
+    /// Takes an optimizer step on the losses of the rows in `batch`. None when the step was
+    /// skipped because the loss or the gradients were not finite
+    fn step(&mut self, batch: &[u32], learning_rate: f64) -> Result<Option<TrainingMetrics>>;
+    /// Losses of the rows in `batch` with the model as it is used to predict
+    fn evaluate(&self, batch: &[u32]) -> Result<TrainingMetrics>;
+    fn snapshot(&self) -> Result<Self::Snapshot>;
+    fn restore(&mut self, snapshot: Self::Snapshot) -> Result<()>;
+}
+
+// Training and validation rows, holding out `fraction` of the source positions. The variations
+// of a position all go to the same side, otherwise the model would be validated on positions it
+// was trained on in another orientation. At least one position is always left to train on
+fn validation_split(positions: &[usize], fraction: f32, rng: &mut StdRng) -> (Vec<u32>, Vec<u32>) {
+    let mut sources = positions.to_vec();
+    sources.sort_unstable();
+    sources.dedup();
+    sources.shuffle(rng);
+    let held_out =
+        ((sources.len() as f32 * fraction).round() as usize).min(sources.len().saturating_sub(1));
+    let held_out: HashSet<usize> = sources
+        .split_off(sources.len() - held_out)
+        .into_iter()
+        .collect();
+    (0..positions.len() as u32).partition(|row| !held_out.contains(&positions[*row as usize]))
+}
+
+// Minimizes the losses in shuffled minibatches, holding out the validation share of the source
+// positions, `positions` has the one of every row. With a validation set the model ends up with
+// the weights of the epoch with the lowest validation loss, and training stops once it has not
+// improved for the patience in epochs
+pub(crate) fn fit_minibatches<M: MinibatchFit>(
+    model: &mut M,
+    positions: &[usize],
+    fit: &FitConfig,
+    generation: usize,
+    loss_weights: LossWeights,
+) -> Result<TrainReport> {
+    let start = Instant::now();
+    ensure!(fit.batch_size > 0, "Batches need at least one sample");
+    ensure!(
+        (0.0..1.0).contains(&fit.validation_fraction),
+        "The validation fraction {} is not in [0, 1)",
+        fit.validation_fraction
+    );
+    let mut rng = fit.rng();
+    let (mut order, validation) = validation_split(positions, fit.validation_fraction, &mut rng);
+    let mut report = TrainReport::default();
+    let mut best: Option<(f32, M::Snapshot)> = None;
+    let steps = fit.epochs * batch_indices(&order, fit.batch_size, fit.last_batch).len();
+    let mut step = 0;
+    for epoch in 0..fit.epochs {
+        let epoch_start = Instant::now();
+        order.shuffle(&mut rng);
+        let mut batches = Vec::new();
+        let mut skipped = 0;
+        for batch in batch_indices(&order, fit.batch_size, fit.last_batch) {
+            let learning_rate =
+                fit.schedule
+                    .learning_rate(fit.learning_rate, generation, step, steps);
+            step += 1;
+            match model.step(&batch, learning_rate)? {
+                Some(metrics) => batches.push((batch.len(), metrics)),
+                None => skipped += 1,
+            }
+        }
+        ensure!(
+            !batches.is_empty() || skipped == 0,
+            "Every step of epoch {} had a loss or gradients that were not finite",
+            epoch + 1
+        );
+        let training = TrainingMetrics {
+            skipped_steps: skipped,
+            ..mean_metrics(&batches)
+        };
+        let validation = match validation.is_empty() {
+            true => None,
+            false => Some(mean_metrics(
+                &batch_indices(&validation, fit.batch_size, LastBatch::Keep)
+                    .iter()
+                    .map(|batch| Ok((batch.len(), model.evaluate(batch)?)))
+                    .collect::<Result<Vec<_>>>()?,
+            )),
+        };
+        report.epochs.push(EpochReport {
+            training,
+            validation,
+            duration: epoch_start.elapsed(),
+        });
+        let Some(validation) = validation else {
+            report.best_epoch = epoch;
+            continue;
+        };
+        let validation_loss = loss_weights.total(&validation);
+        if best
+            .as_ref()
+            .map_or(true, |(best_loss, _)| validation_loss < *best_loss)
+        {
+            best = Some((validation_loss, model.snapshot()?));
+            report.best_epoch = epoch;
+        } else if fit
+            .patience
+            .is_some_and(|patience| epoch - report.best_epoch >= patience)
+        {
+            report.stopped_early = true;
+            break;
+        }
+    }
+    if let Some((_, weights)) = best {
+        model.restore(weights)?;
+    }
+    report.duration = start.elapsed();
+    Ok(report)
+}
+
+// Mean of the losses over `batches`, weighted by their sizes
+fn mean_metrics(batches: &[(usize, TrainingMetrics)]) -> TrainingMetrics {
+    let samples = batches.iter().map(|(size, _)| size).sum::<usize>().max(1) as f32;
+    let mean = |loss: fn(&TrainingMetrics) -> f32| {
+        batches
+            .iter()
+            .map(|(size, metrics)| *size as f32 * loss(metrics))
+            .sum::<f32>()
+            / samples
+    };
+    TrainingMetrics {
+        policy_loss: mean(|metrics| metrics.policy_loss),
+        value_loss: mean(|metrics| metrics.value_loss),
+        margin_loss: mean(|metrics| metrics.margin_loss),
+        ownership_loss: mean(|metrics| metrics.ownership_loss),
+        skipped_steps: 0,
+    }
+}
+
+// Consecutive runs of `batch_size` sample indices of `order`, with the samples left over handled
+// as `last_batch` says. Drop keeps them when there is no full batch at all, so small datasets
+// are still trained on
+fn batch_indices(order: &[u32], batch_size: usize, last_batch: LastBatch) -> Vec<Vec<u32>> {
+    let mut batches: Vec<Vec<u32>> = order
+        .chunks(batch_size)
+        .map(|batch| batch.to_vec())
+        .collect();
+    match batches.last_mut() {
+        Some(last) if last.len() < batch_size && order.len() > batch_size => match last_batch {
+            LastBatch::Keep => {}
+            LastBatch::Drop => {
+                batches.pop();
+            }
+            LastBatch::Pad => {
+                let missing = batch_size - last.len();
+                last.extend(order.iter().cycle().take(missing));
+            }
+        },
+        _ => {}
+    }
+    batches
+}
+
+/// Probabilities of `logits` divided by `temperature`, sharper below 1 and flatter above. A
+/// temperature of 0 puts everything on the largest logit, the first of them on a tie
+pub fn softmax<const N: usize>(logits: [f32; N], temperature: f32) -> [f32; N] {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    if temperature <= 0.0 {
+        let best = logits.iter().position(|logit| *logit == max).unwrap_or(0);
+        return std::array::from_fn(|mv| if mv == best { 1.0 } else { 0.0 });
+    }
+    let exp = logits.map(|logit| ((logit - max) / temperature).exp());
+    let total: f32 = exp.iter().sum();
+    exp.map(|value| value / total)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn keeps_variations_on_one_side_of_the_split() {
+        // 100 positions with 8 variations each
+        let positions: Vec<usize> = (0..800).map(|row| row / 8).collect();
+        let mut rng = StdRng::seed_from_u64(0);
+        let (training, validation) = validation_split(&positions, 0.1, &mut rng);
+        assert_eq!(validation.len(), 80);
+        assert_eq!(training.len() + validation.len(), positions.len());
+        let held_out: HashSet<usize> = validation
+            .iter()
+            .map(|row| positions[*row as usize])
+            .collect();
+        assert!(training
+            .iter()
+            .all(|row| !held_out.contains(&positions[*row as usize])));
+    }
+
+    #[test]
+    fn leaves_a_position_to_train_on() {
+        // Rounds up to holding out both
+        let positions = [0, 0, 1, 1];
+        let mut rng = StdRng::seed_from_u64(0);
+        let (training, validation) = validation_split(&positions, 0.9, &mut rng);
+        assert_eq!((training.len(), validation.len()), (2, 2));
+        assert_eq!(
+            validation_split(&[], 0.9, &mut rng),
+            (Vec::new(), Vec::new())
+        );
+    }
+}
@@ -1,9 +1,21 @@
-use std::any;
+use std::{
+    any,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
 
-use anyhow::{ensure, Result};
-use rand::seq::IteratorRandom;
+use anyhow::{bail, ensure, Context, Result};
+use rand::{
+    rngs::StdRng,
+    seq::{IteratorRandom, SliceRandom},
+    SeedableRng,
+};
 
-use crate::mcts::GameStats;
+use crate::{
+    cache::CacheStats,
+    mcts::{simulate, GameStats, RolloutOutcome},
+    render::Board,
+};
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum SimpleBoardState {
@@ -12,19 +24,30 @@ pub enum SimpleBoardState {
     Opponent,
 }
 
-impl SimpleBoardState {
-    pub fn simple_state(&self) -> [f32; 2] {
-        if *self == Self::Empty {
-            [0.0, 0.0]
-        } else if *self == Self::Player {
-            [1.0, 0.0]
-        } else {
-            [0.0, 1.0]
-        }
+/// Contents of a board cell. SimpleBoardState is enough for games of plain stones, games whose
+/// cells hold more, like the kings of draughts or the stacks of Tak, have their own cell type.
+/// Boards of any of them are flipped with swap_board and encoded with encode_board
+pub trait Cell: Clone {
+    /// Number of values encode writes for one cell
+    const FEATURES: usize;
+    /// Writes the features of the cell to `out`, which has FEATURES entries
+    fn encode(&self, out: &mut [f32]);
+    /// The cell with the pieces of Player and Opponent swapped, anything neutral stays
+    fn swap(&self) -> Self;
+}
+
+impl Cell for SimpleBoardState {
+    const FEATURES: usize = 2;
+
+    fn encode(&self, out: &mut [f32]) {
+        out.copy_from_slice(match self {
+            SimpleBoardState::Empty => &[0.0, 0.0],
+            SimpleBoardState::Player => &[1.0, 0.0],
+            SimpleBoardState::Opponent => &[0.0, 1.0],
+        });
     }
 
-    /// Swaps player and opponent, empty stays the same
-    pub fn swap(&self) -> Self {
+    fn swap(&self) -> Self {
         match &self {
             SimpleBoardState::Empty => SimpleBoardState::Empty,
             SimpleBoardState::Player => Self::Opponent,
@@ -33,6 +56,49 @@ impl SimpleBoardState {
     }
 }
 
+// An empty square for cell types that only describe pieces
+impl<C: Cell> Cell for Option<C> {
+    const FEATURES: usize = C::FEATURES;
+
+    fn encode(&self, out: &mut [f32]) {
+        match self {
+            Some(cell) => cell.encode(out),
+            None => out.fill(0.0),
+        }
+    }
+
+    fn swap(&self) -> Self {
+        self.as_ref().map(|cell| cell.swap())
+    }
+}
+
+/// Swaps the pieces of Player and Opponent on every cell, the usual start of flip_board
+pub fn swap_board<C: Cell>(board: &mut [C]) {
+    for cell in board.iter_mut() {
+        *cell = cell.swap();
+    }
+}
+
+/// State slice with one plane per feature, feature f of square i ends up at f * board.len() + i.
+/// For SimpleBoardState that is the usual plane of Player's stones followed by the one of
+/// Opponent's. Entries after the planes are left at 0 for whatever else the game encodes
+pub fn encode_board<C: Cell, const I: usize>(board: &[C]) -> [f32; I] {
+    let mut out_slice = [0.0; I];
+    encode_board_into(board, &mut out_slice);
+    out_slice
+}
+
+/// encode_board into a slice, for states whose length is only known at runtime
+pub fn encode_board_into<C: Cell>(board: &[C], out_slice: &mut [f32]) {
+    let mut features = vec![0.0; C::FEATURES];
+    for (i, cell) in board.iter().enumerate() {
+        cell.encode(&mut features);
+        for (feature, value) in features.iter().enumerate() {
+            out_slice[feature * board.len() + i] = *value;
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
 pub enum Players {
     Player,
@@ -63,6 +129,136 @@ impl GameResult {
             GameResult::Tie => 0.0,
         }
     }
+
+    /// Like points, but a tie is scored as -contempt instead of 0
+    pub fn points_with_contempt(&self, contempt: f32) -> f32 {
+        match self {
+            GameResult::Tie => -contempt,
+            _ => self.points(),
+        }
+    }
+}
+
+/// Samples and applies pending chance events until a player is to move
+pub fn resolve_chance<const N: usize, const I: usize, T: Game<N, I>>(
+    game: &mut T,
+    rng: &mut StdRng,
+) -> Result<()> {
+    while let Some(outcomes) = game.chance_outcomes() {
+        let (outcome, _) = *outcomes
+            .choose_weighted(rng, |(_, probability)| *probability)
+            .context("Chance event without outcomes")?;
+        game.try_apply_chance_outcome(outcome)?;
+    }
+    Ok(())
+}
+
+/// FEN-like text for a row major board: rows from the top separated by '/', 'x' for Player, 'o'
+/// for Opponent and runs of empty squares as their length, then the player to move. The empty
+/// 3x3 board with Player to move is "3/3/3 x"
+pub fn board_to_string(
+    board: &[SimpleBoardState],
+    width: usize,
+    current_player: Players,
+) -> String {
+    let rows: Vec<String> = board
+        .chunks_exact(width)
+        .map(|row| {
+            let mut out = String::new();
+            let mut empty = 0;
+            for square in row {
+                if *square == SimpleBoardState::Empty {
+                    empty += 1;
+                    continue;
+                }
+                if empty > 0 {
+                    out.push_str(&empty.to_string());
+                    empty = 0;
+                }
+                out.push(match square {
+                    SimpleBoardState::Player => 'x',
+                    _ => 'o',
+                });
+            }
+            if empty > 0 {
+                out.push_str(&empty.to_string());
+            }
+            out
+        })
+        .collect();
+    let to_move = match current_player {
+        Players::Player => 'x',
+        Players::Opponent => 'o',
+    };
+    format!("{} {}", rows.join("/"), to_move)
+}
+
+/// Parses the format of board_to_string for a board of `width` * `height` squares
+pub fn board_from_string(
+    position: &str,
+    width: usize,
+    height: usize,
+) -> Result<(Vec<SimpleBoardState>, Players)> {
+    let (rows, to_move) = position
+        .trim()
+        .split_once(' ')
+        .context("Position is missing the player to move")?;
+    let current_player = match to_move.trim() {
+        "x" => Players::Player,
+        "o" => Players::Opponent,
+        other => bail!("Unknown player to move '{}'", other),
+    };
+    let rows: Vec<&str> = rows.split('/').collect();
+    ensure!(
+        rows.len() == height,
+        "Expected {} rows, got {}",
+        height,
+        rows.len()
+    );
+    let mut board = Vec::with_capacity(width * height);
+    for row in rows {
+        let start = board.len();
+        let mut empty = 0;
+        for c in row.chars() {
+            if let Some(digit) = c.to_digit(10) {
+                empty = empty * 10 + digit as usize;
+                continue;
+            }
+            board.extend(std::iter::repeat(SimpleBoardState::Empty).take(empty));
+            empty = 0;
+            board.push(match c {
+                'x' => SimpleBoardState::Player,
+                'o' => SimpleBoardState::Opponent,
+                other => bail!("Unknown square '{}'", other),
+            });
+        }
+        board.extend(std::iter::repeat(SimpleBoardState::Empty).take(empty));
+        ensure!(
+            board.len() - start == width,
+            "Row '{}' does not have {} squares",
+            row,
+            width
+        );
+    }
+    Ok((board, current_player))
+}
+
+/// Hash of the position that stays the same when the board is flipped, for games that detect
+/// repetitions while self-play keeps flipping the board
+pub fn canonical_hash<const N: usize, const I: usize, T: Game<N, I>>(game: &T) -> u64 {
+    if game.current_player() == Players::Player {
+        return game.position_hash();
+    }
+    let mut flipped = game.clone();
+    flipped.flip_board();
+    flipped.position_hash()
+}
+
+/// How often the last position in `positions` occurred
+pub fn repetitions(positions: &[u64]) -> usize {
+    positions.last().map_or(0, |last| {
+        positions.iter().filter(|hash| *hash == last).count()
+    })
 }
 
 pub fn move_indices<const N: usize, const I: usize, T: Game<N, I>>(game: &T) -> Vec<usize> {
@@ -75,6 +271,45 @@ pub fn move_indices<const N: usize, const I: usize, T: Game<N, I>>(game: &T) ->
         .collect();
 }
 
+/// The pass move when it is the only available move, nothing is left to decide in the position
+pub fn forced_pass<const N: usize, const I: usize, T: Game<N, I>>(game: &T) -> Option<usize> {
+    let pass = game.pass_move()?;
+    let available = game.available_moves();
+    (available[pass] && available.iter().filter(|x| **x).count() == 1).then_some(pass)
+}
+
+/// Number of move sequences of exactly `depth` moves from `game`, the leaves of the game tree cut
+/// off at that depth. Lines that end early in a finished game or a draw by rule are not counted,
+/// chance events are branched on without using up depth. Comparing with known counts checks move
+/// generation, undo_move and the detection of finished games in one go
+pub fn perft<const N: usize, const I: usize, T: Game<N, I>>(
+    game: &mut T,
+    depth: usize,
+) -> Result<u64> {
+    if depth == 0 {
+        return Ok(1);
+    }
+    if let Some(outcomes) = game.chance_outcomes() {
+        let mut total = 0;
+        for (outcome, _) in outcomes {
+            let mut resolved = game.clone();
+            resolved.try_apply_chance_outcome(outcome)?;
+            total += perft(&mut resolved, depth)?;
+        }
+        return Ok(total);
+    }
+    if game.game_ended() || game.is_draw_by_rule() {
+        return Ok(0);
+    }
+    let mut total = 0;
+    for mv in move_indices(game) {
+        game.try_perform_move(mv)?;
+        total += perft(game, depth - 1)?;
+        game.undo_move()?;
+    }
+    Ok(total)
+}
+
 impl TryFrom<SimpleBoardState> for Players {
     type Error = anyhow::Error;
 
@@ -102,46 +337,340 @@ impl From<Players> for SimpleBoardState {
 pub trait Game<const N: usize, const I: usize>: Clone {
     fn winning_player(&self) -> Option<Players>;
     fn available_moves(&self) -> [bool; N];
-    fn perform_move(&mut self, space: usize);
+    /// Plays the move, or fails without changing the game if it is not legal
+    fn try_perform_move(&mut self, space: usize) -> Result<()>;
+    /// Like try_perform_move, for moves known to be legal. Panics on illegal moves
+    fn perform_move(&mut self, space: usize) {
+        self.try_perform_move(space).unwrap()
+    }
+    /// Plays a move made of several indices, like the queen move and arrow of a turn of Amazons.
+    /// Either every part is played or, if one of them is illegal, none of them
+    fn try_perform_moves(&mut self, parts: &[usize]) -> Result<()> {
+        for (i, part) in parts.iter().enumerate() {
+            if let Err(error) = self.try_perform_move(*part) {
+                for _ in 0..i {
+                    self.undo_move()?;
+                }
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+    /// Takes back the last move, fails when no move has been played. Games keep what they need
+    /// to undo a move on a stack instead of copying their whole state
+    fn undo_move(&mut self) -> Result<()>;
     fn new() -> Self;
+    /// Replays a game from the starting position, see Dataset::records
+    fn from_moves(moves: &[usize]) -> Result<Self> {
+        let mut game = Self::new();
+        for (i, mv) in moves.iter().enumerate() {
+            game.try_perform_move(*mv)
+                .with_context(|| format!("Move {} of the game is illegal", i + 1))?;
+        }
+        Ok(game)
+    }
     fn game_ended(&self) -> bool;
+    /// Draw by a rule that stops games which could otherwise go on forever, like a move limit or
+    /// a repeated position. Play stops and the game counts as a tie even though game_ended is
+    /// false and moves are still available
+    fn is_draw_by_rule(&self) -> bool {
+        false
+    }
+    /// Value of a finished game in [-1, 1] from the perspective of `perspective`, None while it is
+    /// still running. 1 for a win, -1 for a loss and 0 for a tie unless the game has a margin to
+    /// grade the outcome by, like the disc difference in Othello
+    fn terminal_value(&self, perspective: Players) -> Option<f32> {
+        if !self.game_ended() && !self.is_draw_by_rule() {
+            return None;
+        }
+        Some(
+            match self.winning_player().filter(|_| !self.is_draw_by_rule()) {
+                Some(player) if player == perspective => 1.0,
+                Some(_) => -1.0,
+                None => 0.0,
+            },
+        )
+    }
     fn current_player(&self) -> Players;
     fn flip_board(&mut self);
+    /// Index that `mv` has after flip_board. The same move unless flipping moves the squares
+    /// around, flipping twice always gives back the original move
+    fn flipped_move(&self, mv: usize) -> usize {
+        mv
+    }
     fn get_game_state_slice(&self) -> [f32; I];
+    /// The position as seen by the player to move, which is what models are trained on, and
+    /// that player. When it is Opponent the state is the one of the flipped board, so move
+    /// indices in that frame have to go through flipped_move
+    fn canonical_state(&self) -> ([f32; I], Players) {
+        if self.current_player() == Players::Player {
+            return (self.get_game_state_slice(), Players::Player);
+        }
+        let mut flipped = self.clone();
+        flipped.flip_board();
+        (flipped.get_game_state_slice(), Players::Opponent)
+    }
+    /// Available moves in the frame of canonical_state
+    fn canonical_moves(&self) -> [bool; N] {
+        if self.current_player() == Players::Player {
+            return self.available_moves();
+        }
+        let mut flipped = self.clone();
+        flipped.flip_board();
+        flipped.available_moves()
+    }
     fn get_game_variations(stats: &GameStats<N, I>) -> Vec<GameStats<N, I>>;
+    /// get_game_variations for games whose symmetries depend on more than the type, like the
+    /// size of a Padded game. Self-play goes through this one
+    fn game_variations(&self, stats: &GameStats<N, I>) -> Vec<GameStats<N, I>> {
+        Self::get_game_variations(stats)
+    }
+    /// Whether terminal_value grades finished games by a score margin instead of only saying who
+    /// won, self-play then records the margin as an extra training target
+    fn has_score_margin(&self) -> bool {
+        false
+    }
+    /// Final owner of every cell of a finished game indexed like the moves, 1 for the cells that
+    /// `perspective` won with, -1 for the ones of the other player and 0 for the rest. Self-play
+    /// records it as a per-cell target for models with an ownership head. None for games that
+    /// have no such notion or have not ended
+    fn ownership(&self, _perspective: Players) -> Option<[f32; N]> {
+        None
+    }
+    /// Move index of passing in games where a player may give up their turn, None when every move
+    /// changes the board. A position where only the pass is available goes on, it does not end
+    /// the game
+    fn pass_move(&self) -> Option<usize> {
+        None
+    }
+    /// Estimated value of a non-terminal position in [-1, 1], from the perspective of Players::Player
+    fn heuristic_value(&self) -> f32 {
+        0.0
+    }
+    /// Hash identifying the position, used to cache evaluations
+    fn position_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for value in self.get_game_state_slice() {
+            value.to_bits().hash(&mut hasher);
+        }
+        (self.current_player() == Players::Player).hash(&mut hasher);
+        hasher.finish()
+    }
+    /// For stochastic games, the outcomes of the pending chance event (a dice roll, a tile draw)
+    /// with their probabilities. None when a player is to move
+    fn chance_outcomes(&self) -> Option<Vec<(usize, f32)>> {
+        None
+    }
+    /// Resolves the pending chance event with one of the outcomes from chance_outcomes
+    fn try_apply_chance_outcome(&mut self, _outcome: usize) -> Result<()> {
+        bail!("Game has no chance events")
+    }
+    /// Human readable name of a move, the move index unless the game has its own notation
+    fn move_to_string(&self, mv: usize) -> String {
+        mv.to_string()
+    }
+    /// Parses a move written like move_to_string, whether it is legal is not checked
+    fn move_from_string(&self, text: &str) -> Result<usize> {
+        let mv = text
+            .trim()
+            .parse()
+            .with_context(|| format!("'{}' is not a move", text))?;
+        ensure!(mv < N, "Move {} is out of range", mv);
+        Ok(mv)
+    }
+    /// Board contents and player to move as a single line of text, for logs and test fixtures
+    fn to_position_string(&self) -> Result<String> {
+        bail!("Game has no position format")
+    }
+    /// The board for renderers, for games on a grid that implement SpatialGame
+    fn board(&self) -> Option<Board> {
+        None
+    }
+    /// Height and width of the grid the first moves and the state planes are laid out on row by
+    /// row, for games with a move per cell of a grid. dyn_game::Padded keeps the cells of those
+    /// where they are on a larger grid
+    fn grid(&self) -> Option<(usize, usize)> {
+        None
+    }
+    /// Sets up the position written by to_position_string, the game has no move history
+    fn from_position_string(_position: &str) -> Result<Self> {
+        bail!("Game has no position format")
+    }
+    /// Position to analyze, either a position string or the moves from the start in the notation
+    /// of move_from_string separated by spaces or commas, like "a1 b2 c3" for Hex. Moves keep the
+    /// history, so they are preferred for games that need it, like repetitions in draughts
+    fn from_setup(setup: &str) -> Result<Self> {
+        if let Ok(game) = Self::from_position_string(setup) {
+            return Ok(game);
+        }
+        let mut game = Self::new();
+        for text in setup.split([' ', ',']).filter(|text| !text.is_empty()) {
+            let mv = game.move_from_string(text)?;
+            game.try_perform_move(mv)
+                .with_context(|| format!("Move {} is illegal", text))?;
+        }
+        Ok(game)
+    }
+}
+
+/// Games played on a grid, whose state can be given as planes that keep the board geometry for
+/// the convolutional models ConvModel and ResNetModel, unlike the flat get_game_state_slice. Those
+/// read the state of the canonical position as its spatial planes, so the state has to be the
+/// planes they start with, see conv_model::spatial_board
+pub trait SpatialGame<const N: usize, const I: usize>: Game<N, I> {
+    /// Channels, height and width of spatial_planes
+    fn plane_shape(&self) -> (usize, usize, usize);
+    /// Planes in channel, row, column order, the stones of the player to move, the stones of the
+    /// other player and a plane of ones when Player is to move
+    fn spatial_planes(&self) -> Vec<f32>;
+    /// Whether the board is a skewed grid of hexes, where row r is shifted r half squares right
+    fn skewed(&self) -> bool {
+        false
+    }
+}
+
+/// spatial_planes for a row major board of SimpleBoardState
+pub fn simple_board_planes(board: &[SimpleBoardState], current_player: Players) -> Vec<f32> {
+    let own: SimpleBoardState = current_player.into();
+    let mut planes = vec![0.0; board.len() * 3];
+    for (i, square) in board.iter().enumerate() {
+        match square {
+            SimpleBoardState::Empty => {}
+            square if *square == own => planes[i] = 1.0,
+            _ => planes[board.len() + i] = 1.0,
+        }
+    }
+    if current_player == Players::Player {
+        planes[board.len() * 2..].fill(1.0);
+    }
+    planes
 }
 
 pub trait Policy<const N: usize, const I: usize, T: Game<N, I>> {
-    fn select_move(&self, game: &T) -> anyhow::Result<usize>;
-    fn select_moves_batch(&self, games: Vec<&T>) -> anyhow::Result<Vec<usize>>;
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> anyhow::Result<usize>;
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> anyhow::Result<Vec<usize>>;
     fn predict_score(&self, game: &T) -> anyhow::Result<f32>;
     fn can_predict_score(&self) -> bool;
+    /// Prior probability of each move, uniform over the available moves unless overridden
+    fn predict_priors(&self, game: &T) -> anyhow::Result<[f32; N]> {
+        let available = game.available_moves();
+        let count = available.iter().filter(|x| **x).count().max(1) as f32;
+        Ok(available.map(|x| if x { 1.0 / count } else { 0.0 }))
+    }
+    /// Evaluation cache statistics for policies that cache, see CachedPolicy
+    fn cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+    /// Proven value of the position for Players::Player, for policies that know some positions
+    /// exactly like TablebasePolicy
+    fn exact_score(&self, _game: &T) -> Option<f32> {
+        None
+    }
+}
+
+// For policies picked at runtime, like the ones named on the command line
+impl<const N: usize, const I: usize, T: Game<N, I>, P: Policy<N, I, T> + ?Sized> Policy<N, I, T>
+    for Box<P>
+{
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> anyhow::Result<usize> {
+        (**self).select_move(game, rng)
+    }
+
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> anyhow::Result<Vec<usize>> {
+        (**self).select_moves_batch(games, rng)
+    }
+
+    fn predict_score(&self, game: &T) -> anyhow::Result<f32> {
+        (**self).predict_score(game)
+    }
+
+    fn can_predict_score(&self) -> bool {
+        (**self).can_predict_score()
+    }
+
+    fn predict_priors(&self, game: &T) -> anyhow::Result<[f32; N]> {
+        (**self).predict_priors(game)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        (**self).cache_stats()
+    }
+
+    fn exact_score(&self, game: &T) -> Option<f32> {
+        (**self).exact_score(game)
+    }
 }
 
-pub struct RandomPolicy {}
+/// Plays uniformly random moves. With rollouts it also predicts scores, as the mean result of
+/// that many random games played out from the position
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RandomPolicy {
+    /// Random games played out for every predicted score, 0 for a policy that cannot predict
+    pub rollouts: usize,
+}
 
 impl<const N: usize, const I: usize, T: Game<N, I>> Policy<N, I, T> for RandomPolicy {
-    fn select_move(&self, game: &T) -> anyhow::Result<usize> {
+    // Passes only when nothing else is available, random games in Go would otherwise mostly end
+    // after a few moves with two passes
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> anyhow::Result<usize> {
+        let pass = game.pass_move();
         let next_move = game
             .available_moves()
             .iter()
             .enumerate()
-            .filter(|(_, available)| **available)
-            .choose(&mut rand::thread_rng())
-            .unwrap()
-            .0;
+            .filter(|(mv, available)| **available && Some(*mv) != pass)
+            .choose(rng)
+            .map(|(mv, _)| mv)
+            .or(pass)
+            .context("No available move to select")?;
         Ok(next_move)
     }
 
-    fn select_moves_batch(&self, games: Vec<&T>) -> anyhow::Result<Vec<usize>> {
-        games.iter().map(|game| self.select_move(*game)).collect()
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> anyhow::Result<Vec<usize>> {
+        games
+            .iter()
+            .map(|game| self.select_move(*game, rng))
+            .collect()
     }
 
+    // The rollouts are seeded by the position, so a position always gets the same score like it
+    // would from a network. Scored by win or loss like the value the search backs up, the margin
+    // of games that have one is not on the same scale
     fn predict_score(&self, game: &T) -> Result<f32> {
-        todo!()
+        ensure!(self.rollouts > 0, "Predicting scores needs rollouts");
+        let mut rng = StdRng::seed_from_u64(game.position_hash());
+        let mut total = 0.0;
+        for _ in 0..self.rollouts {
+            match simulate(game, self, Players::Player, None, &mut rng)? {
+                RolloutOutcome::Finished(result, _) => total += result.points(),
+                RolloutOutcome::CutOff(_) => unreachable!("Rollouts without a depth cap finish"),
+            }
+        }
+        Ok(total / self.rollouts as f32)
     }
 
     fn can_predict_score(&self) -> bool {
-        false
+        self.rollouts > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::othello::Othello;
+
+    #[test]
+    fn rollout_scores_are_wins_and_losses_for_margin_games() -> Result<()> {
+        let policy = RandomPolicy { rollouts: 1 };
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut game = Othello::new();
+        for _ in 0..20 {
+            // A single rollout scores a disc margin as 1, 0 or -1
+            let score = policy.predict_score(&game)?;
+            assert!([-1.0, 0.0, 1.0].contains(&score), "{}", score);
+            let mv = policy.select_move(&game, &mut rng)?;
+            game.try_perform_move(mv)?;
+        }
+        Ok(())
     }
 }
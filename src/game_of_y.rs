@@ -0,0 +1,207 @@
+use std::fmt::Display;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    connectivity::flood_fill,
+    game::{encode_board, swap_board, Game, Players, SimpleBoardState},
+    mcts::GameStats,
+};
+
+/// The game of Y: both players try to build a chain touching all three sides of a triangular
+/// board. The triangle is the upper left half of a Hex board with the same T and U, squares with
+/// x + y >= side length are never playable, so a Hex model can be loaded for Y of the same size.
+/// A full board always has exactly one winner
+#[derive(Clone)]
+pub struct GameOfY<const T: usize, const U: usize> {
+    board: [SimpleBoardState; T],
+    current_player: Players,
+    side_length: usize,
+    winning_player: Option<Players>,
+    // Squares played so far, for undo_move
+    history: Vec<usize>,
+}
+
+impl<const T: usize, const U: usize> GameOfY<T, U> {
+    fn coordinates(&self, index: usize) -> (usize, usize) {
+        (index % self.side_length, index / self.side_length)
+    }
+
+    fn on_board(&self, index: usize) -> bool {
+        let (x, y) = self.coordinates(index);
+        x + y < self.side_length
+    }
+
+    // Whether the group containing `index` touches the sides x = 0, y = 0 and x + y = side - 1
+    fn touches_all_sides(&self, index: usize) -> bool {
+        let piece = self.board[index];
+        let group = flood_fill(index, self.side_length, |square| {
+            self.board[square] == piece
+        });
+        let mut sides = [false; 3];
+        for square in group {
+            let (x, y) = self.coordinates(square);
+            sides[0] |= x == 0;
+            sides[1] |= y == 0;
+            sides[2] |= x + y == self.side_length - 1;
+        }
+        sides.iter().all(|side| *side)
+    }
+
+    // Where `index` ends up under one of the 6 symmetries of the triangle. With z = side - 1 - x - y
+    // the rotations cycle (x, y, z), odd symmetries also swap x and y
+    fn symmetric_square(index: usize, side_length: usize, symmetry: usize) -> usize {
+        let (x, y) = (index % side_length, index / side_length);
+        let z = side_length - 1 - x - y;
+        let (x, y) = match symmetry / 2 {
+            0 => (x, y),
+            1 => (y, z),
+            _ => (z, x),
+        };
+        let (x, y) = if symmetry % 2 == 1 { (y, x) } else { (x, y) };
+        x + y * side_length
+    }
+}
+
+impl<const T: usize, const U: usize> Game<T, U> for GameOfY<T, U> {
+    fn winning_player(&self) -> Option<Players> {
+        self.winning_player
+    }
+
+    fn available_moves(&self) -> [bool; T] {
+        if self.winning_player.is_some() {
+            return [false; T];
+        }
+        std::array::from_fn(|index| {
+            self.on_board(index) && self.board[index] == SimpleBoardState::Empty
+        })
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(
+            self.on_board(space),
+            "Tried to make move outside the triangle"
+        );
+        ensure!(
+            self.board[space] == SimpleBoardState::Empty,
+            "Tried to make move on occupied hex"
+        );
+        self.board[space] = self.current_player.into();
+        // Only the group of the new stone can have changed
+        if self.touches_all_sides(space) {
+            self.winning_player = Some(self.current_player);
+        }
+        self.history.push(space);
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    // The game ends on a win, so there was no winner before the last move
+    fn undo_move(&mut self) -> Result<()> {
+        let space = self.history.pop().context("No move to undo")?;
+        self.board[space] = SimpleBoardState::Empty;
+        self.winning_player = None;
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    fn new() -> Self {
+        let sqrt = (T as f64).sqrt() as usize;
+        assert!(
+            T * 2 == U,
+            "Bad dimensions on y generics, U has to equal T*2"
+        );
+        assert_eq!(sqrt * sqrt, T, "T must be a perfect square");
+        Self {
+            board: [SimpleBoardState::Empty; T],
+            current_player: Players::Player,
+            side_length: sqrt,
+            winning_player: None,
+            history: Vec::new(),
+        }
+    }
+
+    fn game_ended(&self) -> bool {
+        self.winning_player.is_some()
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    // Both players have the same goal, so only the colours change
+    fn flip_board(&mut self) {
+        swap_board(&mut self.board);
+        self.current_player = self.current_player.swap();
+        self.winning_player = self.winning_player.map(|player| player.swap());
+    }
+
+    fn get_game_state_slice(&self) -> [f32; U] {
+        encode_board(&self.board)
+    }
+
+    // The three rotations of the triangle and their mirror images
+    fn get_game_variations(stats: &GameStats<T, U>) -> Vec<GameStats<T, U>> {
+        let side_length = (T as f64).sqrt() as usize;
+        let on_board = |index: usize| index % side_length + index / side_length < side_length;
+        (0..6)
+            .map(|symmetry| {
+                let mut variation = stats.clone();
+                for square in (0..T).filter(|square| on_board(*square)) {
+                    let target = Self::symmetric_square(square, side_length, symmetry);
+                    variation.node_visits[target] = stats.node_visits[square];
+                    variation.game_state[target] = stats.game_state[square];
+                    variation.game_state[T + target] = stats.game_state[T + square];
+                }
+                variation.best_move_index =
+                    Self::symmetric_square(stats.best_move_index, side_length, symmetry);
+                variation
+            })
+            .collect()
+    }
+}
+
+impl<const T: usize, const U: usize> Display for GameOfY<T, U> {
+    // Row y is shifted y half hexes to the right, so hexes touch the ones diagonally below them
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for y in 0..self.side_length {
+            let row: Vec<_> = (0..self.side_length - y)
+                .map(|x| match self.board[x + y * self.side_length] {
+                    SimpleBoardState::Empty => ".",
+                    SimpleBoardState::Player => "X",
+                    SimpleBoardState::Opponent => "O",
+                })
+                .collect();
+            writeln!(f, "{}{}", " ".repeat(y), row.join(" "))?;
+        }
+        let next_player = match self.current_player {
+            Players::Player => "X",
+            Players::Opponent => "O",
+        };
+        writeln!(f, "Next player: {}", next_player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_chain_touching_all_three_sides_wins() -> Result<()> {
+        // Squares are x + 5 * y, X goes from the top side to the left one and then reaches for
+        // the long side, O holds the corner between the first two
+        let mut game = GameOfY::<25, 50>::new();
+        for square in [2, 0, 7, 1, 11, 5, 15, 10] {
+            game.try_perform_move(square)?;
+        }
+        assert_eq!(game.winning_player(), None);
+        assert!(!game.touches_all_sides(15));
+        assert!(game.try_perform_move(9).is_err());
+        game.try_perform_move(16)?;
+        assert_eq!(game.winning_player(), Some(Players::Player));
+        assert!(game.game_ended());
+        game.undo_move()?;
+        assert_eq!(game.winning_player(), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,502 @@
+use std::fmt::Display;
+
+use anyhow::{ensure, Context, Result};
+use tinyvec::ArrayVec;
+
+use crate::{
+    game::{
+        canonical_hash, encode_board, repetitions, simple_board_planes, swap_board, Game, Players,
+        SimpleBoardState, SpatialGame,
+    },
+    mcts::GameStats,
+    render::{AsciiRenderer, Board, BoardRenderer},
+};
+
+const KOMI: f32 = 7.5;
+// Most handicap stones, the number of star points
+const MAX_HANDICAP: usize = 9;
+
+/// Go on a square board with area scoring and the simple ko rule. N is the number of moves, one
+/// per point plus passing as the last move, so N - 1 has to be a perfect square. I = 3 * (N - 1),
+/// the stones and the komi
+#[derive(Debug, Clone)]
+pub struct Go<const N: usize, const I: usize> {
+    board: Vec<SimpleBoardState>,
+    side_length: usize,
+    current_player: Players,
+    // Point the current player may not play on because it would retake a ko
+    ko_point: Option<usize>,
+    passes: usize,
+    plies: usize,
+    // The second player gets the komi, flip_board swaps it along with the stones
+    komi_to: Players,
+    komi: f32,
+    // Move, captured stones, ko point and passes before it for every move played, for undo_move
+    history: Vec<(usize, Vec<usize>, Option<usize>, usize)>,
+    // canonical_hash after every move, to detect repetitions
+    positions: Vec<u64>,
+}
+
+pub type Go7 = Go<50, 147>;
+pub type Go9 = Go<82, 243>;
+
+impl<const N: usize, const I: usize> Go<N, I> {
+    pub const PASS: usize = N - 1;
+
+    fn neighbours(&self, point: usize) -> ArrayVec<[usize; 4]> {
+        let mut out = ArrayVec::default();
+        let (row, column) = (point / self.side_length, point % self.side_length);
+        if row > 0 {
+            out.push(point - self.side_length);
+        }
+        if row + 1 < self.side_length {
+            out.push(point + self.side_length);
+        }
+        if column > 0 {
+            out.push(point - 1);
+        }
+        if column + 1 < self.side_length {
+            out.push(point + 1);
+        }
+        out
+    }
+
+    // Stones connected to `point` and the number of distinct liberties of the group
+    fn group(&self, point: usize) -> (Vec<usize>, usize) {
+        let color = self.board[point];
+        let mut seen = vec![false; self.board.len()];
+        let mut liberty = vec![false; self.board.len()];
+        let mut stones = vec![point];
+        let mut stack = vec![point];
+        seen[point] = true;
+        while let Some(current) = stack.pop() {
+            for neighbour in self.neighbours(current) {
+                if self.board[neighbour] == SimpleBoardState::Empty {
+                    liberty[neighbour] = true;
+                } else if self.board[neighbour] == color && !seen[neighbour] {
+                    seen[neighbour] = true;
+                    stones.push(neighbour);
+                    stack.push(neighbour);
+                }
+            }
+        }
+        (stones, liberty.iter().filter(|x| **x).count())
+    }
+
+    fn is_legal(&self, point: usize) -> bool {
+        if self.board[point] != SimpleBoardState::Empty || self.ko_point == Some(point) {
+            return false;
+        }
+        let own: SimpleBoardState = self.current_player.into();
+        self.neighbours(point).into_iter().any(|neighbour| {
+            let state = self.board[neighbour];
+            if state == SimpleBoardState::Empty {
+                return true;
+            }
+            let (_, liberties) = self.group(neighbour);
+            // Capturing makes room, joining a group with another liberty is not suicide
+            (state == own) == (liberties > 1)
+        })
+    }
+
+    /// Stones plus empty points only reachable from that colour, for Player and Opponent
+    pub fn area_score(&self) -> (f32, f32) {
+        let mut score = [0.0, 0.0];
+        let mut seen = vec![false; self.board.len()];
+        for point in 0..self.board.len() {
+            match self.board[point] {
+                SimpleBoardState::Player => score[0] += 1.0,
+                SimpleBoardState::Opponent => score[1] += 1.0,
+                SimpleBoardState::Empty if !seen[point] => {
+                    let mut region = 0.0;
+                    let mut borders = (false, false);
+                    let mut stack = vec![point];
+                    seen[point] = true;
+                    while let Some(current) = stack.pop() {
+                        region += 1.0;
+                        for neighbour in self.neighbours(current) {
+                            match self.board[neighbour] {
+                                SimpleBoardState::Player => borders.0 = true,
+                                SimpleBoardState::Opponent => borders.1 = true,
+                                SimpleBoardState::Empty if !seen[neighbour] => {
+                                    seen[neighbour] = true;
+                                    stack.push(neighbour);
+                                }
+                                SimpleBoardState::Empty => {}
+                            }
+                        }
+                    }
+                    match borders {
+                        (true, false) => score[0] += region,
+                        (false, true) => score[1] += region,
+                        _ => {}
+                    }
+                }
+                SimpleBoardState::Empty => {}
+            }
+        }
+        match self.komi_to {
+            Players::Player => score[0] += self.komi,
+            Players::Opponent => score[1] += self.komi,
+        }
+        (score[0], score[1])
+    }
+
+    // The komi as it counts for `player`, negative when the other player gets it
+    fn komi_of(&self, player: Players) -> f32 {
+        match self.komi_to == player {
+            true => self.komi,
+            false => -self.komi,
+        }
+    }
+}
+
+impl<const N: usize, const I: usize> Go<N, I> {
+    /// Game where Player, the weaker side, starts with `stones` stones on the star points and
+    /// Opponent moves first and gets `komi`, usually 0.5 with handicap stones. Without stones
+    /// this is an even game with another komi
+    pub fn with_handicap(stones: usize, komi: f32) -> Result<Self> {
+        let mut game = Self::new();
+        game.komi = komi;
+        if stones == 0 {
+            return Ok(game);
+        }
+        ensure!(
+            (2..=MAX_HANDICAP).contains(&stones),
+            "Handicaps go from 2 to {} stones",
+            MAX_HANDICAP
+        );
+        let side = game.side_length;
+        ensure!(side >= 7, "No star points on boards smaller than 7x7");
+        let edge = if side >= 13 { 3 } else { 2 };
+        let (low, middle, high) = (edge, side / 2, side - 1 - edge);
+        let corners = [(low, high), (high, low), (high, high), (low, low)];
+        let sides = [(middle, low), (middle, high), (low, middle), (high, middle)];
+        let mut points: Vec<(usize, usize)> = corners.into_iter().take(stones).collect();
+        if stones > 4 {
+            points.extend(&sides[..(stones - 4) / 2 * 2]);
+        }
+        // An odd number of stones from 5 up puts one on the centre
+        if stones > 4 && stones % 2 == 1 {
+            points.push((middle, middle));
+        }
+        for (row, column) in points {
+            game.board[row * side + column] = SimpleBoardState::Player;
+        }
+        game.current_player = Players::Opponent;
+        Ok(game)
+    }
+}
+
+impl<const N: usize, const I: usize> Game<N, I> for Go<N, I> {
+    // The player with the larger area when the game has ended, None for a tie, which a whole
+    // komi allows, or a running game
+    fn winning_player(&self) -> Option<Players> {
+        if !self.game_ended() {
+            return None;
+        }
+        let (player, opponent) = self.area_score();
+        if player > opponent {
+            Some(Players::Player)
+        } else if player < opponent {
+            Some(Players::Opponent)
+        } else {
+            None
+        }
+    }
+
+    fn available_moves(&self) -> [bool; N] {
+        let mut moves = [false; N];
+        if self.game_ended() {
+            return moves;
+        }
+        for (point, available) in moves[..Self::PASS].iter_mut().enumerate() {
+            *available = self.is_legal(point);
+        }
+        moves[Self::PASS] = true;
+        moves
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(
+            space == Self::PASS || (space < Self::PASS && self.is_legal(space)),
+            "Tried to make an illegal go move"
+        );
+        self.plies += 1;
+        if space == Self::PASS {
+            self.history
+                .push((space, Vec::new(), self.ko_point, self.passes));
+            self.passes += 1;
+            self.ko_point = None;
+            self.current_player = self.current_player.swap();
+            self.positions.push(canonical_hash(self));
+            return Ok(());
+        }
+        let (ko_point, passes) = (self.ko_point, self.passes);
+        self.passes = 0;
+        self.board[space] = self.current_player.into();
+        let opponent: SimpleBoardState = self.current_player.swap().into();
+        let mut captured = Vec::new();
+        for neighbour in self.neighbours(space) {
+            if self.board[neighbour] == opponent {
+                let (stones, liberties) = self.group(neighbour);
+                if liberties == 0 {
+                    for stone in stones {
+                        self.board[stone] = SimpleBoardState::Empty;
+                        captured.push(stone);
+                    }
+                }
+            }
+        }
+        let (stones, liberties) = self.group(space);
+        self.ko_point = if captured.len() == 1 && stones.len() == 1 && liberties == 1 {
+            Some(captured[0])
+        } else {
+            None
+        };
+        self.history.push((space, captured, ko_point, passes));
+        self.current_player = self.current_player.swap();
+        self.positions.push(canonical_hash(self));
+        Ok(())
+    }
+
+    fn undo_move(&mut self) -> Result<()> {
+        let (space, captured, ko_point, passes) = self.history.pop().context("No move to undo")?;
+        self.positions.pop();
+        self.current_player = self.current_player.swap();
+        if space != Self::PASS {
+            self.board[space] = SimpleBoardState::Empty;
+            for stone in captured {
+                self.board[stone] = self.current_player.swap().into();
+            }
+        }
+        self.ko_point = ko_point;
+        self.passes = passes;
+        self.plies -= 1;
+        Ok(())
+    }
+
+    fn new() -> Self {
+        let points = N - 1;
+        let side_length = (points as f64).sqrt() as usize;
+        assert_eq!(
+            side_length * side_length,
+            points,
+            "N - 1 must be a perfect square"
+        );
+        assert!(
+            points * 3 == I,
+            "Bad dimensions on go generics, I has to equal (N-1)*3"
+        );
+        Self {
+            board: vec![SimpleBoardState::Empty; points],
+            side_length,
+            current_player: Players::Player,
+            ko_point: None,
+            passes: 0,
+            plies: 0,
+            komi_to: Players::Opponent,
+            komi: KOMI,
+            history: Vec::new(),
+            positions: Vec::new(),
+        }
+    }
+
+    // Two passes in a row, or a move limit so random rollouts cannot go on forever
+    fn game_ended(&self) -> bool {
+        self.passes >= 2 || self.plies >= 3 * self.board.len()
+    }
+
+    fn pass_move(&self) -> Option<usize> {
+        Some(Self::PASS)
+    }
+
+    fn has_score_margin(&self) -> bool {
+        true
+    }
+
+    // The simple ko rule only stops the shortest cycles, longer ones like triple ko are drawn
+    // when a position comes back for the third time
+    fn is_draw_by_rule(&self) -> bool {
+        repetitions(&self.positions) >= 3
+    }
+
+    // Score difference relative to the largest possible one, the whole board plus the komi
+    fn terminal_value(&self, perspective: Players) -> Option<f32> {
+        if self.is_draw_by_rule() {
+            return Some(0.0);
+        }
+        if !self.game_ended() {
+            return None;
+        }
+        let (player, opponent) = self.area_score();
+        let margin = (player - opponent) / (self.board.len() as f32 + self.komi.abs());
+        Some(match perspective {
+            Players::Player => margin,
+            Players::Opponent => -margin,
+        })
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    fn flip_board(&mut self) {
+        swap_board(&mut self.board);
+        self.current_player = self.current_player.swap();
+        self.komi_to = self.komi_to.swap();
+    }
+
+    // The stones and a plane of Player's komi relative to the points, so the value of a
+    // position can depend on who gets the komi
+    fn get_game_state_slice(&self) -> [f32; I] {
+        let mut state: [f32; I] = encode_board(&self.board);
+        let points = self.board.len();
+        state[points * 2..].fill(self.komi_of(Players::Player) / points as f32);
+        state
+    }
+
+    fn board(&self) -> Option<Board> {
+        Some(Board::read(self))
+    }
+
+    fn get_game_variations(stats: &GameStats<N, I>) -> Vec<GameStats<N, I>> {
+        vec![stats.clone()]
+    }
+}
+
+impl<const N: usize, const I: usize> Display for Go<N, I> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", AsciiRenderer.render(self))?;
+        let (player, opponent) = self.area_score();
+        writeln!(f, "X: {player} O: {opponent}")
+    }
+}
+
+// The komi plane of the state comes before the one of the player to move, for the player to move
+impl<const N: usize, const I: usize> SpatialGame<N, I> for Go<N, I> {
+    fn plane_shape(&self) -> (usize, usize, usize) {
+        (4, self.side_length, self.side_length)
+    }
+
+    fn spatial_planes(&self) -> Vec<f32> {
+        let points = self.board.len();
+        let komi = self.komi_of(self.current_player) / points as f32;
+        let mut planes = simple_board_planes(&self.board, self.current_player);
+        planes.splice(points * 2..points * 2, std::iter::repeat_n(komi, points));
+        planes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASS: usize = Go7::PASS;
+
+    fn play(moves: &[usize]) -> Result<Go7> {
+        let mut game = Go7::new();
+        for &point in moves {
+            game.try_perform_move(point)?;
+        }
+        Ok(game)
+    }
+
+    #[test]
+    fn surrounded_stones_are_captured() -> Result<()> {
+        // X takes the last liberty of the O stone in the middle
+        let mut game = play(&[17, 24, 23, 0, 25, 6])?;
+        assert_eq!(game.board[24], SimpleBoardState::Opponent);
+        game.try_perform_move(31)?;
+        assert_eq!(game.board[24], SimpleBoardState::Empty);
+        game.undo_move()?;
+        assert_eq!(game.board[24], SimpleBoardState::Opponent);
+
+        // And of a group of two
+        let mut game = play(&[17, 24, 18, 25, 23, 0, 26, 6, 31, 42])?;
+        game.try_perform_move(32)?;
+        assert_eq!(game.board[24], SimpleBoardState::Empty);
+        assert_eq!(game.board[25], SimpleBoardState::Empty);
+        assert_eq!(game.board[0], SimpleBoardState::Opponent);
+        Ok(())
+    }
+
+    #[test]
+    fn suicide_is_illegal() -> Result<()> {
+        let mut game = play(&[1, 48, 7])?;
+        assert!(!game.available_moves()[0]);
+        assert!(game.try_perform_move(0).is_err());
+        assert!(game.available_moves()[8]);
+        Ok(())
+    }
+
+    #[test]
+    fn ko_is_retaken_a_move_later() -> Result<()> {
+        // X captures the O stone on 16, O may not take back on 16 straight away
+        let mut game = play(&[9, 10, 15, 18, 23, 24, 0, 16, 17])?;
+        assert_eq!(game.board[16], SimpleBoardState::Empty);
+        assert!(!game.available_moves()[16]);
+        assert!(game.try_perform_move(16).is_err());
+        game.try_perform_move(48)?;
+        game.try_perform_move(47)?;
+        assert!(game.available_moves()[16]);
+        game.try_perform_move(16)?;
+        assert_eq!(game.board[17], SimpleBoardState::Empty);
+        Ok(())
+    }
+
+    #[test]
+    fn two_passes_end_the_game() -> Result<()> {
+        let mut game = play(&[PASS, 24, PASS])?;
+        assert!(!game.game_ended());
+        game.try_perform_move(PASS)?;
+        assert!(game.game_ended());
+        assert!(game.available_moves().iter().all(|available| !available));
+        game.undo_move()?;
+        assert!(!game.game_ended());
+        Ok(())
+    }
+
+    #[test]
+    fn area_and_komi_decide_the_winner() -> Result<()> {
+        // A lone stone owns the whole board, enough to beat the komi
+        let game = play(&[24, PASS, PASS])?;
+        assert_eq!(game.area_score(), (49.0, KOMI));
+        assert_eq!(game.winning_player(), Some(Players::Player));
+        assert_eq!(
+            game.terminal_value(Players::Player),
+            Some((49.0 - KOMI) / (49.0 + KOMI))
+        );
+
+        // Walls on the fourth and fifth columns split the board 28 to 21, the komi turns it
+        let walls: Vec<usize> = (0..7).flat_map(|row| [row * 7 + 3, row * 7 + 4]).collect();
+        let mut game = play(&walls)?;
+        game.try_perform_move(PASS)?;
+        game.try_perform_move(PASS)?;
+        assert_eq!(game.area_score(), (28.0, 21.0 + KOMI));
+        assert_eq!(game.winning_player(), Some(Players::Opponent));
+        Ok(())
+    }
+
+    #[test]
+    fn equal_areas_are_a_tie() -> Result<()> {
+        let mut game = Go7::with_handicap(0, 0.0)?;
+        game.try_perform_move(PASS)?;
+        game.try_perform_move(PASS)?;
+        assert_eq!(game.area_score(), (0.0, 0.0));
+        assert_eq!(game.winning_player(), None);
+        assert_eq!(game.terminal_value(Players::Player), Some(0.0));
+        Ok(())
+    }
+
+    #[test]
+    fn state_tells_who_gets_the_komi() {
+        let mut game = Go7::new();
+        let komi = KOMI / 49.0;
+        assert!(game.get_game_state_slice()[98..]
+            .iter()
+            .all(|x| *x == -komi));
+        game.flip_board();
+        assert!(game.get_game_state_slice()[98..].iter().all(|x| *x == komi));
+    }
+}
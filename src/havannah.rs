@@ -0,0 +1,336 @@
+use std::fmt::Display;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    connectivity::{flood_fill, hex_connections},
+    game::{encode_board, swap_board, Game, Players, SimpleBoardState},
+    mcts::GameStats,
+};
+
+/// Havannah on a hexagonal board. A player wins with a ring around at least one cell, a bridge
+/// between two corners or a fork touching three edges, corners do not belong to any edge. The
+/// hexagon is cut out of a skewed square like the Hex board, so T = (2 * side - 1)^2 and U = 2 * T.
+/// A full board without any of them is a draw
+#[derive(Clone)]
+pub struct Havannah<const T: usize, const U: usize> {
+    board: [SimpleBoardState; T],
+    current_player: Players,
+    // Width of the skewed square, 2 * side - 1
+    width: usize,
+    winning_player: Option<Players>,
+    game_ended: bool,
+    // Squares played so far, for undo_move
+    history: Vec<usize>,
+}
+
+pub type Havannah4 = Havannah<49, 98>;
+pub type Havannah8 = Havannah<225, 450>;
+
+impl<const T: usize, const U: usize> Havannah<T, U> {
+    // Cube coordinates relative to the centre, the board is every square with all three within
+    // side - 1 of it
+    fn cube(index: usize, width: usize) -> [isize; 3] {
+        let centre = (width / 2) as isize;
+        let x = (index % width) as isize - centre;
+        let y = (index / width) as isize - centre;
+        [x, y, -x - y]
+    }
+
+    fn on_board(&self, index: usize) -> bool {
+        let radius = (self.width / 2) as isize;
+        Self::cube(index, self.width)
+            .iter()
+            .all(|coordinate| coordinate.abs() <= radius)
+    }
+
+    // Whether the square is a corner, and otherwise the edge it is on. Corners have two coordinates
+    // at the rim and edge squares exactly one, edges are numbered by the axis and sign of it
+    fn rim(&self, index: usize) -> (bool, Option<usize>) {
+        let radius = (self.width / 2) as isize;
+        let cube = Self::cube(index, self.width);
+        let rims: Vec<usize> = (0..3)
+            .filter(|axis| cube[*axis].abs() == radius)
+            .map(|axis| axis * 2 + usize::from(cube[axis] > 0))
+            .collect();
+        match rims.len() {
+            1 => (false, Some(rims[0])),
+            2 => (true, None),
+            _ => (false, None),
+        }
+    }
+
+    fn is_border(&self, index: usize) -> bool {
+        hex_connections(index, self.width)
+            .into_iter()
+            .filter(|connection| self.on_board(*connection as usize))
+            .count()
+            < 6
+    }
+
+    fn bridge_or_fork(&self, index: usize) -> bool {
+        let piece = self.board[index];
+        let group = flood_fill(index, self.width, |square| self.board[square] == piece);
+        let mut corners = 0;
+        let mut edges = [false; 6];
+        for square in group {
+            match self.rim(square) {
+                (true, _) => corners += 1,
+                (false, Some(edge)) => edges[edge] = true,
+                (false, None) => {}
+            }
+        }
+        corners >= 2 || edges.iter().filter(|edge| **edge).count() >= 3
+    }
+
+    // A new ring goes through `index` and encloses one of its neighbours, which is enclosed when
+    // the squares not owned by the player around it never reach the border
+    fn ring(&self, index: usize) -> bool {
+        let piece = self.board[index];
+        hex_connections(index, self.width)
+            .into_iter()
+            .map(|connection| connection as usize)
+            .filter(|connection| self.on_board(*connection))
+            .any(|inside| {
+                let region = flood_fill(inside, self.width, |square| {
+                    self.on_board(square) && self.board[square] != piece
+                });
+                !region.iter().any(|square| self.is_border(*square))
+            })
+    }
+
+    // Where `index` ends up under one of the 12 symmetries of the hexagon, the 6 rotations and
+    // their mirror images
+    fn symmetric_square(index: usize, width: usize, symmetry: usize) -> usize {
+        let mut cube = Self::cube(index, width);
+        for _ in 0..symmetry / 2 {
+            cube = [-cube[2], -cube[0], -cube[1]];
+        }
+        if symmetry % 2 == 1 {
+            cube = [cube[1], cube[0], cube[2]];
+        }
+        let centre = (width / 2) as isize;
+        ((cube[0] + centre) + (cube[1] + centre) * width as isize) as usize
+    }
+}
+
+impl<const T: usize, const U: usize> Game<T, U> for Havannah<T, U> {
+    fn winning_player(&self) -> Option<Players> {
+        self.winning_player
+    }
+
+    fn available_moves(&self) -> [bool; T] {
+        if self.game_ended {
+            return [false; T];
+        }
+        std::array::from_fn(|index| {
+            self.on_board(index) && self.board[index] == SimpleBoardState::Empty
+        })
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(
+            self.on_board(space),
+            "Tried to make move outside the hexagon"
+        );
+        ensure!(
+            self.board[space] == SimpleBoardState::Empty,
+            "Tried to make move on occupied hex"
+        );
+        self.board[space] = self.current_player.into();
+        if self.bridge_or_fork(space) || self.ring(space) {
+            self.winning_player = Some(self.current_player);
+            self.game_ended = true;
+        } else {
+            self.game_ended = !self.available_moves().contains(&true);
+        }
+        self.history.push(space);
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    // No moves are allowed after the game ended, so the position before any move was still open
+    fn undo_move(&mut self) -> Result<()> {
+        let space = self.history.pop().context("No move to undo")?;
+        self.board[space] = SimpleBoardState::Empty;
+        self.winning_player = None;
+        self.game_ended = false;
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    fn new() -> Self {
+        let width = (T as f64).sqrt() as usize;
+        assert!(
+            T * 2 == U,
+            "Bad dimensions on havannah generics, U has to equal T*2"
+        );
+        assert_eq!(width * width, T, "T must be a perfect square");
+        assert!(width % 2 == 1, "T must be the square of an odd width");
+        Self {
+            board: [SimpleBoardState::Empty; T],
+            current_player: Players::Player,
+            width,
+            winning_player: None,
+            game_ended: false,
+            history: Vec::new(),
+        }
+    }
+
+    fn game_ended(&self) -> bool {
+        self.game_ended
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    // Both players have the same goals, so only the colours change
+    fn flip_board(&mut self) {
+        swap_board(&mut self.board);
+        self.current_player = self.current_player.swap();
+        self.winning_player = self.winning_player.map(|player| player.swap());
+    }
+
+    fn get_game_state_slice(&self) -> [f32; U] {
+        encode_board(&self.board)
+    }
+
+    fn get_game_variations(stats: &GameStats<T, U>) -> Vec<GameStats<T, U>> {
+        let width = (T as f64).sqrt() as usize;
+        let radius = (width / 2) as isize;
+        let on_board = |index: usize| {
+            Self::cube(index, width)
+                .iter()
+                .all(|coordinate| coordinate.abs() <= radius)
+        };
+        (0..12)
+            .map(|symmetry| {
+                let mut variation = stats.clone();
+                for square in (0..T).filter(|square| on_board(*square)) {
+                    let target = Self::symmetric_square(square, width, symmetry);
+                    variation.node_visits[target] = stats.node_visits[square];
+                    variation.game_state[target] = stats.game_state[square];
+                    variation.game_state[T + target] = stats.game_state[T + square];
+                }
+                variation.best_move_index =
+                    Self::symmetric_square(stats.best_move_index, width, symmetry);
+                variation
+            })
+            .collect()
+    }
+}
+
+impl<const T: usize, const U: usize> Display for Havannah<T, U> {
+    // Row y is shifted y half hexes to the right like in the game of Y, squares outside the
+    // hexagon are left blank
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for y in 0..self.width {
+            let row: Vec<_> = (0..self.width)
+                .map(|x| x + y * self.width)
+                .map(|index| match self.board[index] {
+                    _ if !self.on_board(index) => " ",
+                    SimpleBoardState::Empty => ".",
+                    SimpleBoardState::Player => "X",
+                    SimpleBoardState::Opponent => "O",
+                })
+                .collect();
+            writeln!(f, "{}{}", " ".repeat(y), row.join(" ").trim_end())?;
+        }
+        let next_player = match self.current_player {
+            Players::Player => "X",
+            Players::Opponent => "O",
+        };
+        writeln!(f, "Next player: {}", next_player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    // Player to move with only the given stones on the board. Squares are x + 7 * y with the
+    // centre at 24
+    fn position(player: &[usize], opponent: &[usize]) -> Havannah4 {
+        let mut game = Havannah4::new();
+        for square in player {
+            game.board[*square] = SimpleBoardState::Player;
+        }
+        for square in opponent {
+            game.board[*square] = SimpleBoardState::Opponent;
+        }
+        game
+    }
+
+    const AROUND_CENTRE: [usize; 6] = [23, 17, 18, 25, 31, 30];
+
+    #[test]
+    fn rings_win_around_any_cell() -> Result<()> {
+        // Around an empty cell
+        let mut game = position(&AROUND_CENTRE[1..], &[]);
+        game.try_perform_move(23)?;
+        assert_eq!(game.winning_player(), Some(Players::Player));
+
+        // Around an own stone
+        let mut game = position(&[&AROUND_CENTRE[1..], &[24]].concat(), &[]);
+        game.try_perform_move(23)?;
+        assert_eq!(game.winning_player(), Some(Players::Player));
+
+        // Not closed
+        let mut game = position(&AROUND_CENTRE[2..], &[17]);
+        game.try_perform_move(23)?;
+        assert!(!game.game_ended());
+        Ok(())
+    }
+
+    #[test]
+    fn bridges_join_corners_and_forks_three_edges() -> Result<()> {
+        assert_eq!(position(&[], &[]).rim(6), (true, None));
+        assert!(matches!(position(&[], &[]).rim(5), (false, Some(_))));
+
+        // Along the top edge from corner to corner
+        let mut game = position(&[3, 4, 5], &[]);
+        game.try_perform_move(6)?;
+        assert_eq!(game.winning_player(), Some(Players::Player));
+
+        // From the centre to the top, left and bottom right edges
+        let fork = [24, 18, 11, 4, 23, 22, 28, 25, 32];
+        let mut game = position(&fork, &[]);
+        assert!(!game.bridge_or_fork(24));
+        game.try_perform_move(39)?;
+        assert_eq!(game.winning_player(), Some(Players::Player));
+
+        // Two edges and a corner are neither, the corner is on no edge
+        let game = position(&[24, 18, 11, 4, 23, 22, 28, 25, 26, 27], &[]);
+        assert!(!game.bridge_or_fork(24));
+        assert!(!game.ring(24));
+        Ok(())
+    }
+
+    #[test]
+    fn symmetries_keep_cells_on_the_board() {
+        for width in [7, 15] {
+            let radius = (width / 2) as isize;
+            let on_board: Vec<usize> = (0..width * width)
+                .filter(|square| {
+                    Havannah4::cube(*square, width)
+                        .iter()
+                        .all(|coordinate| coordinate.abs() <= radius)
+                })
+                .collect();
+            let cells: HashSet<usize> = on_board.iter().copied().collect();
+            let mut maps = HashSet::new();
+            for symmetry in 0..12 {
+                let map: Vec<usize> = on_board
+                    .iter()
+                    .map(|square| Havannah4::symmetric_square(*square, width, symmetry))
+                    .collect();
+                assert_eq!(map.iter().copied().collect::<HashSet<_>>(), cells);
+                maps.insert(map);
+            }
+            assert_eq!(maps.len(), 12);
+        }
+    }
+}
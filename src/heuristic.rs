@@ -0,0 +1,103 @@
+//! Hand-written baseline players. They do not learn, so how a model does against them measures
+//! its progress on a fixed scale, and they are cheap enough to play out rollouts with
+
+use anyhow::{Context, Result};
+use rand::{rngs::StdRng, seq::SliceRandom};
+
+use crate::fit::softmax;
+use crate::game::{move_indices, Game, Players, Policy};
+
+// Temperature of the priors of a policy that always plays its best move, so a search with them
+// still looks at the other moves
+const PRIOR_TEMPERATURE: f32 = 0.1;
+
+/// Plays the move to the position Game::heuristic_value rates best for the player moving, one
+/// move ahead and taking immediate wins. Hex rates positions by their shortest connecting paths
+/// and tic-tac-toe by its open lines, which prefers the center. Games without a heuristic get
+/// random moves
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HeuristicPolicy {
+    /// Temperature of the move values select_move samples from, 0 always plays the best move
+    pub temperature: f32,
+}
+
+impl HeuristicPolicy {
+    // Value of each move for the player making it. Passes only when nothing else is available,
+    // like RandomPolicy
+    fn move_values<const N: usize, const I: usize, T: Game<N, I>>(
+        &self,
+        game: &T,
+    ) -> Result<Vec<(usize, f32)>> {
+        let mover = game.current_player();
+        let sign = match mover {
+            Players::Player => 1.0,
+            Players::Opponent => -1.0,
+        };
+        let pass = game.pass_move();
+        let mut moves = move_indices(game);
+        if moves.iter().any(|mv| Some(*mv) != pass) {
+            moves.retain(|mv| Some(*mv) != pass);
+        }
+        let mut game = game.clone();
+        let mut values = Vec::with_capacity(moves.len());
+        for mv in moves {
+            game.try_perform_move(mv)?;
+            let value = game
+                .terminal_value(mover)
+                .unwrap_or_else(|| sign * game.heuristic_value());
+            game.undo_move()?;
+            values.push((mv, value));
+        }
+        Ok(values)
+    }
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>> Policy<N, I, T> for HeuristicPolicy {
+    // Ties between equally good moves are broken randomly, so games against it vary
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> Result<usize> {
+        let mut values = self.move_values(game)?;
+        if self.temperature > 0.0 {
+            let priors = self.predict_priors(game)?;
+            return Ok(values.choose_weighted(rng, |(mv, _)| priors[*mv])?.0);
+        }
+        values.shuffle(rng);
+        let (best_move, _) = values
+            .into_iter()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .context("No available move to select")?;
+        Ok(best_move)
+    }
+
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> Result<Vec<usize>> {
+        games
+            .iter()
+            .map(|game| self.select_move(*game, rng))
+            .collect()
+    }
+
+    fn predict_score(&self, game: &T) -> Result<f32> {
+        Ok(game
+            .terminal_value(Players::Player)
+            .unwrap_or_else(|| game.heuristic_value()))
+    }
+
+    fn can_predict_score(&self) -> bool {
+        true
+    }
+
+    fn predict_priors(&self, game: &T) -> Result<[f32; N]> {
+        let mut logits = [f32::NEG_INFINITY; N];
+        for (mv, value) in self.move_values(game)? {
+            logits[mv] = value;
+        }
+        if logits.iter().all(|logit| *logit == f32::NEG_INFINITY) {
+            return Ok([0.0; N]);
+        }
+        let temperature = if self.temperature > 0.0 {
+            self.temperature
+        } else {
+            PRIOR_TEMPERATURE
+        };
+        Ok(softmax(logits, temperature))
+    }
+}
@@ -1,238 +1,287 @@
-use std::{default, fmt::Display};
+use std::{collections::VecDeque, default, fmt::Display};
 
+use anyhow::{ensure, Context, Result};
 use itertools::Itertools;
 use tinyvec::ArrayVec;
 
 use crate::{
-    game::{self, Game, Players, SimpleBoardState},
+    connectivity::rectangular_hex_connections,
+    dyn_game::{DynGame, NewDynGame, Padded, Variation},
+    game::{
+        self, encode_board, encode_board_into, simple_board_planes, swap_board, Game, Players,
+        SimpleBoardState, SpatialGame,
+    },
     mcts::GameStats,
+    render::Board,
 };
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct Hex<const T: usize, const U: usize> {
     // note that T is the total squares, not the width due to constraints in const generics
+    board: HexBoard,
+}
+
+// The rules of Hex on a board sized at runtime, which Hex and DynHex both play on
+#[derive(Clone)]
+struct HexBoard {
     // The board is hexagonal, which can be represented as a skewed square
     // Determining which parts are connected is not trivial
-    board: [SimpleBoardState; T],
+    board: Vec<SimpleBoardState>,
     current_player: Players,
-    side_length: usize,
+    // Player connects x = 0 and x = width - 1, Opponent y = 0 and y = height - 1. Both are the
+    // same unless the board was made with with_dimensions
+    width: usize,
+    height: usize,
     winning_player: Option<Players>,
-    game_ended: bool,
+    // Squares played so far, for undo_move
+    history: Vec<usize>,
+    // Union-find over the cells and a virtual node for each of the four edges, which a stone on
+    // an edge is joined with. A player has won once their two edges are in the same set
+    parent: Vec<usize>,
+    size: Vec<usize>,
+    // Roots attached to another root by each union, in order
+    unions: Vec<usize>,
+    // Length of unions before each move of history
+    move_unions: Vec<usize>,
 }
 
-impl<const T: usize, const U: usize> Hex<T, U> {
-    fn check_connection(&self, index: isize) -> Option<u8> {
-        if index >= 0 && index < T as isize {
-            Some(index as u8)
-        } else {
-            None
+/// Name of a cell like "c10", the column letter is the y coordinate and the row the x
+/// coordinate, so Player connects rows 1 and n
+pub fn cell_name(index: usize, width: usize) -> String {
+    let (x, y) = (index % width, index / width);
+    format!("{}{}", (b'a' + y as u8) as char, x + 1)
+}
+
+/// Index of a cell named like cell_name, Little Golem's two letter cells like "cj" work too. On
+/// rectangular boards the rows go up to the width and the columns up to the height
+pub fn parse_cell(cell: &str, width: usize, height: usize) -> Result<usize> {
+    let mut chars = cell.chars();
+    let column = chars.next().context("Empty cell")?;
+    let rest: String = chars.collect();
+    let row = if rest.len() == 1 && rest.chars().all(|c| c.is_ascii_lowercase()) {
+        rest.as_bytes()[0] as usize - b'a' as usize
+    } else {
+        rest.parse::<usize>()
+            .with_context(|| format!("Bad cell '{}'", cell))?
+            .checked_sub(1)
+            .with_context(|| format!("Bad cell '{}'", cell))?
+    };
+    ensure!(column.is_ascii_lowercase(), "Bad cell '{}'", cell);
+    let column = column as usize - 'a' as usize;
+    ensure!(
+        row < width && column < height,
+        "Cell '{}' is outside the board",
+        cell
+    );
+    Ok(row + column * width)
+}
+
+impl HexBoard {
+    fn new(width: usize, height: usize) -> Result<Self> {
+        ensure!(
+            width <= 26 && height <= 26,
+            "Sides longer than 26 have no column letters"
+        );
+        let cells = width * height;
+        Ok(Self {
+            board: vec![SimpleBoardState::Empty; cells],
+            current_player: Players::Player,
+            width,
+            height,
+            winning_player: None,
+            history: Vec::new(),
+            parent: (0..cells + 4).collect(),
+            size: vec![1; cells + 4],
+            unions: Vec::new(),
+            move_unions: Vec::new(),
+        })
+    }
+
+    fn get_connections(&self, index: usize) -> ArrayVec<[u16; 6]> {
+        rectangular_hex_connections(index, self.width, self.height)
+    }
+
+    fn find(&self, mut node: usize) -> usize {
+        while self.parent[node] != node {
+            node = self.parent[node];
         }
+        node
     }
 
-    fn get_connections(&self, index: usize) -> ArrayVec<[u8; 6]> {
-        let mut out = ArrayVec::<[u8; 6]>::default();
-        let coords = self.coordinates(index);
-        let index = index as isize;
-        let width = self.side_length as isize;
-        // false negative
-        let upper_left_wall = coords.0 == 0;
-        let lower_left_wall = coords.1 == self.side_length - 1;
-        let left_wall = upper_left_wall || lower_left_wall;
-        //false positive
-        let upper_right_wall = coords.1 == 0;
-        let lower_right_wall = coords.0 == self.side_length - 1;
-        let right_wall = upper_right_wall || lower_right_wall;
-
-        //upper left connection
-        if !upper_left_wall {
-            if let Some(connection) = self.check_connection(index - 1) {
-                out.push(connection);
+    // Union by size without path compression, which keeps finds logarithmic and lets the last
+    // union be taken back by undo_union
+    fn union(&mut self, a: usize, b: usize) {
+        let (mut a, mut b) = (self.find(a), self.find(b));
+        if a == b {
+            return;
+        }
+        if self.size[a] > self.size[b] {
+            std::mem::swap(&mut a, &mut b);
+        }
+        self.parent[a] = b;
+        self.size[b] += self.size[a];
+        self.unions.push(a);
+    }
+
+    fn undo_union(&mut self) {
+        if let Some(attached) = self.unions.pop() {
+            let root = self.parent[attached];
+            self.size[root] -= self.size[attached];
+            self.parent[attached] = attached;
+        }
+    }
+
+    // Joins the stone on `index` with the neighbouring stones of its colour and the edges of
+    // its owner it lies on
+    fn connect(&mut self, index: usize) {
+        let stone = self.board[index];
+        for connection in self.get_connections(index) {
+            if self.board[connection as usize] == stone {
+                self.union(index, connection as usize);
             }
+        }
+        let (x, y) = self.coordinates(index);
+        let (side, last) = match stone {
+            SimpleBoardState::Player => (x, self.width - 1),
+            SimpleBoardState::Opponent => (y, self.height - 1),
+            SimpleBoardState::Empty => return,
         };
-        //upper right connection
-        if !upper_right_wall {
-            if let Some(connection) = self.check_connection(index - width) {
-                out.push(connection);
+        let (start, end) = self.edges(stone.try_into().unwrap());
+        if side == 0 {
+            self.union(index, start);
+        }
+        if side == last {
+            self.union(index, end);
+        }
+    }
+
+    // Virtual nodes after the cells for the two edges of a player
+    fn edges(&self, player: Players) -> (usize, usize) {
+        let cells = self.board.len();
+        match player {
+            Players::Player => (cells, cells + 1),
+            Players::Opponent => (cells + 2, cells + 3),
+        }
+    }
+
+    fn update_winner(&mut self) {
+        self.winning_player = [Players::Player, Players::Opponent]
+            .into_iter()
+            .find(|player| {
+                let (start, end) = self.edges(*player);
+                self.find(start) == self.find(end)
+            });
+    }
+
+    // Sets up the union-find from scratch when the board changes other than by a move. Stones
+    // from the history are connected last and in order, so undo_move can still take them back
+    fn rebuild_connections(&mut self) {
+        let cells = self.board.len();
+        self.parent = (0..cells + 4).collect();
+        self.size = vec![1; cells + 4];
+        self.unions.clear();
+        let mut played = vec![false; cells];
+        for space in &self.history {
+            played[*space] = true;
+        }
+        for index in 0..cells {
+            if !played[index] && self.board[index] != SimpleBoardState::Empty {
+                self.connect(index);
             }
+        }
+        self.move_unions.clear();
+        for i in 0..self.history.len() {
+            self.move_unions.push(self.unions.len());
+            self.connect(self.history[i]);
+        }
+        self.update_winner();
+    }
+
+    // Fewest empty cells `player` still has to fill to connect their sides, the number of cells
+    // if the opponent has cut them off. A 0-1 breadth first search, own stones are free and
+    // opposing stones walls
+    fn connection_distance(&self, player: Players) -> usize {
+        let own: SimpleBoardState = player.into();
+        let cost = |index: usize| match self.board[index] {
+            SimpleBoardState::Empty => Some(1),
+            state if state == own => Some(0),
+            _ => None,
         };
-        //left connection
-        if !left_wall {
-            if let Some(connection) = self.check_connection(index + width - 1) {
-                out.push(connection);
-            }
+        // Player connects x = 0 to x = width - 1, Opponent y = 0 to y = height - 1
+        let side = |index: usize| match player {
+            Players::Player => index % self.width,
+            Players::Opponent => index / self.width,
         };
-        //lower left connection
-        if !lower_left_wall {
-            if let Some(connection) = self.check_connection(index + width) {
-                out.push(connection);
-            }
+        let last = match player {
+            Players::Player => self.width - 1,
+            Players::Opponent => self.height - 1,
         };
-        //lower right connection
-        if !lower_right_wall {
-            if let Some(connection) = self.check_connection(index + 1) {
-                out.push(connection);
+        let cells = self.board.len();
+        let mut distances = vec![usize::MAX; cells];
+        let mut queue = VecDeque::new();
+        for index in (0..cells).filter(|index| side(*index) == 0) {
+            if let Some(cost) = cost(index) {
+                distances[index] = cost;
+                queue.push_back(index);
             }
-        };
-        // right connection
-        if !right_wall {
-            if let Some(connection) = self.check_connection(index - width + 1) {
-                out.push(connection);
+        }
+        while let Some(index) = queue.pop_front() {
+            if side(index) == last {
+                return distances[index];
             }
-        };
-
-        out
-    }
-
-    fn check_winning_player(&mut self) {
-        // Find all placed pieces that touches player side, expand until touching opposing side or exhausted
-        //     _
-        //    /0\
-        //   /3 1\
-        //  /6 4 2\
-        //   \7 5/
-        //    \8/
-
-        // Connections:
-        //   \ /
-        //  - O -
-        //   / \
-        // values: i - index for connections, w - board width
-        //  Upper left: i - 1
-        // Upper right: i - w
-        //        left: i + w - 1
-        //  Lower left: i + w
-        // Lower right: i + 1
-        //       right: i - w + 1
-        /*
-                if self
-                    .board
-                    .iter()
-                    .filter(|square| **square != SimpleBoardState::Empty)
-                    .count()
-                    < self.side_length * 2 - 1
-                {
-                    return;
-                }
-        */
-        // Player upper left and lower right
-        let players = [Players::Player, Players::Opponent];
-        //println!();
-        //let mut queue: Vec<usize> = Vec::new();
-        let mut queue: ArrayVec<[u8; 64]> = ArrayVec::default();
-        for player in players {
-            //eprintln!("player = {:#?}", player);
-            let mut initial_squares: ArrayVec<[u8; 64]> = if player == Players::Player {
-                (0..self.side_length)
-                    .map(|index| index * self.side_length)
-                    .filter(|index| self.board[*index] == player.into())
-                    .map(|index| index as u8)
-                    .collect()
-            } else {
-                (0..self.side_length)
-                    .filter(|index| self.board[*index] == player.into())
-                    .map(|el| el as u8)
-                    .collect()
-            };
-            //eprintln!("initial_squares = {:#?}", initial_squares);
-            let target_squares: ArrayVec<[u8; 8]> = if player == Players::Player {
-                (0..self.side_length)
-                    .map(|index| (index * self.side_length + self.side_length - 1) as u8)
-                    .collect()
-            } else {
-                (T - self.side_length..T).map(|i| i as u8).collect()
-            };
-            queue.append(&mut initial_squares);
-            //eprintln!("target_squares = {:#?}", target_squares);
-
-            let mut i = 0;
-            while queue.len() > i {
-                //eprintln!("queue = {:#?}", queue);
-                let index = queue[i];
-                let connections = self.get_connections(index as usize);
-                let mut to_check: ArrayVec<_> = connections
-                    .iter()
-                    .copied()
-                    .filter(|i| !queue.contains(i) && self.board[*i as usize] == player.into())
-                    .collect();
-                //eprintln!("index = {:#?}", index);
-                //eprintln!("connections = {:#?}", connections);
-                //eprintln!("to_check = {:#?}", to_check);
-
-                for target_square in target_squares.iter().copied() {
-                    if to_check.contains(&target_square)
-                        && self.board[target_square as usize] == player.into()
-                    {
-                        self.winning_player = Some(player);
-                        self.game_ended = true;
-                        return;
+            for connection in self.get_connections(index) {
+                let connection = connection as usize;
+                let Some(cost) = cost(connection) else {
+                    continue;
+                };
+                if distances[index] + cost < distances[connection] {
+                    distances[connection] = distances[index] + cost;
+                    if cost == 0 {
+                        queue.push_front(connection);
+                    } else {
+                        queue.push_back(connection);
                     }
                 }
-                queue.append(&mut to_check);
-                i += 1;
             }
-            queue.clear();
         }
-
-        self.game_ended = false;
-        self.winning_player = None;
+        cells
     }
     fn coordinates(&self, index: usize) -> (usize, usize) {
-        let x = index % self.side_length;
-        let y = index / self.side_length;
+        let x = index % self.width;
+        let y = index / self.width;
         (x, y)
     }
-    fn index(&self, x: usize, y: usize) -> usize {
-        x + y * self.side_length
-    }
-}
-
-impl<const T: usize, const U: usize> Game<T, U> for Hex<T, U> {
-    fn winning_player(&self) -> Option<Players> {
-        self.winning_player
-    }
 
-    fn available_moves(&self) -> [bool; T] {
+    fn available_moves(&self) -> impl Iterator<Item = bool> + '_ {
         self.board
             .iter()
-            .cloned()
-            .map(|state| state == SimpleBoardState::Empty)
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap()
+            .map(|state| *state == SimpleBoardState::Empty)
     }
 
-    fn perform_move(&mut self, space: usize) {
-        assert!(
-            self.board[space] == SimpleBoardState::Empty,
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(
+            self.board.get(space) == Some(&SimpleBoardState::Empty),
             "Tried to make move on occupied hex"
         );
         self.board[space] = self.current_player.into();
+        self.history.push(space);
+        self.move_unions.push(self.unions.len());
+        self.connect(space);
+        self.update_winner();
         self.current_player = self.current_player.swap();
-        self.check_winning_player();
+        Ok(())
     }
 
-    fn new() -> Self {
-        let sqrt = (T as f64).sqrt() as usize;
-        assert!(
-            T * 2 == U,
-            "Bad dimensions on hex generics, U has to equal T*2"
-        );
-        assert_eq!(sqrt * sqrt, T, "T must be a perfect square");
-        Self {
-            board: [SimpleBoardState::Empty; T],
-            current_player: Players::Player,
-            side_length: sqrt,
-            winning_player: None,
-            game_ended: false,
+    fn undo_move(&mut self) -> Result<()> {
+        let space = self.history.pop().context("No move to undo")?;
+        let unions = self.move_unions.pop().unwrap();
+        while self.unions.len() > unions {
+            self.undo_union();
         }
-    }
-
-    fn game_ended(&self) -> bool {
-        self.game_ended
-    }
-
-    fn current_player(&self) -> Players {
-        self.current_player
+        self.board[space] = SimpleBoardState::Empty;
+        self.update_winner();
+        self.current_player = self.current_player.swap();
+        Ok(())
     }
 
     fn flip_board(&mut self) {
@@ -248,102 +297,459 @@ impl<const T: usize, const U: usize> Game<T, U> for Hex<T, U> {
         //  /2 4 6\
         //   \5 7/
         //    \8/
-        let width = self.side_length;
-        let mut out: [SimpleBoardState; T] = [SimpleBoardState::Empty; T];
+        // A rectangle turns into its transpose, height * width
+        let (width, height) = (self.width, self.height);
+        let mut out = vec![SimpleBoardState::Empty; self.board.len()];
         for i in 0..width {
             // in chunk index
-            for j in 0..width {
+            for j in 0..height {
                 //chunk index
-                out[i * width + j] = self.board[j * width + i];
+                out[i * height + j] = self.board[j * width + i];
             }
         }
-        out = out.map(|el| el.swap());
+        swap_board(&mut out);
         self.board = out;
+        for space in self.history.iter_mut() {
+            *space = (*space % width) * height + *space / width;
+        }
+        (self.width, self.height) = (height, width);
         self.current_player = self.current_player.swap();
+        self.rebuild_connections();
+    }
+
+    fn flipped_move(&self, mv: usize) -> usize {
+        (mv % self.width) * self.height + mv / self.width
+    }
+
+    // The stones of the winner's group that joins their two edges
+    fn ownership(&self, perspective: Players) -> Option<Vec<f32>> {
+        let winner = self.winning_player?;
+        let group = self.find(self.edges(winner).0);
+        let owner = if winner == perspective { 1.0 } else { -1.0 };
+        let stone = SimpleBoardState::from(winner);
+        Some(
+            (0..self.board.len())
+                .map(
+                    |index| match self.board[index] == stone && self.find(index) == group {
+                        true => owner,
+                        false => 0.0,
+                    },
+                )
+                .collect(),
+        )
+    }
+
+    // Difference of the connection distances, a cell closer to connecting than the opponent is
+    // worth 1 / longer side
+    fn heuristic_value(&self) -> f32 {
+        let player = self.connection_distance(Players::Player) as f32;
+        let opponent = self.connection_distance(Players::Opponent) as f32;
+        ((opponent - player) / self.width.max(self.height) as f32).clamp(-1.0, 1.0)
+    }
+
+    fn render(&self, color: bool) -> String {
+        let last_move = self.history.last().copied();
+        render_board(&self.board, self.width, self.height, last_move, color)
+    }
+}
+
+// Rotating the board half a turn keeps both players' sides. The state holds two values per
+// square, so the squares are reversed as pairs to keep the player and opponent order. Returns
+// where the rotation takes `best_move`
+fn rotate_half_turn(game_state: &mut [f32], node_visits: &mut [f32], best_move: usize) -> usize {
+    let cells = node_visits.len();
+    for plane in game_state.chunks_exact_mut(cells).take(2) {
+        plane.reverse();
+    }
+    node_visits.reverse();
+    cells - 1 - best_move
+}
+
+impl<const T: usize, const U: usize> Hex<T, U> {
+    /// Hex on a board of width * height cells, new gives the square board. The player who
+    /// connects the shorter distance can always win, see the rules of rectangular Hex. Games
+    /// replayed with from_moves or from_setup start from new, so they are always square
+    pub fn with_dimensions(width: usize, height: usize) -> Result<Self> {
+        ensure!(
+            width * height == T && T * 2 == U,
+            "A {}x{} board does not fit Hex<{}, {}>",
+            width,
+            height,
+            T,
+            U
+        );
+        Ok(Self {
+            board: HexBoard::new(width, height)?,
+        })
+    }
+
+    /// Handicap game for a weaker first player, who starts with stones on `stones` while Opponent
+    /// moves first. The stones are not part of the history, undo_move stops at them
+    pub fn with_handicap(width: usize, height: usize, stones: &[usize]) -> Result<Self> {
+        let mut game = Self::with_dimensions(width, height)?;
+        for stone in stones {
+            ensure!(*stone < T, "Handicap stone {} is off the board", stone);
+            ensure!(
+                game.board.board[*stone] == SimpleBoardState::Empty,
+                "Two handicap stones on {}",
+                cell_name(*stone, width)
+            );
+            game.board.board[*stone] = SimpleBoardState::Player;
+        }
+        if !stones.is_empty() {
+            game.board.current_player = Players::Opponent;
+        }
+        game.board.rebuild_connections();
+        ensure!(!game.game_ended(), "The handicap stones already connect");
+        Ok(game)
+    }
+}
+
+impl<const T: usize, const U: usize> Game<T, U> for Hex<T, U> {
+    fn winning_player(&self) -> Option<Players> {
+        self.board.winning_player
+    }
+
+    fn available_moves(&self) -> [bool; T] {
+        let mut available = [false; T];
+        for (square, empty) in available.iter_mut().zip(self.board.available_moves()) {
+            *square = empty;
+        }
+        available
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        self.board.try_perform_move(space)
+    }
+
+    fn undo_move(&mut self) -> Result<()> {
+        self.board.undo_move()
+    }
+
+    fn new() -> Self {
+        let sqrt = (T as f64).sqrt() as usize;
+        assert!(
+            T * 2 == U,
+            "Bad dimensions on hex generics, U has to equal T*2"
+        );
+        assert_eq!(sqrt * sqrt, T, "T must be a perfect square");
+        Self::with_dimensions(sqrt, sqrt).unwrap()
+    }
+
+    fn game_ended(&self) -> bool {
+        self.board.winning_player.is_some()
+    }
+
+    fn current_player(&self) -> Players {
+        self.board.current_player
+    }
+
+    fn flip_board(&mut self) {
+        self.board.flip_board()
+    }
+
+    fn flipped_move(&self, mv: usize) -> usize {
+        self.board.flipped_move(mv)
     }
 
     fn get_game_state_slice(&self) -> [f32; U] {
-        // This should never fail since U == T * 2
-        self.board
-            .iter()
-            .cloned()
-            .map(|space| space.simple_state())
-            .flatten()
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap()
+        encode_board(&self.board.board)
+    }
+
+    fn board(&self) -> Option<Board> {
+        Some(Board::read(self))
+    }
+
+    fn grid(&self) -> Option<(usize, usize)> {
+        Some((self.board.height, self.board.width))
+    }
+
+    fn move_to_string(&self, mv: usize) -> String {
+        cell_name(mv, self.board.width)
+    }
+
+    fn move_from_string(&self, text: &str) -> Result<usize> {
+        parse_cell(text.trim(), self.board.width, self.board.height)
+    }
+
+    // Rows of the skewed square, row y holds the squares x + y * width
+    fn to_position_string(&self) -> Result<String> {
+        Ok(game::board_to_string(
+            &self.board.board,
+            self.board.width,
+            self.board.current_player,
+        ))
+    }
+
+    // The number of rows gives the height, so rectangular positions can be read back too
+    fn from_position_string(position: &str) -> Result<Self> {
+        let height = position.split('/').count();
+        ensure!(T % height == 0, "{} rows do not fit {} cells", height, T);
+        let mut game = Self::with_dimensions(T / height, height)?;
+        let (board, current_player) = game::board_from_string(position, T / height, height)?;
+        game.board.board = board;
+        game.board.current_player = current_player;
+        game.board.rebuild_connections();
+        Ok(game)
+    }
+
+    fn ownership(&self, perspective: Players) -> Option<[f32; T]> {
+        self.board.ownership(perspective)?.try_into().ok()
     }
 
     fn get_game_variations(stats: &GameStats<T, U>) -> Vec<GameStats<T, U>> {
-        let mut game_state = stats.game_state.clone();
-        game_state.reverse();
-        let mut visits = stats.node_visits;
-        visits.reverse();
-
-        let reversed = GameStats {
-            best_move_index: T - stats.best_move_index - 1,
-            game_state,
-            node_visits: visits,
-            score: stats.score,
-        };
+        let mut reversed = stats.clone();
+        reversed.best_move_index = rotate_half_turn(
+            &mut reversed.game_state,
+            &mut reversed.node_visits,
+            stats.best_move_index,
+        );
         vec![stats.clone(), reversed]
     }
+
+    fn heuristic_value(&self) -> f32 {
+        self.board.heuristic_value()
+    }
 }
 
-impl<const T: usize, const U: usize> Display for Hex<T, U> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        //          (0,0)                sum 0
-        //       (0,1) (1,0)             sum 1
-        //    (0,2) (1,1) (2,0)          sum 2
-        // (0,3) (1,2) (2,1) (3,0)       sum 3
-        //    (1,3) (2,2) (3,1)          sum 4
-        //       (2,3) (3,2)             sum 5
-        //          (3,3)                sum 6
-        // The sum of x and y equals height, generate all number combinations that create the sum
-        // variables: h, side_length
-        // widest at h == side_length - 1 (h starts at 0)
-        // amount = side_length - abs(h + 1 - side_length)
-        // start = (0, h) if h < side_length else (h - side_length + 1, 3)
-        let height = self.side_length * 2 - 1;
-        let stride = self.side_length - 1;
-        for h in 0..height {
-            let start_index = if h < self.side_length {
-                h * self.side_length
-            } else {
-                self.side_length * self.side_length - self.side_length + h - (self.side_length - 1)
-            };
-            let middle_distance = (h as isize + 1 - self.side_length as isize).abs();
-            let amount: usize = (self.side_length as isize - middle_distance)
-                .try_into()
-                .unwrap();
-            let mut indices: Vec<usize> = Vec::with_capacity(amount);
-            for i in 0..amount {
-                indices.push(start_index - stride * i);
+// The skewed square as is, each cell touches the ones up, down, left, right, up right and down
+// left of it, which a 3x3 kernel covers
+impl<const T: usize, const U: usize> SpatialGame<T, U> for Hex<T, U> {
+    fn plane_shape(&self) -> (usize, usize, usize) {
+        (3, self.board.height, self.board.width)
+    }
+
+    fn spatial_planes(&self) -> Vec<f32> {
+        simple_board_planes(&self.board.board, self.board.current_player)
+    }
+
+    fn skewed(&self) -> bool {
+        true
+    }
+}
+
+// The stones on the padded grid from the side of the player to move, the cells on the board and
+// the player to move, which lines up with the planes of every side
+impl<const N: usize, const I: usize> SpatialGame<N, I> for Padded<N, I, DynHex> {
+    fn plane_shape(&self) -> (usize, usize, usize) {
+        (4, Self::SIDE, Self::SIDE)
+    }
+
+    fn spatial_planes(&self) -> Vec<f32> {
+        let cells = Self::SIDE * Self::SIDE;
+        let mut planes = self.get_game_state_slice()[..cells * 3].to_vec();
+        let to_move = match self.current_player() {
+            Players::Player => 1.0,
+            Players::Opponent => {
+                let (own, other) = planes.split_at_mut(cells);
+                own.swap_with_slice(&mut other[..cells]);
+                0.0
             }
-            let states: Vec<_> = indices.iter().map(|index| self.board[*index]).collect();
-            let chars: Vec<_> = states
-                .iter()
-                .map(|state| match state {
-                    SimpleBoardState::Empty => " ",
-                    SimpleBoardState::Player => "X",
-                    SimpleBoardState::Opponent => "O",
-                })
-                .collect();
-            let mut row_string = String::from(if h <= height / 2 { "/" } else { "\\" });
-            let padding = String::from(" ".repeat(middle_distance.try_into().unwrap()));
-            for i in 0..chars.len() {
-                row_string.push_str(chars[i]);
-                if i < chars.len() - 1 {
-                    row_string.push_str(" ");
-                } else {
-                    if h <= height / 2 {
-                        row_string.push_str("\\");
-                    } else {
-                        row_string.push_str("/");
-                    }
+        };
+        planes.extend(std::iter::repeat_n(to_move, cells));
+        planes
+    }
+
+    fn skewed(&self) -> bool {
+        true
+    }
+}
+
+// ANSI colours of the two players, Player is red and Opponent blue like in HexGui
+const PLAYER_COLOR: &str = "\x1b[31m";
+const OPPONENT_COLOR: &str = "\x1b[34m";
+const REVERSE: &str = "\x1b[7m";
+const RESET: &str = "\x1b[0m";
+
+// The board as a rhombus, a parallelogram on rectangular boards, with the column letters and row
+// numbers of cell_name on its edges
+fn render_board(
+    board: &[SimpleBoardState],
+    width: usize,
+    height: usize,
+    last_move: Option<usize>,
+    color: bool,
+) -> String {
+    let paint = |text: String, code: &str| {
+        if color {
+            format!("{code}{text}{RESET}")
+        } else {
+            text
+        }
+    };
+    let number_width = width.to_string().len();
+    let letters: Vec<String> = (0..height)
+        .map(|y| ((b'a' + y as u8) as char).to_string())
+        .collect();
+    let letters = paint(letters.join(" "), PLAYER_COLOR);
+    let mut out = format!("{}{}\n", " ".repeat(number_width + 1), letters);
+    for x in 0..width {
+        let number = format!("{:>number_width$}", x + 1);
+        let cells: Vec<String> = (0..height)
+            .map(|y| {
+                let index = x + y * width;
+                let (symbol, code) = match board[index] {
+                    SimpleBoardState::Empty => (".", ""),
+                    SimpleBoardState::Player => ("X", PLAYER_COLOR),
+                    SimpleBoardState::Opponent => ("O", OPPONENT_COLOR),
+                };
+                match (color, last_move == Some(index)) {
+                    (true, true) => paint(symbol.to_string(), &format!("{code}{REVERSE}")),
+                    (true, false) if !code.is_empty() => paint(symbol.to_string(), code),
+                    _ => symbol.to_string(),
                 }
-            }
-            writeln!(f, "{padding}{row_string}")?;
+            })
+            .collect();
+        out.push_str(&format!(
+            "{}{} {} {}\n",
+            " ".repeat(x),
+            paint(number.clone(), OPPONENT_COLOR),
+            cells.join(" "),
+            paint(number.trim_start().to_string(), OPPONENT_COLOR),
+        ));
+    }
+    out.push_str(&format!(
+        "{}{}\n",
+        " ".repeat(width + number_width + 1),
+        letters
+    ));
+    if let Some(last) = last_move {
+        out.push_str(&format!("Last move: {}\n", cell_name(last, width)));
+    }
+    out
+}
+
+impl<const T: usize, const U: usize> Hex<T, U> {
+    /// The board as a rhombus, a parallelogram on rectangular boards, with the column letters and
+    /// row numbers of cell_name on its edges. Player connects the lettered top and bottom edges,
+    /// Opponent the numbered ones. With `color` the edges and stones are coloured by player and
+    /// the last move is highlighted
+    pub fn render(&self, color: bool) -> String {
+        self.board.render(color)
+    }
+}
+
+// {:#} prints the board in colour
+impl<const T: usize, const U: usize> Display for Hex<T, U> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(f.alternate()))
+    }
+}
+
+/// Hex with the side length chosen at runtime, to be searched and trained on through
+/// dyn_game::Padded. Same rules, moves and state layout as a square Hex
+#[derive(Clone)]
+pub struct DynHex(HexBoard);
+
+impl DynHex {
+    pub fn new(side: usize) -> Result<Self> {
+        ensure!(
+            (1..=26).contains(&side),
+            "Hex sides go from 1 to 26, not {}",
+            side
+        );
+        Ok(Self(HexBoard::new(side, side)?))
+    }
+}
+
+impl DynGame for DynHex {
+    fn move_count(&self) -> usize {
+        self.0.board.len()
+    }
+
+    fn state_len(&self) -> usize {
+        self.0.board.len() * 2
+    }
+
+    fn winning_player(&self) -> Option<Players> {
+        self.0.winning_player
+    }
+
+    fn available_moves(&self) -> Vec<bool> {
+        self.0.available_moves().collect()
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        self.0.try_perform_move(space)
+    }
+
+    fn undo_move(&mut self) -> Result<()> {
+        self.0.undo_move()
+    }
+
+    fn game_ended(&self) -> bool {
+        self.0.winning_player.is_some()
+    }
+
+    fn current_player(&self) -> Players {
+        self.0.current_player
+    }
+
+    fn flip_board(&mut self) {
+        self.0.flip_board()
+    }
+
+    fn flipped_move(&self, mv: usize) -> usize {
+        self.0.flipped_move(mv)
+    }
+
+    fn get_game_state_slice(&self) -> Vec<f32> {
+        let mut state = vec![0.0; self.state_len()];
+        encode_board_into(&self.0.board, &mut state);
+        state
+    }
+
+    fn variations(&self, variation: &Variation) -> Vec<Variation> {
+        let mut reversed = variation.clone();
+        reversed.best_move = rotate_half_turn(
+            &mut reversed.state,
+            &mut reversed.visits,
+            variation.best_move,
+        );
+        vec![variation.clone(), reversed]
+    }
+
+    fn grid(&self) -> Option<(usize, usize)> {
+        Some((self.0.height, self.0.width))
+    }
+
+    fn heuristic_value(&self) -> f32 {
+        self.0.heuristic_value()
+    }
+
+    fn move_to_string(&self, mv: usize) -> String {
+        cell_name(mv, self.0.width)
+    }
+
+    fn move_from_string(&self, text: &str) -> Result<usize> {
+        parse_cell(text.trim(), self.0.width, self.0.height)
+    }
+}
+
+impl NewDynGame for DynHex {
+    fn fitting(height: usize, width: usize) -> Option<Self> {
+        DynHex::new(height.min(width).min(26)).ok()
+    }
+}
+
+// {:#} prints the board in colour
+impl Display for DynHex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.render(f.alternate()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::perft;
+
+    #[test]
+    fn perft_matches_known_counts() -> Result<()> {
+        const COUNTS: [u64; 9] = [9, 72, 504, 3024, 15120, 54720, 146880, 207360, 120960];
+        for (depth, expected) in (1..).zip(COUNTS) {
+            let count = perft(&mut Hex::<9, 18>::new(), depth)?;
+            assert_eq!(count, expected, "3x3 Hex perft({})", depth);
         }
         Ok(())
     }
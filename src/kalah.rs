@@ -0,0 +1,282 @@
+use std::fmt::Display;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    game::{Game, Players},
+    mcts::GameStats,
+};
+
+const PITS: usize = 6;
+const START_SEEDS: u8 = 4;
+const TOTAL_SEEDS: usize = 2 * PITS * START_SEEDS as usize;
+// Player's pits, Player's store, Opponent's pits and Opponent's store, in sowing order
+const HOLES: usize = 2 * PITS + 2;
+const PLAYER_STORE: usize = PITS;
+const OPPONENT_STORE: usize = HOLES - 1;
+pub const MOVES: usize = PITS;
+pub const STATE_LEN: usize = HOLES;
+
+/// Kalah with six pits a side and four seeds in each. Moves are the pits of the player to move
+/// counted from their left. Sowing into your own store earns another move, ending in an empty
+/// pit of your own captures the seeds across from it. When one side runs out of seeds the other
+/// side keeps what is left in its pits, and the bigger store wins
+#[derive(Debug, Clone)]
+pub struct Kalah {
+    holes: [u8; HOLES],
+    current_player: Players,
+    // Holes and player to move before every move, sowing cannot be undone from the move alone
+    history: Vec<([u8; HOLES], Players)>,
+}
+
+impl Kalah {
+    fn first_pit(player: Players) -> usize {
+        match player {
+            Players::Player => 0,
+            Players::Opponent => PITS + 1,
+        }
+    }
+
+    fn store(player: Players) -> usize {
+        match player {
+            Players::Player => PLAYER_STORE,
+            Players::Opponent => OPPONENT_STORE,
+        }
+    }
+
+    fn side_empty(&self, player: Players) -> bool {
+        let first = Self::first_pit(player);
+        self.holes[first..first + PITS]
+            .iter()
+            .all(|seeds| *seeds == 0)
+    }
+
+    /// Seeds in the stores of Player and Opponent
+    pub fn stores(&self) -> (u8, u8) {
+        (self.holes[PLAYER_STORE], self.holes[OPPONENT_STORE])
+    }
+}
+
+impl Game<MOVES, STATE_LEN> for Kalah {
+    fn winning_player(&self) -> Option<Players> {
+        if !self.game_ended() {
+            return None;
+        }
+        let (player, opponent) = self.stores();
+        match player.cmp(&opponent) {
+            std::cmp::Ordering::Greater => Some(Players::Player),
+            std::cmp::Ordering::Less => Some(Players::Opponent),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
+
+    fn available_moves(&self) -> [bool; MOVES] {
+        let first = Self::first_pit(self.current_player);
+        std::array::from_fn(|pit| self.holes[first + pit] > 0)
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(space < PITS, "Tried to sow from a pit that does not exist");
+        let player = self.current_player;
+        let mut hole = Self::first_pit(player) + space;
+        let mut seeds = self.holes[hole];
+        ensure!(seeds > 0, "Tried to sow from an empty pit");
+        self.history.push((self.holes, player));
+        self.holes[hole] = 0;
+        let skipped = Self::store(player.swap());
+        while seeds > 0 {
+            hole = (hole + 1) % HOLES;
+            if hole != skipped {
+                self.holes[hole] += 1;
+                seeds -= 1;
+            }
+        }
+
+        let first = Self::first_pit(player);
+        if (first..first + PITS).contains(&hole) && self.holes[hole] == 1 {
+            // Player's pit i faces Opponent's pit 5 - i
+            let across = 2 * PITS - hole;
+            if self.holes[across] > 0 {
+                self.holes[Self::store(player)] += self.holes[across] + 1;
+                self.holes[hole] = 0;
+                self.holes[across] = 0;
+            }
+        }
+
+        if self.side_empty(Players::Player) || self.side_empty(Players::Opponent) {
+            for owner in [Players::Player, Players::Opponent] {
+                let first = Self::first_pit(owner);
+                let left: u8 = self.holes[first..first + PITS].iter().sum();
+                self.holes[first..first + PITS].fill(0);
+                self.holes[Self::store(owner)] += left;
+            }
+        }
+        if hole != Self::store(player) {
+            self.current_player = player.swap();
+        }
+        Ok(())
+    }
+
+    fn undo_move(&mut self) -> Result<()> {
+        let (holes, player) = self.history.pop().context("No move to undo")?;
+        self.holes = holes;
+        self.current_player = player;
+        Ok(())
+    }
+
+    fn new() -> Self {
+        let mut holes = [START_SEEDS; HOLES];
+        holes[PLAYER_STORE] = 0;
+        holes[OPPONENT_STORE] = 0;
+        Self {
+            holes,
+            current_player: Players::Player,
+            history: Vec::new(),
+        }
+    }
+
+    // Leftover seeds are swept into the stores as soon as a side is empty
+    fn game_ended(&self) -> bool {
+        self.side_empty(Players::Player) && self.side_empty(Players::Opponent)
+    }
+
+    fn has_score_margin(&self) -> bool {
+        true
+    }
+
+    fn terminal_value(&self, perspective: Players) -> Option<f32> {
+        if !self.game_ended() {
+            return None;
+        }
+        let (player, opponent) = self.stores();
+        let margin = (player as f32 - opponent as f32) / TOTAL_SEEDS as f32;
+        Some(match perspective {
+            Players::Player => margin,
+            Players::Opponent => -margin,
+        })
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    // Moves count from the left of the player to move, so they stay the same when sides swap
+    fn flip_board(&mut self) {
+        self.holes.rotate_left(PITS + 1);
+        self.current_player = self.current_player.swap();
+    }
+
+    // Seeds in every hole as a fraction of all seeds
+    fn get_game_state_slice(&self) -> [f32; STATE_LEN] {
+        self.holes.map(|seeds| seeds as f32 / TOTAL_SEEDS as f32)
+    }
+
+    fn get_game_variations(
+        stats: &GameStats<MOVES, STATE_LEN>,
+    ) -> Vec<GameStats<MOVES, STATE_LEN>> {
+        vec![stats.clone()]
+    }
+
+    // Seeds in a store stay there, and more than half of them decides the game
+    fn heuristic_value(&self) -> f32 {
+        let (player, opponent) = self.stores();
+        ((player as f32 - opponent as f32) / (TOTAL_SEEDS / 2) as f32).clamp(-1.0, 1.0)
+    }
+}
+
+// Opponent's pits right to left on top, so each pit sits across from the one it faces
+impl Display for Kalah {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let row = |holes: &[u8]| {
+            holes
+                .iter()
+                .map(|seeds| format!("{:2}", seeds))
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let mut top = self.holes[PITS + 1..OPPONENT_STORE].to_vec();
+        top.reverse();
+        let (player, opponent) = self.stores();
+        writeln!(f, "   {}", row(&top))?;
+        writeln!(f, "{:2}{}{:2}", opponent, " ".repeat(3 * PITS + 1), player)?;
+        writeln!(f, "   {}", row(&self.holes[..PITS]))?;
+        let next_player = match self.current_player {
+            Players::Player => "X",
+            Players::Opponent => "O",
+        };
+        writeln!(f, "Next player: {}", next_player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(holes: [u8; HOLES]) -> Kalah {
+        Kalah {
+            holes,
+            current_player: Players::Player,
+            history: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn sowing_skips_the_opponents_store() -> Result<()> {
+        let mut game = position([1, 1, 1, 1, 1, 9, 0, 1, 1, 1, 1, 1, 1, 0]);
+        game.try_perform_move(5)?;
+        assert_eq!(game.holes, [2, 2, 1, 1, 1, 0, 1, 2, 2, 2, 2, 2, 2, 0]);
+        assert_eq!(game.current_player(), Players::Opponent);
+        Ok(())
+    }
+
+    #[test]
+    fn ending_in_the_own_store_moves_again() -> Result<()> {
+        let mut game = Kalah::new();
+        game.try_perform_move(2)?;
+        assert_eq!(game.stores(), (1, 0));
+        assert_eq!(game.current_player(), Players::Player);
+        game.try_perform_move(0)?;
+        assert_eq!(game.current_player(), Players::Opponent);
+        Ok(())
+    }
+
+    #[test]
+    fn ending_in_an_empty_pit_captures_across() -> Result<()> {
+        let mut game = position([1, 0, 1, 0, 0, 0, 0, 2, 2, 2, 2, 5, 2, 0]);
+        game.try_perform_move(2)?;
+        assert_eq!(game.holes, [1, 0, 0, 0, 0, 0, 3, 2, 2, 0, 2, 5, 2, 0]);
+
+        // Nothing across, nothing captured
+        let mut game = position([1, 0, 1, 0, 0, 0, 0, 2, 2, 0, 2, 5, 2, 0]);
+        game.try_perform_move(2)?;
+        assert_eq!(game.holes, [1, 0, 0, 1, 0, 0, 0, 2, 2, 0, 2, 5, 2, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn an_empty_side_sweeps_the_rest_into_the_stores() -> Result<()> {
+        let mut game = position([0, 0, 0, 0, 0, 1, 10, 1, 2, 4, 0, 0, 0, 5]);
+        game.try_perform_move(5)?;
+        assert!(game.game_ended());
+        assert_eq!(game.stores(), (11, 12));
+        assert_eq!(game.available_moves(), [false; MOVES]);
+        assert_eq!(game.winning_player(), Some(Players::Opponent));
+        Ok(())
+    }
+
+    #[test]
+    fn undo_restores_every_move() -> Result<()> {
+        let mut game = Kalah::new();
+        let mut seen = Vec::new();
+        for pit in [2, 5, 1, 3, 0] {
+            seen.push((game.holes, game.current_player()));
+            game.try_perform_move(pit)?;
+        }
+        while let Some((holes, player)) = seen.pop() {
+            game.undo_move()?;
+            assert_eq!((game.holes, game.current_player()), (holes, player));
+        }
+        assert!(game.undo_move().is_err());
+        Ok(())
+    }
+}
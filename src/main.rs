@@ -1,64 +1,580 @@
-use crate::mcts::mcts;
+use crate::mcts::{analyze, MctsConfig, MctsPolicy, SearchMode};
+use alpha_beta::AlphaBetaPolicy;
+use anyhow::Context;
+use balance::first_player_advantage;
+use cache::CachedPolicy;
 use candle_ai::SimpleModel;
 use checkers::Checkers;
-use dataset::{create_dataset, save_dataset};
-use game::{Game, Policy, RandomPolicy};
-use hex::Hex;
-use model::{AiPolicy, TrainableModel};
+use conv_model::{spatial_board, ConvModel};
+use dataset::{create_dataset, load_dataset, save_dataset, SelfPlayConfig};
+use distill::distill;
+use dyn_game::{Padded, PaddedPolicy};
+use game::{resolve_chance, Game, Policy, RandomPolicy};
+use heuristic::HeuristicPolicy;
+use hex::{DynHex, Hex};
+use model::{
+    compare_precision, summarize, AiPolicy, ModelConfig, TrainableModel, TrainingConfig, WarmStart,
+};
+use nim::Nim;
+use render::{SvgRenderer, UnicodeRenderer};
 
-use std::fmt::Display;
+use rand::{rngs::StdRng, SeedableRng};
+use replay::{ReplayBuffer, ReplayConfig};
+use resnet::{ResNetConfig, ResNetModel};
+use std::{
+    any::{type_name, TypeId},
+    fmt::Display,
+};
+#[cfg(feature = "tch")]
+use tch_model::TchModel;
+mod alpha_beta;
+mod amazons;
+mod balance;
+mod book;
+mod breakthrough;
+mod cache;
 mod candle_ai;
 mod checkers;
+mod checkpoint;
+mod conformance;
+mod connect_four;
+mod connectivity;
+mod conv_model;
 mod dataset;
+mod distill;
+mod draughts;
+mod dyn_game;
+mod ensemble;
+mod exploration;
+mod fit;
 mod game;
+mod game_of_y;
+mod go;
+mod havannah;
+mod heuristic;
 mod hex;
+mod kalah;
 mod mcts;
+mod mnk;
 mod model;
+mod nim;
+mod othello;
+mod qubic;
+mod render;
+mod replay;
+mod resnet;
+mod sgf;
+mod tablebase;
+mod tak;
+#[cfg(feature = "tch")]
+mod tch_model;
 
-fn play_games<const N: usize, const I: usize, T: Game<N, I> + Display, U: Policy<N, I, T>>(
+// Policy named on the command line: random, heuristic, alpha-beta searching to the end of the
+// game or alpha-beta:<depth>, mcts with random rollouts, gumbel or gumbel:<considered moves> for
+// the same rollouts under a Gumbel search, or a SimpleModel checkpoint path for MCTS guided by
+// the model. Hex also loads the models trained on PaddedHex, whatever side they played
+fn parse_policy<const N: usize, const I: usize, T: Game<N, I> + 'static>(
+    name: &str,
+) -> anyhow::Result<Box<dyn Policy<N, I, T>>> {
+    Ok(match name {
+        "random" => Box::new(RandomPolicy::default()),
+        "heuristic" => Box::new(HeuristicPolicy::default()),
+        "alpha-beta" => Box::new(AlphaBetaPolicy::exact()),
+        "mcts" => Box::new(MctsPolicy::new(
+            RandomPolicy::default(),
+            MctsConfig::default(),
+        )),
+        path if path.ends_with(".safetensors") => {
+            let padded_hex = TypeId::of::<T>() == TypeId::of::<Hex<N, I>>()
+                && checkpoint::saved_game(path)?.as_deref() == Some(type_name::<PaddedHex>());
+            match padded_hex {
+                true => {
+                    Box::new(MctsPolicy::new(
+                        PaddedPolicy::<PADDED_MOVES, PADDED_STATE, _>(AiPolicy::new(
+                            SimpleModel::<PADDED_MOVES, PADDED_STATE>::load_inference(path)?,
+                        )),
+                        MctsConfig::default(),
+                    ))
+                }
+                false => {
+                    checkpoint::check_game::<T>(path)?;
+                    Box::new(MctsPolicy::new(
+                        AiPolicy::new(SimpleModel::<N, I>::load_inference(path)?),
+                        MctsConfig::default(),
+                    ))
+                }
+            }
+        }
+        "gumbel" => gumbel_policy(16),
+        other => match other.split_once(':') {
+            Some(("alpha-beta", depth)) => Box::new(AlphaBetaPolicy {
+                depth: depth.parse()?,
+            }),
+            Some(("gumbel", considered_moves)) => gumbel_policy(considered_moves.parse()?),
+            _ => anyhow::bail!("Unknown policy '{}'", other),
+        },
+    })
+}
+
+// Random rollouts searched with Gumbel AlphaZero, considering the given number of root moves
+fn gumbel_policy<const N: usize, const I: usize, T: Game<N, I> + 'static>(
+    considered_moves: usize,
+) -> Box<dyn Policy<N, I, T>> {
+    let config = MctsConfig {
+        search_mode: SearchMode::Gumbel { considered_moves },
+        ..Default::default()
+    };
+    Box::new(MctsPolicy::new(RandomPolicy::default(), config))
+}
+
+fn play_games<const N: usize, const I: usize, T: Game<N, I> + Display + 'static>(
     num_games: usize,
-    policy: U,
+    policy: &str,
 ) -> anyhow::Result<()> {
+    let policy = parse_policy::<N, I, T>(policy)?;
+    let mut rng = StdRng::from_entropy();
     for _ in 0..num_games {
         let mut game = T::new();
         println!("{game}");
-        while !game.game_ended() {
-            let next_move = policy.select_move(&game)?;
-            game.perform_move(next_move);
+        loop {
+            resolve_chance(&mut game, &mut rng)?;
+            if game.game_ended() || game.is_draw_by_rule() {
+                break;
+            }
+            let next_move = policy.select_move(&game, &mut rng)?;
+            game.try_perform_move(next_move)?;
             println!("{game}");
         }
     }
     Ok(())
 }
 
+// Alternates training and self-play from `start`
 fn training_loop<
     const N: usize,
     const I: usize,
     T: Game<N, I> + Display,
     M: TrainableModel<N, I>,
 >(
-    generations: usize,
+    start: &T,
+    training: &TrainingConfig<M::Config>,
 ) -> anyhow::Result<()> {
-    let mut dataset = create_dataset::<N, I, T, RandomPolicy>(100, RandomPolicy {}, 0)?;
-    save_dataset(&dataset.clone().into(), String::from("initial_dataset"));
-    for generation in 0..generations {
-        let mut model: M = M::new()?;
-        model.train(dataset)?;
-        // TODO: save model
-        let policy = AiPolicy::<N, I, M> { model };
-        dataset = create_dataset::<N, I, T, AiPolicy<N, I, M>>(50, policy, generation)?;
+    let config = SelfPlayConfig::default();
+    let mut rng = config.rng();
+    let dataset = create_dataset(start, 100, &RandomPolicy::default(), 0, &config)?;
+    if let Some(resignations) = dataset.resignations {
+        println!("Initial self-play {}", resignations);
+    }
+    save_dataset(&dataset.clone().into(), "initial_dataset.bin.zst")?;
+    let mut replay = ReplayBuffer::new(training.replay)?;
+    replay.push(dataset);
+    let mut previous: Option<M> = None;
+    let mut student: Option<M> = training.student.as_ref().map(M::new).transpose()?;
+    for generation in 0..training.generations {
+        let mut model: M = match (previous.take(), training.warm_start) {
+            (Some(model), WarmStart::KeepOptimizer) => model,
+            (Some(mut model), WarmStart::ResetOptimizer) => {
+                model.reset_optimizer()?;
+                model
+            }
+            _ => M::new(&training.model)?,
+        };
+        if generation == 0 {
+            println!("Model {:?}: {}", training.model, summarize(&model, 100)?);
+            println!("{}", model.summary());
+        }
+        model.set_generation(generation);
+        let dataset = replay.training_data(&mut rng)?;
+        let data_hash = dataset.content_hash();
+        let student_states = student.is_some().then(|| dataset.clone());
+        let report = model.train(dataset)?;
+        println!("Generation {} trained, {}", generation, report);
+        report.write_csv(&format!("generation_{}_training.csv", generation))?;
+        let path = format!("generation_{}.safetensors", generation);
+        model.save(&path)?;
+        checkpoint::write_metadata(&path, &checkpoint::training_metadata::<T>(data_hash))?;
+        if let (Some(student), Some(states)) = (&mut student, student_states) {
+            student.set_generation(generation);
+            let report = distill(&model, student, &states, 1.0)?;
+            println!("Student of generation {} distilled, {}", generation, report);
+            student.save(&format!("generation_{}_student.safetensors", generation))?;
+        }
+        // Self-play gets a copy of the new weights on its own device, the model keeps training
+        let self_play_model = model.for_self_play()?;
+        if generation == 0 {
+            let report = compare_precision(&model, &self_play_model, &replay.all(), 100)?;
+            println!("Self-play copy against the trained model: {}", report);
+        }
+        let policy = CachedPolicy::new(AiPolicy::<N, I, M>::new(self_play_model), 100_000);
+        let dataset = create_dataset(start, 50, &policy, generation, &config)?;
+        if let Some(resignations) = dataset.resignations {
+            println!("Generation {} self-play {}", generation, resignations);
+        }
+        previous = Some(model);
         save_dataset(
             &dataset.clone().into(),
-            format!("generation_{}", generation),
+            &format!("generation_{}.bin.zst", generation),
+        )?;
+        replay.push(dataset);
+    }
+    replay.save("replay_buffer.bin.zst")
+}
+
+// Trains a model on datasets saved by self-play, continuing from the checkpoint at `resume` if
+// given, and saves it to `output`. Lets self-play and training run as separate jobs
+fn train_from_datasets<const N: usize, const I: usize, T: Game<N, I>, M: TrainableModel<N, I>>(
+    paths: &[&str],
+    config: &M::Config,
+    resume: Option<&str>,
+    output: &str,
+) -> anyhow::Result<()> {
+    let (first, rest) = paths.split_first().context("No datasets to train on")?;
+    let mut dataset = load_dataset::<N, I>(first)?;
+    for path in rest {
+        dataset.append(load_dataset(path)?);
+    }
+    println!(
+        "Loaded {} samples from {} datasets",
+        dataset.scores.len(),
+        paths.len()
+    );
+    let mut model = match resume {
+        Some(path) => {
+            checkpoint::check_game::<T>(path)?;
+            M::load(path)?
+        }
+        None => M::new(config)?,
+    };
+    let data_hash = dataset.content_hash();
+    let report = model.train(dataset)?;
+    println!("Trained, {}", report);
+    report.write_csv(&format!(
+        "{}_training.csv",
+        output.trim_end_matches(".safetensors")
+    ))?;
+    model.save(output)?;
+    checkpoint::write_metadata(output, &checkpoint::training_metadata::<T>(data_hash))
+}
+
+// Prints the best moves for a position given as in Game::from_setup, searched by MCTS with
+// `policy` evaluating the leaves
+fn analyze_position<const N: usize, const I: usize, T: Game<N, I> + Display + 'static>(
+    setup: &str,
+    policy: &str,
+) -> anyhow::Result<()> {
+    let policy = parse_policy::<N, I, T>(policy)?;
+    let game = T::from_setup(setup)?;
+    println!("{game}");
+    let mut rng = StdRng::from_entropy();
+    let analysis = analyze(&game, &policy, 0, &MctsConfig::default(), &mut rng)?;
+    println!(
+        "Best move {}, value {:.3}",
+        game.move_to_string(analysis.best_move),
+        analysis.value
+    );
+    for (mv, visits, value) in analysis.moves.iter().take(10) {
+        println!(
+            "{:>6} {:>8} visits {:>7.3}",
+            game.move_to_string(*mv),
+            visits,
+            value
         );
     }
     Ok(())
 }
 
+// Calls `$function::<N, I, T>` with the arguments for the game named on the command line. Sizes
+// are const generics, so every game a subcommand accepts is listed here
+macro_rules! with_game {
+    ($name:expr, $function:ident($($arg:expr),*)) => {
+        match $name {
+            "tictactoe" => $function::<9, 18, mnk::TicTacToe>($($arg),*),
+            "checkers" => $function::<9, 18, Checkers>($($arg),*),
+            "connect-four" => $function::<7, 84, connect_four::ConnectFour>($($arg),*),
+            "hex" => $function::<25, 50, Hex<25, 50>>($($arg),*),
+            "y" => $function::<49, 98, game_of_y::GameOfY<49, 98>>($($arg),*),
+            "havannah" => $function::<49, 98, havannah::Havannah4>($($arg),*),
+            "go" => $function::<50, 147, go::Go7>($($arg),*),
+            "othello" => $function::<65, { othello::STATE_LEN }, othello::Othello>($($arg),*),
+            "breakthrough" => {
+                $function::<{ breakthrough::MOVES }, 128, breakthrough::Breakthrough>($($arg),*)
+            }
+            "draughts" => {
+                $function::<{ draughts::MOVES }, { draughts::STATE_LEN }, draughts::Draughts>(
+                    $($arg),*
+                )
+            }
+            "amazons" => {
+                $function::<{ amazons::MOVES }, { amazons::STATE_LEN }, amazons::Amazons>(
+                    $($arg),*
+                )
+            }
+            "qubic" => $function::<{ qubic::SQUARES }, { qubic::STATE_LEN }, qubic::Qubic>($($arg),*),
+            "kalah" => $function::<{ kalah::MOVES }, { kalah::STATE_LEN }, kalah::Kalah>($($arg),*),
+            "tak" => $function::<{ tak::MOVES }, { tak::STATE_LEN }, tak::Tak>($($arg),*),
+            "nim" => $function::<{ nim::MOVES }, { nim::STATE_LEN }, Nim>($($arg),*),
+            other => anyhow::bail!("Unknown game '{}'", other),
+        }
+    };
+}
+
+fn report_balance<const N: usize, const I: usize, T: Game<N, I>>(
+    games: usize,
+    mcts: bool,
+) -> anyhow::Result<()> {
+    let mut rng = StdRng::from_entropy();
+    let report = if mcts {
+        let policy = MctsPolicy::new(RandomPolicy::default(), MctsConfig::default());
+        first_player_advantage::<N, I, T, _>(games, &policy, &mut rng)?
+    } else {
+        first_player_advantage::<N, I, T, _>(games, &RandomPolicy::default(), &mut rng)?
+    };
+    print!("{report}");
+    Ok(())
+}
+
+// `balance <game> [games] [mcts]`, first player statistics of random play, or of MCTS against
+// itself when the last argument is `mcts`
+fn balance_command(args: &[String]) -> anyhow::Result<()> {
+    let name = args
+        .first()
+        .context("Usage: balance <game> [games] [mcts]")?;
+    let games = match args.get(1) {
+        Some(arg) => arg.parse()?,
+        None => 1000,
+    };
+    let mcts = args.get(2).is_some_and(|arg| arg == "mcts");
+    with_game!(name.as_str(), report_balance(games, mcts))
+}
+
+// `play <game> [games] [policy]`, prints every position of games the policy plays against
+// itself, random by default
+fn play_command(args: &[String]) -> anyhow::Result<()> {
+    let name = args
+        .first()
+        .context("Usage: play <game> [games] [policy]")?;
+    let games = match args.get(1) {
+        Some(arg) => arg.parse()?,
+        None => 10,
+    };
+    let policy = args.get(2).map_or("random", String::as_str);
+    with_game!(name.as_str(), play_games(games, policy))
+}
+
+fn report_match<const N: usize, const I: usize, T: Game<N, I> + Display + 'static>(
+    policy: &str,
+    opponent: &str,
+    games: usize,
+) -> anyhow::Result<()> {
+    let policy = parse_policy::<N, I, T>(policy)?;
+    let opponent = parse_policy::<N, I, T>(opponent)?;
+    let mut rng = StdRng::from_entropy();
+    println!(
+        "{}",
+        balance::play_match::<N, I, T, _, _>(games, &policy, &opponent, &mut rng)?
+    );
+    Ok(())
+}
+
+// `match <game> <policy> <opponent> [games]`, results of the policy against the opponent
+fn match_command(args: &[String]) -> anyhow::Result<()> {
+    let [name, policy, opponent, ..] = args else {
+        anyhow::bail!("Usage: match <game> <policy> <opponent> [games]");
+    };
+    let games = match args.get(3) {
+        Some(arg) => arg.parse()?,
+        None => 100,
+    };
+    with_game!(name.as_str(), report_match(policy, opponent, games))
+}
+
+// `analyze <game> <setup> [policy]`, the best moves in a position given as in Game::from_setup
+fn analyze_command(args: &[String]) -> anyhow::Result<()> {
+    let [name, setup, ..] = args else {
+        anyhow::bail!("Usage: analyze <game> <setup> [policy]");
+    };
+    let policy = args.get(2).map_or("random", String::as_str);
+    with_game!(name.as_str(), analyze_position(setup, policy))
+}
+
+fn save_book<const N: usize, const I: usize, T: Game<N, I> + Display + 'static>(
+    output: &str,
+    games: usize,
+    plies: usize,
+) -> anyhow::Result<()> {
+    let policy = MctsPolicy::new(RandomPolicy::default(), MctsConfig::default());
+    let mut rng = StdRng::from_entropy();
+    book::OpeningBook::from_self_play::<N, I, T, _>(games, plies, &policy, &mut rng)?.save(output)
+}
+
+// `book <game> <output> [games] [plies]`, an opening book from MCTS self-play saved as JSON
+fn book_command(args: &[String]) -> anyhow::Result<()> {
+    let [name, output, ..] = args else {
+        anyhow::bail!("Usage: book <game> <output> [games] [plies]");
+    };
+    let games = match args.get(2) {
+        Some(arg) => arg.parse()?,
+        None => 100,
+    };
+    let plies = match args.get(3) {
+        Some(arg) => arg.parse()?,
+        None => 4,
+    };
+    with_game!(name.as_str(), save_book(output, games, plies))
+}
+
+fn train_simple_model<const N: usize, const I: usize, T: Game<N, I> + Display + 'static>(
+    paths: &[&str],
+    resume: Option<&str>,
+    output: &str,
+) -> anyhow::Result<()> {
+    train_from_datasets::<N, I, T, SimpleModel<N, I>>(
+        paths,
+        &ModelConfig::default(),
+        resume,
+        output,
+    )
+}
+
+// `train-datasets <game> <output> <dataset>... [--resume <checkpoint>]`, a SimpleModel trained
+// on saved self-play datasets
+fn train_datasets_command(args: &[String]) -> anyhow::Result<()> {
+    let usage = "Usage: train-datasets <game> <output> <dataset>... [--resume <checkpoint>]";
+    let (args, resume) = match args {
+        [rest @ .., flag, checkpoint] if flag == "--resume" => (rest, Some(checkpoint.as_str())),
+        _ => (args, None),
+    };
+    let [name, output, datasets @ ..] = args else {
+        anyhow::bail!(usage);
+    };
+    anyhow::ensure!(!datasets.is_empty(), usage);
+    let paths: Vec<&str> = datasets.iter().map(String::as_str).collect();
+    with_game!(name.as_str(), train_simple_model(&paths, resume, output))
+}
+
+fn render_position<const N: usize, const I: usize, T: Game<N, I>>(
+    setup: &str,
+    svg: bool,
+) -> anyhow::Result<()> {
+    let game = T::from_setup(setup)?;
+    let board = game.board().context("The game is not on a grid")?;
+    let output = match svg {
+        true => SvgRenderer::default().render_board(&board),
+        false => UnicodeRenderer { color: true }.render_board(&board),
+    };
+    print!("{output}");
+    Ok(())
+}
+
+// `render <game> <setup> [svg]`, draws a position given as in Game::from_setup in colour, or as
+// an SVG image when the last argument is `svg`. Only for the games on a grid
+fn render_command(args: &[String]) -> anyhow::Result<()> {
+    let [name, setup, ..] = args else {
+        anyhow::bail!("Usage: render <game> <setup> [svg]");
+    };
+    let svg = args.get(2).is_some_and(|arg| arg == "svg");
+    with_game!(name.as_str(), render_position(setup, svg))
+}
+
+// Largest Hex side the models train on, smaller boards are padded to it
+const MAX_SIDE: usize = 11;
+const PADDED_MOVES: usize = MAX_SIDE * MAX_SIDE;
+// The stones of both players and the cells on the board, see Padded
+const PADDED_STATE: usize = PADDED_MOVES * 3;
+type PaddedHex = Padded<PADDED_MOVES, PADDED_STATE, DynHex>;
+
 fn main() -> anyhow::Result<()> {
-    //play_games::<25, 50, Hex<25, 50>, RandomPolicy>(1000, RandomPolicy {})
-    //training_loop::<25, 50, Hex<25, 50>>(1)
-    const N: usize = 64;
-    const I: usize = N * 2;
-    training_loop::<N, I, Hex<N, I>, SimpleModel<N, I>>(10)
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("balance") => return balance_command(&args[1..]),
+        Some("play") => return play_command(&args[1..]),
+        Some("match") => return match_command(&args[1..]),
+        Some("analyze") => return analyze_command(&args[1..]),
+        Some("render") => return render_command(&args[1..]),
+        Some("book") => return book_command(&args[1..]),
+        Some("train-datasets") => return train_datasets_command(&args[1..]),
+        _ => {}
+    }
+    let side_length: usize = match args.first() {
+        Some(arg) => arg.parse()?,
+        None => 8,
+    };
+    // Two optional numbers after the model, hidden size and depth, or blocks and filters for resnet
+    let size = |i: usize| args.get(i).map(|arg| arg.parse::<usize>()).transpose();
+    let (first, second) = (size(2)?, size(3)?);
+    let mut model = ModelConfig::default();
+    model.hidden_size = first.unwrap_or(model.hidden_size);
+    model.depth = second.unwrap_or(model.depth);
+    let training = TrainingConfig {
+        generations: 10,
+        warm_start: WarmStart::KeepOptimizer,
+        model,
+        student: None,
+        replay: ReplayConfig::default(),
+    };
+    let start = PaddedHex::fit(DynHex::new(side_length)?)?;
+    match args.get(1).map(String::as_str) {
+        None | Some("simple") => {
+            training_loop::<_, _, _, SimpleModel<PADDED_MOVES, PADDED_STATE>>(&start, &training)
+        }
+        Some("conv") => {
+            let mut training = training;
+            training.model.board = Some(spatial_board(&start)?);
+            training_loop::<_, _, _, ConvModel<PADDED_MOVES, PADDED_STATE>>(&start, &training)
+        }
+        Some("resnet") => {
+            let mut model = ResNetConfig::default();
+            model.blocks = first.unwrap_or(model.blocks);
+            model.filters = second.unwrap_or(model.filters);
+            model.board = Some(spatial_board(&start)?);
+            let training = TrainingConfig {
+                generations: training.generations,
+                warm_start: training.warm_start,
+                model,
+                student: None,
+                replay: training.replay,
+            };
+            training_loop::<_, _, _, ResNetModel<PADDED_MOVES, PADDED_STATE>>(&start, &training)
+        }
+        #[cfg(feature = "tch")]
+        Some("tch") => {
+            training_loop::<_, _, _, TchModel<PADDED_MOVES, PADDED_STATE>>(&start, &training)
+        }
+        Some(other) => anyhow::bail!("Unknown model '{}', expected simple, conv or resnet", other),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use model::TrainableModel;
+
+    // The board is in the state of PaddedHex, so a model trained on one side plays the others
+    #[test]
+    fn padded_hex_models_load_for_every_side() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("padded_{}.safetensors", std::process::id()));
+        let path = path.to_str().unwrap();
+        let model = SimpleModel::<PADDED_MOVES, PADDED_STATE>::new(&Default::default())?;
+        model.save(path)?;
+        checkpoint::write_metadata(path, &checkpoint::training_metadata::<PaddedHex>(0))?;
+        let three = parse_policy::<9, 18, Hex<9, 18>>(path).and_then(|policy| {
+            policy
+                .predict_priors(&Hex::new())
+                .map(|priors| priors.to_vec())
+        });
+        let five = parse_policy::<25, 50, Hex<25, 50>>(path).and_then(|policy| {
+            policy
+                .predict_priors(&Hex::new())
+                .map(|priors| priors.to_vec())
+        });
+        std::fs::remove_file(path)?;
+        for priors in [three?, five?] {
+            assert!(
+                (priors.iter().sum::<f32>() - 1.0).abs() < 1e-4,
+                "{:?}",
+                priors
+            );
+        }
+        Ok(())
+    }
 }
@@ -1,171 +1,1599 @@
-use ego_tree::{iter::Children, NodeId, NodeMut, NodeRef, Tree};
+use std::{
+    collections::VecDeque,
+    fmt::Write,
+    fs,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Instant,
+};
+
 use itertools::Itertools;
 use ordered_float::NotNan;
-use rand::seq::SliceRandom;
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+use serde::Serialize;
+
+use anyhow::{ensure, Context};
 
-use crate::game::{move_indices, Game, GameResult, Players, Policy};
+use crate::{
+    cache::CacheStats,
+    game::{forced_pass, move_indices, resolve_chance, Game, GameResult, Players, Policy},
+};
 
-struct MCTSData<const N: usize, const I: usize, T: Game<N, I>> {
+const ROOT: usize = 0;
+
+struct MCTSNode<const N: usize, const I: usize, T: Game<N, I>> {
     game: T,
     visits: usize,
     score: f32,
     source_move: Option<usize>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    depth: usize,
+    expanded: bool,
+    // Moves that do not have a child node yet along with their priors, only used with lazy expansion
+    untried_moves: Vec<(usize, f32)>,
+    prior: f32,
+    // Set when the position waits for a chance event, the children are then the possible outcomes
+    chance: bool,
+    // Outcome and probability of the chance event that led here, if the parent is a chance node
+    chance_outcome: Option<usize>,
+    probability: f32,
 }
 
-impl<const N: usize, const I: usize, T: Game<N, I>> MCTSData<N, I, T> {
-    fn new(game: T) -> Self {
+impl<const N: usize, const I: usize, T: Game<N, I>> MCTSNode<N, I, T> {
+    fn new(
+        game: T,
+        source_move: Option<usize>,
+        parent: Option<usize>,
+        depth: usize,
+        prior: f32,
+    ) -> Self {
         Self {
             game,
             visits: 0,
             score: 0.,
-            source_move: None,
+            source_move,
+            parent,
+            children: Vec::new(),
+            depth,
+            expanded: false,
+            untried_moves: Vec::new(),
+            prior,
+            chance: false,
+            chance_outcome: None,
+            probability: 1.0,
         }
     }
 }
 
-fn expand<const N: usize, const I: usize, T: Game<N, I>>(
-    node: &mut NodeMut<'_, MCTSData<N, I, T>>,
-) {
-    let game = node.value().game.clone();
-    let moves = move_indices(&game);
-    for mv in moves {
-        let mut new_game = game.clone();
-        new_game.perform_move(mv);
-        let data = MCTSData::<N, I, T> {
-            game: new_game,
-            visits: 0,
-            score: 0.,
-            source_move: Some(mv),
+// Flat arena of nodes, children and parents are referred to by index into `nodes`
+struct MCTSTree<const N: usize, const I: usize, T: Game<N, I>> {
+    nodes: Vec<MCTSNode<N, I, T>>,
+    config: MctsConfig,
+    simulations: usize,
+    max_depth: usize,
+    // Sum of the depths of all selected leaves, for the average depth
+    depth_sum: usize,
+    // Times the tree hit MctsConfig::max_nodes and was pruned
+    prunes: usize,
+    // Nodes added by the current search, pruning drops nodes so the tree size cannot tell
+    created_nodes: usize,
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>> MCTSTree<N, I, T> {
+    fn new(root_game: T, config: &MctsConfig) -> Self {
+        Self {
+            nodes: vec![MCTSNode::new(root_game, None, None, 0, 1.0)],
+            config: config.clone(),
+            simulations: 0,
+            max_depth: 0,
+            depth_sum: 0,
+            prunes: 0,
+            created_nodes: 0,
+        }
+    }
+
+    fn expand(&mut self, node_id: usize, priors: Option<[f32; N]>) -> anyhow::Result<()> {
+        if let Some(outcomes) = self.nodes[node_id].game.chance_outcomes() {
+            self.nodes[node_id].expanded = true;
+            self.nodes[node_id].chance = true;
+            for (outcome, probability) in outcomes {
+                self.add_chance_child(node_id, outcome, probability)?;
+            }
+            return Ok(());
+        }
+        let mut moves = move_indices(&self.nodes[node_id].game);
+        if let (ROOT, Some(mask)) = (node_id, &self.config.root_move_mask) {
+            moves.retain(|mv| mask[*mv]);
+        }
+        let moves = moves
+            .into_iter()
+            .map(|mv| (mv, priors.map_or(0.0, |priors| priors[mv])));
+        self.nodes[node_id].expanded = true;
+        if self.config.lazy_expansion {
+            self.nodes[node_id].untried_moves = moves.collect();
+            return Ok(());
+        }
+        for (mv, prior) in moves {
+            self.add_child(node_id, mv, prior)?;
+        }
+        Ok(())
+    }
+
+    fn add_child(&mut self, node_id: usize, mv: usize, prior: f32) -> anyhow::Result<usize> {
+        let mut new_game = self.nodes[node_id].game.clone();
+        new_game.try_perform_move(mv)?;
+        let depth = self.nodes[node_id].depth + 1;
+        let child_id = self.nodes.len();
+        self.nodes.push(MCTSNode::new(
+            new_game,
+            Some(mv),
+            Some(node_id),
+            depth,
+            prior,
+        ));
+        self.nodes[node_id].children.push(child_id);
+        self.created_nodes += 1;
+        Ok(child_id)
+    }
+
+    fn add_chance_child(
+        &mut self,
+        node_id: usize,
+        outcome: usize,
+        probability: f32,
+    ) -> anyhow::Result<usize> {
+        let mut new_game = self.nodes[node_id].game.clone();
+        new_game.try_apply_chance_outcome(outcome)?;
+        let depth = self.nodes[node_id].depth + 1;
+        let child_id = self.nodes.len();
+        let mut child = MCTSNode::new(new_game, None, Some(node_id), depth, 1.0);
+        child.chance_outcome = Some(outcome);
+        child.probability = probability;
+        self.nodes.push(child);
+        self.nodes[node_id].children.push(child_id);
+        self.created_nodes += 1;
+        Ok(child_id)
+    }
+
+    // Mean score of a node. Chance nodes use the probability weighted mean of their visited
+    // outcomes, so the value is an expectation over the chance event rather than over how often
+    // each outcome happened to be sampled
+    fn mean_value(&self, node_id: usize) -> f32 {
+        let node = &self.nodes[node_id];
+        if node.chance {
+            let (weighted, mass) = node
+                .children
+                .iter()
+                .filter(|child| self.nodes[**child].visits > 0)
+                .fold((0.0, 0.0), |(weighted, mass), child| {
+                    let probability = self.nodes[*child].probability;
+                    (
+                        weighted + probability * self.mean_value(*child),
+                        mass + probability,
+                    )
+                });
+            if mass > 0.0 {
+                return weighted / mass;
+            }
+        }
+        node.score / node.visits.max(1) as f32
+    }
+
+    fn sample_chance_child(&self, node_id: usize, rng: &mut StdRng) -> usize {
+        *self.nodes[node_id]
+            .children
+            .choose_weighted(rng, |child| self.nodes[*child].probability)
+            .unwrap()
+    }
+
+    fn backprop(&mut self, node_id: usize, points: f32) {
+        let mut points = points;
+        let mut current = Some(node_id);
+        while let Some(id) = current {
+            let node = &mut self.nodes[id];
+            node.visits += 1;
+            node.score += points;
+            points *= self.config.discount;
+            current = node.parent;
+        }
+    }
+
+    // Values are scored for Player, this turns them into values for the player to move at a node
+    fn mover_sign(&self, node_id: usize) -> f32 {
+        match self.nodes[node_id].game.current_player() {
+            Players::Player => 1.0,
+            Players::Opponent => -1.0,
+        }
+    }
+
+    // Contempt of the player to move at the root in Player's frame, the simulations score a tie
+    // as minus this
+    fn root_contempt(&self) -> f32 {
+        let root_player = self.root().game.current_player();
+        self.mover_sign(ROOT) * self.config.contempt(root_player)
+    }
+
+    fn ucb(&self, node_id: usize) -> NotNan<f32> {
+        let node = &self.nodes[node_id];
+        // Soundness: only the root has no parent, and the root is never scored
+        let parent = node.parent.unwrap();
+        let sign = self.mover_sign(parent);
+        let exploitation_score = if node.visits > 0 {
+            sign * self.mean_value(node_id)
+        } else {
+            match self.config.q_init {
+                QInit::Infinite => return NotNan::new(f32::MAX).unwrap(),
+                QInit::Zero => 0.0,
+                QInit::Parent => sign * self.mean_value(parent),
+                QInit::Loss => -1.0,
+            }
+        };
+        let parent_visits = self.nodes[parent].visits;
+        let weight = self.config.exploration_weight;
+        NotNan::new(ucb_score(
+            exploitation_score,
+            node.visits,
+            parent_visits,
+            weight,
+        ))
+        .unwrap()
+    }
+
+    // KataGo style minimum number of visits for a root child, sqrt(k * prior * parent visits)
+    fn forced_playouts(&self, node_id: usize, k: f32) -> usize {
+        let node = &self.nodes[node_id];
+        let parent_visits = self.nodes[node.parent.unwrap()].visits;
+        (k * node.prior * parent_visits as f32).sqrt() as usize
+    }
+
+    // Selects the child with the highest ucb score, ties are broken according to the config
+    fn select_child(&self, node_id: usize, rng: &mut StdRng) -> usize {
+        if let (ROOT, Some(k)) = (node_id, self.config.forced_playouts) {
+            let forced = self.nodes[node_id]
+                .children
+                .iter()
+                .copied()
+                .find(|child| self.nodes[*child].visits < self.forced_playouts(*child, k));
+            if let Some(child) = forced {
+                return child;
+            }
+        }
+        let best = self.nodes[node_id]
+            .children
+            .iter()
+            .copied()
+            .max_set_by_key(|child| self.ucb(*child));
+        match self.config.tie_break {
+            TieBreak::Random => *best.choose(rng).unwrap(),
+            TieBreak::Prior => best
+                .into_iter()
+                .max_by(|a, b| self.nodes[*a].prior.total_cmp(&self.nodes[*b].prior))
+                .unwrap(),
+            TieBreak::Heuristic => best
+                .into_iter()
+                .max_by(|a, b| {
+                    let a = self.nodes[*a].game.heuristic_value();
+                    let b = self.nodes[*b].game.heuristic_value();
+                    a.total_cmp(&b)
+                })
+                .unwrap(),
+        }
+    }
+
+    // Picks which untried move gets a child next, all untried moves are tied
+    fn take_untried_move(&mut self, node_id: usize, rng: &mut StdRng) -> (usize, f32) {
+        let untried = &mut self.nodes[node_id].untried_moves;
+        let index = match self.config.tie_break {
+            TieBreak::Prior => untried
+                .iter()
+                .position_max_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap(),
+            _ => rng.gen_range(0..untried.len()),
         };
-        node.append(data);
+        untried.swap_remove(index)
     }
+
+    // Walks down the tree from `start` until an unexpanded node is found. With lazy expansion, the
+    // first untried move on the way gets a new child which is returned as the leaf
+    fn select_leaf(&mut self, start: usize, rng: &mut StdRng) -> anyhow::Result<usize> {
+        let mut node_id = start;
+        while self.nodes[node_id].expanded {
+            if !self.nodes[node_id].untried_moves.is_empty() {
+                let (mv, prior) = self.take_untried_move(node_id, rng);
+                return self.add_child(node_id, mv, prior);
+            }
+            if self.nodes[node_id].children.is_empty() {
+                break;
+            }
+            node_id = if self.nodes[node_id].chance {
+                self.sample_chance_child(node_id, rng)
+            } else {
+                self.select_child(node_id, rng)
+            };
+        }
+        Ok(node_id)
+    }
+
+    fn root(&self) -> &MCTSNode<N, I, T> {
+        &self.nodes[ROOT]
+    }
+
+    fn branching_factor(&self) -> f32 {
+        let (expanded, moves) = self
+            .nodes
+            .iter()
+            .filter(|node| node.expanded && !node.chance)
+            .map(|node| node.children.len() + node.untried_moves.len())
+            .filter(|moves| *moves > 0)
+            .fold((0, 0), |(expanded, total), moves| {
+                (expanded + 1, total + moves)
+            });
+        moves as f32 / expanded.max(1) as f32
+    }
+
+    fn root_visit_histogram(&self) -> Vec<usize> {
+        let mut histogram = Vec::new();
+        let visits = self
+            .root_children()
+            .map(|child| child.visits)
+            .chain(self.root().untried_moves.iter().map(|_| 0));
+        for visits in visits {
+            let bucket = (visits + 1).ilog2() as usize;
+            if histogram.len() <= bucket {
+                histogram.resize(bucket + 1, 0);
+            }
+            histogram[bucket] += 1;
+        }
+        histogram
+    }
+
+    // Removes root moves outside the mask from an already expanded root, their subtrees stay in
+    // the arena unreachable until the next rebuild
+    fn restrict_root(&mut self, mask: &[bool]) {
+        let allowed: Vec<usize> = self
+            .root()
+            .children
+            .iter()
+            .copied()
+            .filter(|child| mask[self.nodes[*child].source_move.unwrap()])
+            .collect();
+        let root = &mut self.nodes[ROOT];
+        root.children = allowed;
+        root.untried_moves.retain(|(mv, _)| mask[*mv]);
+    }
+
+    fn root_children(&self) -> impl Iterator<Item = &MCTSNode<N, I, T>> {
+        self.root().children.iter().map(|child| &self.nodes[*child])
+    }
+
+    // Root visit counts with forced playouts removed from every child but the best one. Visits
+    // are only removed while the child's ucb stays below the best child's, so the pruned target
+    // looks like a search that never forced anything
+    fn pruned_root_visits(&self, k: f32) -> [f32; N] {
+        let mut visit_stats = [0.0_f32; N];
+        let root_visits = self.root().visits;
+        let Some(best) = self
+            .root()
+            .children
+            .iter()
+            .copied()
+            .max_by_key(|child| self.nodes[*child].visits)
+        else {
+            return visit_stats;
+        };
+        let best_ucb = self.ucb(best).into_inner();
+        for child_id in self.root().children.iter().copied() {
+            let child = &self.nodes[child_id];
+            let mut visits = child.visits;
+            if child_id != best && visits > 0 {
+                let mean = self.mover_sign(ROOT) * self.mean_value(child_id);
+                let forced = self.forced_playouts(child_id, k);
+                while visits > 0
+                    && child.visits - visits < forced
+                    && ucb_score(
+                        mean,
+                        visits - 1,
+                        root_visits,
+                        self.config.exploration_weight,
+                    ) < best_ucb
+                {
+                    visits -= 1;
+                }
+                if visits <= 1 {
+                    visits = 0;
+                }
+            }
+            visit_stats[child.source_move.unwrap()] = visits as f32;
+        }
+        visit_stats
+    }
+
+    fn to_dump_node(&self, node_id: usize, max_depth: usize) -> DumpNode {
+        let node = &self.nodes[node_id];
+        let children = if node.depth < max_depth {
+            node.children
+                .iter()
+                .filter(|child| self.nodes[**child].visits > 0)
+                .map(|child| self.to_dump_node(*child, max_depth))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        DumpNode {
+            move_index: node.source_move,
+            visits: node.visits,
+            value: self.mean_value(node_id),
+            children,
+        }
+    }
+
+    fn to_dot(&self, max_depth: usize) -> String {
+        let mut dot = String::from("digraph mcts {\n    node [shape=box];\n");
+        let mut stack = vec![ROOT];
+        while let Some(node_id) = stack.pop() {
+            let node = &self.nodes[node_id];
+            let label = match (node.source_move, node.chance_outcome) {
+                (Some(mv), _) => format!("move {mv}"),
+                (None, Some(outcome)) => format!("chance {outcome} p={:.2}", node.probability),
+                (None, None) => String::from("root"),
+            };
+            let value = self.mean_value(node_id);
+            writeln!(
+                dot,
+                "    n{node_id} [label=\"{label}\\nN={}\\nQ={value:.3}\"];",
+                node.visits
+            )
+            .unwrap();
+            if let Some(parent) = node.parent {
+                writeln!(dot, "    n{parent} -> n{node_id};").unwrap();
+            }
+            if node.depth < max_depth {
+                stack.extend(
+                    node.children
+                        .iter()
+                        .filter(|child| self.nodes[**child].visits > 0),
+                );
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn dump(&self, dump: &TreeDump) -> anyhow::Result<()> {
+        let contents = match dump.format {
+            DumpFormat::Dot => self.to_dot(dump.max_depth),
+            DumpFormat::Json => {
+                serde_json::to_string_pretty(&self.to_dump_node(ROOT, dump.max_depth))?
+            }
+        };
+        fs::write(&dump.path, contents)?;
+        Ok(())
+    }
+
+    // Makes `new_root` the root, dropping every node outside its subtree
+    fn reroot(&mut self, new_root: usize) {
+        self.rebuild(new_root, |_| true);
+        let root = &mut self.nodes[ROOT];
+        root.source_move = None;
+        root.chance_outcome = None;
+        root.probability = 1.0;
+    }
+
+    // Copies the subtree of `new_root` into a fresh arena, skipping nodes that `keep` rejects.
+    // Moves of dropped children go back to the untried moves of their parent, so they can be
+    // searched again. Chance nodes cannot be missing outcomes, they lose all children instead
+    // and are expanded again on their next visit
+    fn rebuild(&mut self, new_root: usize, keep: impl Fn(&MCTSNode<N, I, T>) -> bool) {
+        let mut old_nodes: Vec<Option<MCTSNode<N, I, T>>> = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .map(Some)
+            .collect();
+        let base_depth = old_nodes[new_root].as_ref().unwrap().depth;
+        let mut queue = VecDeque::from([(new_root, None)]);
+        // Breadth first, so children keep their order
+        while let Some((old_id, parent)) = queue.pop_front() {
+            let mut node = old_nodes[old_id].take().unwrap();
+            let new_id = self.nodes.len();
+            let children = std::mem::take(&mut node.children);
+            let (kept, dropped): (Vec<usize>, Vec<usize>) = children
+                .into_iter()
+                .partition(|child| keep(old_nodes[*child].as_ref().unwrap()));
+            if node.chance && !dropped.is_empty() {
+                node.expanded = false;
+            } else {
+                node.untried_moves.extend(dropped.iter().map(|child| {
+                    let child = old_nodes[*child].as_ref().unwrap();
+                    (child.source_move.unwrap(), child.prior)
+                }));
+                queue.extend(kept.into_iter().map(|child| (child, Some(new_id))));
+            }
+            node.parent = parent;
+            node.depth -= base_depth;
+            if let Some(parent) = parent {
+                self.nodes[parent].children.push(new_id);
+            }
+            self.nodes.push(node);
+        }
+    }
+
+    // Drops the least visited subtrees until at most `target` nodes below the root children
+    // remain. The root and its children are always kept, callers hold on to their indices
+    fn prune(&mut self, target: usize) {
+        let mut visits: Vec<usize> = self
+            .nodes
+            .iter()
+            .filter(|node| node.depth > 1)
+            .map(|node| node.visits)
+            .collect();
+        if visits.len() <= target {
+            return;
+        }
+        visits.sort_unstable_by(|a, b| b.cmp(a));
+        // A node never has more visits than its parent, so keeping everything above a visit
+        // threshold keeps the tree connected
+        let threshold = visits[target] + 1;
+        self.rebuild(ROOT, |node| node.depth <= 1 || node.visits >= threshold);
+        self.prunes += 1;
+    }
+
+    // True when the root visits are concentrated enough to stop, or when the budget is used up
+    // and the best move is clearly ahead of the runner up
+    fn adaptive_stop(&self, adaptive: &AdaptiveSimulations, budget_used: bool) -> bool {
+        // Untried moves count as unvisited children, like in best_move_decided
+        let mut visits: Vec<f32> = self
+            .root_children()
+            .map(|child| child.visits as f32)
+            .chain(self.root().untried_moves.iter().map(|_| 0.0))
+            .collect();
+        if visits.len() < 2 {
+            return true;
+        }
+        let total: f32 = visits.iter().sum();
+        if total == 0.0 {
+            return false;
+        }
+        let entropy: f32 = visits
+            .iter()
+            .filter(|visits| **visits > 0.0)
+            .map(|visits| -(visits / total) * (visits / total).ln())
+            .sum();
+        if entropy / (visits.len() as f32).ln() < adaptive.entropy_threshold {
+            return true;
+        }
+        visits.sort_unstable_by(|a, b| b.total_cmp(a));
+        budget_used && visits[1] < visits[0] * adaptive.close_ratio
+    }
+
+    // True when the most visited root child can no longer be overtaken within the remaining budget
+    fn best_move_decided(&self, remaining_simulations: usize) -> bool {
+        // Untried moves count as unvisited children
+        let mut visits: Vec<usize> = self
+            .root_children()
+            .map(|child| child.visits)
+            .chain(self.root().untried_moves.iter().map(|_| 0))
+            .collect();
+        if visits.len() < 2 {
+            return !visits.is_empty();
+        }
+        visits.sort_unstable_by(|a, b| b.cmp(a));
+        visits[0] - visits[1] > remaining_simulations
+    }
+}
+
+fn ucb_score(mean: f32, visits: usize, parent_visits: usize, weight: f32) -> f32 {
+    let exploration_score =
+        f32::sqrt(f32::sqrt(parent_visits as f32) / (visits as f32 + 1.0)) * weight;
+    mean + exploration_score
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchMode {
+    /// Every simulation selects children by ucb from the root
+    Ucb,
+    /// Gumbel AlphaZero: sample `considered_moves` root moves with Gumbel-Top-k and split the
+    /// budget between them with sequential halving, ucb is still used below the root
+    Gumbel { considered_moves: usize },
 }
 
-fn backprop<const N: usize, const I: usize, T: Game<N, I>>(
-    node: &mut NodeMut<'_, MCTSData<N, I, T>>,
-    points: f32,
-) {
-    const DECAY: f32 = 0.9;
-    node.value().visits += 1;
-    node.value().score += points;
-    if node.parent().is_some() {
-        backprop(&mut node.parent().unwrap(), points * DECAY);
+#[derive(Serialize)]
+struct DumpNode {
+    move_index: Option<usize>,
+    visits: usize,
+    value: f32,
+    children: Vec<DumpNode>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// Graphviz, render with `dot -Tsvg tree.dot -o tree.svg`
+    Dot,
+    Json,
+}
+
+/// Writes the top of the search tree to a file after every search, for debugging
+#[derive(Clone, Debug)]
+pub struct TreeDump {
+    pub path: String,
+    /// Number of plies below the root to include, unvisited children are always left out
+    pub max_depth: usize,
+    pub format: DumpFormat,
+}
+
+/// How the move to play is picked from the root children once the search is done
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MoveSelection {
+    MostVisits,
+    HighestMeanValue,
+    /// Secure child, highest mean - weight / sqrt(visits). Avoids moves that only look good
+    /// because they were barely searched
+    LowerConfidenceBound {
+        weight: f32,
+    },
+}
+
+/// Stops the search early when the root visits are concentrated on one move, and keeps it going
+/// past `MctsConfig::simulations` while the top moves are close
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveSimulations {
+    pub min_simulations: usize,
+    pub max_simulations: usize,
+    /// Stop once the entropy of the root visits, normalized to 0..1, drops below this
+    pub entropy_threshold: f32,
+    /// Keep searching past the budget while the second most visited move has at least this
+    /// fraction of the visits of the most visited one
+    pub close_ratio: f32,
+    /// Simulations between checks of the root
+    pub check_interval: usize,
+}
+
+impl Default for AdaptiveSimulations {
+    fn default() -> Self {
+        Self {
+            min_simulations: 100,
+            max_simulations: 2000,
+            entropy_threshold: 0.2,
+            close_ratio: 0.9,
+            check_interval: 50,
+        }
     }
 }
 
-fn ucb<const N: usize, const I: usize, T: Game<N, I>>(
-    node: NodeRef<'_, MCTSData<N, I, T>>,
-) -> NotNan<f32> {
-    if node.value().visits == 0 {
-        return NotNan::new(f32::MAX).unwrap();
+/// AlphaGo style leaf evaluation, (1 - lambda) * predicted score + lambda * rollout result.
+/// Lambda moves linearly from `initial_lambda` to `final_lambda` over the first `generations`
+/// generations, so early generations can lean on rollouts and later ones on the network
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueMixing {
+    pub initial_lambda: f32,
+    pub final_lambda: f32,
+    pub generations: usize,
+}
+
+impl ValueMixing {
+    pub fn lambda(&self, generation: usize) -> f32 {
+        let progress = (generation as f32 / self.generations.max(1) as f32).min(1.0);
+        (self.initial_lambda + (self.final_lambda - self.initial_lambda) * progress).clamp(0.0, 1.0)
     }
-    const EXPLORATION_WEIGHT: f32 = 10.;
-    let exploration_score = f32::sqrt(
-        f32::sqrt(node.parent().unwrap().value().visits as f32)
-            / (node.value().visits as f32 + 1.0),
-    ) * EXPLORATION_WEIGHT;
-    let exploitation_score = node.value().score / node.value().visits as f32;
-    return NotNan::new(exploitation_score + exploration_score).unwrap();
 }
 
-// Selects the child with the highest ucb score, random tie break
-fn select_child<const N: usize, const I: usize, T: Game<N, I>>(
-    children: Children<MCTSData<N, I, T>>,
-) -> NodeId {
-    children
-        .into_iter()
-        .map(|children| (children.id(), children))
-        .max_set_by_key(|(_, x)| ucb(*x))
-        .choose(&mut rand::thread_rng())
-        .unwrap()
-        .0
+impl Default for ValueMixing {
+    fn default() -> Self {
+        Self {
+            initial_lambda: 0.8,
+            final_lambda: 0.2,
+            generations: 10,
+        }
+    }
 }
 
-fn select_leaf<const N: usize, const I: usize, T: Game<N, I>>(
-    tree: &Tree<MCTSData<N, I, T>>,
-    node_id: NodeId,
-) -> NodeId {
-    let mut node = tree.get(node_id).unwrap();
-    while node.has_children() {
-        let next_node_id = select_child(node.children());
-        node = tree.get(next_node_id).unwrap()
+/// Value an unvisited child is scored with in ucb
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QInit {
+    /// Every child is visited once before any child is visited twice
+    Infinite,
+    Zero,
+    /// The current mean value of the parent
+    Parent,
+    /// Only explore children once the visited ones look worse than losing
+    Loss,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TieBreak {
+    Random,
+    /// Prefer the child with the highest policy prior
+    Prior,
+    /// Prefer the child with the highest Game::heuristic_value
+    Heuristic,
+}
+
+#[derive(Clone, Debug)]
+pub struct MctsConfig {
+    pub simulations: usize,
+    /// Scale of the exploration term of the ucb score. Values are in [-1, 1], so the default of
+    /// 10 keeps the visits close to uniform, smaller weights let the search settle on the best
+    /// line with enough simulations
+    pub exploration_weight: f32,
+    /// Stop searching once the most visited move cannot be overtaken by the remaining simulations.
+    /// Only sound for MoveSelection::MostVisits in the Ucb search mode, ignored otherwise
+    pub early_termination: bool,
+    /// Contempt for ties when searching as Player, a tie is scored as -contempt.
+    /// Positive values steer away from draws, negative values steer towards them
+    pub player_contempt: f32,
+    /// Contempt for ties when searching as Opponent
+    pub opponent_contempt: f32,
+    /// Rollouts longer than this are cut off and the position is scored by
+    /// Policy::predict_score, or Game::heuristic_value if the policy cannot predict scores
+    pub max_rollout_depth: Option<usize>,
+    /// Create child nodes one at a time when they are first selected instead of all at once,
+    /// saves memory and game clones on large boards
+    pub lazy_expansion: bool,
+    /// How to choose between children with equal ucb scores, matters most for the first
+    /// simulations where every unvisited child is tied
+    pub tie_break: TieBreak,
+    /// Value of unvisited children. Infinite over-explores with small budgets, since every move
+    /// gets a simulation before any line is searched deeper. Lazy expansion always tries new
+    /// moves first, so it behaves like Infinite regardless
+    pub q_init: QInit,
+    /// KataGo style forced playouts with the given k (KataGo uses 2). Each root child gets at
+    /// least sqrt(k * prior * visits) visits, which are pruned again from the visit target
+    pub forced_playouts: Option<f32>,
+    pub search_mode: SearchMode,
+    /// Blend rollouts and predicted scores at every leaf. Without it a leaf gets either one or
+    /// the other, with rollouts skipped more often in later generations. Ignored for policies that
+    /// cannot predict scores
+    pub value_mixing: Option<ValueMixing>,
+    /// Factor applied to the result for every ply it is propagated up the tree. Values below 1.0
+    /// make wins that are further away count for less, biasing the search towards quick wins and
+    /// slow losses but also shrinking the root score. 1.0 is standard MCTS, the old hard-coded
+    /// value was 0.9. Changes the value targets, so models trained with different discounts are
+    /// not comparable
+    pub discount: f32,
+    /// How much of the value of a finished game comes from Game::terminal_value instead of the
+    /// plain win, loss or tie. 0.0 only counts who won, higher values also reward winning by a
+    /// larger margin in games that grade their outcomes. Like discount it changes the value targets
+    pub margin_weight: f32,
+    /// Self-play should keep MostVisits, evaluation matches can use a more robust selection
+    pub move_selection: MoveSelection,
+    pub tree_dump: Option<TreeDump>,
+    /// Only used by the Ucb search mode
+    pub adaptive_simulations: Option<AdaptiveSimulations>,
+    /// Upper bound on the tree size. When the next expansion could exceed it, the least visited
+    /// subtrees are dropped until about half of it is left. Expanding a chance node can overshoot
+    /// by its number of outcomes beyond N
+    pub max_nodes: Option<usize>,
+    /// Only search the root moves that are true here, e.g. to compare a few candidate moves or
+    /// to stick to an opening book. Indexed by move, deeper nodes are not restricted
+    pub root_move_mask: Option<Vec<bool>>,
+    /// Simulations between SearchObserver reports
+    pub observer_interval: usize,
+    /// Score leaves with Policy::exact_score where the policy has one, e.g. positions in the
+    /// tablebase of a TablebasePolicy, instead of a rollout or a predicted score
+    pub probe_exact_scores: bool,
+}
+
+impl MctsConfig {
+    fn needs_priors(&self) -> bool {
+        self.tie_break == TieBreak::Prior
+            || self.forced_playouts.is_some()
+            || matches!(self.search_mode, SearchMode::Gumbel { .. })
+    }
+
+    pub fn contempt(&self, player: Players) -> f32 {
+        match player {
+            Players::Player => self.player_contempt,
+            Players::Opponent => self.opponent_contempt,
+        }
     }
-    node.id()
 }
 
-fn skip_rollout(generation: usize) -> bool {
+impl Default for MctsConfig {
+    fn default() -> Self {
+        Self {
+            simulations: 1000,
+            exploration_weight: 10.0,
+            early_termination: false,
+            player_contempt: 0.0,
+            opponent_contempt: 0.0,
+            max_rollout_depth: None,
+            lazy_expansion: false,
+            tie_break: TieBreak::Random,
+            q_init: QInit::Infinite,
+            forced_playouts: None,
+            search_mode: SearchMode::Ucb,
+            value_mixing: None,
+            discount: 1.0,
+            margin_weight: 0.0,
+            move_selection: MoveSelection::MostVisits,
+            tree_dump: None,
+            adaptive_simulations: None,
+            max_nodes: None,
+            root_move_mask: None,
+            observer_interval: 100,
+            probe_exact_scores: false,
+        }
+    }
+}
+
+fn skip_rollout(generation: usize, rng: &mut StdRng) -> bool {
     let skip_rollout_prob = (generation as f32 / 10.0 + 0.5).clamp(0.2, 1.0);
-    skip_rollout_prob > rand::random()
+    skip_rollout_prob > rng.gen()
+}
+
+fn run_simulation<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
+    tree: &mut MCTSTree<N, I, T>,
+    start: usize,
+    policy: &U,
+    generation: usize,
+    contempt: f32,
+    rng: &mut StdRng,
+) -> anyhow::Result<()> {
+    let leaf_id = tree.select_leaf(start, rng)?;
+    tree.simulations += 1;
+    tree.max_depth = tree.max_depth.max(tree.nodes[leaf_id].depth);
+    tree.depth_sum += tree.nodes[leaf_id].depth;
+    let game = &tree.nodes[leaf_id].game;
+
+    if let Some(margin) = game.terminal_value(Players::Player) {
+        let result = game.winning_player().filter(|_| !game.is_draw_by_rule());
+        let points = match result {
+            Some(Players::Player) => 1.0,
+            Some(Players::Opponent) => -1.0,
+            None => -contempt,
+        };
+        let weight = tree.config.margin_weight;
+        tree.backprop(leaf_id, (1.0 - weight) * points + weight * margin);
+        return Ok(());
+    }
+
+    let rollout = |rng: &mut StdRng| -> anyhow::Result<f32> {
+        let outcome = simulate::<N, I, T, U>(
+            game,
+            policy,
+            Players::Player,
+            tree.config.max_rollout_depth,
+            rng,
+        )?;
+        Ok(match outcome {
+            RolloutOutcome::Finished(result, margin) => {
+                let weight = tree.config.margin_weight;
+                (1.0 - weight) * result.points_with_contempt(contempt) + weight * margin
+            }
+            RolloutOutcome::CutOff(game) => evaluate_cutoff(&game, policy)?,
+        })
+    };
+    let exact = if tree.config.probe_exact_scores {
+        policy
+            .exact_score(game)
+            .map(|value| if value == 0.0 { -contempt } else { value })
+    } else {
+        None
+    };
+    let points = match (exact, tree.config.value_mixing) {
+        (Some(value), _) => value,
+        (None, Some(mixing)) if policy.can_predict_score() => {
+            let lambda = mixing.lambda(generation);
+            let mut points = 0.0;
+            if lambda < 1.0 {
+                points += (1.0 - lambda) * policy.predict_score(game)?;
+            }
+            if lambda > 0.0 {
+                points += lambda * rollout(rng)?;
+            }
+            points
+        }
+        (None, _) if policy.can_predict_score() && skip_rollout(generation, rng) => {
+            policy.predict_score(game)?
+        }
+        (None, _) => rollout(rng)?,
+    };
+
+    let priors = if tree.config.needs_priors() {
+        Some(policy.predict_priors(&tree.nodes[leaf_id].game)?)
+    } else {
+        None
+    };
+    tree.expand(leaf_id, priors)?;
+    tree.backprop(leaf_id, points);
+    if let Some(max_nodes) = tree.config.max_nodes {
+        // Make sure the next expansion still fits
+        if tree.nodes.len() + N > max_nodes {
+            tree.prune(max_nodes / 2);
+        }
+    }
+    Ok(())
+}
+
+fn sample_gumbel(rng: &mut StdRng) -> f32 {
+    let uniform: f32 = rng.gen::<f32>().max(f32::MIN_POSITIVE);
+    -(-uniform.ln()).ln()
+}
+
+// Monotone transform of a value in [-1, 1] used by Gumbel AlphaZero, grows with the visit count
+// of the most visited child so that search results outweigh the priors as the search goes on
+fn gumbel_sigma(value: f32, max_visits: usize) -> f32 {
+    const C_VISIT: f32 = 50.;
+    const C_SCALE: f32 = 1.;
+    (C_VISIT + max_visits as f32) * C_SCALE * (value + 1.0) / 2.0
+}
+
+// What a Gumbel search runs its simulations with, besides the tree itself
+struct GumbelSearch<'a, const N: usize, U> {
+    root_priors: &'a [f32; N],
+    policy: &'a U,
+    generation: usize,
+    contempt: f32,
+    considered_moves: usize,
+}
+
+// Returns the chosen move and the improved policy, which sums to 1
+fn gumbel_search<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
+    tree: &mut MCTSTree<N, I, T>,
+    search: GumbelSearch<N, U>,
+    rng: &mut StdRng,
+    observer: &mut Option<&mut dyn SearchObserver<N>>,
+) -> anyhow::Result<(usize, [f32; N])> {
+    let GumbelSearch {
+        root_priors,
+        policy,
+        generation,
+        contempt,
+        considered_moves,
+    } = search;
+    if !tree.root().expanded {
+        run_simulation(tree, ROOT, policy, generation, contempt, rng)?;
+    }
+    while !tree.root().untried_moves.is_empty() {
+        let (mv, prior) = tree.take_untried_move(ROOT, rng);
+        tree.add_child(ROOT, mv, prior)?;
+    }
+    let mut children = tree.root().children.clone();
+    ensure!(!children.is_empty(), "Cannot search a finished game");
+
+    let logits: Vec<f32> = children
+        .iter()
+        .map(|child| {
+            root_priors[tree.nodes[*child].source_move.unwrap()]
+                .max(1e-8)
+                .ln()
+        })
+        .collect();
+    let gumbels: Vec<f32> = children.iter().map(|_| sample_gumbel(rng)).collect();
+    let mut remaining: Vec<usize> = (0..children.len()).collect();
+    remaining.sort_by(|a, b| (gumbels[*b] + logits[*b]).total_cmp(&(gumbels[*a] + logits[*a])));
+    remaining.truncate(considered_moves.clamp(1, children.len()));
+
+    let budget = tree.config.simulations.saturating_sub(tree.simulations);
+    let phases = (remaining.len() as f32).log2().ceil().max(1.0) as usize;
+    while remaining.len() > 1 && tree.simulations < tree.config.simulations {
+        let per_move = (budget / (phases * remaining.len())).max(1);
+        for i in remaining.iter().copied() {
+            for _ in 0..per_move {
+                if tree.simulations >= tree.config.simulations {
+                    break;
+                }
+                // Pruning moves nodes around, but the root children keep their order
+                let child = tree.root().children[i];
+                run_simulation(tree, child, policy, generation, contempt, rng)?;
+                report_progress(tree, observer)?;
+            }
+        }
+        children.clone_from(&tree.root().children);
+        let max_visits = children
+            .iter()
+            .map(|c| tree.nodes[*c].visits)
+            .max()
+            .unwrap();
+        let score = |i: usize| {
+            let value = tree.mover_sign(ROOT) * tree.mean_value(children[i]);
+            gumbels[i] + logits[i] + gumbel_sigma(value, max_visits)
+        };
+        remaining.sort_by(|a, b| score(*b).total_cmp(&score(*a)));
+        remaining.truncate(remaining.len().div_ceil(2));
+    }
+
+    // Improved policy: softmax(logits + sigma(completed q)), unvisited moves use the root value
+    let root_value = tree.mover_sign(ROOT) * tree.mean_value(ROOT);
+    let max_visits = children
+        .iter()
+        .map(|c| tree.nodes[*c].visits)
+        .max()
+        .unwrap();
+    let improved_logits: Vec<f32> = children
+        .iter()
+        .zip(&logits)
+        .map(|(child, logit)| {
+            let value = if tree.nodes[*child].visits > 0 {
+                tree.mover_sign(ROOT) * tree.mean_value(*child)
+            } else {
+                root_value
+            };
+            logit + gumbel_sigma(value, max_visits)
+        })
+        .collect();
+    let max_logit = improved_logits.iter().copied().fold(f32::MIN, f32::max);
+    let exps: Vec<f32> = improved_logits
+        .iter()
+        .map(|logit| (logit - max_logit).exp())
+        .collect();
+    let total: f32 = exps.iter().sum();
+    let mut improved_policy = [0.0_f32; N];
+    for (child, exp) in children.iter().zip(exps) {
+        improved_policy[tree.nodes[*child].source_move.unwrap()] = exp / total;
+    }
+
+    let best_move = tree.nodes[children[remaining[0]]].source_move.unwrap();
+    Ok((best_move, improved_policy))
+}
+
+/// Snapshot of a running search
+pub struct SearchProgress<const N: usize> {
+    pub simulations: usize,
+    pub best_move: usize,
+    pub root_value: f32,
+    pub visits: [f32; N],
+}
+
+/// Gets a SearchProgress every MctsConfig::observer_interval simulations, and once more when the
+/// search is done
+pub trait SearchObserver<const N: usize> {
+    fn on_progress(&mut self, progress: &SearchProgress<N>);
+}
+
+impl<const N: usize, F: FnMut(&SearchProgress<N>)> SearchObserver<N> for F {
+    fn on_progress(&mut self, progress: &SearchProgress<N>) {
+        self(progress)
+    }
+}
+
+fn report_progress<const N: usize, const I: usize, T: Game<N, I>>(
+    tree: &MCTSTree<N, I, T>,
+    observer: &mut Option<&mut dyn SearchObserver<N>>,
+) -> anyhow::Result<()> {
+    let Some(observer) = observer else {
+        return Ok(());
+    };
+    if tree.simulations % tree.config.observer_interval.max(1) == 0 {
+        let stats = get_tree_stats(tree)?;
+        observer.on_progress(&SearchProgress {
+            simulations: tree.simulations,
+            best_move: stats.best_move_index,
+            root_value: stats.value,
+            visits: stats.node_visits,
+        });
+    }
+    Ok(())
 }
 
 pub fn mcts<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
     root_game: &T,
     policy: &U,
     generation: usize,
-) -> anyhow::Result<GameStats<N, I>> {
-    const SIMULATIONS: usize = 1000;
-    let mut mcts_tree: Tree<MCTSData<N, I, T>> = Tree::new(MCTSData::new(root_game.clone()));
+    config: &MctsConfig,
+    rng: &mut StdRng,
+) -> anyhow::Result<SearchResult<N, I>> {
+    let mut mcts_tree = MCTSTree::new(root_game.clone(), config);
+    search_tree(&mut mcts_tree, policy, generation, rng, &mut None)
+}
 
-    for _ in 0..SIMULATIONS {
-        let mut cur_node = mcts_tree
-            .get_mut(select_leaf(&mcts_tree, mcts_tree.root().id()))
-            .unwrap();
-        let game = &cur_node.value().game;
-
-        if game.game_ended() {
-            let result = game.winning_player();
-            let points = match result {
-                Some(Players::Player) => 1.0,
-                Some(Players::Opponent) => -1.0,
-                None => 0.0,
+/// Same as mcts, but streams the progress of the search to `observer`
+pub fn mcts_observed<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
+    root_game: &T,
+    policy: &U,
+    generation: usize,
+    config: &MctsConfig,
+    rng: &mut StdRng,
+    observer: &mut dyn SearchObserver<N>,
+) -> anyhow::Result<SearchResult<N, I>> {
+    let mut mcts_tree = MCTSTree::new(root_game.clone(), config);
+    search_tree(&mut mcts_tree, policy, generation, rng, &mut Some(observer))
+}
+
+/// Search of a position that was set up rather than reached in self-play, see analyze
+pub struct Analysis<const N: usize, const I: usize> {
+    pub best_move: usize,
+    /// Root value for the player to move
+    pub value: f32,
+    /// Searched moves with their visits and mean value for the player to move, most visited first
+    pub moves: Vec<(usize, f32, f32)>,
+    /// The underlying search, in the frame of the flipped board if Opponent was to move
+    pub search: SearchResult<N, I>,
+}
+
+/// Searches `game` for the player to move, which can be either player. The tree scores
+/// everything for Player, so the board is flipped first when Opponent is to move and the moves
+/// are mapped back, so they can be played on `game` itself
+pub fn analyze<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
+    game: &T,
+    policy: &U,
+    generation: usize,
+    config: &MctsConfig,
+    rng: &mut StdRng,
+) -> anyhow::Result<Analysis<N, I>> {
+    ensure!(
+        !game.game_ended() && !game.is_draw_by_rule(),
+        "Cannot analyze a finished game"
+    );
+    ensure!(
+        game.chance_outcomes().is_none(),
+        "Resolve the pending chance event before analyzing"
+    );
+    let mut root = game.clone();
+    let mut config = config.clone();
+    let flipped = root.current_player() != Players::Player;
+    if flipped {
+        root.flip_board();
+        // Opponent is searching, as Player of the flipped board
+        std::mem::swap(&mut config.player_contempt, &mut config.opponent_contempt);
+    }
+    let search = mcts(&root, policy, generation, &config, rng)?;
+    let to_game_move = |mv: usize| if flipped { root.flipped_move(mv) } else { mv };
+    let mut moves: Vec<_> = search
+        .stats
+        .node_visits
+        .iter()
+        .enumerate()
+        .filter(|(_, visits)| **visits > 0.0)
+        .map(|(mv, visits)| (to_game_move(mv), *visits, search.move_values[mv]))
+        .collect();
+    moves.sort_by(|a, b| b.1.total_cmp(&a.1));
+    Ok(Analysis {
+        best_move: to_game_move(search.stats.best_move_index),
+        value: search.stats.value,
+        moves,
+        search,
+    })
+}
+
+// Runs `config.simulations` more simulations on a possibly already searched tree
+fn search_tree<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
+    mcts_tree: &mut MCTSTree<N, I, T>,
+    policy: &U,
+    generation: usize,
+    rng: &mut StdRng,
+    observer: &mut Option<&mut dyn SearchObserver<N>>,
+) -> anyhow::Result<SearchResult<N, I>> {
+    let root_game = mcts_tree.root().game.clone();
+    let config = mcts_tree.config.clone();
+    ensure!(
+        root_game.chance_outcomes().is_none(),
+        "The search has to start in a position where a player is to move"
+    );
+    let start = Instant::now();
+    if let Some(max_nodes) = config.max_nodes {
+        ensure!(
+            max_nodes >= 4 * N,
+            "max_nodes has to leave room for the root children, at least {} nodes",
+            4 * N
+        );
+    }
+    if let Some(mask) = &config.root_move_mask {
+        ensure!(
+            mask.len() == N,
+            "The root move mask needs one entry per move"
+        );
+        ensure!(
+            root_game
+                .available_moves()
+                .iter()
+                .zip(mask)
+                .any(|(available, allowed)| *available && *allowed),
+            "The root move mask does not allow any legal move"
+        );
+        if mcts_tree.root().expanded {
+            mcts_tree.restrict_root(mask);
+        }
+    }
+    mcts_tree.simulations = 0;
+    mcts_tree.max_depth = 0;
+    mcts_tree.depth_sum = 0;
+    mcts_tree.prunes = 0;
+    mcts_tree.created_nodes = 0;
+    let cache_stats_before = policy.cache_stats();
+    let priors = policy.predict_priors(&root_game)?;
+    let contempt = mcts_tree.root_contempt();
+
+    let mut saved_simulations = 0;
+    let mut gumbel_result = None;
+    match config.search_mode {
+        SearchMode::Ucb => {
+            let max_simulations = config
+                .adaptive_simulations
+                .map_or(config.simulations, |adaptive| adaptive.max_simulations);
+            for simulation in 0..max_simulations {
+                let remaining = config.simulations.saturating_sub(simulation);
+                if config.early_termination
+                    && config.move_selection == MoveSelection::MostVisits
+                    && remaining > 0
+                    && mcts_tree.best_move_decided(remaining)
+                {
+                    saved_simulations = remaining;
+                    break;
+                }
+                if let Some(adaptive) = &config.adaptive_simulations {
+                    if simulation >= adaptive.min_simulations
+                        && simulation % adaptive.check_interval.max(1) == 0
+                        && mcts_tree.adaptive_stop(adaptive, remaining == 0)
+                    {
+                        saved_simulations = remaining;
+                        break;
+                    }
+                }
+                run_simulation(mcts_tree, ROOT, policy, generation, contempt, rng)?;
+                report_progress(mcts_tree, observer)?;
+            }
+        }
+        SearchMode::Gumbel { considered_moves } => {
+            let search = GumbelSearch {
+                root_priors: &priors,
+                policy,
+                generation,
+                contempt,
+                considered_moves,
             };
-            backprop(&mut cur_node, points);
-            continue;
+            gumbel_result = Some(gumbel_search(mcts_tree, search, rng, observer)?);
         }
+    }
 
-        let points;
-        if policy.can_predict_score() && skip_rollout(generation) {
-            points = policy.predict_score(game)?;
-        } else {
-            let result = simulate::<N, I, T, U>(game, policy, Players::Player)?;
-            points = result.points();
+    if let Some(dump) = &config.tree_dump {
+        mcts_tree.dump(dump)?;
+    }
+    let mut stats = get_tree_stats(mcts_tree)?;
+    if let Some((best_move, improved_policy)) = gumbel_result {
+        stats.best_move_index = best_move;
+        stats.node_visits = improved_policy;
+    }
+    if let Some(observer) = observer {
+        observer.on_progress(&SearchProgress {
+            simulations: mcts_tree.simulations,
+            best_move: stats.best_move_index,
+            root_value: stats.value,
+            visits: stats.node_visits,
+        });
+    }
+    let mut move_values = [0.0_f32; N];
+    for child_id in mcts_tree.root().children.iter().copied() {
+        let child = &mcts_tree.nodes[child_id];
+        if child.visits > 0 {
+            move_values[child.source_move.unwrap()] = mcts_tree.mean_value(child_id);
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f32();
+    Ok(SearchResult {
+        stats,
+        move_values,
+        priors,
+        max_depth: mcts_tree.max_depth,
+        average_depth: mcts_tree.depth_sum as f32 / mcts_tree.simulations.max(1) as f32,
+        branching_factor: mcts_tree.branching_factor(),
+        root_visit_histogram: mcts_tree.root_visit_histogram(),
+        simulations: mcts_tree.simulations,
+        saved_simulations,
+        nodes: mcts_tree.nodes.len(),
+        prunes: mcts_tree.prunes,
+        nodes_per_second: mcts_tree.created_nodes as f32 / elapsed.max(f32::EPSILON),
+        cache_stats: policy
+            .cache_stats()
+            .zip(cache_stats_before)
+            .map(|(after, before)| after.since(&before)),
+    })
+}
+
+struct Ponder<const N: usize, const I: usize, T: Game<N, I>> {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<anyhow::Result<(MCTSTree<N, I, T>, StdRng)>>,
+}
+
+/// Owns the search tree across moves, so the subtree of the move actually played is reused and
+/// the search can keep going in a background thread while the opponent is thinking
+pub struct Searcher<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>> {
+    // Both are None while pondering, the ponder thread owns them then
+    tree: Option<MCTSTree<N, I, T>>,
+    rng: Option<StdRng>,
+    policy: Arc<U>,
+    generation: usize,
+    ponder: Option<Ponder<N, I, T>>,
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>> Searcher<N, I, T, U> {
+    pub fn new(
+        root_game: &T,
+        policy: Arc<U>,
+        generation: usize,
+        config: &MctsConfig,
+        rng: StdRng,
+    ) -> Self {
+        Self {
+            tree: Some(MCTSTree::new(root_game.clone(), config)),
+            rng: Some(rng),
+            policy,
+            generation,
+            ponder: None,
         }
+    }
+
+    /// The current root position
+    pub fn game(&mut self) -> anyhow::Result<&T> {
+        self.stop_pondering()?;
+        Ok(&self.tree.as_ref().unwrap().root().game)
+    }
+
+    /// Runs the configured number of simulations on top of whatever the tree already knows
+    pub fn search(&mut self) -> anyhow::Result<SearchResult<N, I>> {
+        self.stop_pondering()?;
+        search_tree(
+            self.tree.as_mut().unwrap(),
+            self.policy.as_ref(),
+            self.generation,
+            self.rng.as_mut().unwrap(),
+            &mut None,
+        )
+    }
+
+    /// Plays a move at the root, keeping its subtree if it was searched
+    pub fn advance(&mut self, mv: usize) -> anyhow::Result<()> {
+        self.stop_pondering()?;
+        let tree = self.tree.as_mut().unwrap();
+        let child = tree
+            .root()
+            .children
+            .iter()
+            .copied()
+            .find(|child| tree.nodes[*child].source_move == Some(mv));
+        match child {
+            Some(child) => tree.reroot(child),
+            None => {
+                let mut game = tree.root().game.clone();
+                game.try_perform_move(mv)?;
+                *tree = MCTSTree::new(game, &tree.config);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.ponder.is_some()
+    }
 
-        expand(&mut cur_node);
-        backprop(&mut cur_node, points);
+    /// Stops the background search, returns how many simulations it ran
+    pub fn stop_pondering(&mut self) -> anyhow::Result<usize> {
+        let Some(ponder) = self.ponder.take() else {
+            return Ok(0);
+        };
+        ponder.stop.store(true, Ordering::Relaxed);
+        let (tree, rng) = ponder
+            .handle
+            .join()
+            .map_err(|_| anyhow::anyhow!("Ponder thread panicked"))??;
+        let simulations = tree.simulations;
+        self.tree = Some(tree);
+        self.rng = Some(rng);
+        Ok(simulations)
+    }
+}
+
+impl<
+        const N: usize,
+        const I: usize,
+        T: Game<N, I> + Send + 'static,
+        U: Policy<N, I, T> + Send + Sync + 'static,
+    > Searcher<N, I, T, U>
+{
+    /// Keeps searching the current root in a background thread until the next call that needs
+    /// the tree, or until `max_simulations` is reached. Call this after our move, the search then
+    /// focuses on the replies the opponent is expected to play, and `advance` keeps the work
+    /// done on the reply that was actually played
+    pub fn start_pondering(&mut self, max_simulations: usize) -> anyhow::Result<()> {
+        self.stop_pondering()?;
+        let mut tree = self.tree.take().unwrap();
+        let mut rng = self.rng.take().unwrap();
+        let policy = self.policy.clone();
+        let generation = self.generation;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let handle = thread::spawn(move || {
+            let root_game = &tree.root().game;
+            let finished = root_game.game_ended() || root_game.is_draw_by_rule();
+            if finished || root_game.chance_outcomes().is_some() {
+                return Ok((tree, rng));
+            }
+            let contempt = tree.root_contempt();
+            tree.simulations = 0;
+            while !thread_stop.load(Ordering::Relaxed) && tree.simulations < max_simulations {
+                run_simulation(
+                    &mut tree,
+                    ROOT,
+                    policy.as_ref(),
+                    generation,
+                    contempt,
+                    &mut rng,
+                )?;
+            }
+            Ok((tree, rng))
+        });
+        self.ponder = Some(Ponder { stop, handle });
+        Ok(())
+    }
+}
+
+/// Runs a full search for every move, so a search backed agent fits wherever a policy is
+/// expected. Evaluations are delegated to the inner policy
+pub struct MctsPolicy<P> {
+    pub policy: P,
+    pub config: MctsConfig,
+    pub generation: usize,
+}
+
+impl<P> MctsPolicy<P> {
+    pub fn new(policy: P, config: MctsConfig) -> Self {
+        Self {
+            policy,
+            config,
+            generation: 0,
+        }
+    }
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>, P: Policy<N, I, T>> Policy<N, I, T>
+    for MctsPolicy<P>
+{
+    // Through analyze, which searches from the point of view of the player to move
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> anyhow::Result<usize> {
+        if let Some(pass) = forced_pass(game) {
+            return Ok(pass);
+        }
+        let analysis = analyze(game, &self.policy, self.generation, &self.config, rng)?;
+        Ok(analysis.best_move)
+    }
+
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> anyhow::Result<Vec<usize>> {
+        games
+            .iter()
+            .map(|game| self.select_move(*game, rng))
+            .collect()
+    }
+
+    fn predict_score(&self, game: &T) -> anyhow::Result<f32> {
+        self.policy.predict_score(game)
+    }
+
+    fn can_predict_score(&self) -> bool {
+        self.policy.can_predict_score()
+    }
+
+    fn predict_priors(&self, game: &T) -> anyhow::Result<[f32; N]> {
+        self.policy.predict_priors(game)
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.policy.cache_stats()
+    }
+
+    fn exact_score(&self, game: &T) -> Option<f32> {
+        self.policy.exact_score(game)
     }
-    Ok(get_tree_stats(&mcts_tree))
 }
 
 #[derive(Clone)]
 pub struct GameStats<const N: usize, const I: usize> {
     pub best_move_index: usize,
     pub game_state: [f32; I],
+    /// Visits of the root moves. A Gumbel search stores its improved policy here instead, which
+    /// sums to 1 rather than to the number of simulations, so the "visits" Analysis::moves reports
+    /// for it are probabilities
     pub node_visits: [f32; N],
+    /// Mean root value clamped to [-1, 1], the value training target
+    pub value: f32,
+    /// Raw sum of the discounted results backpropagated through the root, only for diagnostics
     pub score: f32,
+    /// Training targets beyond the value and visits that are only known once the game is over,
+    /// filled in by self-play. The final score margin for games with Game::has_score_margin,
+    /// empty otherwise
+    pub extra_targets: Vec<f32>,
+}
+
+/// Everything a search found out, the training sample is in `stats`
+#[derive(Clone)]
+pub struct SearchResult<const N: usize, const I: usize> {
+    pub stats: GameStats<N, I>,
+    /// Mean score of each root move, 0 for moves that were never visited
+    pub move_values: [f32; N],
+    /// Policy priors of each root move
+    pub priors: [f32; N],
+    /// Deepest leaf selected during the search, the root has depth 0
+    pub max_depth: usize,
+    /// Mean depth of the leaves selected during the search
+    pub average_depth: f32,
+    /// Mean number of moves of the expanded nodes, untried moves included
+    pub branching_factor: f32,
+    /// Number of root moves by visit count, bucket k holds the moves with
+    /// 2^k - 1 <= visits < 2^(k + 1) - 1, so bucket 0 are the unvisited moves
+    pub root_visit_histogram: Vec<usize>,
+    pub simulations: usize,
+    /// Simulations left unused because the search terminated early
+    pub saved_simulations: usize,
+    pub nodes: usize,
+    pub nodes_per_second: f32,
+    /// Times the tree was pruned to stay below MctsConfig::max_nodes
+    pub prunes: usize,
+    /// Evaluation cache hits and misses during this search, if the policy caches
+    pub cache_stats: Option<CacheStats>,
 }
 
 fn get_tree_stats<const N: usize, const I: usize, T: Game<N, I>>(
-    tree: &Tree<MCTSData<N, I, T>>,
-) -> GameStats<N, I> {
-    let child_datas: Vec<_> = tree.root().children().map(|thing| thing.value()).collect();
-    let score = tree.root().value().score / tree.root().value().visits as f32;
+    tree: &MCTSTree<N, I, T>,
+) -> anyhow::Result<GameStats<N, I>> {
+    let child_ids = &tree.root().children;
+    let child_datas: Vec<_> = tree.root_children().collect();
+    let value = tree.mean_value(ROOT).clamp(-1.0, 1.0);
     let mut visit_stats = [0.0_f32; N];
     for data in &child_datas {
         // Soundness: Only the root node is none, so source_move here should always be Some
         visit_stats[data.source_move.unwrap()] = data.visits as f32;
     }
-    let best_move_index = child_datas
-        .iter()
-        .max_by_key(|x| x.visits)
-        .unwrap()
-        .source_move
-        .unwrap();
-    GameStats {
+    if let Some(k) = tree.config.forced_playouts {
+        visit_stats = tree.pruned_root_visits(k);
+    }
+    let visited = child_ids.iter().filter(|id| tree.nodes[**id].visits > 0);
+    let mean = |id: usize| tree.mover_sign(ROOT) * tree.mean_value(id);
+    // The prior breaks ties, which decides before any move was visited. The mean value modes
+    // fall back to it when no child has a mean yet
+    let most_visits = child_ids.iter().max_by(|a, b| {
+        let (a, b) = (&tree.nodes[**a], &tree.nodes[**b]);
+        a.visits.cmp(&b.visits).then(a.prior.total_cmp(&b.prior))
+    });
+    let best = match tree.config.move_selection {
+        MoveSelection::MostVisits => most_visits,
+        MoveSelection::HighestMeanValue => visited
+            .max_by(|a, b| mean(**a).total_cmp(&mean(**b)))
+            .or(most_visits),
+        MoveSelection::LowerConfidenceBound { weight } => {
+            let lcb = |id: usize| mean(id) - weight / (tree.nodes[id].visits as f32).sqrt();
+            visited
+                .max_by(|a, b| lcb(**a).total_cmp(&lcb(**b)))
+                .or(most_visits)
+        }
+    };
+    let best_move_index = match best {
+        Some(id) => tree.nodes[*id].source_move.unwrap(),
+        // With lazy expansion the root has no children after its first simulation, its moves
+        // are all untried
+        None => tree
+            .root()
+            .untried_moves
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(mv, _)| *mv)
+            .context("The search ended before the root had any moves to pick from")?,
+    };
+    Ok(GameStats {
         best_move_index,
         node_visits: visit_stats,
-        game_state: tree.root().value().game.get_game_state_slice(),
-        score,
+        game_state: tree.root().game.get_game_state_slice(),
+        value,
+        score: tree.root().score,
+        extra_targets: Vec::new(),
+    })
+}
+
+pub enum RolloutOutcome<T> {
+    /// The result and the terminal_value of the finished game for the simulated player
+    Finished(GameResult, f32),
+    /// The rollout hit the depth cap, holds the position it stopped in
+    CutOff(T),
+}
+
+fn evaluate_cutoff<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
+    game: &T,
+    policy: &U,
+) -> anyhow::Result<f32> {
+    if policy.can_predict_score() {
+        policy.predict_score(game)
+    } else {
+        Ok(game.heuristic_value())
     }
 }
 
@@ -173,20 +1601,821 @@ pub fn simulate<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T
     game: &T,
     policy: &U,
     simulated_player: Players,
-) -> anyhow::Result<GameResult> {
+    max_depth: Option<usize>,
+    rng: &mut StdRng,
+) -> anyhow::Result<RolloutOutcome<T>> {
+    simulate_asymmetric(game, policy, policy, simulated_player, max_depth, rng)
+}
+
+/// Rollout where Player moves with `player_policy` and Opponent with `opponent_policy`, e.g. to
+/// play the newest model against an older generation or to measure exploitability
+pub fn simulate_asymmetric<
+    const N: usize,
+    const I: usize,
+    T: Game<N, I>,
+    U: Policy<N, I, T>,
+    V: Policy<N, I, T>,
+>(
+    game: &T,
+    player_policy: &U,
+    opponent_policy: &V,
+    simulated_player: Players,
+    max_depth: Option<usize>,
+    rng: &mut StdRng,
+) -> anyhow::Result<RolloutOutcome<T>> {
     let mut game = game.clone();
-    while !game.game_ended() {
-        let next_move = policy.select_move(&game)?;
-        game.perform_move(next_move);
+    let mut depth = 0;
+    while !game.game_ended() && !game.is_draw_by_rule() {
+        if max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            return Ok(RolloutOutcome::CutOff(game));
+        }
+        resolve_chance(&mut game, rng)?;
+        if game.game_ended() || game.is_draw_by_rule() {
+            break;
+        }
+        let next_move = match game.current_player() {
+            Players::Player => player_policy.select_move(&game, rng)?,
+            Players::Opponent => opponent_policy.select_move(&game, rng)?,
+        };
+        game.try_perform_move(next_move)?;
+        depth += 1;
     }
-    let winner = game.winning_player();
-    if let Some(player) = winner {
+    let winner = game.winning_player().filter(|_| !game.is_draw_by_rule());
+    let result = if let Some(player) = winner {
         if player == simulated_player {
-            Ok(GameResult::Win)
+            GameResult::Win
         } else {
-            Ok(GameResult::Loss)
+            GameResult::Loss
         }
     } else {
-        Ok(GameResult::Tie)
+        GameResult::Tie
+    };
+    let margin = game.terminal_value(simulated_player).unwrap_or_default();
+    Ok(RolloutOutcome::Finished(result, margin))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        alpha_beta::AlphaBetaPolicy, game::RandomPolicy, heuristic::HeuristicPolicy, hex::Hex,
+        mnk::TicTacToe,
+    };
+    use rand::SeedableRng;
+
+    // A reused tree can end a search with fewer nodes than it started with after pruning, the
+    // node rate has to count the created ones instead
+    #[test]
+    fn counts_created_nodes_when_pruning() -> anyhow::Result<()> {
+        let config = MctsConfig {
+            simulations: 2000,
+            max_nodes: Some(100),
+            ..Default::default()
+        };
+        let rng = StdRng::seed_from_u64(0);
+        let policy = Arc::new(RandomPolicy::default());
+        let mut searcher = Searcher::new(&TicTacToe::new(), policy, 0, &config, rng);
+        for _ in 0..3 {
+            let result = searcher.search()?;
+            assert!(result.prunes > 0);
+            assert!(result.nodes_per_second > 0.0);
+        }
+        Ok(())
+    }
+
+    // A position where the last move draws, with the contempt of either player to move
+    #[test]
+    fn applies_contempt_of_the_player_to_move() -> anyhow::Result<()> {
+        let config = MctsConfig {
+            simulations: 100,
+            player_contempt: 0.5,
+            opponent_contempt: 0.25,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let player_to_move = TicTacToe::from_moves(&[0, 1, 2, 4, 3, 5, 7, 6])?;
+        let mut opponent_to_move = player_to_move.clone();
+        opponent_to_move.flip_board();
+        let policy = RandomPolicy::default();
+
+        // analyze gives the value for the player to move
+        let analysis = analyze(&player_to_move, &policy, 0, &config, &mut rng)?;
+        assert_eq!(analysis.value, -0.5);
+        let analysis = analyze(&opponent_to_move, &policy, 0, &config, &mut rng)?;
+        assert_eq!(analysis.value, -0.25);
+
+        // The tree itself scores for Player, a tie Opponent avoids is good for Player
+        let mut searcher = Searcher::new(&opponent_to_move, Arc::new(policy), 0, &config, rng);
+        assert_eq!(searcher.search()?.stats.value, 0.25);
+        Ok(())
+    }
+
+    // Visits say nothing about which move has the highest mean, so only MostVisits stops early
+    #[test]
+    fn terminates_early_only_for_most_visits() -> anyhow::Result<()> {
+        let game = TicTacToe::from_moves(&[0, 1, 2, 4, 3, 5, 7])?;
+        let mut config = MctsConfig {
+            simulations: 100,
+            early_termination: true,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let search = mcts(&game, &RandomPolicy::default(), 0, &config, &mut rng)?;
+        assert!(search.saved_simulations > 0);
+        config.move_selection = MoveSelection::HighestMeanValue;
+        let search = mcts(&game, &RandomPolicy::default(), 0, &config, &mut rng)?;
+        assert_eq!(search.saved_simulations, 0);
+        Ok(())
+    }
+
+    const SELECTIONS: [MoveSelection; 3] = [
+        MoveSelection::MostVisits,
+        MoveSelection::HighestMeanValue,
+        MoveSelection::LowerConfidenceBound { weight: 1.0 },
+    ];
+
+    // With lazy expansion one simulation leaves the root without children, the best move is the
+    // untried one with the highest prior
+    #[test]
+    fn picks_a_move_before_the_root_has_children() -> anyhow::Result<()> {
+        let game = TicTacToe::new();
+        for move_selection in SELECTIONS {
+            let config = MctsConfig {
+                simulations: 1,
+                lazy_expansion: true,
+                move_selection,
+                ..Default::default()
+            };
+            let mut rng = StdRng::seed_from_u64(0);
+            let search = mcts(&game, &RandomPolicy::default(), 0, &config, &mut rng)?;
+            assert!(game.available_moves()[search.stats.best_move_index]);
+        }
+        Ok(())
+    }
+
+    // The first progress report comes after a single simulation, when at most one child has a
+    // mean value
+    #[test]
+    fn reports_progress_before_the_children_are_visited() -> anyhow::Result<()> {
+        let game = TicTacToe::new();
+        for move_selection in SELECTIONS {
+            for lazy_expansion in [false, true] {
+                let config = MctsConfig {
+                    simulations: 3,
+                    observer_interval: 1,
+                    lazy_expansion,
+                    move_selection,
+                    ..Default::default()
+                };
+                let mut rng = StdRng::seed_from_u64(0);
+                let mut reports = 0;
+                let mut observer = |progress: &SearchProgress<9>| {
+                    assert!(game.available_moves()[progress.best_move]);
+                    reports += 1;
+                };
+                let policy = RandomPolicy::default();
+                mcts_observed(&game, &policy, 0, &config, &mut rng, &mut observer)?;
+                assert!(reports >= 3);
+            }
+        }
+        Ok(())
+    }
+
+    // X to move can complete the top row on square 2
+    #[test]
+    fn gumbel_search_finds_the_winning_move() -> anyhow::Result<()> {
+        let game = TicTacToe::from_moves(&[0, 3, 1, 4])?;
+        let config = MctsConfig {
+            simulations: 200,
+            search_mode: SearchMode::Gumbel {
+                considered_moves: 4,
+            },
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let search = mcts(&game, &RandomPolicy::default(), 0, &config, &mut rng)?;
+        let improved_policy = search.stats.node_visits;
+        assert!((improved_policy.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        for (mv, available) in game.available_moves().iter().enumerate() {
+            assert_eq!(improved_policy[mv] > 0.0, *available);
+        }
+        assert_eq!(search.stats.best_move_index, 2);
+        let most_likely = (0..9).max_by(|a, b| improved_policy[*a].total_cmp(&improved_policy[*b]));
+        assert_eq!(most_likely, Some(2));
+        Ok(())
+    }
+
+    // With a low exploration weight the moves besides the win are only searched because they are
+    // forced, the target leaves those visits out
+    #[test]
+    fn prunes_forced_playouts_from_the_target() -> anyhow::Result<()> {
+        let game = TicTacToe::from_moves(&[0, 3, 1, 4])?;
+        let k = 2.0;
+        let config = MctsConfig {
+            simulations: 1000,
+            exploration_weight: 1.0,
+            forced_playouts: Some(k),
+            ..Default::default()
+        };
+        let mut tree = MCTSTree::new(game, &config);
+        let mut rng = StdRng::seed_from_u64(0);
+        search_tree(&mut tree, &RandomPolicy::default(), 0, &mut rng, &mut None)?;
+        let pruned = tree.pruned_root_visits(k);
+        for child_id in tree.root().children.iter().copied() {
+            let child = &tree.nodes[child_id];
+            let mv = child.source_move.unwrap();
+            let visits = child.visits as f32;
+            let forced = tree.forced_playouts(child_id, k) as f32;
+            match mv {
+                2 => assert_eq!(pruned[mv], visits),
+                _ => assert!(pruned[mv] >= visits - forced),
+            }
+            // Moves that never got more than their forced visits lose some of them
+            if mv != 2 && visits <= forced {
+                assert!(pruned[mv] < visits);
+            }
+        }
+        Ok(())
+    }
+
+    // Forced playouts with a root that lazy expansion left without children
+    #[test]
+    fn prunes_nothing_before_the_root_has_children() -> anyhow::Result<()> {
+        let config = MctsConfig {
+            simulations: 1,
+            lazy_expansion: true,
+            forced_playouts: Some(2.0),
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let search = mcts(
+            &TicTacToe::new(),
+            &RandomPolicy::default(),
+            0,
+            &config,
+            &mut rng,
+        )?;
+        assert_eq!(search.stats.node_visits, [0.0; 9]);
+        Ok(())
+    }
+
+    #[test]
+    fn fails_without_simulations() {
+        let config = MctsConfig {
+            simulations: 0,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let search = mcts(
+            &TicTacToe::new(),
+            &RandomPolicy::default(),
+            0,
+            &config,
+            &mut rng,
+        );
+        assert!(search.is_err());
+    }
+
+    // A cut off rollout stops after the cap, one that finishes first is scored as usual
+    #[test]
+    fn cuts_rollouts_off_at_the_depth_cap() -> anyhow::Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let policy = RandomPolicy::default();
+        let game = TicTacToe::new();
+        match simulate(&game, &policy, Players::Player, Some(3), &mut rng)? {
+            RolloutOutcome::CutOff(game) => {
+                assert_eq!(game.available_moves().iter().filter(|x| **x).count(), 6)
+            }
+            RolloutOutcome::Finished(..) => panic!("The rollout should have been cut off"),
+        }
+        let game = TicTacToe::from_moves(&[0, 1, 2, 4, 3, 5, 7, 6])?;
+        let outcome = simulate(&game, &policy, Players::Player, Some(3), &mut rng)?;
+        assert!(matches!(
+            outcome,
+            RolloutOutcome::Finished(GameResult::Tie, _)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn reports_move_values_and_priors() -> anyhow::Result<()> {
+        let game = TicTacToe::from_moves(&[0, 3, 1, 4])?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let search = mcts(
+            &game,
+            &RandomPolicy::default(),
+            0,
+            &MctsConfig::default(),
+            &mut rng,
+        )?;
+        // Square 2 wins on the spot
+        assert_eq!(search.move_values[2], 1.0);
+        for (mv, available) in game.available_moves().iter().enumerate() {
+            match available {
+                true => assert_eq!(search.priors[mv], 0.2),
+                false => assert_eq!((search.priors[mv], search.move_values[mv]), (0.0, 0.0)),
+            }
+        }
+        Ok(())
+    }
+
+    // The second simulation picks among children that are all unvisited, the heuristic prefers
+    // the center of the empty board
+    #[test]
+    fn breaks_ties_by_prior() -> anyhow::Result<()> {
+        let game = TicTacToe::new();
+        let policy = HeuristicPolicy::default();
+        let priors = policy.predict_priors(&game)?;
+        let center = (0..9).max_by(|a, b| priors[*a].total_cmp(&priors[*b]));
+        assert_eq!(center, Some(4));
+        let config = MctsConfig {
+            simulations: 2,
+            tie_break: TieBreak::Prior,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let search = mcts(&game, &policy, 0, &config, &mut rng)?;
+        assert_eq!(search.stats.best_move_index, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn searches_are_reproducible_with_a_seed() -> anyhow::Result<()> {
+        let game = Hex::<25, 50>::new();
+        let config = MctsConfig {
+            simulations: 200,
+            ..Default::default()
+        };
+        let search = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            mcts(&game, &RandomPolicy::default(), 0, &config, &mut rng)
+        };
+        let (first, second) = (search(3)?, search(3)?);
+        assert_eq!(first.stats.node_visits, second.stats.node_visits);
+        assert_eq!(first.move_values, second.move_values);
+        assert_ne!(first.stats.node_visits, search(4)?.stats.node_visits);
+        Ok(())
+    }
+
+    // The win is one ply below the root, a discount shrinks it once on the way up
+    #[test]
+    fn discounts_results_on_the_way_up() -> anyhow::Result<()> {
+        let game = TicTacToe::from_moves(&[0, 3, 1, 4])?;
+        let value = |discount| -> anyhow::Result<f32> {
+            let config = MctsConfig {
+                discount,
+                exploration_weight: 1.0,
+                ..Default::default()
+            };
+            let mut rng = StdRng::seed_from_u64(0);
+            let search = mcts(&game, &RandomPolicy::default(), 0, &config, &mut rng)?;
+            assert_eq!(search.move_values[2], 1.0);
+            Ok(search.stats.value)
+        };
+        assert!(value(1.0)? > 0.5);
+        assert!(value(0.5)? <= 0.5);
+        Ok(())
+    }
+
+    #[test]
+    fn dumps_the_tree() -> anyhow::Result<()> {
+        for (format, extension) in [(DumpFormat::Dot, "dot"), (DumpFormat::Json, "json")] {
+            let path = std::env::temp_dir().join(format!("alpha-scuffed-tree-dump.{}", extension));
+            let config = MctsConfig {
+                simulations: 50,
+                tree_dump: Some(TreeDump {
+                    path: path.to_string_lossy().into_owned(),
+                    max_depth: 1,
+                    format,
+                }),
+                ..Default::default()
+            };
+            let mut rng = StdRng::seed_from_u64(0);
+            mcts(
+                &TicTacToe::new(),
+                &RandomPolicy::default(),
+                0,
+                &config,
+                &mut rng,
+            )?;
+            let dump = fs::read_to_string(&path)?;
+            fs::remove_file(&path)?;
+            match format {
+                DumpFormat::Dot => assert!(dump.starts_with("digraph")),
+                DumpFormat::Json => {
+                    let root: serde_json::Value = serde_json::from_str(&dump)?;
+                    assert_eq!(root["visits"], 50);
+                    assert_eq!(root["children"].as_array().map(Vec::len), Some(9));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Player calls heads (0) or tails (1) and wins if the coin agrees, it lands heads four times
+    // out of five
+    #[derive(Clone)]
+    struct CoinToss {
+        call: Option<usize>,
+        coin: Option<usize>,
+        caller: Players,
+    }
+
+    impl Game<2, 2> for CoinToss {
+        fn winning_player(&self) -> Option<Players> {
+            let (call, coin) = (self.call?, self.coin?);
+            Some(match call == coin {
+                true => self.caller,
+                false => self.caller.swap(),
+            })
+        }
+
+        fn available_moves(&self) -> [bool; 2] {
+            [self.call.is_none(); 2]
+        }
+
+        fn try_perform_move(&mut self, space: usize) -> anyhow::Result<()> {
+            ensure!(
+                space < 2 && self.call.is_none(),
+                "Move {} is illegal",
+                space
+            );
+            self.call = Some(space);
+            Ok(())
+        }
+
+        fn undo_move(&mut self) -> anyhow::Result<()> {
+            match (self.call, self.coin) {
+                (_, Some(_)) => self.coin = None,
+                (Some(_), None) => self.call = None,
+                (None, None) => anyhow::bail!("No move to undo"),
+            }
+            Ok(())
+        }
+
+        fn new() -> Self {
+            Self {
+                call: None,
+                coin: None,
+                caller: Players::Player,
+            }
+        }
+
+        fn game_ended(&self) -> bool {
+            self.coin.is_some()
+        }
+
+        fn current_player(&self) -> Players {
+            match self.call {
+                None => self.caller,
+                Some(_) => self.caller.swap(),
+            }
+        }
+
+        fn flip_board(&mut self) {
+            self.caller = self.caller.swap();
+        }
+
+        fn get_game_state_slice(&self) -> [f32; 2] {
+            [self.call, self.coin].map(|side| side.map_or(-1.0, |side| side as f32))
+        }
+
+        fn get_game_variations(stats: &GameStats<2, 2>) -> Vec<GameStats<2, 2>> {
+            vec![stats.clone()]
+        }
+
+        fn chance_outcomes(&self) -> Option<Vec<(usize, f32)>> {
+            (self.call.is_some() && self.coin.is_none()).then(|| vec![(0, 0.8), (1, 0.2)])
+        }
+
+        fn try_apply_chance_outcome(&mut self, outcome: usize) -> anyhow::Result<()> {
+            ensure!(
+                self.chance_outcomes().is_some(),
+                "The coin is not in the air"
+            );
+            self.coin = Some(outcome);
+            Ok(())
+        }
+    }
+
+    // Heads wins 0.8 - 0.2 = 0.6 on average, tails loses as much
+    #[test]
+    fn averages_over_chance_outcomes() -> anyhow::Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let config = MctsConfig::default();
+        let search = mcts(
+            &CoinToss::new(),
+            &RandomPolicy::default(),
+            0,
+            &config,
+            &mut rng,
+        )?;
+        assert_eq!(search.stats.best_move_index, 0);
+        assert!(
+            (search.move_values[0] - 0.6).abs() < 0.15,
+            "{:?}",
+            search.move_values
+        );
+        assert!(
+            (search.move_values[1] + 0.6).abs() < 0.15,
+            "{:?}",
+            search.move_values
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ponders_on_the_opponents_time() -> anyhow::Result<()> {
+        let config = MctsConfig {
+            simulations: 100,
+            ..Default::default()
+        };
+        let rng = StdRng::seed_from_u64(0);
+        let policy = Arc::new(RandomPolicy::default());
+        let mut searcher = Searcher::new(&TicTacToe::new(), policy, 0, &config, rng);
+        let best_move = searcher.search()?.stats.best_move_index;
+        searcher.advance(best_move)?;
+        searcher.start_pondering(200)?;
+        assert!(searcher.is_pondering());
+        thread::sleep(std::time::Duration::from_millis(200));
+        assert!(searcher.stop_pondering()? > 0);
+        assert!(!searcher.is_pondering());
+        // The search goes on from the position after our move
+        let game = searcher.game()?;
+        assert!(!game.available_moves()[best_move]);
+        let reply = searcher.search()?.stats.best_move_index;
+        assert!(searcher.game()?.available_moves()[reply]);
+        Ok(())
+    }
+
+    // Either player to move takes the win, the policy searches in the frame of the mover
+    #[test]
+    fn mcts_policy_takes_the_win() -> anyhow::Result<()> {
+        let policy = MctsPolicy::new(RandomPolicy::default(), MctsConfig::default());
+        let mut rng = StdRng::seed_from_u64(0);
+        let game = TicTacToe::from_moves(&[0, 3, 1, 4])?;
+        assert_eq!(policy.select_move(&game, &mut rng)?, 2);
+        let game = TicTacToe::from_moves(&[0, 3, 1, 4, 8])?;
+        assert_eq!(policy.select_move(&game, &mut rng)?, 5);
+        Ok(())
+    }
+
+    // The search settles on the win long before the budget, while the empty board has every
+    // move close and runs past it
+    #[test]
+    fn adapts_the_number_of_simulations() -> anyhow::Result<()> {
+        let mut config = MctsConfig {
+            adaptive_simulations: Some(AdaptiveSimulations::default()),
+            exploration_weight: 1.0,
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let policy = RandomPolicy::default();
+        let game = TicTacToe::from_moves(&[0, 3, 1, 4])?;
+        let search = mcts(&game, &policy, 0, &config, &mut rng)?;
+        assert!(search.simulations < config.simulations);
+        assert!(search.saved_simulations > 0);
+        config.simulations = 100;
+        config.exploration_weight = 10.0;
+        let search = mcts(&TicTacToe::new(), &policy, 0, &config, &mut rng)?;
+        assert!(search.simulations > 100);
+        Ok(())
+    }
+
+    // With Infinite every child is tried before any is searched twice, Loss keeps going with
+    // the children that did not lose
+    #[test]
+    fn initializes_unvisited_children() -> anyhow::Result<()> {
+        let unvisited = |q_init| -> anyhow::Result<usize> {
+            let config = MctsConfig {
+                simulations: 20,
+                exploration_weight: 1.0,
+                q_init,
+                ..Default::default()
+            };
+            let mut rng = StdRng::seed_from_u64(0);
+            let search = mcts(
+                &TicTacToe::new(),
+                &RandomPolicy::default(),
+                0,
+                &config,
+                &mut rng,
+            )?;
+            Ok(search
+                .stats
+                .node_visits
+                .iter()
+                .filter(|visits| **visits == 0.0)
+                .count())
+        };
+        assert_eq!(unvisited(QInit::Infinite)?, 0);
+        assert!(unvisited(QInit::Loss)? > 0);
+        Ok(())
+    }
+
+    #[test]
+    fn moves_lambda_over_the_generations() {
+        let mixing = ValueMixing::default();
+        for (generation, lambda) in [(0, 0.8), (5, 0.5), (10, 0.2), (100, 0.2)] {
+            assert!((mixing.lambda(generation) - lambda).abs() < 1e-6);
+        }
+    }
+
+    // Random play never beats perfect play at tic-tac-toe
+    #[test]
+    fn rolls_out_with_a_policy_per_player() -> anyhow::Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let game = TicTacToe::new();
+        for _ in 0..10 {
+            let outcome = simulate_asymmetric(
+                &game,
+                &RandomPolicy::default(),
+                &AlphaBetaPolicy::exact(),
+                Players::Player,
+                None,
+                &mut rng,
+            )?;
+            match outcome {
+                RolloutOutcome::Finished(result, _) => assert!(!matches!(result, GameResult::Win)),
+                RolloutOutcome::CutOff(_) => panic!("The rollout has no depth cap"),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn searches_only_the_allowed_root_moves() -> anyhow::Result<()> {
+        let game = TicTacToe::from_moves(&[0, 3, 1, 4])?;
+        let mut mask = vec![false; 9];
+        mask[5] = true;
+        mask[6] = true;
+        let mut config = MctsConfig {
+            root_move_mask: Some(mask),
+            ..Default::default()
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let search = mcts(&game, &RandomPolicy::default(), 0, &config, &mut rng)?;
+        assert!([5, 6].contains(&search.stats.best_move_index));
+        for (mv, visits) in search.stats.node_visits.iter().enumerate() {
+            assert_eq!(*visits > 0.0, [5, 6].contains(&mv));
+        }
+        // Only squares that are taken
+        config.root_move_mask = Some((0..9).map(|mv| mv < 2).collect());
+        assert!(mcts(&game, &RandomPolicy::default(), 0, &config, &mut rng).is_err());
+        Ok(())
+    }
+
+    // The raw score sums up every simulation, the value is its mean
+    #[test]
+    fn normalizes_the_root_value() -> anyhow::Result<()> {
+        let game = TicTacToe::from_moves(&[0, 3, 1, 4])?;
+        let mut rng = StdRng::seed_from_u64(0);
+        let search = mcts(
+            &game,
+            &RandomPolicy::default(),
+            0,
+            &MctsConfig::default(),
+            &mut rng,
+        )?;
+        let stats = search.stats;
+        assert!(stats.score > 1.0);
+        assert!((-1.0..=1.0).contains(&stats.value));
+        assert!((stats.value - stats.score / search.simulations as f32).abs() < 0.01);
+        Ok(())
+    }
+
+    #[test]
+    fn reports_the_shape_of_the_tree() -> anyhow::Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let config = MctsConfig::default();
+        let search = mcts(
+            &TicTacToe::new(),
+            &RandomPolicy::default(),
+            0,
+            &config,
+            &mut rng,
+        )?;
+        assert!(search.max_depth as f32 >= search.average_depth);
+        assert!(search.average_depth >= 1.0);
+        assert!((1.0..=9.0).contains(&search.branching_factor));
+        // Every root move lands in one bucket, and with 1000 simulations none is unvisited
+        assert_eq!(search.root_visit_histogram.iter().sum::<usize>(), 9);
+        assert_eq!(search.root_visit_histogram[0], 0);
+        Ok(())
+    }
+
+    // The search as it was on ego_tree, with recursive backprop and a tree.get per step, kept to
+    // measure the arena tree against
+    mod ego_tree_search {
+        use ego_tree::{NodeId, NodeMut, Tree};
+
+        use super::*;
+
+        struct Data<T> {
+            game: T,
+            visits: usize,
+            score: f32,
+        }
+
+        fn backprop<T>(node: &mut NodeMut<'_, Data<T>>, points: f32) {
+            node.value().visits += 1;
+            node.value().score += points;
+            if let Some(mut parent) = node.parent() {
+                backprop(&mut parent, points);
+            }
+        }
+
+        fn select_leaf<T>(tree: &Tree<Data<T>>, rng: &mut StdRng) -> NodeId {
+            let mut node = tree.root();
+            while node.has_children() {
+                let parent_visits = node.value().visits;
+                let best = node
+                    .children()
+                    .max_set_by_key(|child| {
+                        let data = child.value();
+                        let score = match data.visits {
+                            0 => f32::MAX,
+                            visits => {
+                                ucb_score(data.score / visits as f32, visits, parent_visits, 10.0)
+                            }
+                        };
+                        NotNan::new(score).unwrap()
+                    })
+                    .choose(rng)
+                    .unwrap()
+                    .id();
+                node = tree.get(best).unwrap();
+            }
+            node.id()
+        }
+
+        /// Runs a search and returns the number of nodes in the tree
+        pub fn search<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
+            root_game: &T,
+            policy: &U,
+            simulations: usize,
+            rng: &mut StdRng,
+        ) -> anyhow::Result<usize> {
+            let mut tree = Tree::new(Data {
+                game: root_game.clone(),
+                visits: 0,
+                score: 0.0,
+            });
+            for _ in 0..simulations {
+                let leaf = select_leaf(&tree, rng);
+                let mut node = tree.get_mut(leaf).unwrap();
+                let game = node.value().game.clone();
+                let points = match simulate(&game, policy, Players::Player, None, rng)? {
+                    RolloutOutcome::Finished(result, _) => result.points(),
+                    RolloutOutcome::CutOff(_) => 0.0,
+                };
+                if !game.game_ended() {
+                    for mv in move_indices(&game) {
+                        let mut child = game.clone();
+                        child.try_perform_move(mv)?;
+                        node.append(Data {
+                            game: child,
+                            visits: 0,
+                            score: 0.0,
+                        });
+                    }
+                }
+                backprop(&mut node, points);
+            }
+            Ok(tree.nodes().count())
+        }
+    }
+
+    // cargo test --release -- --ignored --nocapture search_speed
+    #[test]
+    #[ignore = "benchmark, run in release"]
+    fn search_speed() -> anyhow::Result<()> {
+        const SEARCHES: usize = 20;
+        let game = Hex::<25, 50>::new();
+        let policy = RandomPolicy::default();
+        let config = MctsConfig::default();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let start = Instant::now();
+        let mut old_nodes = 0;
+        for _ in 0..SEARCHES {
+            old_nodes += ego_tree_search::search(&game, &policy, config.simulations, &mut rng)?;
+        }
+        let old_rate = old_nodes as f32 / start.elapsed().as_secs_f32();
+
+        let start = Instant::now();
+        let mut new_nodes = 0;
+        for _ in 0..SEARCHES {
+            new_nodes += mcts(&game, &policy, 0, &config, &mut rng)?.nodes;
+        }
+        let new_rate = new_nodes as f32 / start.elapsed().as_secs_f32();
+
+        println!(
+            "ego_tree {:.0} nodes/s, arena {:.0} nodes/s, {:.2}x",
+            old_rate,
+            new_rate,
+            new_rate / old_rate
+        );
+        Ok(())
     }
 }
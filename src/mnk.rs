@@ -0,0 +1,244 @@
+use std::fmt::Display;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    game::{
+        encode_board, simple_board_planes, swap_board, Game, Players, SimpleBoardState, SpatialGame,
+    },
+    mcts::GameStats,
+    render::{AsciiRenderer, Board, BoardRenderer},
+};
+
+/// m,n,k-game: players take turns placing stones on a W wide board with T squares, the first to
+/// get K in a row wins. T is the total squares and U = 2 * T the state length, like in Hex
+#[derive(Debug, Clone)]
+pub struct MnkGame<const T: usize, const U: usize, const W: usize, const K: usize> {
+    // Row major
+    board: [SimpleBoardState; T],
+    current_player: Players,
+    winning_player: Option<Players>,
+    // Squares played so far, for undo_move
+    history: Vec<usize>,
+}
+
+pub type TicTacToe = MnkGame<9, 18, 3, 3>;
+/// Freestyle Gomoku, five or more in a row wins
+pub type Gomoku = MnkGame<225, 450, 15, 5>;
+/// Six in a row on a Go board. Real Connect6 places two stones per turn, this does not
+pub type ConnectSix = MnkGame<361, 722, 19, 6>;
+
+impl<const T: usize, const U: usize, const W: usize, const K: usize> MnkGame<T, U, W, K> {
+    const HEIGHT: usize = T / W;
+
+    // Stones of the same player in a row through `square` along the direction, both ways
+    fn line_length(&self, square: usize, direction: (isize, isize)) -> usize {
+        let piece = self.board[square];
+        let mut length = 1;
+        for sign in [1, -1] {
+            let (mut r, mut c) = ((square / W) as isize, (square % W) as isize);
+            loop {
+                r += direction.0 * sign;
+                c += direction.1 * sign;
+                if r < 0 || r >= Self::HEIGHT as isize || c < 0 || c >= W as isize {
+                    break;
+                }
+                if self.board[r as usize * W + c as usize] != piece {
+                    break;
+                }
+                length += 1;
+            }
+        }
+        length
+    }
+
+    // Where `square` ends up under one of the board symmetries. 0..4 mirror the rows and
+    // columns, square boards also have 4..8 which transpose first
+    fn symmetric_square(square: usize, symmetry: usize) -> usize {
+        let (mut r, mut c) = (square / W, square % W);
+        if symmetry >= 4 {
+            (r, c) = (c, r);
+        }
+        if symmetry & 1 == 1 {
+            c = W - 1 - c;
+        }
+        if symmetry & 2 == 2 {
+            r = Self::HEIGHT - 1 - r;
+        }
+        r * W + c
+    }
+}
+
+impl<const T: usize, const U: usize, const W: usize, const K: usize> Game<T, U>
+    for MnkGame<T, U, W, K>
+{
+    fn winning_player(&self) -> Option<Players> {
+        self.winning_player
+    }
+
+    fn available_moves(&self) -> [bool; T] {
+        if self.winning_player.is_some() {
+            return [false; T];
+        }
+        self.board.map(|square| square == SimpleBoardState::Empty)
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(
+            self.board.get(space) == Some(&SimpleBoardState::Empty),
+            "Tried to place a stone on an occupied square"
+        );
+        self.board[space] = self.current_player.into();
+        let directions = [(0, 1), (1, 0), (1, 1), (1, -1)];
+        if directions
+            .into_iter()
+            .any(|direction| self.line_length(space, direction) >= K)
+        {
+            self.winning_player = Some(self.current_player);
+        }
+        self.history.push(space);
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    // No moves are allowed after a win, so the position before any move had no winner
+    fn undo_move(&mut self) -> Result<()> {
+        let space = self.history.pop().context("No move to undo")?;
+        self.board[space] = SimpleBoardState::Empty;
+        self.winning_player = None;
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    fn new() -> Self {
+        assert!(
+            T * 2 == U,
+            "Bad dimensions on mnk generics, U has to equal T*2"
+        );
+        assert!(T % W == 0, "T has to be a multiple of the width W");
+        assert!(
+            K <= W.max(Self::HEIGHT),
+            "K in a row does not fit on the board"
+        );
+        Self {
+            board: [SimpleBoardState::Empty; T],
+            current_player: Players::Player,
+            winning_player: None,
+            history: Vec::new(),
+        }
+    }
+
+    fn game_ended(&self) -> bool {
+        self.winning_player.is_some() || !self.board.contains(&SimpleBoardState::Empty)
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    fn flip_board(&mut self) {
+        swap_board(&mut self.board);
+        self.current_player = self.current_player.swap();
+        self.winning_player = self.winning_player.map(|player| player.swap());
+    }
+
+    fn get_game_state_slice(&self) -> [f32; U] {
+        encode_board(&self.board)
+    }
+
+    fn board(&self) -> Option<Board> {
+        Some(Board::read(self))
+    }
+
+    fn grid(&self) -> Option<(usize, usize)> {
+        Some((Self::HEIGHT, W))
+    }
+
+    // All 8 rotations and reflections on square boards, the 4 mirrorings otherwise
+    fn get_game_variations(stats: &GameStats<T, U>) -> Vec<GameStats<T, U>> {
+        let symmetries = if W == Self::HEIGHT { 8 } else { 4 };
+        (0..symmetries)
+            .map(|symmetry| {
+                let mut variation = stats.clone();
+                for square in 0..T {
+                    let target = Self::symmetric_square(square, symmetry);
+                    variation.node_visits[target] = stats.node_visits[square];
+                    variation.game_state[target] = stats.game_state[square];
+                    variation.game_state[T + target] = stats.game_state[T + square];
+                }
+                variation.best_move_index = Self::symmetric_square(stats.best_move_index, symmetry);
+                variation
+            })
+            .collect()
+    }
+
+    // Lines of K squares each player can still complete, worth K to the power of the stones
+    // already in them. Squares on more lines are worth more, which in tic-tac-toe is the center
+    // and then the corners, and a line with K - 1 stones outweighs the rest so it gets blocked.
+    // Scaled by the most the lines could be worth short of a win
+    fn heuristic_value(&self) -> f32 {
+        let directions = [(0, 1), (1, 0), (1, 1), (1, -1)];
+        let (mut player, mut opponent, mut most) = (0.0, 0.0, 0.0);
+        for start in 0..T {
+            let (r, c) = ((start / W) as isize, (start % W) as isize);
+            for (dr, dc) in directions {
+                let (end_r, end_c) = (r + dr * (K as isize - 1), c + dc * (K as isize - 1));
+                if end_r >= Self::HEIGHT as isize || end_c < 0 || end_c >= W as isize {
+                    continue;
+                }
+                let (mut own, mut other) = (0, 0);
+                for step in 0..K as isize {
+                    match self.board[((r + dr * step) as usize) * W + (c + dc * step) as usize] {
+                        SimpleBoardState::Player => own += 1,
+                        SimpleBoardState::Opponent => other += 1,
+                        SimpleBoardState::Empty => {}
+                    }
+                }
+                if other == 0 {
+                    player += (K as f32).powi(own);
+                }
+                if own == 0 {
+                    opponent += (K as f32).powi(other);
+                }
+                most += (K as f32).powi(K as i32 - 1);
+            }
+        }
+        ((player - opponent) / most).clamp(-1.0, 1.0)
+    }
+}
+
+impl<const T: usize, const U: usize, const W: usize, const K: usize> Display
+    for MnkGame<T, U, W, K>
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", AsciiRenderer.render(self))
+    }
+}
+
+impl<const T: usize, const U: usize, const W: usize, const K: usize> SpatialGame<T, U>
+    for MnkGame<T, U, W, K>
+{
+    fn plane_shape(&self) -> (usize, usize, usize) {
+        (3, T / W, W)
+    }
+
+    fn spatial_planes(&self) -> Vec<f32> {
+        simple_board_planes(&self.board, self.current_player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::perft;
+
+    #[test]
+    fn perft_matches_known_counts() -> Result<()> {
+        const COUNTS: [u64; 9] = [9, 72, 504, 3024, 15120, 54720, 148176, 200448, 127872];
+        for (depth, expected) in (1..).zip(COUNTS) {
+            let count = perft(&mut TicTacToe::new(), depth)?;
+            assert_eq!(count, expected, "Tic-tac-toe perft({})", depth);
+        }
+        Ok(())
+    }
+}
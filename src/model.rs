@@ -1,40 +1,606 @@
 use crate::{
     dataset::Dataset,
-    game::{Game, Policy},
+    fit::softmax,
+    game::{Game, Players, Policy},
+    replay::ReplayConfig,
 };
-use anyhow::{Ok, Result};
+use anyhow::{Context, Ok, Result};
+use itertools::Itertools;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
 
 pub trait TrainableModel<const N: usize, const I: usize> {
-    fn new() -> Result<Self>
+    /// Architecture and optimizer hyperparameters
+    type Config: Clone + std::fmt::Debug + Default;
+    fn new(config: &Self::Config) -> Result<Self>
     where
         Self: Sized;
-    fn train(&mut self, dataset: Dataset<N, I>) -> Result<()>;
-    fn predict(&self, state: [f32; I]) -> Result<([f32; N], f32)>;
-    fn predict_moves(&self, state: [f32; I]) -> Result<[f32; N]>;
+    fn train(&mut self, dataset: Dataset<N, I>) -> Result<TrainReport>;
+    /// Forgets the optimizer state like moment estimates, keeping the weights
+    fn reset_optimizer(&mut self) -> Result<()>;
+    /// Generation the next call to train belongs to, for learning rates that decay by generation
+    fn set_generation(&mut self, _generation: usize) {}
+    /// Writes the weights to `path`
+    fn save(&self, path: &str) -> Result<()>;
+    /// Model with the weights written by save, the optimizer starts out fresh. Models with a
+    /// Config are built with the one saved alongside the weights, or the default one for files
+    /// saved before it was stored
+    fn load(path: &str) -> Result<Self>
+    where
+        Self: Sized;
+    /// Model with the weights written by save that is only used to predict, like the ones of
+    /// self-play workers. It can skip what training needs, like an optimizer
+    fn load_inference(path: &str) -> Result<Self>
+    where
+        Self: Sized,
+    {
+        Self::load(path)
+    }
+    /// Copy of the current weights on the self-play device that is only used to predict. Training
+    /// makes a new one after every round, which is how the new weights reach self-play. The candle
+    /// models compute in candle_ai::self_play_dtype
+    fn for_self_play(&self) -> Result<Self>
+    where
+        Self: Sized;
+    /// Number of weights, everything save writes
+    fn parameter_count(&self) -> usize;
+    /// The layers with the shapes of their weights and the FLOPs they take per state
+    fn summary(&self) -> Architecture;
+    /// Seed the weights were initialized with, None when it is not known like for weights saved
+    /// before seeds were
+    fn init_seed(&self) -> Option<u64> {
+        None
+    }
+    /// Move distribution over the moves true in `legal_moves` and the score
+    fn predict(&self, state: [f32; I], legal_moves: &[bool; N]) -> Result<([f32; N], f32)>;
+    fn predict_moves(&self, state: [f32; I], legal_moves: &[bool; N]) -> Result<[f32; N]>;
+    /// Policy before the softmax, the moves false in `legal_moves` at fit::ILLEGAL_LOGIT
+    fn predict_logits(&self, state: [f32; I], legal_moves: &[bool; N]) -> Result<[f32; N]>;
+    /// Move distribution like predict_moves with the logits divided by `temperature`, see
+    /// fit::softmax
+    fn predict_moves_at(
+        &self,
+        state: [f32; I],
+        legal_moves: &[bool; N],
+        temperature: f32,
+    ) -> Result<[f32; N]> {
+        Ok(softmax(
+            self.predict_logits(state, legal_moves)?,
+            temperature,
+        ))
+    }
     fn predict_score(&self, state: [f32; I]) -> Result<f32>;
+    /// Final score margin from an auxiliary head trained on Dataset::extra_targets, for models
+    /// that have one
+    fn predict_margin(&self, _state: [f32; I]) -> Result<Option<f32>> {
+        Ok(None)
+    }
+    /// Game::ownership of every cell in the frame of the state, for models with an ownership
+    /// head
+    fn predict_ownership(&self, _state: [f32; I]) -> Result<Option<[f32; N]>> {
+        Ok(None)
+    }
+}
+
+/// Losses in the last training step, each averaged over the samples it applies to
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TrainingMetrics {
+    /// Cross-entropy of the move policy against the visit distribution
+    pub policy_loss: f32,
+    /// Squared error of the value
+    pub value_loss: f32,
+    /// Squared error of the score margin over the samples that have one
+    pub margin_loss: f32,
+    /// Squared error of the ownership per cell over the samples that have it
+    pub ownership_loss: f32,
+    /// Optimizer steps left out because the loss or the gradients were not finite, the losses
+    /// are over the other steps
+    pub skipped_steps: usize,
+}
+
+impl std::fmt::Display for TrainingMetrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let skipped = match self.skipped_steps {
+            0 => String::new(),
+            skipped => format!(", skipped steps {}", skipped),
+        };
+        write!(
+            f,
+            "policy loss {:.4}, value loss {:.4}, margin loss {:.4}, ownership loss {:.4}{}",
+            self.policy_loss, self.value_loss, self.margin_loss, self.ownership_loss, skipped
+        )
+    }
+}
+
+/// Losses of one epoch of training
+#[derive(Clone, Copy, Debug)]
+pub struct EpochReport {
+    /// Mean losses of the optimizer steps of the epoch
+    pub training: TrainingMetrics,
+    /// Losses on the held out samples after the epoch, None without a validation split
+    pub validation: Option<TrainingMetrics>,
+    pub duration: Duration,
+}
+
+/// What a call to train did, epoch by epoch
+#[derive(Clone, Debug, Default)]
+pub struct TrainReport {
+    pub epochs: Vec<EpochReport>,
+    /// Index of the epoch the model kept the weights of, the one with the lowest validation loss
+    /// or else the last one
+    pub best_epoch: usize,
+    /// Whether training stopped before all epochs as the validation loss stopped improving
+    pub stopped_early: bool,
+    pub duration: Duration,
+}
+
+impl TrainReport {
+    /// The epoch the model kept the weights of
+    pub fn best(&self) -> Option<&EpochReport> {
+        self.epochs.get(self.best_epoch)
+    }
+
+    pub fn skipped_steps(&self) -> usize {
+        self.epochs
+            .iter()
+            .map(|epoch| epoch.training.skipped_steps)
+            .sum()
+    }
+
+    /// One line per epoch with its losses and duration in seconds, validation losses are empty
+    /// without a validation split
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from(
+            "epoch,policy_loss,value_loss,margin_loss,ownership_loss,skipped_steps,\
+             validation_policy_loss,validation_value_loss,validation_margin_loss,\
+             validation_ownership_loss,seconds\n",
+        );
+        for (i, epoch) in self.epochs.iter().enumerate() {
+            let training = &epoch.training;
+            let validation = match &epoch.validation {
+                Some(validation) => format!(
+                    "{},{},{},{}",
+                    validation.policy_loss,
+                    validation.value_loss,
+                    validation.margin_loss,
+                    validation.ownership_loss
+                ),
+                None => String::from(",,,"),
+            };
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{}\n",
+                i + 1,
+                training.policy_loss,
+                training.value_loss,
+                training.margin_loss,
+                training.ownership_loss,
+                training.skipped_steps,
+                validation,
+                epoch.duration.as_secs_f64()
+            ));
+        }
+        csv
+    }
+
+    pub fn write_csv(&self, path: &str) -> Result<()> {
+        std::fs::write(path, self.to_csv())
+            .with_context(|| format!("Writing training report to {}", path))
+    }
+}
+
+impl std::fmt::Display for TrainReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} epochs in {:?}", self.epochs.len(), self.duration)?;
+        if self.stopped_early {
+            write!(f, ", stopped early")?;
+        }
+        let Some(best) = self.best() else {
+            return std::fmt::Result::Ok(());
+        };
+        write!(f, ", epoch {}: {}", self.best_epoch + 1, best.training)?;
+        match &best.validation {
+            Some(validation) => write!(f, ", validation {}", validation),
+            None => std::fmt::Result::Ok(()),
+        }
+    }
+}
+
+/// What each generation of training_loop starts from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WarmStart {
+    /// A new randomly initialized model, so every generation only learns from the newest dataset
+    Scratch,
+    /// The previous generation's model, optimizer state included. How AlphaZero trains
+    KeepOptimizer,
+    /// The previous generation's weights with a fresh optimizer
+    ResetOptimizer,
+}
+
+/// Hyperparameters of the small models
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ModelConfig {
+    /// Width of the hidden layers, filters of the convolutions
+    pub hidden_size: usize,
+    /// Number of hidden layers or convolutions
+    pub depth: usize,
+    /// Share of the units of the fully connected hidden layers zeroed in every training step, 0
+    /// for no dropout. Predictions always use all of them
+    pub dropout: f32,
+    /// Seed of the initial weights, None for a random one. Either way it is saved with the weights
+    pub seed: Option<u64>,
+    /// Adds an auxiliary head predicting Game::ownership, which gives connection games a target
+    /// for every cell rather than only the one outcome
+    pub ownership: bool,
+    /// Height and width of the board ConvModel reads the planes of, see conv_model::spatial_board.
+    /// None for a square board with one move per square. The other models read the flat state
+    pub board: Option<(usize, usize)>,
+    pub fit: FitConfig,
+}
+
+impl Default for ModelConfig {
+    fn default() -> Self {
+        Self {
+            hidden_size: 32,
+            depth: 2,
+            dropout: 0.0,
+            seed: None,
+            ownership: false,
+            board: None,
+            fit: FitConfig::default(),
+        }
+    }
+}
+
+/// How TrainableModel::train fits the weights to a dataset
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FitConfig {
+    pub learning_rate: f64,
+    /// Passes over the dataset in every call to train
+    pub epochs: usize,
+    /// Samples in every optimizer step
+    pub batch_size: usize,
+    pub last_batch: LastBatch,
+    /// Seed for the order of the samples and the validation split, the same seed shuffles the
+    /// same dataset the same way
+    pub seed: Option<u64>,
+    /// Share of the samples held out to measure the validation loss on, 0 trains on everything
+    pub validation_fraction: f32,
+    /// Epochs without a better validation loss after which training stops, None for all epochs
+    pub patience: Option<usize>,
+    pub schedule: LrSchedule,
+    /// Global norm the gradients of a step are scaled down to when they are larger
+    pub max_grad_norm: Option<f64>,
+}
+
+impl Default for FitConfig {
+    // An epoch takes a step per batch, so it needs far fewer than full batches did
+    fn default() -> Self {
+        Self {
+            learning_rate: 1e-2,
+            epochs: 10,
+            batch_size: 256,
+            last_batch: LastBatch::Keep,
+            seed: None,
+            validation_fraction: 0.1,
+            patience: Some(3),
+            schedule: LrSchedule::default(),
+            max_grad_norm: Some(1.0),
+        }
+    }
+}
+
+/// How the learning rate changes from learning_rate, the default keeps it constant
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LrSchedule {
+    /// Steps at the start of every call to train over which the rate rises linearly to its full
+    /// value, large networks diverge when they start at full rate
+    pub warmup_steps: usize,
+    /// Whether the rate follows half a cosine from its full value down to 0 over the steps of every
+    /// call to train after the warmup
+    pub cosine: bool,
+    /// Generations after which the rate is multiplied by decay_factor, None for no step decay
+    pub decay_every: Option<usize>,
+    pub decay_factor: f64,
+}
+
+impl Default for LrSchedule {
+    fn default() -> Self {
+        Self {
+            warmup_steps: 0,
+            cosine: false,
+            decay_every: None,
+            decay_factor: 0.1,
+        }
+    }
+}
+
+impl LrSchedule {
+    /// Rate for step `step` of the `steps` of a call to train in `generation`
+    pub fn learning_rate(&self, base: f64, generation: usize, step: usize, steps: usize) -> f64 {
+        let decays = self
+            .decay_every
+            .map_or(0, |every| generation / every.max(1));
+        let rate = base * self.decay_factor.powi(decays as i32);
+        if step < self.warmup_steps {
+            return rate * (step + 1) as f64 / self.warmup_steps as f64;
+        }
+        if !self.cosine {
+            return rate;
+        }
+        let progress = (step - self.warmup_steps) as f64
+            / steps.saturating_sub(self.warmup_steps).max(1) as f64;
+        rate * 0.5 * (1.0 + (std::f64::consts::PI * progress.min(1.0)).cos())
+    }
+}
+
+impl FitConfig {
+    pub fn rng(&self) -> StdRng {
+        match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        }
+    }
+}
+
+/// What happens to the samples left over when the dataset is not a whole number of batches
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LastBatch {
+    /// Train on them as a smaller batch
+    Keep,
+    /// Leave them out of the epoch, they are others every epoch as the samples are shuffled
+    Drop,
+    /// Fill the batch up with samples from the start of the epoch
+    Pad,
+}
+
+/// How training_loop trains its models
+#[derive(Clone, Debug)]
+pub struct TrainingConfig<C> {
+    pub generations: usize,
+    pub warm_start: WarmStart,
+    /// Architecture of every model built from scratch
+    pub model: C,
+    /// Architecture of a smaller model distilled from every generation's model on the states it
+    /// was trained on, None for no distillation. It keeps its weights from one generation to
+    /// the next
+    pub student: Option<C>,
+    /// Which self-play data every generation trains on
+    pub replay: ReplayConfig,
+}
+
+/// One layer of a model and what a forward pass of one state costs in it
+#[derive(Clone, Debug)]
+pub struct LayerSummary {
+    /// Name the weights are saved under, without the last part like weight or bias
+    pub name: String,
+    /// Shape of the layer's weight, outputs first
+    pub shape: Vec<usize>,
+    pub parameters: usize,
+    /// Multiplications and additions, each counted once
+    pub flops: u64,
+}
+
+/// The layers of a model by name
+#[derive(Clone, Debug, Default)]
+pub struct Architecture {
+    pub layers: Vec<LayerSummary>,
+}
+
+impl Architecture {
+    /// Layers of the tensors with `shapes` by name, in a model whose convolutions keep boards of
+    /// `squares` cells. A dense weight takes a multiply-add per entry, a convolution one per entry
+    /// and square, and a batch norm a scale and a shift per channel and square. Activations and
+    /// biases are not counted
+    pub fn from_shapes(
+        shapes: impl IntoIterator<Item = (String, Vec<usize>)>,
+        squares: usize,
+    ) -> Self {
+        let mut layers: BTreeMap<String, LayerSummary> = BTreeMap::new();
+        for (name, shape) in shapes {
+            let (layer, part) = name.rsplit_once('.').unwrap_or((&name, ""));
+            let summary = layers
+                .entry(layer.to_string())
+                .or_insert_with(|| LayerSummary {
+                    name: layer.to_string(),
+                    shape: Vec::new(),
+                    parameters: 0,
+                    flops: 0,
+                });
+            let count: usize = shape.iter().product();
+            summary.parameters += count;
+            if part != "weight" {
+                continue;
+            }
+            summary.flops = 2 * count as u64 * if shape.len() == 2 { 1 } else { squares as u64 };
+            summary.shape = shape;
+        }
+        Self {
+            layers: layers.into_values().collect(),
+        }
+    }
+
+    pub fn parameters(&self) -> usize {
+        self.layers.iter().map(|layer| layer.parameters).sum()
+    }
+
+    /// FLOPs of a forward pass of one state
+    pub fn flops(&self) -> u64 {
+        self.layers.iter().map(|layer| layer.flops).sum()
+    }
+}
+
+// A line per layer and one with the totals
+impl std::fmt::Display for Architecture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for layer in &self.layers {
+            let shape = layer.shape.iter().map(usize::to_string).join("x");
+            writeln!(
+                f,
+                "{:<24} {:<16} {:>10} weights {:>12} FLOPs",
+                layer.name, shape, layer.parameters, layer.flops
+            )?;
+        }
+        write!(
+            f,
+            "{} weights, {} FLOPs per state",
+            self.parameters(),
+            self.flops()
+        )
+    }
+}
+
+/// Size and speed of a model
+#[derive(Clone, Debug)]
+pub struct ModelSummary {
+    pub parameters: usize,
+    /// Architecture::flops of the model
+    pub flops: u64,
+    pub init_seed: Option<u64>,
+    /// Average time to predict a single position
+    pub forward_time: Duration,
+}
+
+impl std::fmt::Display for ModelSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} weights, {} FLOPs, {:?} per forward",
+            self.parameters, self.flops, self.forward_time
+        )?;
+        match self.init_seed {
+            Some(seed) => write!(f, ", initialized with seed {}", seed),
+            None => std::fmt::Result::Ok(()),
+        }
+    }
+}
+
+/// Summary of `model`, timing `runs` predictions of the empty state
+pub fn summarize<const N: usize, const I: usize, M: TrainableModel<N, I>>(
+    model: &M,
+    runs: usize,
+) -> Result<ModelSummary> {
+    let state = [0.0; I];
+    // The first prediction pays for allocations the later ones reuse
+    model.predict(state, &[true; N])?;
+    let start = Instant::now();
+    for _ in 0..runs {
+        model.predict(state, &[true; N])?;
+    }
+    Ok(ModelSummary {
+        parameters: model.parameter_count(),
+        flops: model.summary().flops(),
+        init_seed: model.init_seed(),
+        forward_time: start.elapsed() / runs.max(1) as u32,
+    })
+}
+
+/// How far the predictions of a model at reduced precision are from the full precision ones
+#[derive(Clone, Copy, Debug)]
+pub struct PrecisionReport {
+    pub positions: usize,
+    /// Largest difference of a move probability
+    pub max_policy_error: f32,
+    /// Largest difference of a value
+    pub max_value_error: f32,
+    /// Share of the positions where both models like the same move best
+    pub move_agreement: f32,
+}
+
+impl std::fmt::Display for PrecisionReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} positions, policy off by at most {:.5}, value by at most {:.5}, \
+             same best move in {:.1}%",
+            self.positions,
+            self.max_policy_error,
+            self.max_value_error,
+            100.0 * self.move_agreement
+        )
+    }
+}
+
+/// Compares the predictions of `reduced` to those of `reference` on the first `positions`
+/// samples of `dataset`
+pub fn compare_precision<const N: usize, const I: usize, M: TrainableModel<N, I>>(
+    reference: &M,
+    reduced: &M,
+    dataset: &Dataset<N, I>,
+    positions: usize,
+) -> Result<PrecisionReport> {
+    let positions = positions.min(dataset.game_states.len());
+    let best = |moves: &[f32; N]| {
+        (0..N)
+            .max_by(|a, b| moves[*a].total_cmp(&moves[*b]))
+            .unwrap_or(0)
+    };
+    let mut report = PrecisionReport {
+        positions,
+        max_policy_error: 0.0,
+        max_value_error: 0.0,
+        move_agreement: 0.0,
+    };
+    let mut agreeing = 0;
+    for (i, state) in dataset.game_states.iter().take(positions).enumerate() {
+        let legal = dataset.legal_moves.get(i).copied().unwrap_or([true; N]);
+        let (moves, score) = reference.predict(*state, &legal)?;
+        let (reduced_moves, reduced_score) = reduced.predict(*state, &legal)?;
+        for (a, b) in moves.iter().zip(&reduced_moves) {
+            report.max_policy_error = report.max_policy_error.max((a - b).abs());
+        }
+        report.max_value_error = report.max_value_error.max((score - reduced_score).abs());
+        if best(&moves) == best(&reduced_moves) {
+            agreeing += 1;
+        }
+    }
+    report.move_agreement = agreeing as f32 / positions.max(1) as f32;
+    Ok(report)
 }
 
 pub struct AiPolicy<const N: usize, const I: usize, M: TrainableModel<N, I>> {
     pub model: M,
+    /// Temperature of the priors handed to the search
+    pub prior_temperature: f32,
+    /// Temperature of the moves select_move samples, 0 always plays the most likely move
+    pub move_temperature: f32,
+}
+
+impl<const N: usize, const I: usize, M: TrainableModel<N, I>> AiPolicy<N, I, M> {
+    /// Policy with the model's own priors that plays its most likely move
+    pub fn new(model: M) -> Self {
+        Self {
+            model,
+            prior_temperature: 1.0,
+            move_temperature: 0.0,
+        }
+    }
+
+    // The model only knows positions with Player to move, so it gets the canonical state and its
+    // moves are mapped back to the frame of `game`. Unavailable moves get nothing
+    fn predict_moves<T: Game<N, I>>(&self, game: &T, temperature: f32) -> Result<[f32; N]> {
+        let (state, to_move) = game.canonical_state();
+        let visits = self
+            .model
+            .predict_moves_at(state, &game.canonical_moves(), temperature)?;
+        if to_move == Players::Player {
+            return Ok(visits);
+        }
+        Ok(std::array::from_fn(|mv| visits[game.flipped_move(mv)]))
+    }
 }
 
 impl<const N: usize, const I: usize, T: Game<N, I>, M: TrainableModel<N, I>> Policy<N, I, T>
     for AiPolicy<N, I, M>
 {
-    fn select_move(&self, game: &T) -> anyhow::Result<usize> {
-        let state = game.get_game_state_slice();
-        let move_mask: [f32; N] = game
-            .available_moves()
-            .map(|el| if el { 1.0 } else { 0.0 } as f32);
-        let visits = self.model.predict_moves(state)?;
-        let masked_visits: [f32; N] = visits
-            .iter()
-            .zip(move_mask)
-            .map(|(a, b)| a * b)
-            .collect::<Vec<_>>()
-            .try_into()
-            .unwrap();
-        let next_move = masked_visits
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> anyhow::Result<usize> {
+        let visits = self.predict_moves(game, self.move_temperature)?;
+        if self.move_temperature > 0.0 {
+            let moves: Vec<usize> = (0..N).collect();
+            return Ok(*moves.choose_weighted(rng, |mv| visits[*mv])?);
+        }
+        let next_move = visits
             .iter()
             .enumerate()
             .max_by(|(_, a), (_, b)| a.total_cmp(b))
@@ -43,21 +609,29 @@ impl<const N: usize, const I: usize, T: Game<N, I>, M: TrainableModel<N, I>> Pol
         Ok(next_move)
     }
 
-    fn select_moves_batch(&self, games: Vec<&T>) -> anyhow::Result<Vec<usize>> {
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> anyhow::Result<Vec<usize>> {
         // TODO: use actual batching
         Ok(games
             .iter()
-            .map(|game| self.select_move(*game))
+            .map(|game| self.select_move(*game, rng))
             .collect::<Result<Vec<_>>>()?)
     }
 
+    // The model scores for the player to move, the search wants the score for Player
     fn predict_score(&self, game: &T) -> anyhow::Result<f32> {
-        let state = game.get_game_state_slice();
+        let (state, to_move) = game.canonical_state();
         let score = self.model.predict_score(state)?;
-        Ok(score)
+        match to_move {
+            Players::Player => Ok(score),
+            Players::Opponent => Ok(-score),
+        }
     }
 
     fn can_predict_score(&self) -> bool {
         true
     }
+
+    fn predict_priors(&self, game: &T) -> anyhow::Result<[f32; N]> {
+        self.predict_moves(game, self.prior_temperature)
+    }
 }
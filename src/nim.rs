@@ -0,0 +1,221 @@
+use std::fmt::Display;
+
+use anyhow::{ensure, Context, Result};
+use rand::rngs::StdRng;
+
+use crate::{
+    game::{Game, Players, Policy},
+    mcts::GameStats,
+};
+
+const HEAPS: usize = 3;
+const MAX_HEAP: usize = 5;
+const START: [usize; HEAPS] = [3, 4, 5];
+pub const MOVES: usize = HEAPS * MAX_HEAP;
+pub const STATE_LEN: usize = HEAPS * (MAX_HEAP + 1);
+
+/// Nim with heaps of 3, 4 and 5, taking the last object wins. The optimal strategy is known,
+/// which makes it a check that search and training converge to perfect play
+#[derive(Debug, Clone)]
+pub struct Nim {
+    heaps: [usize; HEAPS],
+    current_player: Players,
+    // Moves played so far, for undo_move
+    history: Vec<usize>,
+}
+
+impl Nim {
+    /// Move index of taking `take` objects from `heap`
+    pub fn move_index(heap: usize, take: usize) -> usize {
+        heap * MAX_HEAP + take - 1
+    }
+
+    fn nim_sum(heaps: &[usize; HEAPS]) -> usize {
+        heaps.iter().fold(0, |sum, heap| sum ^ heap)
+    }
+
+    /// Moves that leave a nim-sum of zero, empty if every move loses against perfect play
+    pub fn optimal_moves(&self) -> Vec<usize> {
+        let sum = Self::nim_sum(&self.heaps);
+        if sum == 0 {
+            return Vec::new();
+        }
+        (0..HEAPS)
+            .filter(|heap| self.heaps[*heap] ^ sum < self.heaps[*heap])
+            .map(|heap| Self::move_index(heap, self.heaps[heap] - (self.heaps[heap] ^ sum)))
+            .collect()
+    }
+
+    /// Every position reachable from the start where the player to move can force a win
+    pub fn winning_positions() -> Vec<Nim> {
+        let mut positions = Vec::new();
+        for a in 0..=START[0] {
+            for b in 0..=START[1] {
+                for c in 0..=START[2] {
+                    let heaps = [a, b, c];
+                    if Self::nim_sum(&heaps) != 0 {
+                        positions.push(Nim {
+                            heaps,
+                            current_player: Players::Player,
+                            history: Vec::new(),
+                        });
+                    }
+                }
+            }
+        }
+        positions
+    }
+}
+
+/// Fraction of the winning positions in which the policy plays an optimal move
+pub fn optimal_move_rate<U: Policy<MOVES, STATE_LEN, Nim>>(
+    policy: &U,
+    rng: &mut StdRng,
+) -> anyhow::Result<f32> {
+    let positions = Nim::winning_positions();
+    let mut optimal = 0;
+    for position in &positions {
+        let selected = policy.select_move(position, rng)?;
+        if position.optimal_moves().contains(&selected) {
+            optimal += 1;
+        }
+    }
+    Ok(optimal as f32 / positions.len() as f32)
+}
+
+impl Game<MOVES, STATE_LEN> for Nim {
+    // Whoever took the last object, so the player left without a move loses
+    fn winning_player(&self) -> Option<Players> {
+        self.game_ended().then(|| self.current_player.swap())
+    }
+
+    fn available_moves(&self) -> [bool; MOVES] {
+        std::array::from_fn(|mv| mv % MAX_HEAP < self.heaps[mv / MAX_HEAP])
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(
+            space < MOVES,
+            "Tried to take from a heap that does not exist"
+        );
+        let (heap, take) = (space / MAX_HEAP, space % MAX_HEAP + 1);
+        ensure!(
+            take <= self.heaps[heap],
+            "Tried to take more than the heap holds"
+        );
+        self.heaps[heap] -= take;
+        self.history.push(space);
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    fn undo_move(&mut self) -> Result<()> {
+        let space = self.history.pop().context("No move to undo")?;
+        self.heaps[space / MAX_HEAP] += space % MAX_HEAP + 1;
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    fn new() -> Self {
+        Self {
+            heaps: START,
+            current_player: Players::Player,
+            history: Vec::new(),
+        }
+    }
+
+    fn game_ended(&self) -> bool {
+        self.heaps.iter().all(|heap| *heap == 0)
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    fn flip_board(&mut self) {
+        self.current_player = self.current_player.swap();
+    }
+
+    // One hot size of every heap
+    fn get_game_state_slice(&self) -> [f32; STATE_LEN] {
+        let mut out_slice = [0.0; STATE_LEN];
+        for (heap, size) in self.heaps.iter().enumerate() {
+            out_slice[heap * (MAX_HEAP + 1) + size] = 1.0;
+        }
+        out_slice
+    }
+
+    fn get_game_variations(
+        stats: &GameStats<MOVES, STATE_LEN>,
+    ) -> Vec<GameStats<MOVES, STATE_LEN>> {
+        vec![stats.clone()]
+    }
+}
+
+impl Display for Nim {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (heap, size) in self.heaps.iter().enumerate() {
+            writeln!(f, "{}: {}", heap, "|".repeat(*size))?;
+        }
+        let next_player = match self.current_player {
+            Players::Player => "X",
+            Players::Opponent => "O",
+        };
+        writeln!(f, "Next player: {}", next_player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::candle_ai::SimpleModel;
+    use crate::dataset::{create_dataset, SelfPlayConfig};
+    use crate::game::RandomPolicy;
+    use crate::mcts::{MctsConfig, MctsPolicy};
+    use crate::model::{AiPolicy, ModelConfig, TrainableModel};
+
+    // Trains like training_loop, from scratch every generation, and checks that the search with
+    // the last model finds an optimal move in nearly every winning position. Fewer games or
+    // simulations leave it hovering around the threshold, so it only runs with slow-tests
+    #[test]
+    #[cfg_attr(not(feature = "slow-tests"), ignore = "trains several generations")]
+    fn learns_optimal_play() -> Result<()> {
+        const GENERATIONS: usize = 3;
+        let config = SelfPlayConfig {
+            mcts: MctsConfig {
+                exploration_weight: 1.0,
+                ..Default::default()
+            },
+            seed: Some(0),
+            ..Default::default()
+        };
+        let mut rng = config.rng();
+        let mut dataset = create_dataset(&Nim::new(), 100, &RandomPolicy::default(), 0, &config)?;
+        let model_config = ModelConfig {
+            seed: Some(0),
+            ..Default::default()
+        };
+        let mut rate = 0.0;
+        for generation in 0..GENERATIONS {
+            let mut model = SimpleModel::<MOVES, STATE_LEN>::new(&model_config)?;
+            model.train(dataset)?;
+            let search = MctsPolicy {
+                policy: AiPolicy::new(model),
+                config: config.mcts.clone(),
+                generation,
+            };
+            rate = optimal_move_rate(&search, &mut rng)?;
+            if generation + 1 == GENERATIONS {
+                break;
+            }
+            dataset = create_dataset(&Nim::new(), 50, &search.policy, generation, &config)?;
+        }
+        assert!(
+            rate >= 0.95,
+            "Optimal moves in {:.1}% of the winning positions after {} generations",
+            rate * 100.0,
+            GENERATIONS
+        );
+        Ok(())
+    }
+}
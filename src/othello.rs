@@ -0,0 +1,367 @@
+use std::fmt::Display;
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    game::{
+        encode_board, simple_board_planes, swap_board, Game, Players, SimpleBoardState, SpatialGame,
+    },
+    mcts::GameStats,
+    render::{AsciiRenderer, Board, BoardRenderer},
+};
+
+const SIDE: usize = 8;
+const SQUARES: usize = SIDE * SIDE;
+/// Move index of passing, only legal when no disc can be placed
+pub const PASS: usize = SQUARES;
+/// The discs and the komi
+pub const STATE_LEN: usize = SQUARES * 3;
+const DIRECTIONS: [(isize, isize); 8] = [
+    (-1, -1),
+    (-1, 0),
+    (-1, 1),
+    (0, -1),
+    (0, 1),
+    (1, -1),
+    (1, 0),
+    (1, 1),
+];
+
+#[derive(Debug, Clone)]
+pub struct Othello {
+    // Row major 8x8 board, moves 0..64 place a disc on that square and 64 passes
+    board: [SimpleBoardState; SQUARES],
+    current_player: Players,
+    // Consecutive passes, the game ends when neither player can move
+    passes: usize,
+    // Move, flipped discs and the passes before it for every move played, for undo_move
+    history: Vec<(usize, Vec<usize>, usize)>,
+    // Added to the discs of Player when scoring, negative when Opponent gets the komi. Negated by
+    // flip_board along with the discs
+    komi: f32,
+}
+
+impl Othello {
+    /// Othello where the second player gets `komi` extra discs when scoring. A fractional komi
+    /// rules out ties, which self-play otherwise learns little from
+    pub fn with_komi(komi: f32) -> Self {
+        Self {
+            komi: -komi,
+            ..Self::new()
+        }
+    }
+
+    // Disc difference including the komi, from the perspective of Player
+    fn score_difference(&self) -> f32 {
+        let (player, opponent) = self.disc_count();
+        player as f32 + self.komi - opponent as f32
+    }
+
+    // Squares that placing a disc of the current player on `square` would flip
+    fn flips(&self, square: usize) -> Vec<usize> {
+        let mut flips = Vec::new();
+        if self.board[square] != SimpleBoardState::Empty {
+            return flips;
+        }
+        let own: SimpleBoardState = self.current_player.into();
+        let (row, column) = ((square / SIDE) as isize, (square % SIDE) as isize);
+        for (dr, dc) in DIRECTIONS {
+            let mut line = Vec::new();
+            let (mut r, mut c) = (row + dr, column + dc);
+            while (0..SIDE as isize).contains(&r) && (0..SIDE as isize).contains(&c) {
+                let index = r as usize * SIDE + c as usize;
+                match self.board[index] {
+                    SimpleBoardState::Empty => break,
+                    piece if piece == own => {
+                        flips.append(&mut line);
+                        break;
+                    }
+                    _ => line.push(index),
+                }
+                r += dr;
+                c += dc;
+            }
+        }
+        flips
+    }
+
+    fn placements(&self) -> [bool; SQUARES] {
+        std::array::from_fn(|square| !self.flips(square).is_empty())
+    }
+
+    /// Discs of Player and Opponent
+    pub fn disc_count(&self) -> (usize, usize) {
+        let count = |piece| self.board.iter().filter(|square| **square == piece).count();
+        (
+            count(SimpleBoardState::Player),
+            count(SimpleBoardState::Opponent),
+        )
+    }
+}
+
+impl Game<{ SQUARES + 1 }, STATE_LEN> for Othello {
+    // The player with more discs when the game has ended, None for a tie or a running game
+    fn winning_player(&self) -> Option<Players> {
+        if !self.game_ended() {
+            return None;
+        }
+        let difference = self.score_difference();
+        if difference > 0.0 {
+            Some(Players::Player)
+        } else if difference < 0.0 {
+            Some(Players::Opponent)
+        } else {
+            None
+        }
+    }
+
+    fn available_moves(&self) -> [bool; SQUARES + 1] {
+        let mut moves = [false; SQUARES + 1];
+        if self.game_ended() {
+            return moves;
+        }
+        let placements = self.placements();
+        moves[..SQUARES].copy_from_slice(&placements);
+        moves[PASS] = !placements.iter().any(|x| *x);
+        moves
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(space <= PASS, "Tried to place a disc outside the board");
+        let passes = self.passes;
+        let flips = if space == PASS {
+            ensure!(
+                !self.placements().iter().any(|x| *x),
+                "Tried to pass with a legal placement"
+            );
+            self.passes += 1;
+            Vec::new()
+        } else {
+            let flips = self.flips(space);
+            ensure!(
+                !flips.is_empty(),
+                "Tried to place a disc that flips nothing"
+            );
+            let own = self.current_player.into();
+            self.board[space] = own;
+            for square in &flips {
+                self.board[*square] = own;
+            }
+            self.passes = 0;
+            flips
+        };
+        self.history.push((space, flips, passes));
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    fn undo_move(&mut self) -> Result<()> {
+        let (space, flips, passes) = self.history.pop().context("No move to undo")?;
+        // Back to the player who made the move, the discs it flipped were the opponent's
+        self.current_player = self.current_player.swap();
+        if space != PASS {
+            self.board[space] = SimpleBoardState::Empty;
+            for square in flips {
+                self.board[square] = self.current_player.swap().into();
+            }
+        }
+        self.passes = passes;
+        Ok(())
+    }
+
+    fn new() -> Self {
+        let mut board = [SimpleBoardState::Empty; SQUARES];
+        board[3 * SIDE + 3] = SimpleBoardState::Opponent;
+        board[4 * SIDE + 4] = SimpleBoardState::Opponent;
+        board[3 * SIDE + 4] = SimpleBoardState::Player;
+        board[4 * SIDE + 3] = SimpleBoardState::Player;
+        Self {
+            board,
+            current_player: Players::Player,
+            passes: 0,
+            history: Vec::new(),
+            komi: 0.0,
+        }
+    }
+
+    fn game_ended(&self) -> bool {
+        self.passes >= 2
+    }
+
+    fn pass_move(&self) -> Option<usize> {
+        Some(PASS)
+    }
+
+    fn has_score_margin(&self) -> bool {
+        true
+    }
+
+    // Disc difference over the whole board, so a wipeout is worth 1 unless the komi made up for
+    // some of it
+    fn terminal_value(&self, perspective: Players) -> Option<f32> {
+        if !self.game_ended() {
+            return None;
+        }
+        let margin = (self.score_difference() / SQUARES as f32).clamp(-1.0, 1.0);
+        Some(match perspective {
+            Players::Player => margin,
+            Players::Opponent => -margin,
+        })
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    fn flip_board(&mut self) {
+        swap_board(&mut self.board);
+        self.current_player = self.current_player.swap();
+        self.komi = -self.komi;
+    }
+
+    // The discs and a plane of Player's komi relative to the squares, so the value of a position
+    // can depend on who gets the komi
+    fn get_game_state_slice(&self) -> [f32; STATE_LEN] {
+        let mut state: [f32; STATE_LEN] = encode_board(&self.board);
+        state[SQUARES * 2..].fill(self.komi / SQUARES as f32);
+        state
+    }
+
+    fn board(&self) -> Option<Board> {
+        Some(Board::read(self))
+    }
+
+    fn get_game_variations(
+        stats: &GameStats<{ SQUARES + 1 }, STATE_LEN>,
+    ) -> Vec<GameStats<{ SQUARES + 1 }, STATE_LEN>> {
+        vec![stats.clone()]
+    }
+
+    fn heuristic_value(&self) -> f32 {
+        let (player, opponent) = self.disc_count();
+        (player as f32 - opponent as f32) / (player + opponent) as f32
+    }
+}
+
+// The komi plane of the state comes before the one of the player to move, for the player to move
+impl SpatialGame<{ SQUARES + 1 }, STATE_LEN> for Othello {
+    fn plane_shape(&self) -> (usize, usize, usize) {
+        (4, SIDE, SIDE)
+    }
+
+    fn spatial_planes(&self) -> Vec<f32> {
+        let komi = match self.current_player {
+            Players::Player => self.komi,
+            Players::Opponent => -self.komi,
+        };
+        let mut planes = simple_board_planes(&self.board, self.current_player);
+        planes.splice(
+            SQUARES * 2..SQUARES * 2,
+            std::iter::repeat_n(komi / SQUARES as f32, SQUARES),
+        );
+        planes
+    }
+}
+
+impl Display for Othello {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", AsciiRenderer.render(self))?;
+        let (player, opponent) = self.disc_count();
+        writeln!(f, "X: {player} O: {opponent}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Player to move with only the given discs on the board
+    fn position(player: &[usize], opponent: &[usize]) -> Othello {
+        let mut game = Othello::new();
+        game.board = [SimpleBoardState::Empty; SQUARES];
+        for square in player {
+            game.board[*square] = SimpleBoardState::Player;
+        }
+        for square in opponent {
+            game.board[*square] = SimpleBoardState::Opponent;
+        }
+        game
+    }
+
+    #[test]
+    fn placing_flips_in_every_direction() -> Result<()> {
+        let at = |(r, c): (isize, isize), distance: isize| -> Vec<usize> {
+            DIRECTIONS
+                .iter()
+                .map(|(dr, dc)| ((r + dr * distance) * SIDE as isize + c + dc * distance) as usize)
+                .collect()
+        };
+        let (neighbours, ends) = (at((3, 3), 1), at((3, 3), 2));
+        let mut game = position(&ends, &neighbours);
+        let mut flips = game.flips(27);
+        flips.sort();
+        assert_eq!(flips, neighbours);
+        game.try_perform_move(27)?;
+        assert_eq!(game.disc_count(), (17, 0));
+        game.undo_move()?;
+        assert_eq!(game.disc_count(), (8, 8));
+
+        // Nothing to close the line off, nothing flipped in that direction
+        let game = position(&ends[1..], &neighbours);
+        assert_eq!(game.flips(27).len(), 7);
+        Ok(())
+    }
+
+    #[test]
+    fn passing_is_only_legal_without_placements() -> Result<()> {
+        let mut game = Othello::new();
+        assert!(!game.available_moves()[PASS]);
+        assert!(game.try_perform_move(PASS).is_err());
+
+        let mut game = position(&[1], &[0]);
+        let moves = game.available_moves();
+        assert!(moves[PASS] && moves[..SQUARES].iter().all(|available| !available));
+        game.try_perform_move(PASS)?;
+        assert!(!game.game_ended());
+        assert!(game.available_moves()[2]);
+        assert!(!game.available_moves()[PASS]);
+        Ok(())
+    }
+
+    #[test]
+    fn two_passes_end_the_game() -> Result<()> {
+        let mut game = position(&[0], &[63]);
+        game.try_perform_move(PASS)?;
+        assert!(!game.game_ended());
+        game.try_perform_move(PASS)?;
+        assert!(game.game_ended());
+        assert_eq!(game.available_moves(), [false; SQUARES + 1]);
+        assert_eq!(game.winning_player(), None);
+        assert_eq!(game.terminal_value(Players::Player), Some(0.0));
+        Ok(())
+    }
+
+    #[test]
+    fn flipping_the_board_gives_the_komi_to_the_other_side() -> Result<()> {
+        let mut game = Othello::with_komi(2.5);
+        game.board = position(&[0], &[63]).board;
+        let komi = 2.5 / SQUARES as f32;
+        assert!(game.get_game_state_slice()[SQUARES * 2..]
+            .iter()
+            .all(|x| *x == -komi));
+        game.try_perform_move(PASS)?;
+        game.try_perform_move(PASS)?;
+        assert_eq!(game.winning_player(), Some(Players::Opponent));
+
+        game.flip_board();
+        assert!(game.get_game_state_slice()[SQUARES * 2..]
+            .iter()
+            .all(|x| *x == komi));
+        assert_eq!(game.winning_player(), Some(Players::Player));
+        assert_eq!(
+            game.terminal_value(Players::Player),
+            Some(2.5 / SQUARES as f32)
+        );
+        Ok(())
+    }
+}
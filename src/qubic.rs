@@ -0,0 +1,292 @@
+use std::{fmt::Display, sync::OnceLock};
+
+use anyhow::{ensure, Context, Result};
+
+use crate::{
+    game::{encode_board, swap_board, Game, Players, SimpleBoardState},
+    mcts::GameStats,
+};
+
+const SIDE: usize = 4;
+pub const SQUARES: usize = SIDE * SIDE * SIDE;
+pub const STATE_LEN: usize = SQUARES * 2;
+const PERMUTATIONS: [[usize; 3]; 6] = [
+    [0, 1, 2],
+    [0, 2, 1],
+    [1, 2, 0],
+    [1, 0, 2],
+    [2, 0, 1],
+    [2, 1, 0],
+];
+
+/// Qubic, tic-tac-toe on a 4x4x4 cube. Four in a row along any of the 76 lines wins, a full
+/// cube without one is a draw. Cell (x, y, z) has index x + 4 * y + 16 * z
+#[derive(Debug, Clone)]
+pub struct Qubic {
+    board: [SimpleBoardState; SQUARES],
+    current_player: Players,
+    winning_player: Option<Players>,
+    // Cells played so far, for undo_move
+    history: Vec<usize>,
+}
+
+fn coordinates(index: usize) -> [usize; 3] {
+    [index % SIDE, index / SIDE % SIDE, index / (SIDE * SIDE)]
+}
+
+fn index(coordinates: [usize; 3]) -> usize {
+    coordinates[0] + SIDE * coordinates[1] + SIDE * SIDE * coordinates[2]
+}
+
+/// The 76 winning lines: 48 along an axis, 24 diagonals of the planes through the cube and the 4
+/// diagonals through its centre
+pub fn winning_lines() -> Vec<[usize; SIDE]> {
+    let mut lines = Vec::new();
+    // Every direction with its first non-zero component positive, so each line is found once
+    let directions = (0..27)
+        .map(|i| [i % 3, i / 3 % 3, i / 9].map(|d| d as isize - 1))
+        .filter(|d| d.iter().find(|c| **c != 0).is_some_and(|c| *c > 0));
+    for direction in directions {
+        for start in 0..SQUARES {
+            let start = coordinates(start).map(|c| c as isize);
+            let cells: Vec<_> = (0..SIDE as isize)
+                .map(|step| [0, 1, 2].map(|axis| start[axis] + direction[axis] * step))
+                .collect();
+            let on_board = cells
+                .iter()
+                .flatten()
+                .all(|c| (0..SIDE as isize).contains(c));
+            if on_board {
+                let cells: Vec<_> = cells
+                    .iter()
+                    .map(|cell| index(cell.map(|c| c as usize)))
+                    .collect();
+                lines.push(cells.try_into().unwrap());
+            }
+        }
+    }
+    lines
+}
+
+// The winning lines through `cell`, 7 for the corners and the centre cells and 4 for the others.
+// Worked out once, every move looks them up
+fn lines_through(cell: usize) -> &'static [[usize; SIDE]] {
+    static LINES: OnceLock<Vec<Vec<[usize; SIDE]>>> = OnceLock::new();
+    let lines = LINES.get_or_init(|| {
+        let lines = winning_lines();
+        (0..SQUARES)
+            .map(|cell| {
+                lines
+                    .iter()
+                    .filter(|line| line.contains(&cell))
+                    .copied()
+                    .collect()
+            })
+            .collect()
+    });
+    &lines[cell]
+}
+
+impl Qubic {
+    // The 24 rotations of the cube as an axis permutation and the axes it mirrors. Rotations are
+    // the ones among the 48 symmetries that keep the handedness
+    fn rotations() -> Vec<([usize; 3], [bool; 3])> {
+        let mut rotations = Vec::new();
+        for (p, permutation) in PERMUTATIONS.iter().enumerate() {
+            for mask in 0..8 {
+                let mirrored = [0, 1, 2].map(|axis| mask >> axis & 1 == 1);
+                // PERMUTATIONS alternates even and odd permutations
+                let odd = (p % 2 == 1) ^ (mirrored.iter().filter(|m| **m).count() % 2 == 1);
+                if !odd {
+                    rotations.push((*permutation, mirrored));
+                }
+            }
+        }
+        rotations
+    }
+
+    fn rotate(square: usize, (permutation, mirrored): ([usize; 3], [bool; 3])) -> usize {
+        let old = coordinates(square);
+        index([0, 1, 2].map(|axis| {
+            let c = old[permutation[axis]];
+            if mirrored[axis] {
+                SIDE - 1 - c
+            } else {
+                c
+            }
+        }))
+    }
+}
+
+impl Game<SQUARES, STATE_LEN> for Qubic {
+    fn winning_player(&self) -> Option<Players> {
+        self.winning_player
+    }
+
+    fn available_moves(&self) -> [bool; SQUARES] {
+        if self.winning_player.is_some() {
+            return [false; SQUARES];
+        }
+        self.board.map(|square| square == SimpleBoardState::Empty)
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(
+            self.board.get(space) == Some(&SimpleBoardState::Empty),
+            "Tried to place a stone on an occupied cell"
+        );
+        self.board[space] = self.current_player.into();
+        let own: SimpleBoardState = self.current_player.into();
+        // Only lines through the new stone can have been completed
+        if lines_through(space)
+            .iter()
+            .any(|line| line.iter().all(|cell| self.board[*cell] == own))
+        {
+            self.winning_player = Some(self.current_player);
+        }
+        self.history.push(space);
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    // A win ends the game, so nobody had won before the last move
+    fn undo_move(&mut self) -> Result<()> {
+        let space = self.history.pop().context("No move to undo")?;
+        self.board[space] = SimpleBoardState::Empty;
+        self.winning_player = None;
+        self.current_player = self.current_player.swap();
+        Ok(())
+    }
+
+    fn new() -> Self {
+        Self {
+            board: [SimpleBoardState::Empty; SQUARES],
+            current_player: Players::Player,
+            winning_player: None,
+            history: Vec::new(),
+        }
+    }
+
+    fn game_ended(&self) -> bool {
+        self.winning_player.is_some() || !self.board.contains(&SimpleBoardState::Empty)
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    fn flip_board(&mut self) {
+        swap_board(&mut self.board);
+        self.current_player = self.current_player.swap();
+        self.winning_player = self.winning_player.map(|player| player.swap());
+    }
+
+    fn get_game_state_slice(&self) -> [f32; STATE_LEN] {
+        encode_board(&self.board)
+    }
+
+    // The 24 rotations of the cube
+    fn get_game_variations(
+        stats: &GameStats<SQUARES, STATE_LEN>,
+    ) -> Vec<GameStats<SQUARES, STATE_LEN>> {
+        Self::rotations()
+            .into_iter()
+            .map(|rotation| {
+                let mut variation = stats.clone();
+                for square in 0..SQUARES {
+                    let target = Self::rotate(square, rotation);
+                    variation.node_visits[target] = stats.node_visits[square];
+                    variation.game_state[target] = stats.game_state[square];
+                    variation.game_state[SQUARES + target] = stats.game_state[SQUARES + square];
+                }
+                variation.best_move_index = Self::rotate(stats.best_move_index, rotation);
+                variation
+            })
+            .collect()
+    }
+}
+
+// The four layers side by side, bottom layer first
+impl Display for Qubic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for y in 0..SIDE {
+            let layers: Vec<String> = (0..SIDE)
+                .map(|z| {
+                    (0..SIDE)
+                        .map(|x| match self.board[index([x, y, z])] {
+                            SimpleBoardState::Empty => '.',
+                            SimpleBoardState::Player => 'X',
+                            SimpleBoardState::Opponent => 'O',
+                        })
+                        .collect()
+                })
+                .collect();
+            writeln!(f, "{}", layers.join("  "))?;
+        }
+        let next_player = match self.current_player {
+            Players::Player => "X",
+            Players::Opponent => "O",
+        };
+        writeln!(f, "Next player: {}", next_player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn there_are_76_lines() {
+        let lines = winning_lines();
+        assert_eq!(lines.len(), 76);
+        let distinct: HashSet<_> = lines
+            .iter()
+            .map(|line| {
+                let mut line = *line;
+                line.sort();
+                line
+            })
+            .collect();
+        assert_eq!(distinct.len(), 76);
+        let through: usize = (0..SQUARES).map(|cell| lines_through(cell).len()).sum();
+        assert_eq!(through, 76 * SIDE);
+    }
+
+    #[test]
+    fn rotations_are_the_24_that_keep_the_handedness() {
+        let lines: HashSet<Vec<usize>> = winning_lines()
+            .iter()
+            .map(|line| {
+                let mut line = line.to_vec();
+                line.sort();
+                line
+            })
+            .collect();
+        let mut maps = HashSet::new();
+        for rotation in Qubic::rotations() {
+            let map: Vec<usize> = (0..SQUARES)
+                .map(|cell| Qubic::rotate(cell, rotation))
+                .collect();
+            assert_eq!(map.iter().collect::<HashSet<_>>().len(), SQUARES);
+            // The images of the unit steps along the axes keep their orientation
+            let origin = coordinates(map[0]).map(|c| c as isize);
+            let [a, b, c] = [1, SIDE, SIDE * SIDE].map(|cell| {
+                let image = coordinates(map[cell]).map(|c| c as isize);
+                [0, 1, 2].map(|axis| image[axis] - origin[axis])
+            });
+            let determinant = a[0] * (b[1] * c[2] - b[2] * c[1])
+                - a[1] * (b[0] * c[2] - b[2] * c[0])
+                + a[2] * (b[0] * c[1] - b[1] * c[0]);
+            assert_eq!(determinant, 1, "{:?}", rotation);
+            for line in &lines {
+                let mut image: Vec<usize> = line.iter().map(|cell| map[*cell]).collect();
+                image.sort();
+                assert!(lines.contains(&image));
+            }
+            maps.insert(map);
+        }
+        assert_eq!(maps.len(), 24);
+    }
+}
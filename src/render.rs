@@ -0,0 +1,247 @@
+//! Board rendering kept apart from the game logic. Renderers read the board through
+//! SpatialGame, so any game with planes can be shown as text or drawn as an SVG
+
+use std::fmt::Write;
+
+use crate::game::{Game, Players, SimpleBoardState, SpatialGame};
+
+const PLAYER_COLOR: &str = "\x1b[31m";
+const OPPONENT_COLOR: &str = "\x1b[34m";
+const RESET: &str = "\x1b[0m";
+
+pub trait BoardRenderer<const N: usize, const I: usize, T: Game<N, I>> {
+    fn render(&self, game: &T) -> String;
+}
+
+/// The board as rows of squares and the player to move, decoded from the spatial planes. Games
+/// on a grid hand it out through Game::board, so a game picked at runtime can be drawn too
+pub struct Board {
+    rows: Vec<Vec<SimpleBoardState>>,
+    to_move: Players,
+    skewed: bool,
+}
+
+impl Board {
+    pub fn read<const N: usize, const I: usize, T: SpatialGame<N, I>>(game: &T) -> Self {
+        let (_, height, width) = game.plane_shape();
+        let planes = game.spatial_planes();
+        let squares = height * width;
+        let to_move = game.current_player();
+        let rows = (0..height)
+            .map(|row| {
+                (row * width..(row + 1) * width)
+                    .map(|square| {
+                        if planes[square] == 1.0 {
+                            to_move.into()
+                        } else if planes[squares + square] == 1.0 {
+                            to_move.swap().into()
+                        } else {
+                            SimpleBoardState::Empty
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            rows,
+            to_move,
+            skewed: game.skewed(),
+        }
+    }
+
+    fn next_player(&self) -> String {
+        let next_player = match self.to_move {
+            Players::Player => "X",
+            Players::Opponent => "O",
+        };
+        format!("Next player: {}\n", next_player)
+    }
+}
+
+/// Plain text, "." for empty squares and X and O for the stones of Player and Opponent
+pub struct AsciiRenderer;
+
+impl AsciiRenderer {
+    pub fn render_board(&self, board: &Board) -> String {
+        let mut out = String::new();
+        for (i, row) in board.rows.iter().enumerate() {
+            let indent = if board.skewed { i } else { 0 };
+            let squares: Vec<_> = row
+                .iter()
+                .map(|square| match square {
+                    SimpleBoardState::Empty => ".",
+                    SimpleBoardState::Player => "X",
+                    SimpleBoardState::Opponent => "O",
+                })
+                .collect();
+            writeln!(out, "{}{}", " ".repeat(indent), squares.join(" ")).unwrap();
+        }
+        out + &board.next_player()
+    }
+}
+
+impl<const N: usize, const I: usize, T: SpatialGame<N, I>> BoardRenderer<N, I, T>
+    for AsciiRenderer
+{
+    fn render(&self, game: &T) -> String {
+        self.render_board(&Board::read(game))
+    }
+}
+
+/// Box drawing grid, the stones coloured red for Player and blue for Opponent if `color` is
+/// set. Skewed boards have no grid that lines up, so they get indented rows of stones instead
+#[derive(Default)]
+pub struct UnicodeRenderer {
+    pub color: bool,
+}
+
+impl UnicodeRenderer {
+    fn stone(&self, square: SimpleBoardState) -> String {
+        let (symbol, code) = match square {
+            SimpleBoardState::Empty => return " ".to_string(),
+            SimpleBoardState::Player => ("X", PLAYER_COLOR),
+            SimpleBoardState::Opponent => ("O", OPPONENT_COLOR),
+        };
+        if self.color {
+            format!("{code}{symbol}{RESET}")
+        } else {
+            symbol.to_string()
+        }
+    }
+}
+
+impl UnicodeRenderer {
+    pub fn render_board(&self, board: &Board) -> String {
+        let mut out = String::new();
+        if board.skewed {
+            for (i, row) in board.rows.iter().enumerate() {
+                let stones: Vec<_> = row
+                    .iter()
+                    .map(|square| match square {
+                        SimpleBoardState::Empty => "·".to_string(),
+                        square => self.stone(*square),
+                    })
+                    .collect();
+                writeln!(out, "{}{}", " ".repeat(i), stones.join(" ")).unwrap();
+            }
+            return out + &board.next_player();
+        }
+        let width = board.rows.first().map_or(0, |row| row.len());
+        let line = |left: &str, middle: &str, right: &str| {
+            format!("{}{}{}\n", left, vec!["═"; width].join(middle), right)
+        };
+        out.push_str(&line("╔", "╦", "╗"));
+        for (i, row) in board.rows.iter().enumerate() {
+            if i > 0 {
+                out.push_str(&line("╠", "╬", "╣"));
+            }
+            let stones: Vec<_> = row.iter().map(|square| self.stone(*square)).collect();
+            writeln!(out, "║{}║", stones.join("║")).unwrap();
+        }
+        out.push_str(&line("╚", "╩", "╝"));
+        out + &board.next_player()
+    }
+}
+
+impl<const N: usize, const I: usize, T: SpatialGame<N, I>> BoardRenderer<N, I, T>
+    for UnicodeRenderer
+{
+    fn render(&self, game: &T) -> String {
+        self.render_board(&Board::read(game))
+    }
+}
+
+/// Standalone SVG image that can be written to a file or inlined in HTML. Squares are drawn as
+/// squares, skewed boards as hexagons with each row shifted half a hexagon to the right
+pub struct SvgRenderer {
+    /// Width of a square in pixels
+    pub cell_size: f32,
+}
+
+impl Default for SvgRenderer {
+    fn default() -> Self {
+        Self { cell_size: 40.0 }
+    }
+}
+
+impl SvgRenderer {
+    // Outline of the square or hexagon centred on (x, y)
+    fn cell_shape(&self, x: f32, y: f32, skewed: bool) -> String {
+        let half = self.cell_size / 2.0;
+        if !skewed {
+            return format!(
+                r##"<rect x="{}" y="{}" width="{}" height="{}" fill="#e8c98c" stroke="black"/>"##,
+                x - half,
+                y - half,
+                self.cell_size,
+                self.cell_size
+            );
+        }
+        // Pointy topped hexagon whose flat sides touch the neighbours in the same row
+        let radius = half / (std::f32::consts::PI / 6.0).cos();
+        let points: Vec<String> = (0..6)
+            .map(|corner| {
+                let angle = std::f32::consts::PI / 3.0 * corner as f32;
+                format!(
+                    "{:.1},{:.1}",
+                    x + radius * angle.sin(),
+                    y - radius * angle.cos()
+                )
+            })
+            .collect();
+        format!(
+            r##"<polygon points="{}" fill="#e8c98c" stroke="black"/>"##,
+            points.join(" ")
+        )
+    }
+}
+
+impl SvgRenderer {
+    pub fn render_board(&self, board: &Board) -> String {
+        let size = self.cell_size;
+        let height = board.rows.len();
+        let width = board.rows.first().map_or(0, |row| row.len());
+        // Hexagon rows overlap by a quarter of their height
+        let row_height = if board.skewed {
+            size * 3f32.sqrt() / 2.0
+        } else {
+            size
+        };
+        let shift = if board.skewed { size / 2.0 } else { 0.0 };
+        let image_width = size * width as f32 + shift * height.saturating_sub(1) as f32 + size;
+        let image_height = row_height * height as f32 + size;
+        let mut out = format!(
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.0}" height="{:.0}">"#,
+            image_width, image_height
+        );
+        for (i, row) in board.rows.iter().enumerate() {
+            for (j, square) in row.iter().enumerate() {
+                let x = size + size * j as f32 + shift * i as f32;
+                let y = size + row_height * i as f32;
+                out.push_str(&self.cell_shape(x, y, board.skewed));
+                let fill = match square {
+                    SimpleBoardState::Empty => continue,
+                    SimpleBoardState::Player => "#c0392b",
+                    SimpleBoardState::Opponent => "#2e5cb8",
+                };
+                write!(
+                    out,
+                    r#"<circle cx="{:.1}" cy="{:.1}" r="{:.1}" fill="{}"/>"#,
+                    x,
+                    y,
+                    size * 0.4,
+                    fill
+                )
+                .unwrap();
+            }
+        }
+        out.push_str("</svg>\n");
+        out
+    }
+}
+
+impl<const N: usize, const I: usize, T: SpatialGame<N, I>> BoardRenderer<N, I, T> for SvgRenderer {
+    fn render(&self, game: &T) -> String {
+        self.render_board(&Board::read(game))
+    }
+}
@@ -0,0 +1,261 @@
+//! Self-play data kept over several generations, so a model trains on more games than the last
+//! round of self-play produced and does not forget positions its latest games no longer reach
+
+use std::collections::VecDeque;
+
+use anyhow::{ensure, Context, Result};
+use rand::{distributions::WeightedIndex, prelude::Distribution, rngs::StdRng};
+use serde::{Deserialize, Serialize};
+
+use crate::dataset::{read_data, write_data, Dataset, SerializableDataset};
+
+/// How much a replay buffer holds before it drops its oldest data
+#[derive(Clone, Copy, Debug)]
+pub enum Capacity {
+    /// The datasets of this many generations
+    Generations(usize),
+    /// Whole generations while the newer ones hold fewer than this many positions, so the buffer
+    /// holds at least this many once there are enough
+    Positions(usize),
+}
+
+/// How positions are drawn from a replay buffer
+#[derive(Clone, Copy, Debug)]
+pub enum Sampling {
+    Uniform,
+    /// Positions are drawn in proportion to this to the power of how many generations older
+    /// than the newest they are, below 1 to favour new data
+    Recency(f32),
+}
+
+/// How training_loop keeps its self-play data
+#[derive(Clone, Copy, Debug)]
+pub struct ReplayConfig {
+    pub capacity: Capacity,
+    pub sampling: Sampling,
+    /// Positions drawn for every generation's training, None trains on the whole buffer as it is
+    pub samples: Option<usize>,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        Self {
+            capacity: Capacity::Generations(4),
+            sampling: Sampling::Uniform,
+            samples: None,
+        }
+    }
+}
+
+pub struct ReplayBuffer<const N: usize, const I: usize> {
+    pub config: ReplayConfig,
+    // Oldest first
+    generations: VecDeque<Dataset<N, I>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct SerializableBuffer<const N: usize, const I: usize> {
+    generations: Vec<SerializableDataset<N, I>>,
+}
+
+impl<const N: usize, const I: usize> ReplayBuffer<N, I> {
+    pub fn new(config: ReplayConfig) -> Result<Self> {
+        match config.capacity {
+            Capacity::Generations(generations) => {
+                ensure!(generations > 0, "A replay buffer has to hold a generation")
+            }
+            Capacity::Positions(positions) => {
+                ensure!(positions > 0, "A replay buffer has to hold a position")
+            }
+        }
+        if let Sampling::Recency(decay) = config.sampling {
+            ensure!(
+                decay > 0.0 && decay <= 1.0,
+                "Recency decay {} is not in (0, 1]",
+                decay
+            );
+        }
+        Ok(Self {
+            config,
+            generations: VecDeque::new(),
+        })
+    }
+
+    /// Adds the dataset of the newest generation and drops what no longer fits
+    pub fn push(&mut self, dataset: Dataset<N, I>) {
+        self.generations.push_back(dataset);
+        match self.config.capacity {
+            Capacity::Generations(generations) => {
+                while self.generations.len() > generations {
+                    self.generations.pop_front();
+                }
+            }
+            Capacity::Positions(positions) => {
+                while self.len() - self.generations[0].scores.len() >= positions {
+                    self.generations.pop_front();
+                }
+            }
+        }
+    }
+
+    /// Positions held over all generations
+    pub fn len(&self) -> usize {
+        self.generations
+            .iter()
+            .map(|dataset| dataset.scores.len())
+            .sum()
+    }
+
+    pub fn generations(&self) -> usize {
+        self.generations.len()
+    }
+
+    /// Every position held, oldest first
+    pub fn all(&self) -> Dataset<N, I> {
+        let mut generations = self.generations.iter().cloned();
+        let mut all = generations.next().unwrap_or_default();
+        for dataset in generations {
+            all.append(dataset);
+        }
+        all
+    }
+
+    /// `count` positions drawn with replacement by the sampling weights
+    pub fn sample(&self, count: usize, rng: &mut StdRng) -> Result<Dataset<N, I>> {
+        let all = self.all();
+        ensure!(!all.scores.is_empty(), "The replay buffer is empty");
+        let newest = self.generations.len() - 1;
+        let weights = self
+            .generations
+            .iter()
+            .enumerate()
+            .flat_map(|(generation, dataset)| {
+                let weight = match self.config.sampling {
+                    Sampling::Uniform => 1.0,
+                    Sampling::Recency(decay) => decay.powi((newest - generation) as i32),
+                };
+                std::iter::repeat(weight).take(dataset.scores.len())
+            });
+        let distribution = WeightedIndex::new(weights)?;
+        let indices: Vec<usize> = (0..count).map(|_| distribution.sample(rng)).collect();
+        Ok(all.subset(&indices))
+    }
+
+    /// What training_loop trains on, see ReplayConfig::samples
+    pub fn training_data(&self, rng: &mut StdRng) -> Result<Dataset<N, I>> {
+        match self.config.samples {
+            Some(count) => self.sample(count, rng),
+            None => Ok(self.all()),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        let buffer = SerializableBuffer {
+            generations: self.generations.iter().cloned().map(Into::into).collect(),
+        };
+        write_data(path, &buffer)
+    }
+
+    /// Buffer saved at `path`, which has to be for the same game. The config is not saved, the
+    /// buffer drops what does not fit in `config`
+    pub fn load(path: &str, config: ReplayConfig) -> Result<Self> {
+        let saved: SerializableBuffer<N, I> = read_data(path)?;
+        let mut buffer = Self::new(config)?;
+        for (generation, dataset) in saved.generations.into_iter().enumerate() {
+            dataset
+                .validate()
+                .with_context(|| format!("Generation {} of {} does not fit", generation, path))?;
+            buffer.push(dataset.into());
+        }
+        Ok(buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    // `positions` samples whose state and score are the generation
+    fn generation(generation: usize, positions: usize) -> Dataset<2, 1> {
+        Dataset {
+            game_states: vec![[generation as f32]; positions],
+            visit_stats: vec![[0.5, 0.5]; positions],
+            scores: vec![generation as f32; positions],
+            ..Default::default()
+        }
+    }
+
+    fn generations_held(buffer: &ReplayBuffer<2, 1>) -> Vec<f32> {
+        buffer
+            .generations
+            .iter()
+            .map(|dataset| dataset.scores[0])
+            .collect()
+    }
+
+    fn config(capacity: Capacity, sampling: Sampling) -> ReplayConfig {
+        ReplayConfig {
+            capacity,
+            sampling,
+            samples: None,
+        }
+    }
+
+    #[test]
+    fn drops_generations_the_newer_ones_can_stand_in_for() -> Result<()> {
+        let mut buffer = ReplayBuffer::new(config(Capacity::Positions(10), Sampling::Uniform))?;
+        for index in 0..3 {
+            buffer.push(generation(index, 4));
+        }
+        // The two newest hold 8 positions, too few without the oldest
+        assert_eq!(generations_held(&buffer), [0.0, 1.0, 2.0]);
+        buffer.push(generation(3, 4));
+        assert_eq!(generations_held(&buffer), [1.0, 2.0, 3.0]);
+        assert_eq!(buffer.len(), 12);
+        // A generation that fills the buffer on its own is all that is kept
+        buffer.push(generation(4, 20));
+        assert_eq!(generations_held(&buffer), [4.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn recency_favours_new_generations() -> Result<()> {
+        let mut buffer =
+            ReplayBuffer::new(config(Capacity::Generations(3), Sampling::Recency(0.5)))?;
+        for index in 0..3 {
+            buffer.push(generation(index, 100));
+        }
+        let mut rng = StdRng::seed_from_u64(0);
+        let samples = buffer.sample(7000, &mut rng)?;
+        let mut counts = [0i32; 3];
+        for score in &samples.scores {
+            counts[*score as usize] += 1;
+        }
+        // Weighted 1, 2 and 4 from the oldest
+        for (count, expected) in counts.into_iter().zip([1000, 2000, 4000]) {
+            assert!((count - expected).abs() < 150, "{:?}", counts);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn loads_what_fits_the_new_config() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("replay_{}.bin.zst", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mut buffer = ReplayBuffer::new(config(Capacity::Generations(3), Sampling::Uniform))?;
+        for index in 0..3 {
+            buffer.push(generation(index, 4));
+        }
+        buffer.save(path)?;
+        let loaded = ReplayBuffer::<2, 1>::load(path, buffer.config);
+        let smaller =
+            ReplayBuffer::<2, 1>::load(path, config(Capacity::Generations(2), Sampling::Uniform));
+        std::fs::remove_file(path)?;
+        let loaded = loaded?;
+        assert_eq!(loaded.generations(), 3);
+        assert_eq!(loaded.all().content_hash(), buffer.all().content_hash());
+        assert_eq!(generations_held(&smaller?), [1.0, 2.0]);
+        Ok(())
+    }
+}
@@ -0,0 +1,376 @@
+//! The residual network of AlphaZero: a convolution over the spatial planes of the position, read
+//! like ConvModel reads them, a tower of residual blocks, and a policy and a value head on top,
+//! every convolution batch normalized
+
+use anyhow::ensure;
+use candle_core::{DType, Device, Tensor};
+use candle_nn::{
+    batch_norm, conv2d, conv2d_no_bias, linear, BatchNorm, Conv2d, Conv2dConfig, Dropout, Linear,
+    Module, ModuleT, VarBuilder,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::candle_ai::{
+    self, device_for, select_device, self_play_dtype, CandleModel, Heads, Role, Weights,
+};
+use crate::checkpoint;
+use crate::conv_model::{plane_count, spatial_input, square_board};
+use crate::fit::{softmax, LossWeights};
+use crate::model::{Architecture, FitConfig, TrainReport, TrainableModel};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ResNetConfig {
+    /// Residual blocks in the tower, AlphaZero used 19 or 39
+    pub blocks: usize,
+    /// Filters of every convolution in the tower, 256 in AlphaZero
+    pub filters: usize,
+    /// Height and width of the spatial planes, see conv_model::spatial_board. None for a square
+    /// board with one move per square
+    pub board: Option<(usize, usize)>,
+    /// Share of the units of the value head's hidden layer zeroed in every training step
+    pub dropout: f32,
+    /// Seed of the initial weights, None for a random one
+    pub seed: Option<u64>,
+    /// Adds the head predicting Game::ownership, which needs one move per square
+    pub ownership: bool,
+    pub fit: FitConfig,
+}
+
+impl Default for ResNetConfig {
+    // Small enough to train on a CPU
+    fn default() -> Self {
+        Self {
+            blocks: 4,
+            filters: 64,
+            board: None,
+            dropout: 0.0,
+            seed: None,
+            ownership: false,
+            fit: FitConfig::default(),
+        }
+    }
+}
+
+// Convolution without bias, the batch norm after it has one
+struct ConvNorm {
+    conv: Conv2d,
+    norm: BatchNorm,
+}
+
+impl ConvNorm {
+    fn new(inputs: usize, outputs: usize, kernel: usize, vb: VarBuilder) -> anyhow::Result<Self> {
+        let config = Conv2dConfig {
+            padding: kernel / 2,
+            ..Default::default()
+        };
+        Ok(Self {
+            conv: conv2d_no_bias(inputs, outputs, kernel, config, vb.pp("conv"))?,
+            norm: batch_norm(outputs, 1e-5, vb.pp("norm"))?,
+        })
+    }
+
+    fn forward_t(&self, xs: &Tensor, train: bool) -> candle_core::Result<Tensor> {
+        self.norm.forward_t(&self.conv.forward(xs)?, train)
+    }
+}
+
+// Two convolutions with the input added back in before the last relu
+struct ResidualBlock {
+    first: ConvNorm,
+    second: ConvNorm,
+}
+
+impl ResidualBlock {
+    fn forward_t(&self, xs: &Tensor, train: bool) -> candle_core::Result<Tensor> {
+        let x = self.first.forward_t(xs, train)?.relu()?;
+        let x = self.second.forward_t(&x, train)?;
+        (x + xs)?.relu()
+    }
+}
+
+pub struct ResNetModel<const N: usize, const I: usize> {
+    config: ResNetConfig,
+    // Channels, height and width of the spatial planes
+    shape: (usize, usize, usize),
+    input: ConvNorm,
+    tower: Vec<ResidualBlock>,
+    policy_conv: ConvNorm,
+    policy_head: Linear,
+    value_conv: ConvNorm,
+    value_hidden: Linear,
+    dropout: Dropout,
+    score_head: Linear,
+    margin_head: Linear,
+    // 1x1 convolution of the tower to a plane of cell ownership, with ResNetConfig::ownership
+    ownership_conv: Option<Conv2d>,
+    // Holds the running statistics of the batch norms as well as the weights
+    weights: Weights,
+    generation: usize,
+    device: Device,
+    /// Weight of the value loss relative to the policy cross-entropy
+    pub value_weight: f32,
+    /// Weight of the margin loss relative to the policy cross-entropy
+    pub margin_weight: f32,
+    /// Weight of the ownership loss relative to the policy cross-entropy
+    pub ownership_weight: f32,
+}
+
+impl<const N: usize, const I: usize> ResNetModel<N, I> {
+    pub fn with_device(config: ResNetConfig, device: Device) -> anyhow::Result<Self> {
+        Self::build(config, Weights::trainable(config.seed), device)
+    }
+
+    /// Model with the weights saved at `path` that can only predict
+    pub fn for_inference(config: ResNetConfig, path: &str, device: Device) -> anyhow::Result<Self> {
+        let model = Self::build(config, Weights::inference::<N, I>(path, &device)?, device)?;
+        model.weights.check_unused(path)?;
+        Ok(model)
+    }
+
+    fn build(config: ResNetConfig, weights: Weights, device: Device) -> anyhow::Result<Self> {
+        let (height, width) = match config.board {
+            Some(board) => board,
+            None => square_board(N)?,
+        };
+        let channels = plane_count(I, height, width)? + 1;
+        let squares = height * width;
+        let filters = config.filters;
+        let vb = weights.var_builder(&device);
+        let input = ConvNorm::new(channels, filters, 3, vb.pp("input"))?;
+        let tower = (0..config.blocks)
+            .map(|block| {
+                let vb = vb.pp(format!("block {}", block));
+                Ok(ResidualBlock {
+                    first: ConvNorm::new(filters, filters, 3, vb.pp("first"))?,
+                    second: ConvNorm::new(filters, filters, 3, vb.pp("second"))?,
+                })
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let policy_conv = ConvNorm::new(filters, 2, 1, vb.pp("policy_conv"))?;
+        let policy_head = linear(2 * squares, N, vb.pp("policy_head"))?;
+        let value_conv = ConvNorm::new(filters, 1, 1, vb.pp("value_conv"))?;
+        let value_hidden = linear(squares, filters, vb.pp("value_hidden"))?;
+        let score_head = linear(filters, 1, vb.pp("score_head"))?;
+        let margin_head = linear(filters, 1, vb.pp("margin_head"))?;
+        ensure!(
+            !config.ownership || squares == N,
+            "The ownership head needs one move per square"
+        );
+        let ownership_conv = config
+            .ownership
+            .then(|| conv2d(filters, 1, 1, Default::default(), vb.pp("ownership_conv")))
+            .transpose()?;
+        Ok(Self {
+            config,
+            shape: (channels, height, width),
+            input,
+            tower,
+            policy_conv,
+            policy_head,
+            value_conv,
+            value_hidden,
+            dropout: candle_ai::dropout(config.dropout)?,
+            score_head,
+            margin_head,
+            ownership_conv,
+            weights,
+            generation: 0,
+            device,
+            value_weight: 1.0,
+            margin_weight: 1.0,
+            ownership_weight: 1.0,
+        })
+    }
+
+    /// Copy that only predicts, computing in `dtype`. The batch norms use their running
+    /// statistics in it too
+    pub fn with_dtype(&self, dtype: DType) -> anyhow::Result<Self> {
+        Self::build(
+            self.config,
+            self.weights.frozen(dtype, &self.device)?,
+            self.device.clone(),
+        )
+    }
+
+    /// The model with the weights saved at `path`, which have to be for the same configuration
+    pub fn with_weights(mut self, path: &str) -> anyhow::Result<Self> {
+        self.weights.load::<N, I>(path)?;
+        Ok(self)
+    }
+
+    pub fn config(&self) -> ResNetConfig {
+        self.config
+    }
+}
+
+impl<const N: usize, const I: usize> CandleModel for ResNetModel<N, I> {
+    fn heads(&self, xs: &Tensor, train: bool) -> candle_core::Result<Heads> {
+        let (_, height, width) = self.shape;
+        let x = spatial_input(xs, height, width)?;
+        let mut x = self.input.forward_t(&x, train)?.relu()?;
+        for block in &self.tower {
+            x = block.forward_t(&x, train)?;
+        }
+        let policy = self.policy_conv.forward_t(&x, train)?.relu()?;
+        let visit_logits = self.policy_head.forward(&policy.flatten_from(1)?)?;
+        let value = self.value_conv.forward_t(&x, train)?.relu()?;
+        let value = self.value_hidden.forward(&value.flatten_from(1)?)?.relu()?;
+        let value = self.dropout.forward_t(&value, train)?;
+        Ok(Heads {
+            visit_logits,
+            score: self.score_head.forward(&value)?.tanh()?,
+            margin: self.margin_head.forward(&value)?.tanh()?,
+            ownership: self
+                .ownership_conv
+                .as_ref()
+                .map(|conv| conv.forward(&x)?.flatten_from(1)?.tanh())
+                .transpose()?,
+        })
+    }
+
+    fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn loss_weights(&self) -> LossWeights {
+        LossWeights {
+            value: self.value_weight,
+            margin: self.margin_weight,
+            ownership: self.ownership_weight,
+        }
+    }
+
+    fn fit_config(&self) -> &FitConfig {
+        &self.config.fit
+    }
+
+    fn weights(&self) -> &Weights {
+        &self.weights
+    }
+
+    fn weights_mut(&mut self) -> &mut Weights {
+        &mut self.weights
+    }
+
+    fn generation(&self) -> usize {
+        self.generation
+    }
+}
+
+impl<const N: usize, const I: usize> TrainableModel<N, I> for ResNetModel<N, I> {
+    type Config = ResNetConfig;
+
+    fn new(config: &ResNetConfig) -> anyhow::Result<Self> {
+        Self::with_device(*config, select_device())
+    }
+
+    fn reset_optimizer(&mut self) -> anyhow::Result<()> {
+        self.weights.reset_optimizer();
+        Ok(())
+    }
+
+    fn set_generation(&mut self, generation: usize) {
+        self.generation = generation;
+    }
+
+    fn save(&self, path: &str) -> anyhow::Result<()> {
+        let metadata = checkpoint::model_metadata::<N, I>(&self.config, self.generation);
+        self.weights.save(path, metadata)
+    }
+
+    // Built with the config saved with the weights, the default for files saved without one
+    fn load(path: &str) -> anyhow::Result<Self> {
+        let config = checkpoint::saved_config(path)?.unwrap_or_default();
+        Self::new(&config)?.with_weights(path)
+    }
+
+    fn load_inference(path: &str) -> anyhow::Result<Self> {
+        let config = checkpoint::saved_config(path)?.unwrap_or_default();
+        Self::for_inference(config, path, select_device())
+    }
+
+    // The batch norms keep their running statistics, which are among the weights
+    fn for_self_play(&self) -> anyhow::Result<Self> {
+        let device = device_for(Role::SelfPlay);
+        let weights = self.weights.frozen(self_play_dtype(), &device)?;
+        Self::build(self.config, weights, device)
+    }
+
+    fn parameter_count(&self) -> usize {
+        self.weights.parameter_count()
+    }
+
+    fn init_seed(&self) -> Option<u64> {
+        self.weights.seed()
+    }
+
+    fn summary(&self) -> Architecture {
+        let (_, height, width) = self.shape;
+        Architecture::from_shapes(self.weights.shapes(), height * width)
+    }
+
+    fn train(&mut self, dataset: crate::dataset::Dataset<N, I>) -> anyhow::Result<TrainReport> {
+        candle_ai::train(self, dataset)
+    }
+
+    fn predict(&self, state: [f32; I], legal_moves: &[bool; N]) -> anyhow::Result<([f32; N], f32)> {
+        let (logits, score, _) = candle_ai::predict(self, state, legal_moves)?;
+        Ok((softmax(logits, 1.0), score))
+    }
+
+    fn predict_moves(&self, state: [f32; I], legal_moves: &[bool; N]) -> anyhow::Result<[f32; N]> {
+        Ok(self.predict(state, legal_moves)?.0)
+    }
+
+    fn predict_logits(&self, state: [f32; I], legal_moves: &[bool; N]) -> anyhow::Result<[f32; N]> {
+        Ok(candle_ai::predict(self, state, legal_moves)?.0)
+    }
+
+    fn predict_score(&self, state: [f32; I]) -> anyhow::Result<f32> {
+        Ok(candle_ai::predict(self, state, &[true; N])?.1)
+    }
+
+    fn predict_margin(&self, state: [f32; I]) -> anyhow::Result<Option<f32>> {
+        Ok(Some(candle_ai::predict(self, state, &[true; N])?.2))
+    }
+
+    fn predict_ownership(&self, state: [f32; I]) -> anyhow::Result<Option<[f32; N]>> {
+        candle_ai::predict_ownership(self, state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connect_four::ConnectFour;
+    use crate::conv_model::spatial_board;
+    use crate::game::Game;
+
+    #[test]
+    fn loads_with_the_board_it_was_saved_with() -> anyhow::Result<()> {
+        let path = std::env::temp_dir().join(format!("resnet_{}.safetensors", std::process::id()));
+        let path = path.to_str().unwrap();
+        // Seven moves on a 6x7 board
+        let config = ResNetConfig {
+            blocks: 1,
+            filters: 8,
+            seed: Some(0),
+            board: Some(spatial_board(&ConnectFour::new())?),
+            ..Default::default()
+        };
+        let model = ResNetModel::<7, 84>::new(&config)?;
+        model.save(path)?;
+        let loaded = ResNetModel::<7, 84>::load(path);
+        let inference = ResNetModel::<7, 84>::load_inference(path);
+        std::fs::remove_file(path)?;
+        let state = ConnectFour::from_moves(&[3, 3, 2])?.get_game_state_slice();
+        let legal = [true; 7];
+        let (moves, score) = model.predict(state, &legal)?;
+        for loaded in [loaded?, inference?] {
+            let (loaded_moves, loaded_score) = loaded.predict(state, &legal)?;
+            assert_eq!(loaded_score, score);
+            assert_eq!(loaded_moves, moves);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,123 @@
+//! SGF (Smart Game Format) for Hex games, as read and written by HexGui and Little Golem. Black
+//! moves first and is Players::Player. Black connects rows 1 and n, so the row of a cell is the x
+//! coordinate of the Hex board and its column letter the y coordinate
+
+use std::fs;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::{
+    dataset::Dataset,
+    game::{Game, Players},
+    hex::{cell_name, parse_cell, Hex},
+};
+
+// GM property value of Hex
+const HEX_GAME: &str = "11";
+
+/// SGF of a Hex game given as its moves from the empty board
+pub fn write_hex_sgf<const T: usize, const U: usize>(moves: &[usize]) -> Result<String> {
+    let game = Hex::<T, U>::from_moves(moves)?;
+    let side_length = (T as f64).sqrt() as usize;
+    let mut sgf = format!(
+        "(;FF[4]GM[{}]SZ[{}]AP[alpha-scuffed]",
+        HEX_GAME, side_length
+    );
+    match game.winning_player() {
+        Some(Players::Player) => sgf.push_str("RE[B+]"),
+        Some(Players::Opponent) => sgf.push_str("RE[W+]"),
+        None => {}
+    }
+    for (i, mv) in moves.iter().enumerate() {
+        let color = if i % 2 == 0 { 'B' } else { 'W' };
+        sgf.push_str(&format!(";{}[{}]", color, cell_name(*mv, side_length)));
+    }
+    sgf.push(')');
+    Ok(sgf)
+}
+
+// Properties of the nodes on the main line. The first ')' closes the main line, so the other
+// variations after it are never read
+fn main_line_properties(sgf: &str) -> Result<Vec<(String, String)>> {
+    let mut properties: Vec<(String, String)> = Vec::new();
+    let mut identifier = String::new();
+    let mut chars = sgf.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            ')' => break,
+            '[' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next().context("Unclosed property value")? {
+                        ']' => break,
+                        '\\' => value.push(chars.next().context("Unclosed property value")?),
+                        other => value.push(other),
+                    }
+                }
+                // Properties can have several values, like AB[a1][b2]
+                if identifier.is_empty() {
+                    let (last, _) = properties.last().context("Value without a property")?;
+                    identifier = last.clone();
+                }
+                properties.push((identifier.clone(), value));
+                identifier.clear();
+            }
+            c if c.is_ascii_uppercase() => identifier.push(c),
+            _ => identifier.clear(),
+        }
+    }
+    Ok(properties)
+}
+
+/// Moves of the main line of a Hex SGF, to be replayed with Game::from_moves. Setup stones and
+/// the swap rule are not supported, a resignation ends the moves
+pub fn read_hex_sgf<const T: usize, const U: usize>(sgf: &str) -> Result<Vec<usize>> {
+    let side_length = (T as f64).sqrt() as usize;
+    let mut moves = Vec::new();
+    for (identifier, value) in main_line_properties(sgf)? {
+        match identifier.as_str() {
+            "GM" => ensure!(value == HEX_GAME, "Not a Hex game, GM is {}", value),
+            "SZ" => ensure!(
+                value.trim() == side_length.to_string(),
+                "Board size is {}, expected {}",
+                value,
+                side_length
+            ),
+            "AB" | "AW" | "AE" => bail!("Setup stones are not supported"),
+            "B" | "W" => {
+                let expected = if moves.len() % 2 == 0 { "B" } else { "W" };
+                ensure!(
+                    identifier == expected,
+                    "Move {} is played by {}, expected {}",
+                    moves.len() + 1,
+                    identifier,
+                    expected
+                );
+                match value.as_str() {
+                    "resign" => break,
+                    "swap-pieces" | "swap-sides" | "swap" => {
+                        bail!("The swap rule is not supported")
+                    }
+                    cell => moves.push(parse_cell(cell, side_length, side_length)?),
+                }
+            }
+            _ => {}
+        }
+    }
+    Hex::<T, U>::from_moves(&moves)?;
+    Ok(moves)
+}
+
+/// Writes every recorded game of a Hex dataset to `name`_<game>.sgf
+pub fn save_hex_sgf<const T: usize, const U: usize>(
+    dataset: &Dataset<T, U>,
+    name: &str,
+) -> Result<()> {
+    for (i, moves) in dataset.records.iter().enumerate() {
+        fs::write(
+            format!("./{}_{}.sgf", name, i),
+            write_hex_sgf::<T, U>(moves)?,
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,302 @@
+//! Exact values of every position of games small enough to be solved outright, tic-tac-toe or
+//! 4x4 Hex. They make a perfect player, a perfect leaf evaluation for the search and ground truth
+//! for the value head
+
+use std::collections::HashMap;
+
+use anyhow::{ensure, Result};
+use rand::{rngs::StdRng, seq::SliceRandom};
+
+use crate::game::{canonical_hash, move_indices, resolve_chance, Game, Players, Policy};
+
+// Deeper lines than this mean the game has cycles, which the solver cannot handle
+const MAX_DEPTH: usize = 1000;
+
+/// Win (1), draw (0) or loss (-1) for the player to move of every position reachable from the
+/// start that is not finished. Positions are keyed by canonical_hash, so they are also found on
+/// the flipped boards the search works with. The game is solved bottom up from the finished
+/// positions, so it has to be free of cycles, chance events, and rules like move limits that
+/// depend on more than the position
+#[derive(Clone, Debug, Default)]
+pub struct Tablebase {
+    values: HashMap<u64, i8>,
+}
+
+impl Tablebase {
+    /// Solves T from its start position, giving up after `max_positions` positions so a game
+    /// that is too large fails instead of running out of memory
+    pub fn generate<const N: usize, const I: usize, T: Game<N, I>>(
+        max_positions: usize,
+    ) -> Result<Self> {
+        let mut tablebase = Self::default();
+        tablebase.solve(&mut T::new(), 0, max_positions)?;
+        Ok(tablebase)
+    }
+
+    fn solve<const N: usize, const I: usize, T: Game<N, I>>(
+        &mut self,
+        game: &mut T,
+        depth: usize,
+        max_positions: usize,
+    ) -> Result<i8> {
+        if let Some(result) = Self::result(game) {
+            return Ok(result);
+        }
+        let hash = canonical_hash(game);
+        let sign = Self::sign(game.current_player());
+        if let Some(value) = self.values.get(&hash) {
+            return Ok(sign * value);
+        }
+        ensure!(
+            game.chance_outcomes().is_none(),
+            "Games with chance events cannot be solved"
+        );
+        ensure!(
+            depth < MAX_DEPTH,
+            "Line of {} moves without an end, the game has cycles",
+            MAX_DEPTH
+        );
+        ensure!(
+            self.values.len() < max_positions,
+            "More than {} positions to solve",
+            max_positions
+        );
+        let maximizing = game.current_player() == Players::Player;
+        let mut best = if maximizing { -1 } else { 1 };
+        for mv in move_indices(game) {
+            game.try_perform_move(mv)?;
+            let value = self.solve(game, depth + 1, max_positions)?;
+            game.undo_move()?;
+            // No cutoff once a win is found, the other moves lead to positions that belong in
+            // the tablebase too
+            best = if maximizing {
+                best.max(value)
+            } else {
+                best.min(value)
+            };
+        }
+        self.values.insert(hash, sign * best);
+        Ok(best)
+    }
+
+    fn sign(player: Players) -> i8 {
+        match player {
+            Players::Player => 1,
+            Players::Opponent => -1,
+        }
+    }
+
+    // Result for Player of a finished game, scored like the search scores it
+    fn result<const N: usize, const I: usize, T: Game<N, I>>(game: &T) -> Option<i8> {
+        if !game.game_ended() && !game.is_draw_by_rule() {
+            return None;
+        }
+        Some(
+            match game.winning_player().filter(|_| !game.is_draw_by_rule()) {
+                Some(Players::Player) => 1,
+                Some(Players::Opponent) => -1,
+                None => 0,
+            },
+        )
+    }
+
+    /// Value of the position for Players::Player, None for positions not in the tablebase
+    pub fn value<const N: usize, const I: usize, T: Game<N, I>>(&self, game: &T) -> Option<f32> {
+        Self::result(game)
+            .or_else(|| {
+                let value = self.values.get(&canonical_hash(game))?;
+                Some(Self::sign(game.current_player()) * value)
+            })
+            .map(f32::from)
+    }
+
+    /// The moves keeping the best result for the player to move, None when the position or one of
+    /// its successors is not in the tablebase
+    pub fn best_moves<const N: usize, const I: usize, T: Game<N, I>>(
+        &self,
+        game: &T,
+    ) -> Result<Option<Vec<usize>>> {
+        let sign = f32::from(Self::sign(game.current_player()));
+        let mut values = Vec::new();
+        let mut next = game.clone();
+        for mv in move_indices(game) {
+            next.try_perform_move(mv)?;
+            let value = self.value(&next);
+            next.undo_move()?;
+            match value {
+                Some(value) => values.push((mv, sign * value)),
+                None => return Ok(None),
+            }
+        }
+        let best = values.iter().map(|(_, value)| *value).fold(-1.0, f32::max);
+        Ok(Some(
+            values
+                .into_iter()
+                .filter(|(_, value)| *value == best)
+                .map(|(mv, _)| mv)
+                .collect(),
+        ))
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+}
+
+/// Plays and scores positions in the tablebase perfectly, choosing randomly between moves with
+/// the same result, and leaves every other position to `policy`
+pub struct TablebasePolicy<P> {
+    pub tablebase: Tablebase,
+    pub policy: P,
+}
+
+impl<const N: usize, const I: usize, T: Game<N, I>, P: Policy<N, I, T>> Policy<N, I, T>
+    for TablebasePolicy<P>
+{
+    fn select_move(&self, game: &T, rng: &mut StdRng) -> Result<usize> {
+        match self.tablebase.best_moves(game)? {
+            Some(moves) if !moves.is_empty() => Ok(*moves.choose(rng).unwrap()),
+            _ => self.policy.select_move(game, rng),
+        }
+    }
+
+    fn select_moves_batch(&self, games: Vec<&T>, rng: &mut StdRng) -> Result<Vec<usize>> {
+        games
+            .iter()
+            .map(|game| self.select_move(*game, rng))
+            .collect()
+    }
+
+    fn predict_score(&self, game: &T) -> Result<f32> {
+        match self.tablebase.value(game) {
+            Some(value) => Ok(value),
+            None => self.policy.predict_score(game),
+        }
+    }
+
+    fn can_predict_score(&self) -> bool {
+        self.policy.can_predict_score()
+    }
+
+    fn predict_priors(&self, game: &T) -> Result<[f32; N]> {
+        self.policy.predict_priors(game)
+    }
+
+    fn exact_score(&self, game: &T) -> Option<f32> {
+        self.tablebase.value(game)
+    }
+
+    fn cache_stats(&self) -> Option<crate::cache::CacheStats> {
+        self.policy.cache_stats()
+    }
+}
+
+/// How well a policy's predicted scores match the exact values, over the positions of random games
+#[derive(Debug, Clone, Default)]
+pub struct ValueAccuracy {
+    pub positions: usize,
+    /// Predictions on the right side of ±1/3 for a win or loss, or within it for a draw
+    pub correct: usize,
+    pub absolute_error: f32,
+}
+
+impl ValueAccuracy {
+    pub fn accuracy(&self) -> f32 {
+        self.correct as f32 / self.positions.max(1) as f32
+    }
+
+    pub fn mean_absolute_error(&self) -> f32 {
+        self.absolute_error / self.positions.max(1) as f32
+    }
+}
+
+/// Compares `policy.predict_score` with the tablebase in every unfinished position of `games`
+/// random games
+pub fn value_accuracy<const N: usize, const I: usize, T: Game<N, I>, U: Policy<N, I, T>>(
+    tablebase: &Tablebase,
+    policy: &U,
+    games: usize,
+    rng: &mut StdRng,
+) -> Result<ValueAccuracy> {
+    ensure!(
+        policy.can_predict_score(),
+        "The policy does not predict scores"
+    );
+    let mut accuracy = ValueAccuracy::default();
+    for _ in 0..games {
+        let mut game = T::new();
+        resolve_chance(&mut game, rng)?;
+        while !game.game_ended() && !game.is_draw_by_rule() {
+            if let Some(exact) = tablebase.value(&game) {
+                let predicted = policy.predict_score(&game)?;
+                accuracy.positions += 1;
+                accuracy.absolute_error += (predicted - exact).abs();
+                let class = |value: f32| (value * 1.5).round().clamp(-1.0, 1.0);
+                if class(predicted) == exact {
+                    accuracy.correct += 1;
+                }
+            }
+            let mv = *move_indices(&game).choose(rng).unwrap();
+            game.try_perform_move(mv)?;
+            resolve_chance(&mut game, rng)?;
+        }
+    }
+    Ok(accuracy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::first_player_advantage;
+    use crate::game::RandomPolicy;
+    use crate::hex::Hex;
+    use crate::mnk::TicTacToe;
+    use rand::SeedableRng;
+
+    // Tic-tac-toe is a draw, so the tablebase playing itself has to draw every game
+    #[test]
+    fn solves_tic_tac_toe() -> Result<()> {
+        let tablebase = Tablebase::generate::<9, 18, TicTacToe>(10_000)?;
+        assert_eq!(tablebase.value(&TicTacToe::new()), Some(0.0));
+        let policy = TablebasePolicy {
+            tablebase,
+            policy: RandomPolicy::default(),
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let report = first_player_advantage::<9, 18, TicTacToe, _>(100, &policy, &mut rng)?;
+        assert_eq!(report.draws, report.games, "Perfect play lost a game");
+        Ok(())
+    }
+
+    // The first player wins 3x3 Hex by taking the centre but loses in the corner, and perfect play
+    // keeps the win
+    #[test]
+    fn solves_hex_3x3() -> Result<()> {
+        let tablebase = Tablebase::generate::<9, 18, Hex<9, 18>>(100_000)?;
+        assert_eq!(tablebase.value(&Hex::<9, 18>::new()), Some(1.0));
+        assert_eq!(tablebase.value(&Hex::<9, 18>::from_moves(&[4])?), Some(1.0));
+        assert_eq!(
+            tablebase.value(&Hex::<9, 18>::from_moves(&[0])?),
+            Some(-1.0)
+        );
+        let policy = TablebasePolicy {
+            tablebase,
+            policy: RandomPolicy::default(),
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let report = first_player_advantage::<9, 18, Hex<9, 18>, _>(100, &policy, &mut rng)?;
+        assert_eq!(
+            report.first_player_wins, report.games,
+            "Perfect play lost a won game"
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[ignore = "solves about 8 million positions"]
+    fn solves_hex_4x4() -> Result<()> {
+        let tablebase = Tablebase::generate::<16, 32, Hex<16, 32>>(10_000_000)?;
+        assert_eq!(tablebase.value(&Hex::<16, 32>::new()), Some(1.0));
+        Ok(())
+    }
+}
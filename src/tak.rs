@@ -0,0 +1,811 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    fmt::Display,
+    hash::{Hash, Hasher},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::{
+    game::{encode_board, swap_board, Cell, Game, Players},
+    mcts::GameStats,
+};
+
+const SIDE: usize = 5;
+const SQUARES: usize = SIDE * SIDE;
+const STONES: usize = 21;
+const CAPSTONES: usize = 1;
+// Stones that can be picked up from a stack, also the most a spread can drop
+const CARRY_LIMIT: usize = SIDE;
+// Drop sequences of at most CARRY_LIMIT stones, see Tak::drops
+const PATTERNS: usize = (1 << CARRY_LIMIT) - 1;
+const PLACEMENTS: usize = SQUARES * 3;
+pub const MOVES: usize = PLACEMENTS + SQUARES * 4 * PATTERNS;
+// Stones under the top whose owners are encoded, deeper ones are left out
+const ENCODED_DEPTH: usize = CARRY_LIMIT;
+pub const STATE_LEN: usize = SQUARES * Stack::FEATURES + 4;
+// Plies after which the game is a draw, spreads alone can go on forever
+const PLY_LIMIT: usize = 300;
+// Up, down, left and right as in the spread notation "+", "-", "<" and ">"
+const DIRECTIONS: [(isize, isize); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+const DIRECTION_SYMBOLS: [char; 4] = ['+', '-', '<', '>'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Stone {
+    #[default]
+    Flat,
+    Wall,
+    Capstone,
+}
+
+/// A square of the board. Only the top stone can be a wall or a capstone, everything under it
+/// is flat, so a stack is its owners from the bottom up and the kind of its top stone
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Stack {
+    owners: Vec<Players>,
+    top: Stone,
+}
+
+impl Stack {
+    fn controller(&self) -> Option<Players> {
+        self.owners.last().copied()
+    }
+
+    fn blocks(&self) -> bool {
+        !self.owners.is_empty() && self.top != Stone::Flat
+    }
+}
+
+// The kind of the top stone for each owner, then one hot owners of the stones under it from the
+// top down
+impl Cell for Stack {
+    const FEATURES: usize = 6 + 2 * ENCODED_DEPTH;
+
+    fn encode(&self, out: &mut [f32]) {
+        out.fill(0.0);
+        let Some(controller) = self.controller() else {
+            return;
+        };
+        let owner_offset = |player: Players| match player {
+            Players::Player => 0,
+            Players::Opponent => 1,
+        };
+        out[owner_offset(controller) * 3 + self.top as usize] = 1.0;
+        for (depth, owner) in self
+            .owners
+            .iter()
+            .rev()
+            .skip(1)
+            .take(ENCODED_DEPTH)
+            .enumerate()
+        {
+            out[6 + depth * 2 + owner_offset(*owner)] = 1.0;
+        }
+    }
+
+    fn swap(&self) -> Self {
+        Self {
+            owners: self.owners.iter().map(|owner| owner.swap()).collect(),
+            top: self.top,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Reserve {
+    stones: usize,
+    capstones: usize,
+}
+
+/// Tak on a 5x5 board with 21 stones and a capstone each. A move places a flat stone, a
+/// standing wall or the capstone on an empty square, or spreads stones from the top of a stack
+/// you control in a straight line, dropping at least one on every square. Walls and capstones
+/// cannot be covered, but a capstone dropped alone flattens a wall. A road of your flats and
+/// capstones between opposite edges wins, and if the board fills up or someone runs out of
+/// stones the player with more flats on top wins. In the first two plies each player places a
+/// flat of the other colour.
+///
+/// Placements are square * 3 + 0, 1 or 2 for a flat, a wall or the capstone. A spread is
+/// PLACEMENTS + (square * 4 + direction) * PATTERNS + pattern - 1, with pattern from Tak::drops
+#[derive(Debug, Clone)]
+pub struct Tak {
+    // Row major from the top left, square a5
+    board: [Stack; SQUARES],
+    // Stones in hand of Player and Opponent
+    reserves: [Reserve; 2],
+    current_player: Players,
+    ended: bool,
+    winning_player: Option<Players>,
+    // Board and reserves before every move, for undo_move
+    history: Vec<([Stack; SQUARES], [Reserve; 2])>,
+}
+
+impl Tak {
+    fn reserve(&mut self, player: Players) -> &mut Reserve {
+        match player {
+            Players::Player => &mut self.reserves[0],
+            Players::Opponent => &mut self.reserves[1],
+        }
+    }
+
+    fn neighbour(square: usize, direction: usize) -> Option<usize> {
+        let (dr, dc) = DIRECTIONS[direction];
+        let row = (square / SIDE) as isize + dr;
+        let column = (square % SIDE) as isize + dc;
+        let on_board = (0..SIDE as isize).contains(&row) && (0..SIDE as isize).contains(&column);
+        on_board.then(|| row as usize * SIDE + column as usize)
+    }
+
+    /// Stones dropped on each square of a spread. The pattern in binary, read from its highest
+    /// bit, has a 1 for the first stone of every drop and a 0 for each further stone, so its
+    /// length is the number of stones carried: 0b1 drops [1], 0b110 drops [1, 2]
+    pub fn drops(pattern: usize) -> Vec<usize> {
+        let carried = (usize::BITS - pattern.leading_zeros()) as usize;
+        let mut drops: Vec<usize> = Vec::new();
+        for bit in (0..carried).rev() {
+            match drops.last_mut() {
+                Some(last) if pattern >> bit & 1 == 0 => *last += 1,
+                _ => drops.push(1),
+            }
+        }
+        drops
+    }
+
+    fn pattern(drops: &[usize]) -> usize {
+        drops
+            .iter()
+            .fold(0, |pattern, drop| (pattern << drop) | 1 << (drop - 1))
+    }
+
+    // Squares a spread drops on, None if it is not legal for the player to move
+    fn spread_targets(
+        &self,
+        square: usize,
+        direction: usize,
+        drops: &[usize],
+    ) -> Option<Vec<usize>> {
+        let stack = &self.board[square];
+        let carried: usize = drops.iter().sum();
+        if self.history.len() < 2
+            || stack.controller() != Some(self.current_player)
+            || carried > stack.owners.len()
+        {
+            return None;
+        }
+        let mut targets = Vec::with_capacity(drops.len());
+        let mut current = square;
+        for (i, drop) in drops.iter().enumerate() {
+            current = Self::neighbour(current, direction)?;
+            let target = &self.board[current];
+            let flattens = i == drops.len() - 1
+                && *drop == 1
+                && stack.top == Stone::Capstone
+                && target.top == Stone::Wall;
+            if target.blocks() && !flattens {
+                return None;
+            }
+            targets.push(current);
+        }
+        Some(targets)
+    }
+
+    fn has_road(&self, player: Players) -> bool {
+        let road = |square: usize| {
+            let stack = &self.board[square];
+            stack.controller() == Some(player) && stack.top != Stone::Wall
+        };
+        let edges: [(fn(usize) -> bool, fn(usize) -> bool); 2] = [
+            (|square| square < SIDE, |square| square >= SQUARES - SIDE),
+            (
+                |square| square % SIDE == 0,
+                |square| square % SIDE == SIDE - 1,
+            ),
+        ];
+        edges.iter().any(|(start, end)| {
+            let mut reached: Vec<usize> = (0..SQUARES).filter(|s| start(*s) && road(*s)).collect();
+            let mut seen = [false; SQUARES];
+            reached.iter().for_each(|square| seen[*square] = true);
+            while let Some(square) = reached.pop() {
+                if end(square) {
+                    return true;
+                }
+                for direction in 0..4 {
+                    if let Some(next) = Self::neighbour(square, direction) {
+                        if !seen[next] && road(next) {
+                            seen[next] = true;
+                            reached.push(next);
+                        }
+                    }
+                }
+            }
+            false
+        })
+    }
+
+    /// Squares with a flat on top owned by Player and Opponent, which decide a game that ends
+    /// without a road
+    pub fn flat_count(&self) -> (usize, usize) {
+        let count = |player: Players| {
+            self.board
+                .iter()
+                .filter(|stack| stack.controller() == Some(player) && stack.top == Stone::Flat)
+                .count()
+        };
+        (count(Players::Player), count(Players::Opponent))
+    }
+
+    // Roads first, the one of the player who moved wins when both get one, then flats
+    fn update_result(&mut self) {
+        let mover = self.current_player;
+        if let Some(player) = [mover, mover.swap()]
+            .into_iter()
+            .find(|player| self.has_road(*player))
+        {
+            self.ended = true;
+            self.winning_player = Some(player);
+            return;
+        }
+        let board_full = self.board.iter().all(|stack| !stack.owners.is_empty());
+        let out_of_stones = self
+            .reserves
+            .iter()
+            .any(|reserve| reserve.stones + reserve.capstones == 0);
+        if board_full || out_of_stones {
+            self.ended = true;
+            let (player, opponent) = self.flat_count();
+            self.winning_player = match player.cmp(&opponent) {
+                std::cmp::Ordering::Greater => Some(Players::Player),
+                std::cmp::Ordering::Less => Some(Players::Opponent),
+                std::cmp::Ordering::Equal => None,
+            };
+        }
+    }
+
+    fn square_name(square: usize) -> String {
+        format!(
+            "{}{}",
+            (b'a' + (square % SIDE) as u8) as char,
+            SIDE - square / SIDE
+        )
+    }
+
+    fn parse_square(text: &str) -> Result<usize> {
+        let mut chars = text.chars();
+        let (Some(column), Some(rank), None) = (chars.next(), chars.next(), chars.next()) else {
+            bail!("'{}' is not a square", text);
+        };
+        let column = (column as usize).wrapping_sub('a' as usize);
+        let rank = rank.to_digit(10).unwrap_or(0) as usize;
+        ensure!(
+            column < SIDE && (1..=SIDE).contains(&rank),
+            "Square '{}' is outside the board",
+            text
+        );
+        Ok((SIDE - rank) * SIDE + column)
+    }
+}
+
+impl Game<MOVES, STATE_LEN> for Tak {
+    fn winning_player(&self) -> Option<Players> {
+        self.winning_player
+    }
+
+    fn available_moves(&self) -> [bool; MOVES] {
+        let mut moves = [false; MOVES];
+        if self.ended {
+            return moves;
+        }
+        let opening = self.history.len() < 2;
+        let reserve = match self.current_player {
+            Players::Player => self.reserves[0],
+            Players::Opponent => self.reserves[1],
+        };
+        for (square, stack) in self.board.iter().enumerate() {
+            if stack.owners.is_empty() {
+                moves[square * 3] = opening || reserve.stones > 0;
+                moves[square * 3 + 1] = !opening && reserve.stones > 0;
+                moves[square * 3 + 2] = !opening && reserve.capstones > 0;
+                continue;
+            }
+            for direction in 0..4 {
+                for pattern in 1..=PATTERNS {
+                    let mv = PLACEMENTS + (square * 4 + direction) * PATTERNS + pattern - 1;
+                    moves[mv] = self
+                        .spread_targets(square, direction, &Self::drops(pattern))
+                        .is_some();
+                }
+            }
+        }
+        moves
+    }
+
+    fn try_perform_move(&mut self, space: usize) -> Result<()> {
+        ensure!(!self.ended, "Tried to move after the game ended");
+        ensure!(
+            space < MOVES,
+            "Tried to make a Tak move that does not exist"
+        );
+        let snapshot = (self.board.clone(), self.reserves);
+        let player = self.current_player;
+        if space < PLACEMENTS {
+            let (square, kind) = (space / 3, space % 3);
+            ensure!(
+                self.board[square].owners.is_empty(),
+                "Tried to place a stone on an occupied square"
+            );
+            // The opening flats are taken from the other player's stones
+            let opening = self.history.len() < 2;
+            let owner = if opening { player.swap() } else { player };
+            let top = [Stone::Flat, Stone::Wall, Stone::Capstone][kind];
+            ensure!(
+                !opening || top == Stone::Flat,
+                "Only flat stones can be placed in the opening"
+            );
+            let reserve = self.reserve(owner);
+            let left = if top == Stone::Capstone {
+                &mut reserve.capstones
+            } else {
+                &mut reserve.stones
+            };
+            ensure!(*left > 0, "No stones of that kind left to place");
+            *left -= 1;
+            self.board[square] = Stack {
+                owners: vec![owner],
+                top,
+            };
+        } else {
+            let spread = space - PLACEMENTS;
+            let (square, direction) = (spread / PATTERNS / 4, spread / PATTERNS % 4);
+            let drops = Self::drops(spread % PATTERNS + 1);
+            let targets = self
+                .spread_targets(square, direction, &drops)
+                .context("Tried to make an illegal spread")?;
+            let source = &mut self.board[square];
+            let carried: usize = drops.iter().sum();
+            let mut stones = source.owners.split_off(source.owners.len() - carried);
+            let top = std::mem::take(&mut source.top);
+            for (target, drop) in targets.iter().zip(drops) {
+                let rest = stones.split_off(drop);
+                let target = &mut self.board[*target];
+                target.owners.extend(stones);
+                target.top = if rest.is_empty() { top } else { Stone::Flat };
+                stones = rest;
+            }
+        }
+        self.history.push(snapshot);
+        self.update_result();
+        self.current_player = player.swap();
+        Ok(())
+    }
+
+    // Moves are only legal while the game is running, so it was before the last one
+    fn undo_move(&mut self) -> Result<()> {
+        let (board, reserves) = self.history.pop().context("No move to undo")?;
+        self.board = board;
+        self.reserves = reserves;
+        self.current_player = self.current_player.swap();
+        self.ended = false;
+        self.winning_player = None;
+        Ok(())
+    }
+
+    fn new() -> Self {
+        Self {
+            board: std::array::from_fn(|_| Stack::default()),
+            reserves: [Reserve {
+                stones: STONES,
+                capstones: CAPSTONES,
+            }; 2],
+            current_player: Players::Player,
+            ended: false,
+            winning_player: None,
+            history: Vec::new(),
+        }
+    }
+
+    fn game_ended(&self) -> bool {
+        self.ended
+    }
+
+    fn is_draw_by_rule(&self) -> bool {
+        !self.ended && self.history.len() >= PLY_LIMIT
+    }
+
+    fn current_player(&self) -> Players {
+        self.current_player
+    }
+
+    fn flip_board(&mut self) {
+        swap_board(&mut self.board);
+        self.reserves.swap(0, 1);
+        self.current_player = self.current_player.swap();
+        self.winning_player = self.winning_player.map(|player| player.swap());
+    }
+
+    // The stack planes, then the stones and capstones left to Player and Opponent
+    fn get_game_state_slice(&self) -> [f32; STATE_LEN] {
+        let mut out_slice: [f32; STATE_LEN] = encode_board(&self.board);
+        for (i, reserve) in self.reserves.iter().enumerate() {
+            out_slice[SQUARES * Stack::FEATURES + i * 2] = reserve.stones as f32 / STONES as f32;
+            out_slice[SQUARES * Stack::FEATURES + i * 2 + 1] =
+                reserve.capstones as f32 / CAPSTONES as f32;
+        }
+        out_slice
+    }
+
+    // The state slice leaves out stones deep in a stack, which the hash has to tell apart
+    fn position_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        for stack in &self.board {
+            for owner in &stack.owners {
+                (*owner == Players::Player).hash(&mut hasher);
+            }
+            (stack.owners.len(), stack.top as u8).hash(&mut hasher);
+        }
+        for reserve in &self.reserves {
+            (reserve.stones, reserve.capstones).hash(&mut hasher);
+        }
+        (
+            self.current_player == Players::Player,
+            self.history.len() < 2,
+        )
+            .hash(&mut hasher);
+        hasher.finish()
+    }
+
+    // The 8 rotations and reflections of the square, spreads turn with the board
+    fn get_game_variations(
+        stats: &GameStats<MOVES, STATE_LEN>,
+    ) -> Vec<GameStats<MOVES, STATE_LEN>> {
+        let symmetric = |square: usize, symmetry: usize| {
+            let (mut r, mut c) = (square / SIDE, square % SIDE);
+            if symmetry >= 4 {
+                (r, c) = (c, r);
+            }
+            if symmetry & 1 == 1 {
+                c = SIDE - 1 - c;
+            }
+            if symmetry & 2 == 2 {
+                r = SIDE - 1 - r;
+            }
+            r * SIDE + c
+        };
+        let symmetric_move = |mv: usize, symmetry: usize| {
+            if mv < PLACEMENTS {
+                return symmetric(mv / 3, symmetry) * 3 + mv % 3;
+            }
+            let spread = mv - PLACEMENTS;
+            let (square, direction) = (spread / PATTERNS / 4, spread / PATTERNS % 4);
+            // Where the square one step away ends up gives the new direction
+            let next = Self::neighbour(square, direction).unwrap();
+            let (from, to) = (symmetric(square, symmetry), symmetric(next, symmetry));
+            let direction = (0..4)
+                .find(|d| Self::neighbour(from, *d) == Some(to))
+                .unwrap();
+            PLACEMENTS + (from * 4 + direction) * PATTERNS + spread % PATTERNS
+        };
+        (0..8)
+            .map(|symmetry| {
+                let mut variation = stats.clone();
+                for plane in 0..Stack::FEATURES {
+                    for square in 0..SQUARES {
+                        variation.game_state[plane * SQUARES + symmetric(square, symmetry)] =
+                            stats.game_state[plane * SQUARES + square];
+                    }
+                }
+                variation.node_visits = [0.0; MOVES];
+                for (mv, visits) in stats.node_visits.iter().enumerate() {
+                    if *visits > 0.0 {
+                        variation.node_visits[symmetric_move(mv, symmetry)] = *visits;
+                    }
+                }
+                variation.best_move_index = symmetric_move(stats.best_move_index, symmetry);
+                variation
+            })
+            .collect()
+    }
+
+    // Portable Tak Notation, like "a1", "Sb2" and "Cc3" for placements and "3a1>21" for a
+    // spread of three stones dropping two and then one
+    fn move_to_string(&self, mv: usize) -> String {
+        if mv < PLACEMENTS {
+            let prefix = ["", "S", "C"][mv % 3];
+            return format!("{}{}", prefix, Self::square_name(mv / 3));
+        }
+        let spread = mv - PLACEMENTS;
+        let (square, direction) = (spread / PATTERNS / 4, spread / PATTERNS % 4);
+        let drops = Self::drops(spread % PATTERNS + 1);
+        let carried: usize = drops.iter().sum();
+        let count = if carried > 1 {
+            carried.to_string()
+        } else {
+            String::new()
+        };
+        let drops = if drops.len() > 1 {
+            drops.iter().map(|drop| drop.to_string()).collect()
+        } else {
+            String::new()
+        };
+        format!(
+            "{}{}{}{}",
+            count,
+            Self::square_name(square),
+            DIRECTION_SYMBOLS[direction],
+            drops
+        )
+    }
+
+    fn move_from_string(&self, text: &str) -> Result<usize> {
+        let text = text.trim();
+        let Some(direction_at) = text.find(DIRECTION_SYMBOLS) else {
+            let (kind, square) = match text.chars().next() {
+                Some('F') => (0, &text[1..]),
+                Some('S') => (1, &text[1..]),
+                Some('C') => (2, &text[1..]),
+                _ => (0, text),
+            };
+            return Ok(Self::parse_square(square)? * 3 + kind);
+        };
+        let (start, drops) = text.split_at(direction_at);
+        let direction = DIRECTION_SYMBOLS
+            .iter()
+            .position(|symbol| drops.starts_with(*symbol))
+            .unwrap();
+        let (count, square) = start.split_at(start.len().saturating_sub(2));
+        let square = Self::parse_square(square)?;
+        let carried = if count.is_empty() {
+            1
+        } else {
+            count
+                .parse()
+                .with_context(|| format!("Bad stone count in '{}'", text))?
+        };
+        let drops: Vec<usize> = if drops.len() > 1 {
+            drops[1..]
+                .chars()
+                .map(|c| c.to_digit(10).map(|d| d as usize))
+                .collect::<Option<_>>()
+                .with_context(|| format!("Bad drops in '{}'", text))?
+        } else {
+            vec![carried]
+        };
+        ensure!(
+            drops.iter().sum::<usize>() == carried && !drops.contains(&0),
+            "Drops of '{}' do not add up to the stones carried",
+            text
+        );
+        ensure!(
+            carried <= CARRY_LIMIT,
+            "Cannot carry more than {} stones",
+            CARRY_LIMIT
+        );
+        Ok(PLACEMENTS + (square * 4 + direction) * PATTERNS + Self::pattern(&drops) - 1)
+    }
+}
+
+// Stacks from the bottom up, X and O for the stones of Player and Opponent and a trailing S or C
+// when the top is a wall or the capstone
+impl Display for Tak {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cells: Vec<String> = self
+            .board
+            .iter()
+            .map(|stack| {
+                let mut cell: String = stack
+                    .owners
+                    .iter()
+                    .map(|owner| match owner {
+                        Players::Player => 'X',
+                        Players::Opponent => 'O',
+                    })
+                    .collect();
+                match (stack.owners.is_empty(), stack.top) {
+                    (true, _) => cell.push('.'),
+                    (false, Stone::Wall) => cell.push('S'),
+                    (false, Stone::Capstone) => cell.push('C'),
+                    (false, Stone::Flat) => {}
+                }
+                cell
+            })
+            .collect();
+        let width = cells.iter().map(|cell| cell.len()).max().unwrap_or(1);
+        for (row, chunk) in cells.chunks_exact(SIDE).enumerate() {
+            let chunk: Vec<String> = chunk
+                .iter()
+                .map(|cell| format!("{:<width$}", cell))
+                .collect();
+            writeln!(f, "{} {}", SIDE - row, chunk.join(" "))?;
+        }
+        let columns: Vec<String> = (0..SIDE)
+            .map(|column| format!("{:<width$}", (b'a' + column as u8) as char))
+            .collect();
+        writeln!(f, "  {}", columns.join(" "))?;
+        let next_player = match self.current_player {
+            Players::Player => "X",
+            Players::Opponent => "O",
+        };
+        writeln!(f, "Next player: {}", next_player)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+
+    use super::*;
+    use crate::game::move_indices;
+
+    fn stack(owners: &[Players], top: Stone) -> Stack {
+        Stack {
+            owners: owners.to_vec(),
+            top,
+        }
+    }
+
+    // Player to move past the opening with only the given stacks on the board
+    fn position(stacks: &[(usize, Stack)]) -> Tak {
+        let mut game = Tak::new();
+        game.history = vec![(game.board.clone(), game.reserves); 2];
+        for (square, stack) in stacks {
+            game.board[*square] = stack.clone();
+        }
+        game
+    }
+
+    fn spread(square: usize, direction: usize, drops: &[usize]) -> usize {
+        PLACEMENTS + (square * 4 + direction) * PATTERNS + Tak::pattern(drops) - 1
+    }
+
+    const P: Players = Players::Player;
+    const O: Players = Players::Opponent;
+    const RIGHT: usize = 3;
+
+    #[test]
+    fn patterns_round_trip() {
+        assert_eq!(Tak::drops(0b1), vec![1]);
+        assert_eq!(Tak::drops(0b110), vec![1, 2]);
+        for pattern in 1..=PATTERNS {
+            let drops = Tak::drops(pattern);
+            assert!(!drops.contains(&0));
+            assert!(drops.iter().sum::<usize>() <= CARRY_LIMIT);
+            assert_eq!(Tak::pattern(&drops), pattern);
+        }
+    }
+
+    #[test]
+    fn only_a_lone_capstone_dropped_last_flattens_a_wall() -> Result<()> {
+        let wall = (13, stack(&[O], Stone::Wall));
+        let mut game = position(&[(12, stack(&[P, P], Stone::Capstone)), wall.clone()]);
+        let moves = game.available_moves();
+        assert!(!moves[spread(12, RIGHT, &[2])]);
+        assert!(!moves[spread(12, RIGHT, &[1, 1])]);
+        assert!(moves[spread(12, RIGHT, &[1])]);
+        game.try_perform_move(spread(12, RIGHT, &[1]))?;
+        assert_eq!(game.board[13], stack(&[O, P], Stone::Capstone));
+        assert_eq!(game.board[12], stack(&[P], Stone::Flat));
+
+        // Dropped last after the stones under it
+        let game = position(&[(11, stack(&[P, P], Stone::Capstone)), wall.clone()]);
+        assert!(game.available_moves()[spread(11, RIGHT, &[1, 1])]);
+        // But not a flat
+        let game = position(&[(12, stack(&[P], Stone::Flat)), wall]);
+        assert!(!game.available_moves()[spread(12, RIGHT, &[1])]);
+        Ok(())
+    }
+
+    #[test]
+    fn walls_do_not_make_roads() {
+        let mut game = position(&[]);
+        for square in 10..15 {
+            game.board[square] = stack(&[P], Stone::Flat);
+        }
+        assert!(game.has_road(P));
+        assert!(!game.has_road(O));
+        game.board[12] = stack(&[P], Stone::Wall);
+        assert!(!game.has_road(P));
+        game.board[12] = stack(&[O, P], Stone::Capstone);
+        assert!(game.has_road(P));
+    }
+
+    #[test]
+    fn flats_decide_when_the_stones_run_out() -> Result<()> {
+        let mut game = position(&[
+            (0, stack(&[P], Stone::Flat)),
+            (1, stack(&[P], Stone::Flat)),
+            (2, stack(&[P], Stone::Wall)),
+            (6, stack(&[P], Stone::Flat)),
+            (24, stack(&[O], Stone::Flat)),
+        ]);
+        game.current_player = O;
+        game.reserves[1] = Reserve {
+            stones: 1,
+            capstones: 0,
+        };
+        game.try_perform_move(23 * 3)?;
+        assert!(game.game_ended());
+        assert_eq!(game.flat_count(), (3, 2));
+        assert_eq!(game.winning_player(), Some(P));
+        game.undo_move()?;
+        assert!(!game.game_ended());
+        Ok(())
+    }
+
+    #[test]
+    fn openings_place_a_flat_of_the_other_colour() -> Result<()> {
+        let mut game = Tak::new();
+        let moves = game.available_moves();
+        assert!((0..SQUARES)
+            .all(|square| moves[square * 3] && !moves[square * 3 + 1] && !moves[square * 3 + 2]));
+        assert!(moves[PLACEMENTS..].iter().all(|available| !available));
+        assert!(game.try_perform_move(1).is_err());
+        game.try_perform_move(0)?;
+        assert_eq!(game.board[0], stack(&[O], Stone::Flat));
+        assert_eq!(game.reserves[1].stones, STONES - 1);
+        game.try_perform_move(24 * 3)?;
+        assert_eq!(game.board[24], stack(&[P], Stone::Flat));
+        assert!(game.available_moves()[3 + 1]);
+        Ok(())
+    }
+
+    #[test]
+    fn notation_round_trips() -> Result<()> {
+        let game = Tak::new();
+        for text in ["a1", "Sb2", "Cc3", "3a1>21", "e5-", "2c3<11"] {
+            let mv = game.move_from_string(text)?;
+            assert_eq!(game.move_to_string(mv), text);
+        }
+        assert_eq!(game.move_from_string("Fa5")?, 0);
+        assert_eq!(game.move_from_string("3a1>21")?, spread(20, RIGHT, &[2, 1]));
+        for text in ["f1", "a6", "3a1>12x", "2a1>3", "6a1>"] {
+            assert!(game.move_from_string(text).is_err(), "{}", text);
+        }
+        for mv in 0..MOVES {
+            assert_eq!(game.move_from_string(&game.move_to_string(mv))?, mv);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn variations_map_legal_moves_to_legal_moves() -> Result<()> {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut game = Tak::new();
+        let mut symmetric: Vec<Tak> = vec![Tak::new(); 8];
+        for _ in 0..40 {
+            if game.game_ended() {
+                break;
+            }
+            let moves = move_indices(&game);
+            // Visits that tell which move each one came from
+            let mut node_visits = [0.0; MOVES];
+            for mv in &moves {
+                node_visits[*mv] = *mv as f32 + 1.0;
+            }
+            let variations = Tak::get_game_variations(&GameStats {
+                best_move_index: moves[0],
+                game_state: game.get_game_state_slice(),
+                node_visits,
+                value: 0.0,
+                score: 0.0,
+                extra_targets: Vec::new(),
+            });
+            assert_eq!(variations.len(), 8);
+            let mv = *moves.choose(&mut rng).unwrap();
+            for (variation, other) in variations.iter().zip(symmetric.iter_mut()) {
+                assert_eq!(variation.game_state, other.get_game_state_slice());
+                let available = other.available_moves();
+                let mapped: Vec<usize> = (0..MOVES)
+                    .filter(|new| variation.node_visits[*new] > 0.0)
+                    .collect();
+                assert_eq!(mapped.len(), moves.len());
+                assert!(mapped.iter().all(|new| available[*new]));
+                let new = mapped
+                    .into_iter()
+                    .find(|new| variation.node_visits[*new] == mv as f32 + 1.0)
+                    .unwrap();
+                other.try_perform_move(new)?;
+            }
+            game.try_perform_move(mv)?;
+        }
+        Ok(())
+    }
+}
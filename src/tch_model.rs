@@ -0,0 +1,443 @@
+//! SimpleModel on libtorch through tch, for hardware candle does not run on. Built with the tch
+//! feature, which needs libtorch as the tch crate describes. The layers are named like the ones of
+//! SimpleModel, so either can load the safetensors of the other
+
+use anyhow::{bail, ensure, Context};
+use tch::nn::{self, Module, OptimizerConfig};
+use tch::{Device, Kind, Tensor};
+
+use crate::candle_ai::{requested_device, Role};
+use crate::checkpoint;
+use crate::dataset::Dataset;
+use crate::fit::{
+    self, fit_minibatches, softmax, Heads, LossTensor, LossWeights, Losses, MinibatchFit, Samples,
+    TrainingRows,
+};
+use crate::model::{Architecture, ModelConfig, TrainReport, TrainableModel, TrainingMetrics};
+
+/// The training device, see device_for
+pub fn select_device() -> Device {
+    device_for(Role::Training)
+}
+
+/// The device asked for `role` in DEVICE_VARIABLE or SELF_PLAY_DEVICE_VARIABLE: cpu, cuda,
+/// cuda:<ordinal> or mps. The CPU when none or an unavailable one is asked for
+pub fn device_for(role: Role) -> Device {
+    let requested = requested_device(role);
+    let device = match requested.as_str() {
+        "" | "cpu" => return Device::Cpu,
+        "mps" if tch::utils::has_mps() => Some(Device::Mps),
+        name => name
+            .strip_prefix("cuda")
+            .and_then(|ordinal| match ordinal {
+                "" => Some(0),
+                ordinal => ordinal.strip_prefix(':')?.parse().ok(),
+            })
+            .filter(|ordinal| *ordinal < tch::Cuda::device_count() as usize)
+            .map(Device::Cuda),
+    };
+    device.unwrap_or_else(|| {
+        eprintln!("Device '{}' is not available, using the CPU", requested);
+        Device::Cpu
+    })
+}
+
+pub struct TchModel<const N: usize, const I: usize> {
+    config: ModelConfig,
+    // Hidden layers, each followed by a relu and the dropout
+    layers: Vec<nn::Linear>,
+    visit_head: nn::Linear,
+    score_head: nn::Linear,
+    margin_head: nn::Linear,
+    ownership_head: Option<nn::Linear>,
+    vars: nn::VarStore,
+    // Made on the first step like for the candle models, None again after reset_optimizer
+    optimizer: Option<nn::Optimizer>,
+    // Seed the weights were initialized with, from the metadata for loaded ones
+    init_seed: Option<u64>,
+    generation: usize,
+    /// Weight of the value loss relative to the policy cross-entropy
+    pub value_weight: f32,
+    /// Weight of the margin loss relative to the policy cross-entropy
+    pub margin_weight: f32,
+    /// Weight of the ownership loss relative to the policy cross-entropy
+    pub ownership_weight: f32,
+}
+
+impl<const N: usize, const I: usize> TchModel<N, I> {
+    /// Model on `device`, TrainableModel::new picks it with select_device
+    pub fn with_device(config: ModelConfig, device: Device) -> anyhow::Result<Self> {
+        // libtorch draws the initial weights from its global generator
+        let init_seed = config.seed.unwrap_or_else(rand::random);
+        tch::manual_seed(init_seed as i64);
+        let mut model = Self::build(config, device)?;
+        model.init_seed = Some(init_seed);
+        Ok(model)
+    }
+
+    // The layers with weights drawn from the global generator as it is, for callers that seed it
+    // or overwrite the weights
+    fn build(config: ModelConfig, device: Device) -> anyhow::Result<Self> {
+        ensure!(config.depth > 0, "The model needs a hidden layer");
+        ensure!(
+            (0.0..1.0).contains(&config.dropout),
+            "The dropout rate {} is not in [0, 1)",
+            config.dropout
+        );
+        let hidden_size = config.hidden_size as i64;
+        let vars = nn::VarStore::new(device);
+        let root = vars.root();
+        let layers = (0..config.depth)
+            .map(|layer| {
+                let inputs = if layer == 0 { I as i64 } else { hidden_size };
+                let path = &root / format!("layer {}", layer + 1);
+                nn::linear(path, inputs, hidden_size, Default::default())
+            })
+            .collect();
+        let visit_head = nn::linear(
+            &root / "visit_head",
+            hidden_size,
+            N as i64,
+            Default::default(),
+        );
+        let score_head = nn::linear(&root / "score_head", hidden_size, 1, Default::default());
+        let margin_head = nn::linear(&root / "margin_head", hidden_size, 1, Default::default());
+        let ownership_head = config.ownership.then(|| {
+            nn::linear(
+                &root / "ownership_head",
+                hidden_size,
+                N as i64,
+                Default::default(),
+            )
+        });
+        Ok(Self {
+            config,
+            layers,
+            visit_head,
+            score_head,
+            margin_head,
+            ownership_head,
+            vars,
+            optimizer: None,
+            init_seed: None,
+            generation: 0,
+            value_weight: 1.0,
+            margin_weight: 1.0,
+            ownership_weight: 1.0,
+        })
+    }
+
+    // Dropout only applies when `train` is set
+    fn heads(&self, xs: &Tensor, train: bool) -> Heads<Tensor> {
+        let mut x = xs.shallow_clone();
+        for layer in &self.layers {
+            x = layer
+                .forward(&x)
+                .relu()
+                .dropout(self.config.dropout as f64, train);
+        }
+        Heads {
+            visit_logits: self.visit_head.forward(&x),
+            score: self.score_head.forward(&x).tanh(),
+            margin: self.margin_head.forward(&x).tanh(),
+            ownership: self
+                .ownership_head
+                .as_ref()
+                .map(|head| head.forward(&x).tanh()),
+        }
+    }
+
+    fn loss_weights(&self) -> LossWeights {
+        LossWeights {
+            value: self.value_weight,
+            margin: self.margin_weight,
+            ownership: self.ownership_weight,
+        }
+    }
+
+    // A single state as a batch of one on the model's device
+    fn state_tensor(&self, state: [f32; I]) -> Tensor {
+        Tensor::from_slice(&state)
+            .view([1, I as i64])
+            .to_device(self.vars.device())
+    }
+
+    // Policy logits with the illegal moves penalized, value and margin of a single state
+    fn predict_all(
+        &self,
+        state: [f32; I],
+        legal_moves: &[bool; N],
+    ) -> anyhow::Result<([f32; N], f32, f32)> {
+        let heads = tch::no_grad(|| self.heads(&self.state_tensor(state), false));
+        let penalty = Tensor::from_slice(&fit::illegal_penalty(&[*legal_moves]))
+            .view([1, N as i64])
+            .to_device(self.vars.device());
+        let logits = (heads.visit_logits + penalty)
+            .view([-1])
+            .to_device(Device::Cpu);
+        let logits = Vec::<f32>::try_from(&logits)?;
+        Ok((
+            logits.try_into().unwrap(),
+            heads.score.f_double_value(&[0, 0])? as f32,
+            heads.margin.f_double_value(&[0, 0])? as f32,
+        ))
+    }
+}
+
+impl LossTensor for Tensor {
+    fn row_index(&self, batch: &[u32]) -> anyhow::Result<Self> {
+        let batch: Vec<i64> = batch.iter().map(|row| *row as i64).collect();
+        Ok(Tensor::from_slice(&batch).to_device(self.device()))
+    }
+
+    fn select_rows(&self, index: &Self) -> anyhow::Result<Self> {
+        Ok(self.index_select(0, index))
+    }
+
+    fn plus(&self, other: &Self) -> anyhow::Result<Self> {
+        Ok(self + other)
+    }
+
+    fn minus(&self, other: &Self) -> anyhow::Result<Self> {
+        Ok(self - other)
+    }
+
+    fn times(&self, other: &Self) -> anyhow::Result<Self> {
+        Ok(self * other)
+    }
+
+    fn scaled(&self, factor: f64) -> anyhow::Result<Self> {
+        Ok(self * factor)
+    }
+
+    fn squared(&self) -> anyhow::Result<Self> {
+        Ok(self.square())
+    }
+
+    fn log_softmax_rows(&self) -> anyhow::Result<Self> {
+        Ok(self.log_softmax(1, Kind::Float))
+    }
+
+    fn row_sums(&self) -> anyhow::Result<Self> {
+        Ok(self.sum_dim_intlist(&[1i64][..], true, Kind::Float))
+    }
+
+    fn sum_all(&self) -> anyhow::Result<Self> {
+        Ok(self.sum(Kind::Float))
+    }
+
+    fn mean_all(&self) -> anyhow::Result<Self> {
+        Ok(self.mean(Kind::Float))
+    }
+
+    fn scalar(&self) -> anyhow::Result<f32> {
+        Ok(self.f_double_value(&[])? as f32)
+    }
+}
+
+// A model with the samples it is fit to
+struct TchFit<'a, const N: usize, const I: usize> {
+    model: &'a mut TchModel<N, I>,
+    samples: Samples<Tensor>,
+}
+
+impl<const N: usize, const I: usize> TchFit<'_, N, I> {
+    fn losses(&self, batch: &[u32], train: bool) -> anyhow::Result<Losses<Tensor>> {
+        self.samples
+            .losses(batch, |xs| Ok(self.model.heads(xs, train)))
+    }
+}
+
+impl<const N: usize, const I: usize> MinibatchFit for TchFit<'_, N, I> {
+    type Snapshot = Vec<(String, Tensor)>;
+
+    // Skips the step when the loss or the gradients are not finite, which would turn the weights
+    // into NaN for good, and scales the gradients down to max_grad_norm otherwise
+    fn step(
+        &mut self,
+        batch: &[u32],
+        learning_rate: f64,
+    ) -> anyhow::Result<Option<TrainingMetrics>> {
+        let fit = self.model.config.fit;
+        let losses = self.losses(batch, true)?;
+        let loss = losses.total(self.model.loss_weights())?;
+        if !loss.f_double_value(&[])?.is_finite() {
+            return Ok(None);
+        }
+        let optimizer = match &mut self.model.optimizer {
+            Some(optimizer) => optimizer,
+            None => self
+                .model
+                .optimizer
+                .insert(nn::AdamW::default().build(&self.model.vars, fit.learning_rate)?),
+        };
+        optimizer.set_lr(learning_rate);
+        optimizer.zero_grad();
+        loss.backward();
+        let mut squared_norm = 0.0;
+        for var in self.model.vars.trainable_variables() {
+            let grad = var.grad();
+            if grad.defined() {
+                squared_norm += grad.square().sum(Kind::Double).f_double_value(&[])?;
+            }
+        }
+        if !squared_norm.sqrt().is_finite() {
+            return Ok(None);
+        }
+        if let Some(max_norm) = fit.max_grad_norm {
+            optimizer.clip_grad_norm(max_norm);
+        }
+        optimizer.step();
+        Ok(Some(losses.metrics()?))
+    }
+
+    fn evaluate(&self, batch: &[u32]) -> anyhow::Result<TrainingMetrics> {
+        tch::no_grad(|| self.losses(batch, false))?.metrics()
+    }
+
+    fn snapshot(&self) -> anyhow::Result<Self::Snapshot> {
+        Ok(self
+            .model
+            .vars
+            .variables()
+            .into_iter()
+            .map(|(name, var)| (name, var.detach().copy()))
+            .collect())
+    }
+
+    fn restore(&mut self, snapshot: Self::Snapshot) -> anyhow::Result<()> {
+        let vars = self.model.vars.variables();
+        tch::no_grad(|| {
+            for (name, tensor) in &snapshot {
+                if let Some(var) = vars.get(name) {
+                    var.shallow_clone().copy_(tensor);
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+impl<const N: usize, const I: usize> TrainableModel<N, I> for TchModel<N, I> {
+    type Config = ModelConfig;
+
+    fn new(config: &ModelConfig) -> anyhow::Result<Self> {
+        Self::with_device(*config, select_device())
+    }
+
+    fn reset_optimizer(&mut self) -> anyhow::Result<()> {
+        self.optimizer = None;
+        Ok(())
+    }
+
+    fn set_generation(&mut self, generation: usize) {
+        self.generation = generation;
+    }
+
+    fn save(&self, path: &str) -> anyhow::Result<()> {
+        self.vars.save(path)?;
+        let mut metadata = checkpoint::model_metadata::<N, I>(&self.config, self.generation);
+        if let Some(seed) = self.init_seed {
+            metadata.insert(checkpoint::INIT_SEED.to_string(), seed.to_string());
+        }
+        checkpoint::write_metadata(path, &metadata)
+    }
+
+    // Built with the config saved with the weights, the default for files saved without one
+    fn load(path: &str) -> anyhow::Result<Self> {
+        checkpoint::check_dimensions::<N, I>(path)?;
+        let config = checkpoint::saved_config(path)?.unwrap_or_default();
+        let mut model = Self::build(config, select_device())?;
+        model
+            .vars
+            .load(path)
+            .with_context(|| format!("Loading model weights from {}", path))?;
+        checkpoint::check_unused(path, model.vars.variables().keys())?;
+        model.init_seed = checkpoint::init_seed(path)?;
+        Ok(model)
+    }
+
+    // Frozen variables need no gradients, and without an optimizer there is no state besides them
+    fn load_inference(path: &str) -> anyhow::Result<Self> {
+        let mut model = Self::load(path)?;
+        model.vars.freeze();
+        Ok(model)
+    }
+
+    fn for_self_play(&self) -> anyhow::Result<Self> {
+        let mut model = Self::build(self.config, device_for(Role::SelfPlay))?;
+        model.vars.copy(&self.vars)?;
+        model.vars.freeze();
+        model.init_seed = self.init_seed;
+        model.generation = self.generation;
+        Ok(model)
+    }
+
+    fn summary(&self) -> Architecture {
+        let shapes = self.vars.variables().into_iter().map(|(name, var)| {
+            let shape = var.size().into_iter().map(|size| size as usize).collect();
+            (name, shape)
+        });
+        Architecture::from_shapes(shapes, 1)
+    }
+
+    fn parameter_count(&self) -> usize {
+        self.vars.variables().values().map(|var| var.numel()).sum()
+    }
+
+    fn init_seed(&self) -> Option<u64> {
+        self.init_seed
+    }
+
+    fn train(&mut self, dataset: Dataset<N, I>) -> anyhow::Result<TrainReport> {
+        if self.vars.trainable_variables().is_empty() {
+            bail!("A model loaded for inference cannot be trained");
+        }
+        let rows = TrainingRows::new(&dataset);
+        let positions = dataset.source_positions();
+        let device = self.vars.device();
+        let samples = Samples::new::<N, I>(rows, |values, (count, columns)| {
+            Ok(Tensor::from_slice(values)
+                .view([count as i64, columns as i64])
+                .to_device(device))
+        })?;
+        let fit = self.config.fit;
+        let generation = self.generation;
+        let loss_weights = self.loss_weights();
+        let mut tch_fit = TchFit {
+            model: self,
+            samples,
+        };
+        fit_minibatches(&mut tch_fit, &positions, &fit, generation, loss_weights)
+    }
+
+    fn predict(&self, state: [f32; I], legal_moves: &[bool; N]) -> anyhow::Result<([f32; N], f32)> {
+        let (logits, score, _) = self.predict_all(state, legal_moves)?;
+        Ok((softmax(logits, 1.0), score))
+    }
+
+    fn predict_moves(&self, state: [f32; I], legal_moves: &[bool; N]) -> anyhow::Result<[f32; N]> {
+        Ok(self.predict(state, legal_moves)?.0)
+    }
+
+    fn predict_logits(&self, state: [f32; I], legal_moves: &[bool; N]) -> anyhow::Result<[f32; N]> {
+        Ok(self.predict_all(state, legal_moves)?.0)
+    }
+
+    fn predict_score(&self, state: [f32; I]) -> anyhow::Result<f32> {
+        Ok(self.predict_all(state, &[true; N])?.1)
+    }
+
+    fn predict_margin(&self, state: [f32; I]) -> anyhow::Result<Option<f32>> {
+        Ok(Some(self.predict_all(state, &[true; N])?.2))
+    }
+
+    fn predict_ownership(&self, state: [f32; I]) -> anyhow::Result<Option<[f32; N]>> {
+        let heads = tch::no_grad(|| self.heads(&self.state_tensor(state), false));
+        let Some(ownership) = heads.ownership else {
+            return Ok(None);
+        };
+        let ownership = Vec::<f32>::try_from(&ownership.view([-1]).to_device(Device::Cpu))?;
+        Ok(Some(ownership.try_into().unwrap()))
+    }
+}